@@ -1,26 +1,49 @@
 use crate::db;
 use crate::modules;
 
+use modules::backup;
 use modules::cloud::CloudService;
 use modules::dashboard::DashboardService;
 use modules::dining::DiningService;
 use modules::finance::FinanceService;
 use modules::gifts::GiftsService;
 use modules::grocery::GroceryService;
+use modules::household::sharing;
 use modules::household::HouseholdService;
 use modules::maintenance::MaintenanceService;
+use modules::notifications::{self, DesktopNotifier, LogNotifier};
 use modules::registry::ModuleRegistry;
 use modules::settings::SettingsService;
+use modules::sync::auto;
 use modules::travel::TravelService;
 use slint::VecModel;
 use std::path::Path;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
 
 slint::include_modules!();
 
+/// Opens the app database. Set `MYHOME_PASSPHRASE` to keep it encrypted at rest.
+#[allow(dead_code)]
+fn open_db(path: &str) -> db::Db {
+    db::Db::open_from_env(path).expect("Failed to open DB")
+}
+
+/// Runs a task that opens its own database connection in the background. A
+/// connection cannot be shared between threads, so such futures are not
+/// `Send`; each runs to completion on a blocking thread instead.
+fn spawn_with_db<Fut>(task: impl FnOnce() -> Fut + Send + 'static)
+where
+    Fut: std::future::Future<Output = ()>,
+{
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || runtime.block_on(task()));
+}
+
 #[allow(dead_code)]
 fn refresh_modules(ui: &AppWindow, db_path: &str) {
-    let database = db::Db::new(db_path).expect("Failed to open DB");
+    let database = open_db(db_path);
     let registry = ModuleRegistry::new(&database);
     let all_modules = registry.get_all_modules().expect("Failed to get modules");
 
@@ -38,7 +61,7 @@ fn refresh_modules(ui: &AppWindow, db_path: &str) {
 
 #[allow(dead_code)]
 fn refresh_dashboard_summary(ui: &AppWindow, db_path: &str) {
-    let database = db::Db::new(db_path).expect("Failed to open DB");
+    let database = open_db(db_path);
     let dashboard_service = DashboardService::new(&database);
     let settings_service = SettingsService::new(&database);
     let settings = settings_service
@@ -52,16 +75,19 @@ fn refresh_dashboard_summary(ui: &AppWindow, db_path: &str) {
 
     ui.set_welcome_message(format!("Welcome home, {}!", settings.user_name).into());
 
-    if let Ok(summary) = dashboard_service.get_summary() {
-        ui.set_dashboard_balance(format!("{:.2}", summary.net_balance).into());
-        ui.set_dashboard_trip_count(summary.active_trips as i32);
-        ui.set_dashboard_grocery_count(summary.grocery_items as i32);
+    match dashboard_service.get_summary() {
+        Ok(summary) => {
+            ui.set_dashboard_balance(format!("{:.2}", summary.net_balance).into());
+            ui.set_dashboard_trip_count(summary.active_trips as i32);
+            ui.set_dashboard_grocery_count(summary.grocery_items as i32);
+        }
+        Err(e) => eprintln!("Failed to load the dashboard summary: {}", e),
     }
 }
 
 #[allow(dead_code)]
 fn refresh_maintenance(ui: &AppWindow, db_path: &str) {
-    let database = db::Db::new(db_path).expect("Failed to open DB");
+    let database = open_db(db_path);
     let maintenance_service = MaintenanceService::new(&database);
 
     if let Ok(appliances) = maintenance_service.get_appliances() {
@@ -87,7 +113,7 @@ fn refresh_maintenance(ui: &AppWindow, db_path: &str) {
 
 #[allow(dead_code)]
 fn refresh_dining(ui: &AppWindow, db_path: &str) {
-    let database = db::Db::new(db_path).expect("Failed to open DB");
+    let database = open_db(db_path);
     let dining_service = DiningService::new(&database);
 
     if let Ok(restaurants) = dining_service.get_restaurants() {
@@ -118,7 +144,7 @@ fn refresh_dining(ui: &AppWindow, db_path: &str) {
 
 #[allow(dead_code)]
 fn refresh_travel(ui: &AppWindow, db_path: &str) {
-    let database = db::Db::new(db_path).expect("Failed to open DB");
+    let database = open_db(db_path);
     let travel_service = TravelService::new(&database);
 
     if let Ok(trips) = travel_service.get_trips() {
@@ -146,7 +172,7 @@ fn refresh_travel(ui: &AppWindow, db_path: &str) {
 
 #[allow(dead_code)]
 fn refresh_grocery(ui: &AppWindow, db_path: &str) {
-    let database = db::Db::new(db_path).expect("Failed to open DB");
+    let database = open_db(db_path);
     let grocery_service = GroceryService::new(&database);
 
     if let Ok(groceries) = grocery_service.get_grocery_list() {
@@ -186,7 +212,7 @@ fn refresh_grocery(ui: &AppWindow, db_path: &str) {
 
 #[allow(dead_code)]
 fn refresh_finance(ui: &AppWindow, db_path: &str) {
-    let database = db::Db::new(db_path).expect("Failed to open DB");
+    let database = open_db(db_path);
     let finance_service = FinanceService::new(&database);
     let settings_service = SettingsService::new(&database);
     let _settings = settings_service
@@ -198,7 +224,10 @@ fn refresh_finance(ui: &AppWindow, db_path: &str) {
             theme: "Dark".to_string(),
         });
 
-    ui.set_finance_balance(format!("{:.2}", finance_service.get_total_balance()).into());
+    match finance_service.get_total_balance() {
+        Ok(total) => ui.set_finance_balance(format!("{:.2}", total).into()),
+        Err(e) => eprintln!("Failed to total the account balances: {}", e),
+    }
 
     let mut first_account_id = None;
     if let Ok(accounts) = finance_service.get_accounts() {
@@ -235,10 +264,15 @@ fn refresh_finance(ui: &AppWindow, db_path: &str) {
                 is_income: tx.amount_cents > 0,
                 date: short_date.into(),
                 merchant: tx.merchant.into(),
-                category: tx
-                    .category_name
-                    .unwrap_or_else(|| "Uncategorized".to_string())
-                    .into(),
+                category: if tx.transfer_id.is_some() {
+                    "Transfer".to_string()
+                } else if tx.is_split {
+                    "Split".to_string()
+                } else {
+                    tx.category_name
+                        .unwrap_or_else(|| "Uncategorized".to_string())
+                }
+                .into(),
             });
         }
         ui.set_finance_transactions(tx_model.into());
@@ -252,7 +286,7 @@ fn refresh_finance(ui: &AppWindow, db_path: &str) {
 // --- Gifts Refresh ---
 #[allow(dead_code)]
 fn refresh_gifts(ui: &AppWindow, db_path: &str) {
-    let database = db::Db::new(db_path).expect("Failed to open DB");
+    let database = open_db(db_path);
     let gifts_service = GiftsService::new(&database);
 
     if let Ok(people) = gifts_service.get_people() {
@@ -297,18 +331,24 @@ fn refresh_gifts(ui: &AppWindow, db_path: &str) {
 // --- Household Refresh ---
 #[allow(dead_code)]
 fn refresh_household(ui: &AppWindow, db_path: &str) {
-    let database = db::Db::new(db_path).expect("Failed to open DB");
+    let database = open_db(db_path);
     let household_service = HouseholdService::new(&database);
 
     if let Ok(members) = household_service.get_members() {
         let mut ui_members = Vec::new();
         for member in members {
+            let shared = household_service
+                .get_member_access(&member.id)
+                .ok()
+                .flatten()
+                .is_some_and(|access| access.status != "revoked");
             ui_members.push(MemberData {
                 id: member.id.into(),
                 name: member.name.into(),
                 relationship: member.relationship.into(),
                 date_of_birth: member.date_of_birth.unwrap_or_default().into(),
                 is_primary: member.is_primary,
+                shared,
             });
         }
         let rc_members = std::rc::Rc::new(slint::VecModel::from(ui_members));
@@ -334,7 +374,7 @@ fn refresh_household(ui: &AppWindow, db_path: &str) {
 // --- Settings Refresh ---
 #[allow(dead_code)]
 fn refresh_settings(ui: &AppWindow, db_path: &str) {
-    let database = db::Db::new(db_path).expect("Failed to open DB");
+    let database = open_db(db_path);
     let settings_service = SettingsService::new(&database);
 
     if let Ok(settings) = settings_service.get_settings() {
@@ -359,7 +399,7 @@ fn refresh_settings(ui: &AppWindow, db_path: &str) {
 // --- Analytics Refresh ---
 #[allow(dead_code)]
 fn refresh_analytics(ui: &AppWindow, db_path: &str) {
-    let database = db::Db::new(db_path).expect("Failed to open DB");
+    let database = open_db(db_path);
     let dashboard_service = DashboardService::new(&database);
 
     if let Ok(expenditures) = dashboard_service.get_expenditure_by_category() {
@@ -422,7 +462,7 @@ pub fn db_path() -> String {
 pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let db_path_string = db_path();
     let db_path = db_path_string.as_str();
-    let database = db::Db::new(db_path).expect("Failed to open DB");
+    let database = open_db(db_path);
     database.init().expect("Failed to init DB");
 
     let registry = ModuleRegistry::new(&database);
@@ -437,6 +477,9 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     // --- Onboarding / First Launch Seeding ---
     // If the database is completely empty (no accounts), seed a starting state
     let finance_service = FinanceService::new(&database);
+    if let Err(e) = finance_service.seed_default_categories() {
+        eprintln!("Failed to seed default categories: {}", e);
+    }
     if let Ok(accounts) = finance_service.get_accounts() {
         if accounts.is_empty() {
             println!("First launch detected: Seeding default mock data...");
@@ -489,7 +532,7 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle = ui.as_weak();
     let db_path_clone = db_path.to_string();
     ui.on_toggle_module(move |id, is_enabled| {
-        let database = db::Db::new(&db_path_clone).expect("Failed to open DB");
+        let database = open_db(&db_path_clone);
         let registry = ModuleRegistry::new(&database);
         registry
             .toggle_module(id.as_str(), is_enabled)
@@ -503,17 +546,18 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle2 = ui.as_weak();
     let db_path_clone2 = db_path.to_string();
     ui.on_create_account(move |name, acc_type, starting_balance| {
-        let database = db::Db::new(&db_path_clone2).expect("Failed to open DB");
+        let database = open_db(&db_path_clone2);
         let finance_service = FinanceService::new(&database);
 
         // Ensure starting_balance is converted from generic float to cents
-        finance_service
-            .create_account(
-                name.as_str(),
-                acc_type.as_str(),
-                (starting_balance * 100.0) as i64,
-            )
-            .expect("Failed to create test account");
+        if let Err(e) = finance_service.create_account(
+            name.as_str(),
+            acc_type.as_str(),
+            (starting_balance * 100.0) as i64,
+        ) {
+            eprintln!("Failed to create test account: {}", e);
+            return;
+        }
 
         if let Some(ui) = ui_handle2.upgrade() {
             refresh_finance(&ui, &db_path_clone2);
@@ -524,7 +568,7 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle3 = ui.as_weak();
     let db_path_clone3 = db_path.to_string();
     ui.on_create_transaction(move |account_id, amount, merchant, category_name| {
-        let database = db::Db::new(&db_path_clone3).expect("Failed to open DB");
+        let database = open_db(&db_path_clone3);
         let finance_service = FinanceService::new(&database);
 
         // Find an account id to use if "auto_first_account" is passed
@@ -540,33 +584,29 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
 
-        // We'll create the category dynamically if it doesn't exist, just for testing
-        let mut cat_id = None;
-        if let Ok(cats) = finance_service.get_categories() {
-            if let Some(c) = cats.iter().find(|c| c.name == category_name.as_str()) {
-                cat_id = Some(c.id.clone());
-            } else {
-                let _ =
-                    finance_service.create_category(category_name.as_str(), "expense", "#555555");
-                if let Ok(cats2) = finance_service.get_categories() {
-                    if let Some(c2) = cats2.iter().find(|c| c.name == category_name.as_str()) {
-                        cat_id = Some(c2.id.clone());
-                    }
+        // Names or paths like "Food > Dining Out"; missing ones are created
+        let category_type = if amount < 0.0 { "expense" } else { "income" };
+        let cat_id =
+            match finance_service.find_or_create_category(category_name.as_str(), category_type) {
+                Ok(id) => Some(id),
+                Err(e) => {
+                    eprintln!("Failed to find category '{}': {}", category_name, e);
+                    None
                 }
-            }
-        }
+            };
 
         let now = chrono::Utc::now().to_rfc3339();
 
-        finance_service
-            .create_transaction(
-                &target_account_id,
-                (amount as f64 * 100.0) as i64,
-                merchant.as_str(),
-                &now,
-                cat_id.as_deref(),
-            )
-            .expect("Failed to create transaction");
+        if let Err(e) = finance_service.create_transaction(
+            &target_account_id,
+            (amount as f64 * 100.0) as i64,
+            merchant.as_str(),
+            &now,
+            cat_id.as_deref(),
+        ) {
+            eprintln!("Failed to create transaction: {}", e);
+            return;
+        }
 
         if let Some(ui) = ui_handle3.upgrade() {
             refresh_finance(&ui, &db_path_clone3);
@@ -577,7 +617,7 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle_upi = ui.as_weak();
     let db_path_clone_upi = db_path.to_string();
     ui.on_process_upi_payment(move |account_id, amount, merchant, category_name, upi_app| {
-        let database = db::Db::new(&db_path_clone_upi).expect("Failed to open DB");
+        let database = open_db(&db_path_clone_upi);
         let finance_service = FinanceService::new(&database);
 
         let mut target_account_id = account_id.to_string();
@@ -592,19 +632,13 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
 
-        let mut cat_id = None;
-        if let Ok(cats) = finance_service.get_categories() {
-            if let Some(c) = cats.iter().find(|c| c.name == category_name.as_str()) {
-                cat_id = Some(c.id.clone());
-            } else {
-                let _ = finance_service.create_category(category_name.as_str(), "expense", "#555555");
-                if let Ok(cats2) = finance_service.get_categories() {
-                    if let Some(c2) = cats2.iter().find(|c| c.name == category_name.as_str()) {
-                        cat_id = Some(c2.id.clone());
-                    }
-                }
+        let cat_id = match finance_service.find_or_create_category(category_name.as_str(), "expense") {
+            Ok(id) => Some(id),
+            Err(e) => {
+                eprintln!("Failed to find category '{}': {}", category_name, e);
+                None
             }
-        }
+        };
 
         let now = chrono::Utc::now().to_rfc3339();
         
@@ -612,15 +646,16 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         let merchant_str = format!("{} (via {})", merchant, upi_app);
 
         // QR Amount is passed as positive value, but since it's a payment, we negate it.
-        finance_service
-            .create_transaction(
-                &target_account_id,
-                -(amount as f64 * 100.0) as i64,
-                &merchant_str,
-                &now,
-                cat_id.as_deref(),
-            )
-            .expect("Failed to create UPI transaction");
+        if let Err(e) = finance_service.create_transaction(
+            &target_account_id,
+            -(amount as f64 * 100.0) as i64,
+            &merchant_str,
+            &now,
+            cat_id.as_deref(),
+        ) {
+            eprintln!("Failed to create UPI transaction: {}", e);
+            return;
+        }
 
         if let Some(ui) = ui_handle_upi.upgrade() {
             refresh_finance(&ui, &db_path_clone_upi);
@@ -631,12 +666,13 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle4 = ui.as_weak();
     let db_path_clone4 = db_path.to_string();
     ui.on_add_grocery_item(move |name, category| {
-        let database = db::Db::new(&db_path_clone4).expect("Failed to open DB");
+        let database = open_db(&db_path_clone4);
         let grocery_service = GroceryService::new(&database);
 
-        grocery_service
-            .add_grocery_item(name.as_str(), Some(category.as_str()))
-            .expect("Failed to add grocery item");
+        if let Err(e) = grocery_service.add_grocery_item(name.as_str(), Some(category.as_str())) {
+            eprintln!("Failed to add grocery item: {}", e);
+            return;
+        }
 
         if let Some(ui) = ui_handle4.upgrade() {
             refresh_grocery(&ui, &db_path_clone4);
@@ -647,12 +683,13 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle5 = ui.as_weak();
     let db_path_clone5 = db_path.to_string();
     ui.on_add_inventory_item(move |name, quantity| {
-        let database = db::Db::new(&db_path_clone5).expect("Failed to open DB");
+        let database = open_db(&db_path_clone5);
         let grocery_service = GroceryService::new(&database);
 
-        grocery_service
-            .add_inventory_item(name.as_str(), quantity as f64)
-            .expect("Failed to add inventory item");
+        if let Err(e) = grocery_service.add_inventory_item(name.as_str(), quantity as f64) {
+            eprintln!("Failed to add inventory item: {}", e);
+            return;
+        }
 
         if let Some(ui) = ui_handle5.upgrade() {
             refresh_grocery(&ui, &db_path_clone5);
@@ -662,18 +699,19 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle6 = ui.as_weak();
     let db_path_clone6 = db_path.to_string();
     ui.on_add_travel_trip(move |name, destination, start_date, end_date, trip_type| {
-        let database = db::Db::new(&db_path_clone6).expect("Failed to open DB");
+        let database = open_db(&db_path_clone6);
         let travel_service = TravelService::new(&database);
 
-        travel_service
-            .add_trip(
-                name.as_str(),
-                destination.as_str(),
-                start_date.as_str(),
-                end_date.as_str(),
-                Some(trip_type.as_str()),
-            )
-            .expect("Failed to add trip");
+        if let Err(e) = travel_service.add_trip(
+            name.as_str(),
+            destination.as_str(),
+            start_date.as_str(),
+            end_date.as_str(),
+            Some(trip_type.as_str()),
+        ) {
+            eprintln!("Failed to add trip: {}", e);
+            return;
+        }
 
         if let Some(ui) = ui_handle6.upgrade() {
             refresh_travel(&ui, &db_path_clone6);
@@ -684,17 +722,18 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle7 = ui.as_weak();
     let db_path_clone7 = db_path.to_string();
     ui.on_add_restaurant(move |name, cuisine, location, visited| {
-        let database = db::Db::new(&db_path_clone7).expect("Failed to open DB");
+        let database = open_db(&db_path_clone7);
         let dining_service = DiningService::new(&database);
 
-        dining_service
-            .add_restaurant(
-                name.as_str(),
-                Some(cuisine.as_str()),
-                Some(location.as_str()),
-                visited,
-            )
-            .expect("Failed to add restaurant");
+        if let Err(e) = dining_service.add_restaurant(
+            name.as_str(),
+            Some(cuisine.as_str()),
+            Some(location.as_str()),
+            visited,
+        ) {
+            eprintln!("Failed to add restaurant: {}", e);
+            return;
+        }
 
         if let Some(ui) = ui_handle7.upgrade() {
             refresh_dining(&ui, &db_path_clone7);
@@ -704,17 +743,18 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle8 = ui.as_weak();
     let db_path_clone8 = db_path.to_string();
     ui.on_add_appliance(move |name, brand, purchase_date, warranty_expiry| {
-        let database = db::Db::new(&db_path_clone8).expect("Failed to open DB");
+        let database = open_db(&db_path_clone8);
         let maintenance_service = MaintenanceService::new(&database);
 
-        maintenance_service
-            .add_appliance(
-                name.as_str(),
-                Some(brand.as_str()),
-                Some(purchase_date.as_str()),
-                Some(warranty_expiry.as_str()),
-            )
-            .expect("Failed to add appliance");
+        if let Err(e) = maintenance_service.add_appliance(
+            name.as_str(),
+            Some(brand.as_str()),
+            Some(purchase_date.as_str()),
+            Some(warranty_expiry.as_str()),
+        ) {
+            eprintln!("Failed to add appliance: {}", e);
+            return;
+        }
 
         if let Some(ui) = ui_handle8.upgrade() {
             refresh_maintenance(&ui, &db_path_clone8);
@@ -724,16 +764,17 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle9 = ui.as_weak();
     let db_path_clone9 = db_path.to_string();
     ui.on_add_person(move |name, relationship, date_of_birth| {
-        let database = db::Db::new(&db_path_clone9).expect("Failed to open DB");
+        let database = open_db(&db_path_clone9);
         let gifts_service = GiftsService::new(&database);
 
-        gifts_service
-            .add_person(
-                name.as_str(),
-                Some(relationship.as_str()),
-                Some(date_of_birth.as_str()),
-            )
-            .expect("Failed to add person");
+        if let Err(e) = gifts_service.add_person(
+            name.as_str(),
+            Some(relationship.as_str()),
+            Some(date_of_birth.as_str()),
+        ) {
+            eprintln!("Failed to add person: {}", e);
+            return;
+        }
 
         if let Some(ui) = ui_handle9.upgrade() {
             refresh_gifts(&ui, &db_path_clone9);
@@ -743,14 +784,17 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle10 = ui.as_weak();
     let db_path_clone10 = db_path.to_string();
     ui.on_add_gift_idea(move |person_id, description, estimated_price| {
-        let database = db::Db::new(&db_path_clone10).expect("Failed to open DB");
+        let database = open_db(&db_path_clone10);
         let gifts_service = GiftsService::new(&database);
 
         let price_val = estimated_price as i64;
 
-        gifts_service
-            .add_gift_idea(person_id.as_str(), description.as_str(), Some(price_val))
-            .expect("Failed to add gift idea");
+        if let Err(e) =
+            gifts_service.add_gift_idea(person_id.as_str(), description.as_str(), Some(price_val))
+        {
+            eprintln!("Failed to add gift idea: {}", e);
+            return;
+        }
 
         if let Some(ui) = ui_handle10.upgrade() {
             refresh_gifts(&ui, &db_path_clone10);
@@ -760,17 +804,18 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle11 = ui.as_weak();
     let db_path_clone11 = db_path.to_string();
     ui.on_add_member(move |name, relationship, date_of_birth, is_primary| {
-        let database = db::Db::new(&db_path_clone11).expect("Failed to open DB");
+        let database = open_db(&db_path_clone11);
         let household_service = HouseholdService::new(&database);
 
-        household_service
-            .add_member(
-                name.as_str(),
-                relationship.as_str(),
-                Some(date_of_birth.as_str()),
-                is_primary,
-            )
-            .expect("Failed to add member");
+        if let Err(e) = household_service.add_member(
+            name.as_str(),
+            relationship.as_str(),
+            Some(date_of_birth.as_str()),
+            is_primary,
+        ) {
+            eprintln!("Failed to add member: {}", e);
+            return;
+        }
 
         if let Some(ui) = ui_handle11.upgrade() {
             refresh_household(&ui, &db_path_clone11);
@@ -780,18 +825,19 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle12 = ui.as_weak();
     let db_path_clone12 = db_path.to_string();
     ui.on_add_document(move |member_id, name, doc_type, number, expiry_date| {
-        let database = db::Db::new(&db_path_clone12).expect("Failed to open DB");
+        let database = open_db(&db_path_clone12);
         let household_service = HouseholdService::new(&database);
 
-        household_service
-            .add_document(
-                Some(member_id.as_str()),
-                name.as_str(),
-                doc_type.as_str(),
-                Some(number.as_str()),
-                Some(expiry_date.as_str()),
-            )
-            .expect("Failed to add document");
+        if let Err(e) = household_service.add_document(
+            Some(member_id.as_str()),
+            name.as_str(),
+            doc_type.as_str(),
+            Some(number.as_str()),
+            Some(expiry_date.as_str()),
+        ) {
+            eprintln!("Failed to add document: {}", e);
+            return;
+        }
 
         if let Some(ui) = ui_handle12.upgrade() {
             refresh_household(&ui, &db_path_clone12);
@@ -801,7 +847,7 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle13 = ui.as_weak();
     let db_path_clone13 = db_path.to_string();
     ui.on_update_currency(move |code| {
-        let database = db::Db::new(&db_path_clone13).expect("Failed to open DB");
+        let database = open_db(&db_path_clone13);
         let settings_service = SettingsService::new(&database);
 
         // Find the symbol for the code
@@ -824,7 +870,7 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     let ui_handle14 = ui.as_weak();
     let db_path_clone14 = db_path.to_string();
     ui.on_update_profile(move |name, theme| {
-        let database = db::Db::new(&db_path_clone14).expect("Failed to open DB");
+        let database = open_db(&db_path_clone14);
         let settings_service = SettingsService::new(&database);
 
         settings_service
@@ -842,8 +888,8 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         let ui_handle = ui_handle15.clone();
         let db_path = db_path_clone15.clone();
 
-        tokio::spawn(async move {
-            let database = db::Db::new(&db_path).expect("Failed to open DB");
+        spawn_with_db(move || async move {
+            let database = open_db(&db_path);
             let cloud_service = CloudService::new(&database);
             if cloud_service.link_account().await.is_ok() {
                 slint::invoke_from_event_loop(move || {
@@ -856,26 +902,76 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         });
     });
 
-    let ui_handle16 = ui.as_weak();
-    let db_path_clone16 = db_path.to_string();
-    ui.on_sync_now(move || {
-        let ui_handle = ui_handle16.clone();
-        let db_path = db_path_clone16.clone();
+    // Syncs after edits settle, every 15 minutes, and when asked to
+    let sync_db_path = db_path.to_string();
+    let status_db_path = db_path.to_string();
+    let status_ui_handle = ui.as_weak();
+    let auto_sync = Arc::new(auto::spawn_auto_sync(
+        {
+            let db_path = db_path.to_string();
+            move || Ok(open_db(&db_path))
+        },
+        CloudService::is_linked,
+        move || {
+            let db_path = sync_db_path.clone();
+            async move {
+                let database = open_db(&db_path);
+                CloudService::new(&database)
+                    .sync_database(Path::new(&db_path))
+                    .await
+            }
+        },
+        auto::AutoSyncConfig::default(),
+        move |_status| {
+            let ui_handle = status_ui_handle.clone();
+            let db_path = status_db_path.clone();
+            let _ = slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    let database = open_db(&db_path);
+                    let cloud_service = CloudService::new(&database);
+                    ui.set_cloud_sync_status(cloud_service.get_sync_status().into());
+                    ui.set_last_sync_time(cloud_service.get_last_sync_time().into());
+                    refresh_all_modules(&ui, &db_path);
+                }
+            });
+        },
+    ));
+
+    let auto_sync_now = auto_sync.clone();
+    ui.on_sync_now(move || auto_sync_now.sync_now());
 
-        tokio::spawn(async move {
-            let database = db::Db::new(&db_path).expect("Failed to open DB");
+    let ui_handle_backups = ui.as_weak();
+    let db_path_backups = db_path.to_string();
+    ui.on_list_remote_backups(move || {
+        let ui_handle = ui_handle_backups.clone();
+        let db_path = db_path_backups.clone();
+
+        spawn_with_db(move || async move {
+            let database = open_db(&db_path);
             let cloud_service = CloudService::new(&database);
-            if cloud_service
-                .sync_database(Path::new(&db_path))
-                .await
-                .is_ok()
-            {
+            let Ok(remote) = cloud_service.drive_backend().await else {
+                return;
+            };
+            if let Ok(backups) = backup::list_remote_backups(&remote).await {
+                let labels: Vec<String> = backups
+                    .iter()
+                    .map(|b| {
+                        let modified = b
+                            .modified
+                            .map(|m| {
+                                m.with_timezone(&chrono::Local)
+                                    .format("%Y-%m-%d %H:%M")
+                                    .to_string()
+                            })
+                            .unwrap_or_default();
+                        format!("{} · {} · {} KB", b.name, modified, b.size.div_ceil(1024))
+                    })
+                    .collect();
                 slint::invoke_from_event_loop(move || {
                     if let Some(ui) = ui_handle.upgrade() {
-                        ui.set_last_sync_time(
-                            chrono::Local::now().format("%H:%M:%S").to_string().into(),
-                        );
-                        refresh_all_modules(&ui, &db_path);
+                        let labels: Vec<slint::SharedString> =
+                            labels.into_iter().map(Into::into).collect();
+                        ui.set_remote_backups(Rc::new(slint::VecModel::from(labels)).into());
                     }
                 })
                 .unwrap();
@@ -883,18 +979,128 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         });
     });
 
-    let _ui_handle17 = ui.as_weak();
+    let ui_handle_restore = ui.as_weak();
+    let db_path_restore = db_path.to_string();
+    ui.on_restore_backup(move |label| {
+        let ui_handle = ui_handle_restore.clone();
+        let db_path = db_path_restore.clone();
+        let name = label.split(" · ").next().unwrap_or_default().to_string();
+
+        spawn_with_db(move || async move {
+            let mut database = open_db(&db_path);
+            let downloaded = Path::new(&db_path).with_extension("download.db");
+            let fetched = async {
+                let remote = CloudService::new(&database).drive_backend().await?;
+                backup::download_backup(&remote, &name, &downloaded).await?;
+                backup::download_files(&remote, &name, Path::new(&db_path)).await
+            }
+            .await;
+            let restored = fetched.and_then(|_| {
+                backup::restore_downloaded(&mut database, Path::new(&db_path), &downloaded)
+            });
+            let _ = std::fs::remove_file(&downloaded);
+            if let Err(e) = restored {
+                eprintln!("Restore failed: {:#}", e);
+                return;
+            }
+            slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    refresh_all_modules(&ui, &db_path);
+                }
+            })
+            .unwrap();
+        });
+    });
+
+    let ui_handle17 = ui.as_weak();
     let db_path_clone17 = db_path.to_string();
     ui.on_invite_household_member(move |email| {
         let db_path = db_path_clone17.clone();
-        let email = email.to_string();
-        tokio::spawn(async move {
-            let database = db::Db::new(&db_path).expect("Failed to open DB");
+        let email = email.trim().to_string();
+        let database = open_db(&db_path);
+        let household_service = HouseholdService::new(&database);
+
+        // Invitees without a member entry yet get one named after their email
+        let invited = household_service
+            .find_member_by_email(&email)
+            .and_then(|found| {
+                let member_id = match found {
+                    Some(id) => id,
+                    None => household_service.add_member(&email, "family", None, false)?,
+                };
+                household_service.invite_member(&member_id, Some(&email), &sharing::default_roles())
+            });
+        let code = match invited {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("Failed to invite {}: {}", email, e);
+                return;
+            }
+        };
+
+        if let Some(ui) = ui_handle17.upgrade() {
+            ui.set_invite_code(code.into());
+            refresh_household(&ui, &db_path);
+        }
+        spawn_with_db(move || async move {
+            let database = open_db(&db_path);
             let cloud_service = CloudService::new(&database);
-            let _ = cloud_service.invite_member(&email).await;
+            if let Err(e) = cloud_service.invite_member(&email).await {
+                eprintln!("Failed to share the Drive folder with {}: {}", email, e);
+            }
         });
     });
 
+    let ui_handle18 = ui.as_weak();
+    let db_path_clone18 = db_path.to_string();
+    ui.on_accept_household_invite(move |code| {
+        let database = open_db(&db_path_clone18);
+        if let Err(e) = HouseholdService::new(&database).accept_invite(code.as_str()) {
+            eprintln!("Failed to accept invite: {}", e);
+            return;
+        }
+
+        if let Some(ui) = ui_handle18.upgrade() {
+            refresh_all_modules(&ui, &db_path_clone18);
+        }
+    });
+
+    let ui_handle_revoke = ui.as_weak();
+    let db_path_revoke = db_path.to_string();
+    ui.on_revoke_household_member(move |member_id| {
+        let db_path = db_path_revoke.clone();
+        let database = open_db(&db_path);
+        let email = match HouseholdService::new(&database).revoke_member(member_id.as_str()) {
+            Ok(email) => email,
+            Err(e) => {
+                eprintln!("Failed to revoke {}: {}", member_id, e);
+                return;
+            }
+        };
+
+        if let Some(ui) = ui_handle_revoke.upgrade() {
+            refresh_household(&ui, &db_path);
+        }
+        // Their devices keep reading the Drive folder until it is unshared
+        if let Some(email) = email {
+            spawn_with_db(move || async move {
+                let database = open_db(&db_path);
+                let cloud_service = CloudService::new(&database);
+                if let Err(e) = cloud_service.remove_member(&email).await {
+                    eprintln!("Failed to unshare the Drive folder with {}: {}", email, e);
+                }
+            });
+        }
+    });
+
+    // Reminders (bills, expiring documents, birthdays, ...) are checked once a minute
+    let scheduler_db_path = db_path.to_string();
+    let _scheduler = notifications::spawn_scheduler(
+        move || Ok(open_db(&scheduler_db_path)),
+        vec![Arc::new(DesktopNotifier), Arc::new(LogNotifier)],
+        Duration::from_secs(60),
+    );
+
     refresh_all_modules(&ui, db_path);
 
     ui.run()?;
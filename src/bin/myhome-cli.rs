@@ -0,0 +1,1646 @@
+//! Headless interface over the same services the Slint app uses, for
+//! scripting household data and working over SSH.
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use myhome::db::Db;
+use myhome::modules::backup::{self, RetentionPolicy};
+use myhome::modules::cloud::CloudService;
+use myhome::modules::dashboard::DashboardService;
+use myhome::modules::dining::DiningService;
+use myhome::modules::finance::import::ImportSummary;
+use myhome::modules::finance::receipts::ReceiptStore;
+use myhome::modules::finance::rules::{RuleActions, RuleConditions};
+use myhome::modules::finance::search::{TransactionQuery, TransactionSort};
+use myhome::modules::finance::splits::SplitLine;
+use myhome::modules::finance::{FinanceService, TransactionDetails};
+use myhome::modules::gifts::GiftsService;
+use myhome::modules::grocery::GroceryService;
+use myhome::modules::household::sharing::{self, Role};
+use myhome::modules::household::vault::DocumentVault;
+use myhome::modules::household::HouseholdService;
+use myhome::modules::maintenance::MaintenanceService;
+use myhome::modules::notifications::{LogNotifier, NotificationService, Notifier};
+use myhome::modules::registry::ModuleRegistry;
+use myhome::modules::sync::backend::{LocalFolderBackend, RemoteObject, SyncBackend};
+use myhome::modules::sync::{Changeset, SyncService, VersionVector};
+use myhome::modules::trash::{TrashKind, TrashService};
+use myhome::modules::travel::TravelService;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Parser)]
+#[command(
+    name = "myhome-cli",
+    version,
+    about = "Command-line access to the My Home database"
+)]
+struct Cli {
+    /// Database file to open
+    #[arg(long, env = "MYHOME_DB", default_value = "myhome_dev.db")]
+    db: PathBuf,
+
+    /// Print JSON instead of a table
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List records of one kind
+    List {
+        #[arg(value_parser = parse_kind)]
+        kind: TrashKind,
+        /// Maximum number of transactions to show
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+    },
+    /// Find transactions and total them, e.g. `search --text swiggy --from 2024-03-01`
+    Search(SearchArgs),
+    /// Create a record
+    #[command(subcommand)]
+    Add(AddCommand),
+    /// Change fields of a record, e.g. `update trip <id> --set status=active`
+    Update {
+        #[arg(value_parser = parse_kind)]
+        kind: TrashKind,
+        id: String,
+        /// FIELD=VALUE; VALUE is read as JSON when it parses, otherwise as text
+        #[arg(long = "set", value_parser = parse_assignment, required = true)]
+        set: Vec<(String, String)>,
+    },
+    /// Move a record to the trash
+    Delete {
+        #[arg(value_parser = parse_kind)]
+        kind: TrashKind,
+        id: String,
+    },
+    /// Bring a record back from the trash
+    Restore {
+        #[arg(value_parser = parse_kind)]
+        kind: TrashKind,
+        id: String,
+    },
+    /// Inspect or empty the trash
+    #[command(subcommand)]
+    Trash(TrashCommand),
+    /// Print a report
+    #[command(subcommand)]
+    Report(ReportCommand),
+    /// Reminders and the notification inbox
+    #[command(subcommand)]
+    Notifications(NotificationsCommand),
+    /// Import a bank statement (CSV, OFX or QIF, picked by file extension)
+    Import {
+        /// Account the transactions go into; CSV may take it from the profile
+        #[arg(long)]
+        account: Option<String>,
+        file: PathBuf,
+        /// CSV column profile, see `import-profiles`
+        #[arg(long, default_value = "generic")]
+        profile: String,
+        /// Date format for QIF files
+        #[arg(long, default_value = "%m/%d/%Y")]
+        date_format: String,
+    },
+    /// List the CSV import profiles
+    ImportProfiles,
+    /// Maintain the category tree
+    #[command(subcommand)]
+    Categories(CategoriesCommand),
+    /// Exchange rates used to convert totals into the household currency
+    #[command(subcommand)]
+    Rates(RatesCommand),
+    /// Run the categorization rules or ask for a category
+    #[command(subcommand)]
+    Rules(RulesCommand),
+    /// List, rename or merge transaction tags
+    #[command(subcommand)]
+    Tags(TagsCommand),
+    /// Show or replace how a transaction is divided between categories
+    Split {
+        transaction_id: String,
+        /// AMOUNT=CATEGORY_ID[:NOTE] with the amount in cents, e.g.
+        /// `-1500=<category-id>:Detergent`; the lines must add up to the
+        /// transaction
+        #[arg(long = "line", value_parser = parse_split_line, allow_hyphen_values = true)]
+        lines: Vec<SplitLine>,
+        /// Remove the split
+        #[arg(long, conflicts_with = "lines")]
+        clear: bool,
+    },
+    /// Record a transaction as the payment of a bill's current due date
+    PayBill {
+        bill_id: String,
+        transaction_id: String,
+    },
+    /// Post autopay bills that are due
+    Autopay {
+        /// Post everything due on or before this date; defaults to today
+        #[arg(long)]
+        on: Option<String>,
+    },
+    /// Receipt photos and PDFs attached to transactions
+    #[command(subcommand)]
+    Receipt(ReceiptCommand),
+    /// Encrypted files attached to household documents
+    Vault {
+        #[arg(long, env = "MYHOME_VAULT_PASSPHRASE", hide_env_values = true)]
+        passphrase: String,
+        #[command(subcommand)]
+        command: VaultCommand,
+    },
+    /// Write a backup copy of the database, its document vault and receipts
+    ///
+    /// Without a path the copy goes to the compressed backup history next to
+    /// the database, and older copies are pruned.
+    Backup {
+        path: Option<PathBuf>,
+        /// Always keep this many of the most recent copies
+        #[arg(long, default_value_t = RetentionPolicy::default().keep_last)]
+        keep_last: usize,
+        /// Keep the newest copy of each of this many days
+        #[arg(long, default_value_t = RetentionPolicy::default().daily_days)]
+        daily_days: i64,
+        /// Keep the newest copy of each of this many months
+        #[arg(long, default_value_t = RetentionPolicy::default().monthly_months)]
+        monthly_months: i64,
+    },
+    /// Sync with the other devices through the linked cloud account
+    Sync {
+        /// Sync through a local or network folder (e.g. a NAS share or a
+        /// Syncthing folder) instead of Google Drive
+        #[arg(long)]
+        folder: Option<PathBuf>,
+        /// Only print how the last sync went
+        #[arg(long, conflicts_with = "folder")]
+        status: bool,
+    },
+    /// List the database backups kept in the cloud account
+    Backups {
+        /// Look in a local or network folder instead of Google Drive
+        #[arg(long)]
+        folder: Option<PathBuf>,
+        /// List the backup history next to the database instead
+        #[arg(long, conflicts_with = "folder")]
+        local: bool,
+    },
+    /// Replace the database with a backup from the cloud account; the
+    /// current database is kept next to it
+    RestoreBackup {
+        /// Backup name as shown by `backups`; defaults to the newest
+        name: Option<String>,
+        /// Restore from a local or network folder instead of Google Drive
+        #[arg(long)]
+        folder: Option<PathBuf>,
+        /// Restore from the backup history next to the database instead
+        #[arg(long, conflicts_with = "folder")]
+        local: bool,
+    },
+    /// Exchange row-level changes with another device
+    #[command(subcommand)]
+    Changes(ChangesCommand),
+    /// Share the household with other members' devices
+    #[command(subcommand)]
+    Household(HouseholdCommand),
+}
+
+#[derive(Args)]
+struct SearchArgs {
+    /// First day, YYYY-MM-DD
+    #[arg(long)]
+    from: Option<String>,
+    /// Last day, YYYY-MM-DD
+    #[arg(long)]
+    to: Option<String>,
+    #[arg(long)]
+    account_id: Option<String>,
+    /// Also matches its subcategories
+    #[arg(long)]
+    category_id: Option<String>,
+    /// Smallest amount in cents, ignoring the sign
+    #[arg(long)]
+    min_amount: Option<i64>,
+    /// Largest amount in cents, ignoring the sign
+    #[arg(long)]
+    max_amount: Option<i64>,
+    /// Text in the merchant or payee
+    #[arg(long)]
+    text: Option<String>,
+    /// Only transactions with this tag; repeat for several
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+    #[arg(long)]
+    pending: Option<bool>,
+    /// manual, import or bank_sync
+    #[arg(long)]
+    source: Option<String>,
+    /// newest, oldest, largest or smallest
+    #[arg(long, default_value = "newest", value_parser = parse_sort)]
+    sort: TransactionSort,
+    #[arg(long, default_value_t = 50)]
+    limit: usize,
+    /// `next_cursor` from the previous page
+    #[arg(long)]
+    cursor: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum HouseholdCommand {
+    /// Show each member's sharing status and roles
+    Members,
+    /// Invite a member and print the code to accept on their device
+    Invite {
+        member_id: String,
+        #[arg(long)]
+        email: Option<String>,
+        /// MODULE=ROLE (owner, editor, viewer or none); by default the
+        /// member may edit everything but household documents
+        #[arg(long = "role", value_parser = parse_role)]
+        roles: Vec<(String, Role)>,
+        /// Also share the Google Drive folder with --email
+        #[arg(long, requires = "email")]
+        share_drive: bool,
+    },
+    /// Link this device to the member an invite code was issued for
+    Accept { code: String },
+    /// Replace a member's roles
+    Roles {
+        member_id: String,
+        #[arg(long = "role", value_parser = parse_role, required = true)]
+        roles: Vec<(String, Role)>,
+    },
+    /// Take away a member's access on all their devices
+    Revoke {
+        member_id: String,
+        /// Also stop sharing the Google Drive folder with them
+        #[arg(long)]
+        unshare_drive: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CategoriesCommand {
+    /// Add the default category tree if the household has no categories
+    Seed,
+    /// Move everything filed under SOURCE to TARGET and trash SOURCE
+    Merge {
+        source_id: String,
+        target_id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum RatesCommand {
+    /// List the stored rates, newest first
+    List,
+    /// Record how much of QUOTE one BASE bought, e.g. `set USD INR 83.1`
+    Set {
+        base: String,
+        quote: String,
+        rate: f64,
+        /// Defaults to today
+        #[arg(long)]
+        date: Option<String>,
+    },
+    /// Import an ECB reference-rate XML file or a CSV of rates
+    Import { file: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum RulesCommand {
+    /// Run the rules over every existing transaction
+    Apply {
+        /// Also replace categories, payees and tags that are already set
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// The category earlier transactions from MERCHANT were filed under
+    Suggest { merchant: String },
+}
+
+#[derive(Subcommand)]
+enum TagsCommand {
+    /// Every tag in use and how many transactions carry it
+    List,
+    /// Rename FROM to TO everywhere; merges them if TO is already in use
+    Rename { from: String, to: String },
+}
+
+#[derive(Subcommand)]
+enum ChangesCommand {
+    /// Write the changes another device has not seen to a changeset file
+    Export {
+        out: PathBuf,
+        /// Changeset last received from the other device; exports
+        /// everything when omitted
+        #[arg(long)]
+        since: Option<PathBuf>,
+    },
+    /// Merge a changeset file written by another device
+    Import { file: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum AddCommand {
+    Account {
+        name: String,
+        #[arg(long = "type", default_value = "checking")]
+        account_type: String,
+        /// Starting balance in cents
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        balance: i64,
+        /// Defaults to the household currency
+        #[arg(long)]
+        currency: Option<String>,
+    },
+    Category {
+        name: String,
+        #[arg(long = "type", default_value = "expense")]
+        category_type: String,
+        #[arg(long, default_value = "#555555")]
+        color: String,
+        /// Create it as a subcategory, with the parent's type and color
+        #[arg(long, conflicts_with_all = ["category_type", "color"])]
+        parent: Option<String>,
+    },
+    Transaction {
+        account_id: String,
+        /// Amount in cents, negative for expenses
+        #[arg(allow_negative_numbers = true)]
+        amount: i64,
+        merchant: String,
+        /// Defaults to now
+        #[arg(long)]
+        date: Option<String>,
+        #[arg(long)]
+        category_id: Option<String>,
+        #[arg(long)]
+        payee: Option<String>,
+        #[arg(long)]
+        notes: Option<String>,
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// JPEG, PNG or PDF receipt to attach
+        #[arg(long)]
+        receipt: Option<PathBuf>,
+    },
+    /// Move money between two accounts
+    Transfer {
+        from_account_id: String,
+        to_account_id: String,
+        /// Amount in cents
+        amount: i64,
+        /// Defaults to now
+        #[arg(long)]
+        date: Option<String>,
+        #[arg(long)]
+        notes: Option<String>,
+    },
+    Budget {
+        name: String,
+        /// Limit per period in cents
+        amount: i64,
+        /// monthly, quarterly, annual or custom
+        #[arg(long, default_value = "monthly")]
+        period: String,
+        #[arg(long)]
+        category_id: Option<String>,
+        /// First day of the budget; defaults to today
+        #[arg(long)]
+        start: Option<String>,
+        /// Last day, for custom periods
+        #[arg(long)]
+        end: Option<String>,
+    },
+    Bill {
+        name: String,
+        /// Amount in cents
+        amount: i64,
+        /// First due date, YYYY-MM-DD
+        first_due: String,
+        /// weekly, biweekly, monthly, quarterly or annual
+        #[arg(long, default_value = "monthly")]
+        recurrence: String,
+        #[arg(long)]
+        account_id: Option<String>,
+        #[arg(long)]
+        category_id: Option<String>,
+    },
+    /// A categorization rule; give at least one condition and one action
+    Rule {
+        name: String,
+        #[arg(long)]
+        merchant_contains: Option<String>,
+        #[arg(long)]
+        merchant_regex: Option<String>,
+        /// Smallest amount in cents, ignoring the sign
+        #[arg(long)]
+        min_amount: Option<i64>,
+        /// Largest amount in cents, ignoring the sign
+        #[arg(long)]
+        max_amount: Option<i64>,
+        #[arg(long)]
+        account_id: Option<String>,
+        /// UPI app the payment was made with, e.g. GPay
+        #[arg(long)]
+        upi_app: Option<String>,
+        #[arg(long)]
+        category_id: Option<String>,
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        #[arg(long)]
+        payee: Option<String>,
+    },
+    Trip {
+        name: String,
+        destination: String,
+        start_date: String,
+        end_date: String,
+        #[arg(long = "type")]
+        trip_type: Option<String>,
+    },
+    Restaurant {
+        name: String,
+        #[arg(long)]
+        cuisine: Option<String>,
+        #[arg(long)]
+        location: Option<String>,
+        #[arg(long)]
+        visited: bool,
+    },
+    Appliance {
+        name: String,
+        #[arg(long)]
+        brand: Option<String>,
+        #[arg(long)]
+        purchase_date: Option<String>,
+        #[arg(long)]
+        warranty_expiry: Option<String>,
+    },
+    Person {
+        name: String,
+        #[arg(long)]
+        relationship: Option<String>,
+        #[arg(long)]
+        date_of_birth: Option<String>,
+    },
+    GiftIdea {
+        person_id: String,
+        description: String,
+        /// Estimated price in cents
+        #[arg(long)]
+        price: Option<i64>,
+    },
+    Member {
+        name: String,
+        relationship: String,
+        #[arg(long)]
+        date_of_birth: Option<String>,
+        #[arg(long)]
+        primary: bool,
+    },
+    Document {
+        name: String,
+        document_type: String,
+        #[arg(long)]
+        member_id: Option<String>,
+        #[arg(long)]
+        number: Option<String>,
+        #[arg(long)]
+        expiry_date: Option<String>,
+    },
+    GroceryItem {
+        name: String,
+        #[arg(long)]
+        category: Option<String>,
+    },
+    InventoryItem {
+        name: String,
+        #[arg(long, default_value_t = 1.0)]
+        quantity: f64,
+    },
+}
+
+#[derive(Subcommand)]
+enum TrashCommand {
+    List,
+    /// Permanently delete one record
+    Purge {
+        #[arg(value_parser = parse_kind)]
+        kind: TrashKind,
+        id: String,
+    },
+    /// Permanently delete everything older than the given age
+    Empty {
+        #[arg(long, default_value_t = 30)]
+        older_than_days: i64,
+    },
+}
+
+#[derive(Subcommand)]
+enum NotificationsCommand {
+    /// Reminders that have not fired yet
+    Upcoming {
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Fired reminders that have not been dismissed
+    Inbox,
+    /// Refresh reminders, post due autopay bills and print what is due
+    Run,
+    /// Put a reminder off for a while
+    Snooze {
+        id: String,
+        #[arg(long, default_value_t = 24)]
+        hours: i64,
+    },
+    Dismiss {
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum VaultCommand {
+    /// Encrypt a file and attach it to a document
+    Attach { document_id: String, file: PathBuf },
+    /// Decrypt a document's file into a directory
+    Open {
+        document_id: String,
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Delete a document's file
+    Detach { document_id: String },
+    /// Switch the vault to a new passphrase
+    Rotate {
+        #[arg(long, env = "MYHOME_NEW_VAULT_PASSPHRASE", hide_env_values = true)]
+        new_passphrase: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ReceiptCommand {
+    /// Attach a JPEG, PNG or PDF, replacing the transaction's receipt
+    Attach {
+        transaction_id: String,
+        file: PathBuf,
+    },
+    /// Where a transaction's receipt and its thumbnail are stored
+    Show { transaction_id: String },
+    /// Detach a transaction's receipt
+    Detach { transaction_id: String },
+}
+
+#[derive(Subcommand)]
+enum ReportCommand {
+    /// Balance, trips and open grocery items
+    Summary,
+    /// Spending grouped by category
+    Spending,
+    /// Income and spending per category, with subcategories rolled up
+    Categories {
+        /// First day; defaults to the start of this month
+        #[arg(long)]
+        from: Option<String>,
+        /// Last day; defaults to today
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Income and spending per transaction tag
+    Tags {
+        /// First day; defaults to the start of this month
+        #[arg(long)]
+        from: Option<String>,
+        /// Last day; defaults to today
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Account balances converted into the household currency
+    NetWorth {
+        /// Date whose exchange rates are used; defaults to today
+        #[arg(long)]
+        on: Option<String>,
+    },
+    /// Household documents expired or expiring within 90 days
+    Expiring,
+    /// Spent vs. allotted for each budget
+    Budgets {
+        /// Date whose period is reported; defaults to today
+        #[arg(long)]
+        on: Option<String>,
+    },
+}
+
+fn parse_kind(s: &str) -> Result<TrashKind, String> {
+    serde_json::from_value(Value::String(s.replace('-', "_"))).map_err(|_| {
+        let kinds: Vec<String> = TrashKind::ALL
+            .iter()
+            .map(|k| {
+                serde_json::to_value(k)
+                    .unwrap()
+                    .as_str()
+                    .unwrap()
+                    .replace('_', "-")
+            })
+            .collect();
+        format!("expected one of: {}", kinds.join(", "))
+    })
+}
+
+fn parse_assignment(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("expected FIELD=VALUE, got '{}'", s))
+}
+
+fn parse_split_line(s: &str) -> Result<SplitLine, String> {
+    let (amount, rest) = parse_assignment(s)?;
+    let amount_cents = amount
+        .parse()
+        .map_err(|_| format!("expected an amount in cents, got '{}'", amount))?;
+    let (category, note) = match rest.split_once(':') {
+        Some((category, note)) => (category, Some(note.to_string())),
+        None => (rest.as_str(), None),
+    };
+    Ok(SplitLine {
+        category_id: Some(category.to_string()).filter(|c| !c.is_empty()),
+        amount_cents,
+        note,
+    })
+}
+
+fn parse_sort(s: &str) -> Result<TransactionSort, String> {
+    serde_json::from_value(Value::String(s.to_string()))
+        .map_err(|_| "expected one of: newest, oldest, largest, smallest".to_string())
+}
+
+fn parse_role(s: &str) -> Result<(String, Role), String> {
+    let (module, role) = parse_assignment(s)?;
+    if !sharing::SHARED_MODULES.contains(&module.as_str()) {
+        return Err(format!(
+            "expected one of: {}",
+            sharing::SHARED_MODULES.join(", ")
+        ));
+    }
+    let role = serde_json::from_value(Value::String(role))
+        .map_err(|_| "expected owner, editor, viewer or none".to_string())?;
+    Ok((module, role))
+}
+
+fn open_db(path: &Path) -> Result<Db> {
+    let db =
+        Db::open_from_env(path).with_context(|| format!("failed to open {}", path.display()))?;
+    db.init()?;
+    ModuleRegistry::new(&db).setup_default_modules()?;
+    Ok(db)
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let mut db = open_db(&cli.db)?;
+
+    let output = match cli.command {
+        Command::List { kind, limit } => list(&db, kind, limit)?,
+        Command::Search(args) => search(&db, args)?,
+        Command::Add(add) => json!({ "id": add_record(&db, &cli.db, add)? }),
+        Command::Update { kind, id, set } => {
+            update(&db, kind, &id, &set)?;
+            json!({ "id": id })
+        }
+        Command::Delete { kind, id } => {
+            delete(&db, kind, &id)?;
+            json!({ "id": id })
+        }
+        Command::Restore { kind, id } => {
+            TrashService::new(&db).restore(kind, &id)?;
+            json!({ "id": id })
+        }
+        Command::Trash(command) => trash(&db, command)?,
+        Command::Report(command) => report(&db, command)?,
+        Command::Notifications(command) => notifications(&db, command)?,
+        Command::Import {
+            account,
+            file,
+            profile,
+            date_format,
+        } => serde_json::to_value(import(
+            &db,
+            account.as_deref(),
+            &file,
+            &profile,
+            &date_format,
+        )?)?,
+        Command::ImportProfiles => {
+            serde_json::to_value(FinanceService::new(&db).get_import_profiles()?)?
+        }
+        Command::Categories(command) => categories(&db, command)?,
+        Command::Rates(command) => rates(&db, command)?,
+        Command::Rules(command) => rules(&db, command)?,
+        Command::Tags(command) => tags(&db, command)?,
+        Command::Split {
+            transaction_id,
+            lines,
+            clear,
+        } => split(&db, &transaction_id, &lines, clear)?,
+        Command::PayBill {
+            bill_id,
+            transaction_id,
+        } => {
+            FinanceService::new(&db).mark_bill_paid(&bill_id, &transaction_id)?;
+            json!({ "id": bill_id })
+        }
+        Command::Autopay { on } => {
+            let on = on.unwrap_or_else(|| chrono::Utc::now().date_naive().to_string());
+            json!({ "posted": FinanceService::new(&db).post_autopay_bills(&on)? })
+        }
+        Command::Receipt(command) => receipt(&db, &cli.db, command)?,
+        Command::Vault {
+            passphrase,
+            command,
+        } => vault(&db, &cli.db, &passphrase, command)?,
+        Command::Backup {
+            path: Some(path), ..
+        } => backup_to(&db, &cli.db, &path)?,
+        Command::Backup {
+            path: None,
+            keep_last,
+            daily_days,
+            monthly_months,
+        } => snapshot(
+            &db,
+            &cli.db,
+            &RetentionPolicy {
+                keep_last,
+                daily_days,
+                monthly_months,
+            },
+        )?,
+        Command::Sync { status, folder } => sync(&db, &cli.db, status, folder.as_deref())?,
+        Command::Backups { folder, local } => backups(&db, &cli.db, folder.as_deref(), local)?,
+        Command::RestoreBackup {
+            name,
+            folder,
+            local,
+        } => restore_backup(&mut db, &cli.db, name, folder.as_deref(), local)?,
+        Command::Changes(command) => changes(&db, command)?,
+        Command::Household(command) => household(&db, command)?,
+    };
+
+    print(&output, cli.json)
+}
+
+fn search(db: &Db, args: SearchArgs) -> Result<Value> {
+    let page = FinanceService::new(db).search_transactions(&TransactionQuery {
+        from: args.from,
+        to: args.to,
+        account_id: args.account_id,
+        category_id: args.category_id,
+        min_amount_cents: args.min_amount,
+        max_amount_cents: args.max_amount,
+        text: args.text,
+        tags: args.tags,
+        is_pending: args.pending,
+        source: args.source,
+        sort: args.sort,
+        limit: args.limit,
+        cursor: args.cursor,
+    })?;
+    Ok(serde_json::to_value(page)?)
+}
+
+fn trash(db: &Db, command: TrashCommand) -> Result<Value> {
+    let service = TrashService::new(db);
+    let output = match command {
+        TrashCommand::List => serde_json::to_value(service.get_trash()?)?,
+        TrashCommand::Purge { kind, id } => {
+            service.purge(kind, &id)?;
+            json!({ "id": id })
+        }
+        TrashCommand::Empty { older_than_days } => {
+            let purged = service.empty_trash(older_than_days)?;
+            json!({ "purged": purged })
+        }
+    };
+    Ok(output)
+}
+
+fn report(db: &Db, command: ReportCommand) -> Result<Value> {
+    let today = chrono::Utc::now().date_naive();
+    let month_start = || today.format("%Y-%m-01").to_string();
+    let output = match command {
+        ReportCommand::Summary => serde_json::to_value(DashboardService::new(db).get_summary()?)?,
+        ReportCommand::Spending => Value::Array(
+            DashboardService::new(db)
+                .get_expenditure_by_category()?
+                .into_iter()
+                .map(|(category, amount)| json!({ "category": category, "amount": amount }))
+                .collect(),
+        ),
+        ReportCommand::Categories { from, to } => {
+            let from = from.unwrap_or_else(month_start);
+            let to = to.unwrap_or_else(|| today.to_string());
+            serde_json::to_value(FinanceService::new(db).get_category_totals(&from, &to)?)?
+        }
+        ReportCommand::Tags { from, to } => {
+            let from = from.unwrap_or_else(month_start);
+            let to = to.unwrap_or_else(|| today.to_string());
+            serde_json::to_value(FinanceService::new(db).get_tag_totals(&from, &to)?)?
+        }
+        ReportCommand::NetWorth { on } => {
+            let on = on.unwrap_or_else(|| today.to_string());
+            serde_json::to_value(FinanceService::new(db).get_net_worth(&on)?)?
+        }
+        ReportCommand::Expiring => {
+            let report = HouseholdService::new(db).get_expiry_report(today)?;
+            serde_json::to_value(
+                [
+                    report.expired,
+                    report.within_30_days,
+                    report.within_60_days,
+                    report.within_90_days,
+                ]
+                .concat(),
+            )?
+        }
+        ReportCommand::Budgets { on } => {
+            let on = on.unwrap_or_else(|| today.to_string());
+            serde_json::to_value(FinanceService::new(db).get_budget_statuses(&on)?)?
+        }
+    };
+    Ok(output)
+}
+
+fn categories(db: &Db, command: CategoriesCommand) -> Result<Value> {
+    let service = FinanceService::new(db);
+    let output = match command {
+        CategoriesCommand::Seed => json!({ "created": service.seed_default_categories()? }),
+        CategoriesCommand::Merge {
+            source_id,
+            target_id,
+        } => json!({ "moved_transactions": service.merge_category(&source_id, &target_id)? }),
+    };
+    Ok(output)
+}
+
+fn rates(db: &Db, command: RatesCommand) -> Result<Value> {
+    let service = FinanceService::new(db);
+    let output = match command {
+        RatesCommand::List => serde_json::to_value(service.get_exchange_rates()?)?,
+        RatesCommand::Set {
+            base,
+            quote,
+            rate,
+            date,
+        } => {
+            let date = date.unwrap_or_else(|| chrono::Utc::now().date_naive().to_string());
+            json!({ "id": service.set_exchange_rate(&base, &quote, &date, rate)? })
+        }
+        RatesCommand::Import { file } => {
+            let content = std::fs::read_to_string(&file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            serde_json::to_value(service.import_exchange_rates(&content)?)?
+        }
+    };
+    Ok(output)
+}
+
+fn rules(db: &Db, command: RulesCommand) -> Result<Value> {
+    let service = FinanceService::new(db);
+    let output = match command {
+        RulesCommand::Apply { overwrite } => {
+            json!({ "updated": service.apply_rules_to_history(overwrite)? })
+        }
+        RulesCommand::Suggest { merchant } => {
+            serde_json::to_value(service.suggest_category(&merchant)?)?
+        }
+    };
+    Ok(output)
+}
+
+fn tags(db: &Db, command: TagsCommand) -> Result<Value> {
+    let service = FinanceService::new(db);
+    let output = match command {
+        TagsCommand::List => Value::Array(
+            service
+                .get_tags()?
+                .into_iter()
+                .map(|(tag, count)| json!({ "tag": tag, "count": count }))
+                .collect(),
+        ),
+        TagsCommand::Rename { from, to } => json!({ "updated": service.rename_tag(&from, &to)? }),
+    };
+    Ok(output)
+}
+
+fn split(db: &Db, transaction_id: &str, lines: &[SplitLine], clear: bool) -> Result<Value> {
+    let service = FinanceService::new(db);
+    if clear || !lines.is_empty() {
+        service.set_splits(transaction_id, lines)?;
+    }
+    Ok(serde_json::to_value(service.get_splits(transaction_id)?)?)
+}
+
+fn receipt(db: &Db, db_path: &Path, command: ReceiptCommand) -> Result<Value> {
+    let store = ReceiptStore::new(db, ReceiptStore::default_dir(db_path));
+    let output = match command {
+        ReceiptCommand::Attach {
+            transaction_id,
+            file,
+        } => serde_json::to_value(store.attach_file(&transaction_id, &file)?)?,
+        ReceiptCommand::Show { transaction_id } => {
+            serde_json::to_value(store.get_receipt(&transaction_id)?)?
+        }
+        ReceiptCommand::Detach { transaction_id } => {
+            store.remove_receipt(&transaction_id)?;
+            json!({ "id": transaction_id })
+        }
+    };
+    Ok(output)
+}
+
+/// Copies the database, its vault files and receipts to `path`.
+fn backup_to(db: &Db, db_path: &Path, path: &Path) -> Result<Value> {
+    db.backup(path)?;
+    let files = DocumentVault::copy_files(
+        &DocumentVault::default_dir(db_path),
+        &DocumentVault::default_dir(path),
+    )?;
+    let receipts = ReceiptStore::copy_files(
+        &ReceiptStore::default_dir(db_path),
+        &ReceiptStore::default_dir(path),
+    )?;
+    Ok(json!({ "backup": path, "vault_files": files, "receipt_files": receipts }))
+}
+
+/// Adds a snapshot to the backup history next to the database.
+fn snapshot(db: &Db, db_path: &Path, policy: &RetentionPolicy) -> Result<Value> {
+    let snapshot = backup::snapshot(db, db_path)?;
+    let name = snapshot.entry.name.clone();
+    let history = LocalFolderBackend::new(backup::local_history_dir(db_path));
+    let runtime = tokio::runtime::Runtime::new()?;
+    let pruned = runtime.block_on(backup::store_snapshot(&history, "", snapshot, policy))?;
+    Ok(json!({ "backup": name, "pruned": pruned }))
+}
+
+fn sync(db: &Db, db_path: &Path, status_only: bool, folder: Option<&Path>) -> Result<Value> {
+    let service = SyncService::new(db);
+    if !status_only {
+        service.mark_sync_started()?;
+        let runtime = tokio::runtime::Runtime::new()?;
+        let synced = sync_backend(&runtime, db, folder).and_then(|backend| {
+            runtime.block_on(CloudService::new(db).sync_with(backend.as_ref(), db_path))
+        });
+        match synced {
+            Ok(()) => service.mark_sync_succeeded()?,
+            Err(e) => {
+                service.mark_sync_failed(&format!("{:#}", e))?;
+                return Err(e);
+            }
+        }
+    }
+    Ok(serde_json::to_value(service.get_status()?)?)
+}
+
+fn backups(db: &Db, db_path: &Path, folder: Option<&Path>, local: bool) -> Result<Value> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let backups = list_backups(&runtime, db, db_path, folder, local)?;
+    Ok(Value::Array(
+        backups
+            .into_iter()
+            .map(|b| {
+                json!({
+                    "name": b.name,
+                    "size": b.size,
+                    "modified": b.modified.map(|m| m.to_rfc3339()),
+                })
+            })
+            .collect(),
+    ))
+}
+
+fn restore_backup(
+    db: &mut Db,
+    db_path: &Path,
+    name: Option<String>,
+    folder: Option<&Path>,
+    local: bool,
+) -> Result<Value> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let backend: Box<dyn SyncBackend> = if local {
+        Box::new(LocalFolderBackend::new(backup::local_history_dir(db_path)))
+    } else {
+        sync_backend(&runtime, db, folder)?
+    };
+    let name = match name {
+        Some(name) => name,
+        None => list_backups(&runtime, db, db_path, folder, local)?
+            .into_iter()
+            .next()
+            .map(|b| b.name)
+            .ok_or_else(|| anyhow!("no backups found"))?,
+    };
+    let downloaded = db_path.with_extension("download.db");
+    runtime.block_on(backup::download_backup(
+        backend.as_ref(),
+        &name,
+        &downloaded,
+    ))?;
+    let files = runtime.block_on(backup::download_files(backend.as_ref(), &name, db_path));
+    let restored = files.and_then(|_| backup::restore_downloaded(db, db_path, &downloaded));
+    std::fs::remove_file(&downloaded)?;
+    Ok(json!({ "restored": name, "previous": restored? }))
+}
+
+fn changes(db: &Db, command: ChangesCommand) -> Result<Value> {
+    let service = SyncService::new(db);
+    let output = match command {
+        ChangesCommand::Export { out, since } => {
+            let since = match since {
+                Some(path) => read_changeset(&path)?.vector,
+                None => VersionVector::new(),
+            };
+            let changeset = service.export_changes(&since)?;
+            std::fs::write(&out, serde_json::to_vec(&changeset)?)
+                .with_context(|| format!("failed to write {}", out.display()))?;
+            json!({ "file": out, "changes": changeset.changes.len() })
+        }
+        ChangesCommand::Import { file } => {
+            serde_json::to_value(service.import_changes(&read_changeset(&file)?)?)?
+        }
+    };
+    Ok(output)
+}
+
+fn list(db: &Db, kind: TrashKind, limit: usize) -> Result<Value> {
+    let records = match kind {
+        TrashKind::Account => serde_json::to_value(FinanceService::new(db).get_accounts()?)?,
+        TrashKind::Transaction => {
+            serde_json::to_value(FinanceService::new(db).get_transactions(limit)?)?
+        }
+        TrashKind::Category => serde_json::to_value(FinanceService::new(db).get_categories()?)?,
+        TrashKind::Budget => serde_json::to_value(FinanceService::new(db).get_budgets()?)?,
+        TrashKind::Bill => serde_json::to_value(FinanceService::new(db).get_bills()?)?,
+        TrashKind::Rule => serde_json::to_value(FinanceService::new(db).get_rules()?)?,
+        TrashKind::Trip => serde_json::to_value(TravelService::new(db).get_trips()?)?,
+        TrashKind::Restaurant => serde_json::to_value(DiningService::new(db).get_restaurants()?)?,
+        TrashKind::Appliance => {
+            serde_json::to_value(MaintenanceService::new(db).get_appliances()?)?
+        }
+        TrashKind::Person => serde_json::to_value(GiftsService::new(db).get_people()?)?,
+        TrashKind::GiftIdea => serde_json::to_value(GiftsService::new(db).get_gift_ideas()?)?,
+        TrashKind::Member => serde_json::to_value(HouseholdService::new(db).get_members()?)?,
+        TrashKind::Document => serde_json::to_value(HouseholdService::new(db).get_documents()?)?,
+        TrashKind::GroceryItem => {
+            serde_json::to_value(GroceryService::new(db).get_grocery_list()?)?
+        }
+        TrashKind::InventoryItem => serde_json::to_value(GroceryService::new(db).get_inventory()?)?,
+    };
+    Ok(records)
+}
+
+fn add_record(db: &Db, db_path: &Path, add: AddCommand) -> Result<String> {
+    let id = match add {
+        AddCommand::Account {
+            name,
+            account_type,
+            balance,
+            currency,
+        } => match currency {
+            Some(currency) => FinanceService::new(db).create_account_in_currency(
+                &name,
+                &account_type,
+                balance,
+                &currency,
+            )?,
+            None => FinanceService::new(db).create_account(&name, &account_type, balance)?,
+        },
+        AddCommand::Category {
+            name,
+            category_type,
+            color,
+            parent,
+        } => match parent {
+            Some(parent) => FinanceService::new(db).create_subcategory(&parent, &name)?,
+            None => FinanceService::new(db).create_category(&name, &category_type, &color)?,
+        },
+        AddCommand::Budget {
+            name,
+            amount,
+            period,
+            category_id,
+            start,
+            end,
+        } => FinanceService::new(db).create_budget(
+            &name,
+            category_id.as_deref(),
+            amount,
+            &period,
+            start.as_deref(),
+            end.as_deref(),
+        )?,
+        AddCommand::Bill {
+            name,
+            amount,
+            first_due,
+            recurrence,
+            account_id,
+            category_id,
+        } => FinanceService::new(db).create_bill(
+            &name,
+            amount,
+            &recurrence,
+            &first_due,
+            account_id.as_deref(),
+            category_id.as_deref(),
+        )?,
+        AddCommand::Rule {
+            name,
+            merchant_contains,
+            merchant_regex,
+            min_amount,
+            max_amount,
+            account_id,
+            upi_app,
+            category_id,
+            tags,
+            payee,
+        } => FinanceService::new(db).create_rule(
+            &name,
+            &RuleConditions {
+                merchant_contains,
+                merchant_regex,
+                min_amount_cents: min_amount,
+                max_amount_cents: max_amount,
+                account_id,
+                upi_app,
+            },
+            &RuleActions {
+                category_id,
+                tags,
+                payee,
+            },
+        )?,
+        AddCommand::Transaction {
+            account_id,
+            amount,
+            merchant,
+            date,
+            category_id,
+            payee,
+            notes,
+            tags,
+            receipt,
+        } => {
+            let date = date.unwrap_or_else(|| chrono::Utc::now().to_rfc3339());
+            let id = FinanceService::new(db).create_transaction_with_details(
+                &account_id,
+                amount,
+                &merchant,
+                &date,
+                category_id.as_deref(),
+                &TransactionDetails {
+                    payee,
+                    notes,
+                    tags,
+                    receipt_path: None,
+                },
+            )?;
+            if let Some(receipt) = receipt {
+                ReceiptStore::new(db, ReceiptStore::default_dir(db_path))
+                    .attach_file(&id, &receipt)?;
+            }
+            id
+        }
+        AddCommand::Transfer {
+            from_account_id,
+            to_account_id,
+            amount,
+            date,
+            notes,
+        } => {
+            let date = date.unwrap_or_else(|| chrono::Utc::now().to_rfc3339());
+            FinanceService::new(db).create_transfer(
+                &from_account_id,
+                &to_account_id,
+                amount,
+                &date,
+                notes.as_deref(),
+            )?
+        }
+        AddCommand::Trip {
+            name,
+            destination,
+            start_date,
+            end_date,
+            trip_type,
+        } => TravelService::new(db).add_trip(
+            &name,
+            &destination,
+            &start_date,
+            &end_date,
+            trip_type.as_deref(),
+        )?,
+        AddCommand::Restaurant {
+            name,
+            cuisine,
+            location,
+            visited,
+        } => DiningService::new(db).add_restaurant(
+            &name,
+            cuisine.as_deref(),
+            location.as_deref(),
+            visited,
+        )?,
+        AddCommand::Appliance {
+            name,
+            brand,
+            purchase_date,
+            warranty_expiry,
+        } => MaintenanceService::new(db).add_appliance(
+            &name,
+            brand.as_deref(),
+            purchase_date.as_deref(),
+            warranty_expiry.as_deref(),
+        )?,
+        AddCommand::Person {
+            name,
+            relationship,
+            date_of_birth,
+        } => GiftsService::new(db).add_person(
+            &name,
+            relationship.as_deref(),
+            date_of_birth.as_deref(),
+        )?,
+        AddCommand::GiftIdea {
+            person_id,
+            description,
+            price,
+        } => GiftsService::new(db).add_gift_idea(&person_id, &description, price)?,
+        AddCommand::Member {
+            name,
+            relationship,
+            date_of_birth,
+            primary,
+        } => HouseholdService::new(db).add_member(
+            &name,
+            &relationship,
+            date_of_birth.as_deref(),
+            primary,
+        )?,
+        AddCommand::Document {
+            name,
+            document_type,
+            member_id,
+            number,
+            expiry_date,
+        } => HouseholdService::new(db).add_document(
+            member_id.as_deref(),
+            &name,
+            &document_type,
+            number.as_deref(),
+            expiry_date.as_deref(),
+        )?,
+        AddCommand::GroceryItem { name, category } => {
+            GroceryService::new(db).add_grocery_item(&name, category.as_deref())?
+        }
+        AddCommand::InventoryItem { name, quantity } => {
+            GroceryService::new(db).add_inventory_item(&name, quantity)?
+        }
+    };
+    Ok(id)
+}
+
+/// Loads the record through `list`, applies the assignments to its JSON
+/// form and hands the result back to the service's `update_*` method.
+fn update(db: &Db, kind: TrashKind, id: &str, set: &[(String, String)]) -> Result<()> {
+    fn edited<T: DeserializeOwned>(
+        records: Value,
+        id: &str,
+        set: &[(String, String)],
+    ) -> Result<T> {
+        let mut record = records
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|r| r["id"] == id)
+            .cloned()
+            .ok_or_else(|| anyhow!("no record with id {}", id))?;
+        let fields = record
+            .as_object_mut()
+            .expect("records serialize as objects");
+        for (key, raw) in set {
+            if key == "id" || !fields.contains_key(key) {
+                bail!("unknown or read-only field '{}'", key);
+            }
+            let value = serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.clone()));
+            fields.insert(key.clone(), value);
+        }
+        serde_json::from_value(record).with_context(|| "invalid field value")
+    }
+
+    let records = list(db, kind, i64::MAX as usize)?;
+    match kind {
+        TrashKind::Account => FinanceService::new(db).update_account(&edited(records, id, set)?)?,
+        TrashKind::Transaction => {
+            FinanceService::new(db).update_transaction(&edited(records, id, set)?)?
+        }
+        TrashKind::Category => {
+            FinanceService::new(db).update_category(&edited(records, id, set)?)?
+        }
+        TrashKind::Budget => FinanceService::new(db).update_budget(&edited(records, id, set)?)?,
+        TrashKind::Bill => FinanceService::new(db).update_bill(&edited(records, id, set)?)?,
+        TrashKind::Rule => FinanceService::new(db).update_rule(&edited(records, id, set)?)?,
+        TrashKind::Trip => TravelService::new(db).update_trip(&edited(records, id, set)?)?,
+        TrashKind::Restaurant => {
+            DiningService::new(db).update_restaurant(&edited(records, id, set)?)?
+        }
+        TrashKind::Appliance => {
+            MaintenanceService::new(db).update_appliance(&edited(records, id, set)?)?
+        }
+        TrashKind::Person => GiftsService::new(db).update_person(&edited(records, id, set)?)?,
+        TrashKind::GiftIdea => {
+            GiftsService::new(db).update_gift_idea(&edited(records, id, set)?)?
+        }
+        TrashKind::Member => HouseholdService::new(db).update_member(&edited(records, id, set)?)?,
+        TrashKind::Document => {
+            HouseholdService::new(db).update_document(&edited(records, id, set)?)?
+        }
+        TrashKind::GroceryItem => {
+            GroceryService::new(db).update_grocery_item(&edited(records, id, set)?)?
+        }
+        TrashKind::InventoryItem => {
+            GroceryService::new(db).update_inventory_item(&edited(records, id, set)?)?
+        }
+    }
+    Ok(())
+}
+
+fn import(
+    db: &Db,
+    account: Option<&str>,
+    file: &Path,
+    profile: &str,
+    date_format: &str,
+) -> Result<ImportSummary> {
+    let finance = FinanceService::new(db);
+    let content = std::fs::read_to_string(file)
+        .with_context(|| format!("failed to read {}", file.display()))?;
+    let extension = file
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let require_account = || account.ok_or_else(|| anyhow!("--account is required"));
+
+    let summary = match extension.as_str() {
+        "ofx" | "qfx" => finance.import_ofx(require_account()?, &content)?,
+        "qif" => finance.import_qif(require_account()?, &content, date_format)?,
+        _ => {
+            let profile = finance
+                .get_import_profiles()?
+                .into_iter()
+                .find(|p| p.name == profile)
+                .ok_or_else(|| anyhow!("unknown import profile '{}'", profile))?;
+            finance.import_csv(account, &content, &profile)?
+        }
+    };
+    Ok(summary)
+}
+
+fn notifications(db: &Db, command: NotificationsCommand) -> Result<Value> {
+    let service = NotificationService::new(db);
+    let now = chrono::Utc::now();
+
+    let output = match command {
+        NotificationsCommand::Upcoming { limit } => {
+            serde_json::to_value(service.get_upcoming(limit)?)?
+        }
+        NotificationsCommand::Inbox => serde_json::to_value(service.get_inbox()?)?,
+        NotificationsCommand::Run => {
+            service.sync_reminders(now.date_naive())?;
+            FinanceService::new(db).post_autopay_bills(&now.date_naive().to_string())?;
+            let notifiers: Vec<Arc<dyn Notifier>> = vec![Arc::new(LogNotifier)];
+            json!({ "fired": service.fire_due(&notifiers, now)? })
+        }
+        NotificationsCommand::Snooze { id, hours } => {
+            service.snooze(&id, now + chrono::Duration::hours(hours))?;
+            json!({ "id": id })
+        }
+        NotificationsCommand::Dismiss { id } => {
+            service.dismiss(&id)?;
+            json!({ "id": id })
+        }
+    };
+    Ok(output)
+}
+
+/// The folder backend when `folder` is given, Google Drive otherwise.
+fn sync_backend(
+    runtime: &tokio::runtime::Runtime,
+    db: &Db,
+    folder: Option<&Path>,
+) -> Result<Box<dyn SyncBackend>> {
+    Ok(match folder {
+        Some(folder) => Box::new(LocalFolderBackend::new(folder)),
+        None => Box::new(runtime.block_on(CloudService::new(db).drive_backend())?),
+    })
+}
+
+/// Backups newest first, from the local history or the cloud account.
+fn list_backups(
+    runtime: &tokio::runtime::Runtime,
+    db: &Db,
+    db_path: &Path,
+    folder: Option<&Path>,
+    local: bool,
+) -> Result<Vec<RemoteObject>> {
+    if local {
+        let history = LocalFolderBackend::new(backup::local_history_dir(db_path));
+        return runtime.block_on(backup::list_history(&history, ""));
+    }
+    let backend = sync_backend(runtime, db, folder)?;
+    runtime.block_on(backup::list_remote_backups(backend.as_ref()))
+}
+
+fn read_changeset(path: &Path) -> Result<Changeset> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    serde_json::from_slice(&bytes).with_context(|| format!("{} is not a changeset", path.display()))
+}
+
+fn vault(db: &Db, db_path: &Path, passphrase: &str, command: VaultCommand) -> Result<Value> {
+    let mut vault = DocumentVault::open(db, DocumentVault::default_dir(db_path), passphrase)?;
+
+    let output = match command {
+        VaultCommand::Attach { document_id, file } => {
+            vault.attach_file(&document_id, &file)?;
+            json!({ "id": document_id })
+        }
+        VaultCommand::Open { document_id, out } => {
+            let file = vault.read_file(&document_id)?;
+            let path = out.join(&file.file_name);
+            std::fs::write(&path, &file.contents)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            json!({ "id": document_id, "file": path })
+        }
+        VaultCommand::Detach { document_id } => {
+            vault.remove_file(&document_id)?;
+            json!({ "id": document_id })
+        }
+        VaultCommand::Rotate { new_passphrase } => {
+            json!({ "rewrapped": vault.rotate_master_key(&new_passphrase)? })
+        }
+    };
+    Ok(output)
+}
+
+fn household(db: &Db, command: HouseholdCommand) -> Result<Value> {
+    let service = HouseholdService::new(db);
+    let output = match command {
+        HouseholdCommand::Members => {
+            let current = service.current_member_id()?;
+            let mut members = Vec::new();
+            for member in service.get_members()? {
+                let access = service.get_member_access(&member.id)?;
+                members.push(json!({
+                    "id": member.id,
+                    "name": member.name,
+                    "this_device": current.as_deref() == Some(member.id.as_str()),
+                    "status": access.as_ref().map(|a| a.status.clone()),
+                    "email": access.as_ref().and_then(|a| a.email.clone()),
+                    "devices": access.as_ref().map_or(0, |a| a.devices.len()),
+                    "roles": access.map(|a| a.roles),
+                }));
+            }
+            Value::Array(members)
+        }
+        HouseholdCommand::Invite {
+            member_id,
+            email,
+            roles,
+            share_drive,
+        } => {
+            let roles = if roles.is_empty() {
+                sharing::default_roles()
+            } else {
+                roles.into_iter().collect()
+            };
+            let code = service.invite_member(&member_id, email.as_deref(), &roles)?;
+            if let (true, Some(email)) = (share_drive, &email) {
+                tokio::runtime::Runtime::new()?
+                    .block_on(CloudService::new(db).invite_member(email))?;
+            }
+            json!({ "id": member_id, "code": code })
+        }
+        HouseholdCommand::Accept { code } => json!({ "id": service.accept_invite(&code)? }),
+        HouseholdCommand::Roles { member_id, roles } => {
+            service.set_member_roles(&member_id, &roles.into_iter().collect())?;
+            json!({ "id": member_id })
+        }
+        HouseholdCommand::Revoke {
+            member_id,
+            unshare_drive,
+        } => {
+            let email = service.revoke_member(&member_id)?;
+            if let (true, Some(email)) = (unshare_drive, &email) {
+                tokio::runtime::Runtime::new()?
+                    .block_on(CloudService::new(db).remove_member(email))?;
+            }
+            json!({ "id": member_id })
+        }
+    };
+    Ok(output)
+}
+
+fn delete(db: &Db, kind: TrashKind, id: &str) -> Result<()> {
+    match kind {
+        TrashKind::Account => FinanceService::new(db).delete_account(id)?,
+        TrashKind::Transaction => FinanceService::new(db).delete_transaction(id)?,
+        TrashKind::Category => FinanceService::new(db).delete_category(id)?,
+        TrashKind::Budget => FinanceService::new(db).delete_budget(id)?,
+        TrashKind::Bill => FinanceService::new(db).delete_bill(id)?,
+        TrashKind::Rule => FinanceService::new(db).delete_rule(id)?,
+        TrashKind::Trip => TravelService::new(db).delete_trip(id)?,
+        TrashKind::Restaurant => DiningService::new(db).delete_restaurant(id)?,
+        TrashKind::Appliance => MaintenanceService::new(db).delete_appliance(id)?,
+        TrashKind::Person => GiftsService::new(db).delete_person(id)?,
+        TrashKind::GiftIdea => GiftsService::new(db).delete_gift_idea(id)?,
+        TrashKind::Member => HouseholdService::new(db).delete_member(id)?,
+        TrashKind::Document => HouseholdService::new(db).delete_document(id)?,
+        TrashKind::GroceryItem => GroceryService::new(db).delete_grocery_item(id)?,
+        TrashKind::InventoryItem => GroceryService::new(db).delete_inventory_item(id)?,
+    }
+    Ok(())
+}
+
+/// Prints `value` as pretty JSON, or as an aligned table when it is a list of
+/// records and a key/value listing when it is a single record.
+fn print<T: Serialize>(value: &T, as_json: bool) -> Result<()> {
+    let value = serde_json::to_value(value)?;
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
+
+    let cell = |v: &Value| match v {
+        Value::Null => "-".to_string(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+
+    match &value {
+        Value::Array(rows) => {
+            let Some(Value::Object(first)) = rows.first() else {
+                println!("(none)");
+                return Ok(());
+            };
+            let columns: Vec<&String> = first.keys().collect();
+            let table: Vec<Vec<String>> = rows
+                .iter()
+                .map(|row| columns.iter().map(|c| cell(&row[c.as_str()])).collect())
+                .collect();
+            let widths: Vec<usize> = columns
+                .iter()
+                .enumerate()
+                .map(|(i, c)| {
+                    table
+                        .iter()
+                        .map(|r| r[i].chars().count())
+                        .chain([c.len()])
+                        .max()
+                        .unwrap_or(0)
+                })
+                .collect();
+
+            let line = |cells: Vec<String>| {
+                cells
+                    .iter()
+                    .zip(&widths)
+                    .map(|(c, w)| format!("{:<w$}", c, w = w))
+                    .collect::<Vec<_>>()
+                    .join("  ")
+                    .trim_end()
+                    .to_string()
+            };
+            println!("{}", line(columns.iter().map(|c| c.to_string()).collect()));
+            for row in table {
+                println!("{}", line(row));
+            }
+        }
+        Value::Object(fields) => {
+            let fields: &Map<String, Value> = fields;
+            for (key, v) in fields {
+                println!("{}: {}", key, cell(v));
+            }
+        }
+        other => println!("{}", cell(other)),
+    }
+    Ok(())
+}
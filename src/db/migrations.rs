@@ -0,0 +1,462 @@
+use rusqlite::{Connection, OptionalExtension, Result};
+
+/// Pseudo module id for migrations that belong to the shared schema rather
+/// than to a single feature module.
+pub const CORE_MODULE: &str = "core";
+
+/// A single numbered schema change owned by a module.
+///
+/// Versions start at 1 and increase by one per module. The core version is
+/// tracked in `PRAGMA user_version`, every other module's version lives in
+/// `module_state.schema_version`.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub module_id: &'static str,
+    pub version: u32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// Builds the SQL that makes changes to `table` visible to sync: triggers
+/// that bump this device's entry in `sync_vector_clocks` on every insert,
+/// update and delete, plus a starting clock for rows that already exist.
+/// Writes made while `sync_device.applying` is set come from another device
+/// and are not counted.
+macro_rules! track_changes {
+    ($($table:literal),+ $(,)?) => {
+        concat!($(
+            track_changes!(@trigger $table, "INSERT", "NEW"),
+            track_changes!(@trigger $table, "UPDATE", "NEW"),
+            track_changes!(@trigger $table, "DELETE", "OLD"),
+            "INSERT OR IGNORE INTO sync_vector_clocks (table_name, row_id, device_id, clock)
+             SELECT '", $table, "', id, (SELECT device_id FROM sync_device), 1 FROM ", $table, ";\n",
+        )+)
+    };
+    (@trigger $table:literal, $event:literal, $row:literal) => {
+        concat!(
+            "CREATE TRIGGER IF NOT EXISTS sync_", $table, "_", $event, " AFTER ", $event, " ON ", $table, "
+             WHEN (SELECT applying FROM sync_device) = 0
+             BEGIN
+                 INSERT INTO sync_vector_clocks (table_name, row_id, device_id, clock)
+                 SELECT '", $table, "', ", $row, ".id, device_id, 1 FROM sync_device WHERE true
+                 ON CONFLICT (table_name, row_id, device_id) DO UPDATE SET clock = clock + 1;
+             END;\n",
+        )
+    };
+}
+
+/// Every migration known to this binary, core first.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        module_id: CORE_MODULE,
+        version: 1,
+        description: "Baseline schema",
+        sql: include_str!("schema.sql"),
+    },
+    Migration {
+        module_id: CORE_MODULE,
+        version: 2,
+        description: "Snooze for scheduled notifications",
+        sql: "ALTER TABLE scheduled_notifications ADD COLUMN snoozed_until TEXT;",
+    },
+    Migration {
+        module_id: CORE_MODULE,
+        version: 3,
+        description: "Row-level change tracking for sync",
+        sql: concat!(
+            "CREATE TABLE IF NOT EXISTS sync_device (
+                 device_id TEXT NOT NULL,
+                 applying INTEGER NOT NULL DEFAULT 0
+             );
+             INSERT INTO sync_device (device_id)
+             SELECT lower(hex(randomblob(16))) WHERE NOT EXISTS (SELECT 1 FROM sync_device);\n",
+            track_changes!(
+                "members",
+                "accounts",
+                "categories",
+                "transactions",
+                "budgets",
+                "bills",
+                "documents",
+                "shopping_lists",
+                "shopping_list_items",
+                "inventory_items",
+                "trips",
+                "appliances",
+                "restaurants",
+                "people",
+                "gift_ideas",
+            ),
+        ),
+    },
+    Migration {
+        module_id: CORE_MODULE,
+        version: 4,
+        description: "Sync status",
+        sql: "CREATE TABLE IF NOT EXISTS sync_status (
+                  id INTEGER PRIMARY KEY CHECK (id = 1),
+                  state TEXT NOT NULL DEFAULT 'idle', -- idle|syncing|error
+                  message TEXT,
+                  last_success_at TEXT,
+                  last_attempt_at TEXT,
+                  consecutive_failures INTEGER NOT NULL DEFAULT 0
+              );
+              INSERT OR IGNORE INTO sync_status (id) VALUES (1);",
+    },
+    Migration {
+        module_id: "grocery",
+        version: 1,
+        description: "Soft delete for shopping list items",
+        sql: "ALTER TABLE shopping_list_items ADD COLUMN deleted_at TEXT;",
+    },
+    Migration {
+        module_id: "finance",
+        version: 1,
+        description: "Bill payment history",
+        sql: "CREATE TABLE IF NOT EXISTS bill_payments (
+                  id TEXT PRIMARY KEY,
+                  bill_id TEXT NOT NULL REFERENCES bills(id),
+                  transaction_id TEXT REFERENCES transactions(id),
+                  due_date TEXT NOT NULL,
+                  amount_cents INTEGER NOT NULL,
+                  is_autopay INTEGER NOT NULL DEFAULT 0,
+                  created_at TEXT NOT NULL
+              );",
+    },
+    Migration {
+        module_id: "finance",
+        version: 2,
+        description: "Change tracking for bill payments",
+        sql: track_changes!("bill_payments"),
+    },
+    Migration {
+        module_id: "finance",
+        version: 3,
+        description: "Transfers between accounts",
+        // Both transactions of a transfer share its id
+        sql: "ALTER TABLE transactions ADD COLUMN transfer_id TEXT;",
+    },
+    Migration {
+        module_id: "finance",
+        version: 4,
+        description: "Split transactions",
+        sql: concat!(
+            "CREATE TABLE IF NOT EXISTS transaction_splits (
+                 id TEXT PRIMARY KEY,
+                 transaction_id TEXT NOT NULL REFERENCES transactions(id),
+                 category_id TEXT REFERENCES categories(id),
+                 amount_cents INTEGER NOT NULL,
+                 note TEXT,
+                 sort_order INTEGER NOT NULL DEFAULT 0,
+                 created_at TEXT NOT NULL,
+                 updated_at TEXT NOT NULL
+             );\n",
+            track_changes!("transaction_splits"),
+        ),
+    },
+    Migration {
+        module_id: "finance",
+        version: 5,
+        description: "Exchange rates",
+        // The id is BASE/QUOTE/DATE so a rate entered on two devices is one row
+        sql: concat!(
+            "CREATE TABLE IF NOT EXISTS exchange_rates (
+                 id TEXT PRIMARY KEY,
+                 base_code TEXT NOT NULL,
+                 quote_code TEXT NOT NULL,
+                 rate_date TEXT NOT NULL,
+                 rate REAL NOT NULL,
+                 source TEXT NOT NULL DEFAULT 'manual',
+                 created_at TEXT NOT NULL,
+                 updated_at TEXT NOT NULL
+             );\n",
+            track_changes!("exchange_rates"),
+        ),
+    },
+    Migration {
+        module_id: "finance",
+        version: 6,
+        description: "Categorization rules",
+        sql: concat!(
+            "CREATE TABLE IF NOT EXISTS categorization_rules (
+                 id TEXT PRIMARY KEY,
+                 name TEXT NOT NULL,
+                 priority INTEGER NOT NULL DEFAULT 0,
+                 is_enabled INTEGER NOT NULL DEFAULT 1,
+                 merchant_contains TEXT,
+                 merchant_regex TEXT,
+                 min_amount_cents INTEGER,
+                 max_amount_cents INTEGER,
+                 account_id TEXT REFERENCES accounts(id),
+                 upi_app TEXT,
+                 category_id TEXT REFERENCES categories(id),
+                 tags TEXT, -- JSON array
+                 payee TEXT,
+                 created_at TEXT NOT NULL,
+                 updated_at TEXT NOT NULL,
+                 deleted_at TEXT
+             );\n",
+            track_changes!("categorization_rules"),
+        ),
+    },
+    Migration {
+        module_id: "finance",
+        version: 7,
+        description: "Opening balances",
+        // Balances are derived from this plus the transactions, so merging
+        // another device's transactions can recompute them. The backfill is
+        // the same on every device, so it is not tracked as a change.
+        sql: "ALTER TABLE accounts ADD COLUMN opening_balance_cents INTEGER NOT NULL DEFAULT 0;
+              UPDATE sync_device SET applying = 1;
+              UPDATE accounts SET opening_balance_cents = current_balance_cents - COALESCE(
+                  (SELECT SUM(t.amount_cents) FROM transactions t
+                   WHERE t.account_id = accounts.id
+                     AND (t.deleted_at IS NULL OR t.deleted_at = accounts.deleted_at)), 0);
+              UPDATE sync_device SET applying = 0;",
+    },
+];
+
+/// Highest version this binary knows for `module_id`, or 0 if it has none.
+pub fn latest_version(migrations: &[Migration], module_id: &str) -> u32 {
+    migrations
+        .iter()
+        .filter(|m| m.module_id == module_id)
+        .map(|m| m.version)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Returns the version currently recorded in the database for `module_id`.
+pub fn current_version(conn: &Connection, module_id: &str) -> Result<u32> {
+    if module_id == CORE_MODULE {
+        return conn.query_row("PRAGMA user_version", [], |row| row.get(0));
+    }
+
+    let has_state_table: bool = conn.query_row(
+        "SELECT count(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'module_state'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_state_table {
+        return Ok(0);
+    }
+
+    let version: Option<u32> = conn
+        .query_row(
+            "SELECT schema_version FROM module_state WHERE module_id = ?1",
+            [module_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(version.unwrap_or(0))
+}
+
+/// Fails if the database records a version of any module newer than
+/// anything in `migrations`, i.e. it was written by a newer build.
+pub fn check_supported(conn: &Connection, migrations: &[Migration]) -> Result<()> {
+    let core = current_version(conn, CORE_MODULE)?;
+    let core_latest = latest_version(migrations, CORE_MODULE);
+    if core > core_latest {
+        return Err(newer_than_binary(CORE_MODULE, core, core_latest));
+    }
+    if core > 0 {
+        // Also catches modules this binary has never heard of
+        let mut stmt = conn.prepare(
+            "SELECT module_id, schema_version FROM module_state WHERE schema_version > 0",
+        )?;
+        let recorded = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?))
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        for (module_id, current) in recorded {
+            let latest = latest_version(migrations, &module_id);
+            if current > latest {
+                return Err(newer_than_binary(&module_id, current, latest));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies every pending migration inside a single transaction.
+///
+/// Core migrations run before module migrations so modules can rely on the
+/// shared tables. If the database records a version newer than anything this
+/// binary knows about, nothing is applied and an error is returned instead.
+pub fn run(conn: &Connection, migrations: &[Migration]) -> Result<()> {
+    let mut module_ids = vec![CORE_MODULE];
+    for m in migrations {
+        if !module_ids.contains(&m.module_id) {
+            module_ids.push(m.module_id);
+        }
+    }
+
+    check_supported(conn, migrations)?;
+
+    let tx = conn.unchecked_transaction()?;
+
+    for module_id in module_ids {
+        let current = current_version(&tx, module_id)?;
+        let mut pending: Vec<&Migration> = migrations
+            .iter()
+            .filter(|m| m.module_id == module_id && m.version > current)
+            .collect();
+        pending.sort_by_key(|m| m.version);
+
+        for m in pending {
+            tx.execute_batch(m.sql)
+                .map_err(|e| migration_failed(m, e))?;
+            record_version(&tx, module_id, m.version)?;
+        }
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+fn record_version(conn: &Connection, module_id: &str, version: u32) -> Result<()> {
+    if module_id == CORE_MODULE {
+        // PRAGMA arguments cannot be bound as parameters
+        conn.execute_batch(&format!("PRAGMA user_version = {}", version))?;
+    } else {
+        let enabled = crate::modules::registry::ModuleRegistry::is_enabled_by_default(module_id);
+        conn.execute(
+            "INSERT INTO module_state (module_id, is_enabled, schema_version) VALUES (?1, ?2, ?3)
+             ON CONFLICT(module_id) DO UPDATE SET schema_version = excluded.schema_version",
+            (module_id, if enabled { 1 } else { 0 }, version),
+        )?;
+    }
+    Ok(())
+}
+
+/// Names the migration that failed in the error, keeping SQLite's code.
+fn migration_failed(m: &Migration, error: rusqlite::Error) -> rusqlite::Error {
+    let code = match &error {
+        rusqlite::Error::SqliteFailure(code, _) => *code,
+        _ => rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR),
+    };
+    rusqlite::Error::SqliteFailure(
+        code,
+        Some(format!(
+            "migration {} of module '{}' ({}) failed: {}",
+            m.version, m.module_id, m.description, error
+        )),
+    )
+}
+
+fn newer_than_binary(module_id: &str, found: u32, supported: u32) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
+        Some(format!(
+            "schema version {} of module '{}' is newer than the supported version {}",
+            found, module_id, supported
+        )),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_MIGRATIONS: &[Migration] = &[
+        Migration {
+            module_id: CORE_MODULE,
+            version: 1,
+            description: "Baseline schema",
+            sql: include_str!("schema.sql"),
+        },
+        Migration {
+            module_id: "dining",
+            version: 2,
+            description: "Restaurant phone",
+            sql: "ALTER TABLE restaurants ADD COLUMN phone TEXT;",
+        },
+        Migration {
+            module_id: "dining",
+            version: 1,
+            description: "Restaurant notes",
+            sql: "ALTER TABLE restaurants ADD COLUMN notes TEXT;",
+        },
+    ];
+
+    fn column_exists(conn: &Connection, table: &str, column: &str) -> bool {
+        conn.query_row(
+            "SELECT count(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+            (table, column),
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_migrations_apply_in_order_and_record_versions() {
+        let conn = Connection::open_in_memory().unwrap();
+        run(&conn, TEST_MIGRATIONS).unwrap();
+
+        assert_eq!(current_version(&conn, CORE_MODULE).unwrap(), 1);
+        assert_eq!(current_version(&conn, "dining").unwrap(), 2);
+        assert!(column_exists(&conn, "restaurants", "notes"));
+        assert!(column_exists(&conn, "restaurants", "phone"));
+
+        // Running again is a no-op rather than a duplicate-column error
+        run(&conn, TEST_MIGRATIONS).unwrap();
+    }
+
+    #[test]
+    fn test_failed_migration_rolls_back() {
+        let conn = Connection::open_in_memory().unwrap();
+        let broken = [
+            TEST_MIGRATIONS[0],
+            Migration {
+                module_id: "dining",
+                version: 1,
+                description: "Broken",
+                sql: "ALTER TABLE no_such_table ADD COLUMN x TEXT;",
+            },
+        ];
+
+        let error = run(&conn, &broken).unwrap_err().to_string();
+        assert!(error.contains("migration 1 of module 'dining' (Broken)"));
+        assert_eq!(current_version(&conn, CORE_MODULE).unwrap(), 0);
+        assert_eq!(current_version(&conn, "dining").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_refuses_database_newer_than_binary() {
+        let conn = Connection::open_in_memory().unwrap();
+        run(&conn, TEST_MIGRATIONS).unwrap();
+
+        // Core ahead of the binary
+        conn.execute_batch("PRAGMA user_version = 7").unwrap();
+        assert!(run(&conn, MIGRATIONS).is_err());
+        conn.execute_batch("PRAGMA user_version = 1").unwrap();
+
+        // Module ahead of the binary
+        assert!(run(&conn, MIGRATIONS).is_err());
+    }
+
+    #[test]
+    fn test_new_module_row_keeps_registry_default() {
+        let conn = Connection::open_in_memory().unwrap();
+        let finance = [
+            TEST_MIGRATIONS[0],
+            Migration {
+                module_id: "finance",
+                version: 1,
+                description: "Noop",
+                sql: "UPDATE restaurants SET rating = rating;",
+            },
+        ];
+        run(&conn, &finance).unwrap();
+
+        let enabled: i32 = conn
+            .query_row(
+                "SELECT is_enabled FROM module_state WHERE module_id = 'finance'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(enabled, 1);
+    }
+}
@@ -1,38 +1,203 @@
-use rusqlite::{Connection, Result};
-use std::path::Path;
+pub mod migrations;
+
+use rusqlite::{Connection, OpenFlags, Result};
+use std::path::{Path, PathBuf};
 
 pub struct Db {
     pub conn: Connection,
+    /// Set when the file is encrypted with SQLCipher. Kept so backups can be
+    /// keyed the same way as the live database.
+    passphrase: Option<String>,
+}
+
+/// Keys a freshly opened connection. SQLCipher derives the page key from the
+/// passphrase with PBKDF2-HMAC-SHA512 and a random per-file salt, so the same
+/// passphrase never yields the same key for two databases.
+fn apply_key(conn: &Connection, passphrase: &str) -> Result<()> {
+    conn.pragma_update(None, "key", passphrase)?;
+    // Pin the cipher settings so a future SQLCipher upgrade can still open old files
+    conn.pragma_update(None, "cipher_compatibility", 4)?;
+    // The key is only checked on first read; fail here instead of on a later query
+    conn.query_row("SELECT count(*) FROM sqlite_master", [], |row| {
+        row.get::<_, i64>(0)
+    })?;
+    Ok(())
 }
 
 impl Db {
+    /// Opens a plaintext database.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         let conn = Connection::open(path)?;
-        Ok(Db { conn })
+        Ok(Db {
+            conn,
+            passphrase: None,
+        })
+    }
+
+    /// Opens (or creates) a database encrypted with `passphrase`.
+    /// Returns `SQLITE_NOTADB` if the passphrase is wrong.
+    pub fn open_encrypted<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        apply_key(&conn, passphrase)?;
+        Ok(Db {
+            conn,
+            passphrase: Some(passphrase.to_string()),
+        })
+    }
+
+    /// Opens the database at `path`, encrypted with `MYHOME_PASSPHRASE` when
+    /// that is set. The app and the CLI both open it this way.
+    pub fn open_from_env<P: AsRef<Path>>(path: P) -> Result<Self> {
+        match std::env::var("MYHOME_PASSPHRASE") {
+            Ok(passphrase) if !passphrase.is_empty() => Self::open_encrypted(path, &passphrase),
+            _ => Self::new(path),
+        }
     }
 
+    /// The file the database lives in; `None` for in-memory databases.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.conn
+            .path()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.passphrase.is_some()
+    }
+
+    /// Brings the schema up to date by applying any pending migrations.
+    /// Fails without touching the file if it was written by a newer build.
     pub fn init(&self) -> Result<()> {
-        let schema = include_str!("schema.sql");
-        self.conn.execute_batch(schema)?;
+        migrations::run(&self.conn, migrations::MIGRATIONS)
+    }
+
+    /// Re-encrypts every page under a new passphrase.
+    pub fn change_passphrase(&mut self, new_passphrase: &str) -> Result<()> {
+        if !self.is_encrypted() {
+            return Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_MISUSE),
+                Some("database is not encrypted; use export_encrypted instead".to_string()),
+            ));
+        }
+        self.conn.pragma_update(None, "rekey", new_passphrase)?;
+        self.passphrase = Some(new_passphrase.to_string());
         Ok(())
     }
 
+    /// Writes an encrypted copy of this database to `target_path`, which is
+    /// how an existing plaintext database is moved to encrypted storage.
+    pub fn export_encrypted<P: AsRef<Path>>(&self, target_path: P, passphrase: &str) -> Result<()> {
+        let target = target_path.as_ref().to_string_lossy().to_string();
+        self.conn.execute(
+            "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+            (&target, passphrase),
+        )?;
+        let exported = self
+            .conn
+            .query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))
+            .and_then(|_| {
+                let version: i64 = self
+                    .conn
+                    .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+                self.conn
+                    .execute_batch(&format!("PRAGMA encrypted.user_version = {}", version))
+            });
+        self.conn.execute_batch("DETACH DATABASE encrypted")?;
+        exported
+    }
+
     /// Safely backups the database to a target path.
+    /// Backups of an encrypted database are encrypted with the same passphrase.
     pub fn backup<P: AsRef<Path>>(&self, target_path: P) -> Result<()> {
         let mut target_conn = Connection::open(target_path)?;
+        if let Some(passphrase) = &self.passphrase {
+            apply_key(&target_conn, passphrase)?;
+        }
         let backup = rusqlite::backup::Backup::new(&self.conn, &mut target_conn)?;
         backup.run_to_completion(10, std::time::Duration::from_millis(100), None)?;
         Ok(())
     }
 
+    /// Checks that the backup at `path` can replace this database: it must be
+    /// keyed like this one, pass SQLite's integrity check and not come from a
+    /// newer build. Returns its core schema version.
+    pub fn check_backup<P: AsRef<Path>>(&self, path: P) -> Result<u32> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        if let Some(passphrase) = &self.passphrase {
+            apply_key(&conn, passphrase)?;
+        }
+        let integrity: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+        if integrity != "ok" {
+            return Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CORRUPT),
+                Some(format!("backup failed the integrity check: {}", integrity)),
+            ));
+        }
+        let version = migrations::current_version(&conn, migrations::CORE_MODULE)?;
+        if version == 0 {
+            return Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_NOTADB),
+                Some("backup is not a My Home database".to_string()),
+            ));
+        }
+        migrations::check_supported(&conn, migrations::MIGRATIONS)?;
+        Ok(version)
+    }
+
     /// Restores the database from a source path.
-    #[allow(dead_code)]
+    /// The source must be encrypted with the current passphrase, if any.
     pub fn restore<P: AsRef<Path>>(&mut self, source_path: P) -> Result<()> {
         let source_conn = Connection::open(source_path)?;
+        if let Some(passphrase) = &self.passphrase {
+            apply_key(&source_conn, passphrase)?;
+        }
         let backup = rusqlite::backup::Backup::new(&source_conn, &mut self.conn)?;
         backup.run_to_completion(10, std::time::Duration::from_millis(100), None)?;
         Ok(())
     }
+
+    /// Stamps `deleted_at` on a live row. `table` must be a trusted table name.
+    pub fn soft_delete(&self, table: &str, id: &str) -> Result<()> {
+        let now = chrono::Utc::now().to_rfc3339();
+        let changed = self.conn.execute(
+            &format!(
+                "UPDATE {} SET deleted_at = ?1, updated_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+                table
+            ),
+            (&now, id),
+        )?;
+        expect_changed(changed)
+    }
+
+    /// Clears `deleted_at` on a soft-deleted row.
+    pub fn restore_deleted(&self, table: &str, id: &str) -> Result<()> {
+        let now = chrono::Utc::now().to_rfc3339();
+        let changed = self.conn.execute(
+            &format!(
+                "UPDATE {} SET deleted_at = NULL, updated_at = ?1 WHERE id = ?2 AND deleted_at IS NOT NULL",
+                table
+            ),
+            (&now, id),
+        )?;
+        expect_changed(changed)
+    }
+}
+
+/// RFC 3339 timestamp before which soft-deleted rows are eligible for purging.
+/// Comparable as text with the `deleted_at` values written by `Db::soft_delete`.
+pub fn purge_cutoff(older_than_days: i64) -> String {
+    (chrono::Utc::now() - chrono::Duration::days(older_than_days)).to_rfc3339()
+}
+
+/// Turns "no rows matched" into `QueryReturnedNoRows` so callers can tell a
+/// missing or already-deleted id apart from success.
+pub fn expect_changed(changed: usize) -> Result<()> {
+    if changed == 0 {
+        Err(rusqlite::Error::QueryReturnedNoRows)
+    } else {
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -66,4 +231,102 @@ mod tests {
 
         assert!(table_exists, "The module_state table was not created.");
     }
+
+    fn temp_db_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("myhome_{}_{}.db", name, uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_encrypted_database_round_trip() {
+        let path = temp_db_path("encrypted");
+        {
+            let db = Db::open_encrypted(&path, "correct horse").unwrap();
+            db.init().unwrap();
+            assert!(db.is_encrypted());
+            db.conn
+                .execute(
+                    "INSERT INTO module_state (module_id, is_enabled) VALUES ('vault', 1)",
+                    [],
+                )
+                .unwrap();
+        }
+
+        // The plaintext header must not be present on disk
+        let header = std::fs::read(&path).unwrap();
+        assert!(!header.starts_with(b"SQLite format 3"));
+
+        assert!(Db::open_encrypted(&path, "wrong").is_err());
+        assert!(Db::new(&path).and_then(|db| db.init()).is_err());
+
+        let mut db = Db::open_encrypted(&path, "correct horse").unwrap();
+        db.change_passphrase("battery staple").unwrap();
+        drop(db);
+
+        assert!(Db::open_encrypted(&path, "correct horse").is_err());
+        let db = Db::open_encrypted(&path, "battery staple").unwrap();
+        let count: i64 = db
+            .conn
+            .query_row(
+                "SELECT count(*) FROM module_state WHERE module_id = 'vault'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 1);
+
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_backup_and_restore() {
+        let path = temp_db_path("live");
+        let backup_path = temp_db_path("backup");
+
+        let mut db = Db::open_encrypted(&path, "secret").unwrap();
+        db.init().unwrap();
+        db.conn
+            .execute(
+                "INSERT INTO module_state (module_id, is_enabled) VALUES ('before', 1)",
+                [],
+            )
+            .unwrap();
+        db.backup(&backup_path).unwrap();
+
+        assert!(Db::new(&backup_path).and_then(|b| b.init()).is_err());
+
+        db.conn.execute("DELETE FROM module_state", []).unwrap();
+        db.restore(&backup_path).unwrap();
+        let restored: String = db
+            .conn
+            .query_row(
+                "SELECT module_id FROM module_state WHERE module_id = 'before'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(restored, "before");
+
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(backup_path).unwrap();
+    }
+
+    #[test]
+    fn test_export_plaintext_to_encrypted() {
+        let plain = Db::new(":memory:").unwrap();
+        plain.init().unwrap();
+
+        let path = temp_db_path("exported");
+        plain.export_encrypted(&path, "secret").unwrap();
+
+        let db = Db::open_encrypted(&path, "secret").unwrap();
+        assert_eq!(
+            migrations::current_version(&db.conn, migrations::CORE_MODULE).unwrap(),
+            migrations::latest_version(migrations::MIGRATIONS, migrations::CORE_MODULE)
+        );
+
+        drop(db);
+        std::fs::remove_file(path).unwrap();
+    }
 }
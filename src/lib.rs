@@ -2,6 +2,8 @@ mod app;
 pub mod db;
 pub mod modules;
 
+pub use app::run;
+
 // ── Android entry point ───────────────────────────────────────────────────────
 #[cfg(target_os = "android")]
 #[no_mangle]
@@ -1,10 +1,7 @@
-mod app;
-mod db;
-mod modules;
-
-// Desktop entry point
+// Desktop entry point; the app lives in the library so this binary and
+// myhome-cli share one copy of the modules
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    app::run()?;
+    myhome::run()?;
     Ok(())
 }
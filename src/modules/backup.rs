@@ -0,0 +1,649 @@
+//! Whole-database backups kept in a sync backend, and restoring from them.
+//!
+//! Each backup is a gzip-compressed copy of the database named after the
+//! time it was taken. A `manifest.json` next to the copies records their
+//! SHA-256 checksums and schema versions, and [`RetentionPolicy`] decides
+//! which copies survive pruning. The same code keeps the local history
+//! (a folder next to the database) and the remote ones in the cloud
+//! backend, where each device keeps its own history under
+//! [`device_prefix`] so devices backing up at the same time never rewrite
+//! each other's manifest. The document vault files and receipts a backup
+//! refers to are kept under [`FILES_PREFIX`] in the same history, each
+//! stored once however many backups list it.
+//!
+//! Restoring is split in two so the app can download on a spawned task and
+//! then swap the database in on its own connection: [`download_backup`]
+//! fetches and verifies a copy, [`restore_downloaded`] validates it, keeps a
+//! safety copy of the local database and restores.
+
+use crate::db::migrations::{self, CORE_MODULE};
+use crate::db::Db;
+use crate::modules::finance::receipts::ReceiptStore;
+use crate::modules::household::vault::DocumentVault;
+use crate::modules::household::HouseholdService;
+use crate::modules::sync::backend::{RemoteObject, SyncBackend};
+use crate::modules::sync::SyncService;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Datelike, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the single uncompressed snapshot older builds uploaded on sync.
+pub const SNAPSHOT_NAME: &str = "myhome.db";
+/// Folder in the cloud backend holding the devices' backup histories.
+pub const BACKUP_PREFIX: &str = "backups/";
+pub const MANIFEST_NAME: &str = "manifest.json";
+/// Folder in a backup history holding the files backups refer to.
+pub const FILES_PREFIX: &str = "files/";
+
+/// Which backups survive pruning. A backup is kept if any rule keeps it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// The most recent backups, however close together.
+    pub keep_last: usize,
+    /// The newest backup of each of the last `daily_days` days.
+    pub daily_days: i64,
+    /// The newest backup of each of the last `monthly_months` months.
+    pub monthly_months: i64,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_last: 5,
+            daily_days: 7,
+            monthly_months: 12,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BackupEntry {
+    /// File name, relative to the folder holding the manifest.
+    pub name: String,
+    pub created_at: String,
+    /// Size of the compressed file in bytes.
+    pub size: u64,
+    /// Hex SHA-256 of the compressed file.
+    pub sha256: String,
+    pub schema_version: u32,
+    /// Files stored next to the database, as `<folder>/<name>` under
+    /// [`FILES_PREFIX`]. Backups taken by older builds have none.
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BackupManifest {
+    pub backups: Vec<BackupEntry>,
+}
+
+/// A compressed copy of the database, ready to be stored.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub entry: BackupEntry,
+    pub data: Vec<u8>,
+    /// Where each of `entry.files` is read from when it is stored.
+    pub sources: Vec<PathBuf>,
+}
+
+/// Folder next to the database that keeps its local backup history.
+pub fn local_history_dir(db_path: &Path) -> PathBuf {
+    db_path.with_extension("backups")
+}
+
+/// Folder in the cloud backend holding one device's backup history. Only
+/// that device writes its manifest, so no update to it is ever lost.
+pub fn device_prefix(device_id: &str) -> String {
+    format!("{}{}/", BACKUP_PREFIX, device_id)
+}
+
+/// A directory next to the database whose files backups carry, stored
+/// under `folder` in [`FILES_PREFIX`].
+struct FileDir {
+    folder: &'static str,
+    dir: PathBuf,
+    /// The files in `dir` worth keeping.
+    list: fn(&Path) -> Result<Vec<PathBuf>>,
+}
+
+fn file_dirs(db_path: &Path) -> [FileDir; 2] {
+    [
+        FileDir {
+            folder: "vault",
+            dir: DocumentVault::default_dir(db_path),
+            list: DocumentVault::stored_files,
+        },
+        FileDir {
+            folder: "receipts",
+            dir: ReceiptStore::default_dir(db_path),
+            list: ReceiptStore::stored_files,
+        },
+    ]
+}
+
+/// Takes a compressed copy of the database at `db_path`, listing the vault
+/// files and receipts next to it. The uncompressed copy is written beside the database
+/// and removed again.
+pub fn snapshot(db: &Db, db_path: &Path) -> Result<Snapshot> {
+    let scratch = db_path.with_extension("db.backup");
+    db.backup(&scratch)?;
+    let raw = std::fs::read(&scratch);
+    let _ = std::fs::remove_file(&scratch);
+    let raw = raw.with_context(|| format!("Failed to read {}", scratch.display()))?;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&raw)?;
+    let data = encoder.finish()?;
+
+    let (mut files, mut sources) = (Vec::new(), Vec::new());
+    for file_dir in file_dirs(db_path) {
+        for path in (file_dir.list)(&file_dir.dir)? {
+            if let Some(name) = path.file_name() {
+                files.push(format!("{}/{}", file_dir.folder, name.to_string_lossy()));
+                sources.push(path);
+            }
+        }
+    }
+
+    let now = Utc::now();
+    Ok(Snapshot {
+        entry: BackupEntry {
+            name: format!("myhome-{}.db.gz", now.format("%Y%m%dT%H%M%S%.3fZ")),
+            created_at: now.to_rfc3339(),
+            size: data.len() as u64,
+            sha256: sha256_hex(&data),
+            schema_version: migrations::current_version(&db.conn, CORE_MODULE)?,
+            files,
+        },
+        data,
+        sources,
+    })
+}
+
+/// Adds a snapshot to the history under `prefix` and prunes copies the
+/// policy no longer keeps, along with files no remaining copy lists.
+/// Returns the names of the pruned copies.
+pub async fn store_snapshot(
+    backend: &dyn SyncBackend,
+    prefix: &str,
+    snapshot: Snapshot,
+    policy: &RetentionPolicy,
+) -> Result<Vec<String>> {
+    let mut manifest = read_manifest(backend, prefix).await?;
+    let files_prefix = format!("{}{}", prefix, FILES_PREFIX);
+    let mut stored: HashSet<String> = backend
+        .list(&files_prefix)
+        .await?
+        .into_iter()
+        .filter_map(|o| o.name.strip_prefix(&files_prefix).map(str::to_string))
+        .collect();
+    // A file name always means the same contents, so one upload serves
+    // every backup listing it
+    for (name, source) in snapshot.entry.files.iter().zip(&snapshot.sources) {
+        if !stored.contains(name) {
+            let contents = std::fs::read(source)
+                .with_context(|| format!("Failed to read {}", source.display()))?;
+            backend
+                .put(&format!("{}{}", files_prefix, name), contents)
+                .await?;
+            stored.insert(name.clone());
+        }
+    }
+    backend
+        .put(&format!("{}{}", prefix, snapshot.entry.name), snapshot.data)
+        .await?;
+    manifest.backups.push(snapshot.entry);
+
+    let keep = backups_to_keep(&manifest.backups, policy, Utc::now());
+    let (kept, pruned): (Vec<_>, Vec<_>) = manifest
+        .backups
+        .into_iter()
+        .partition(|e| keep.contains(&e.name));
+    manifest.backups = kept;
+    // Write the manifest before deleting so it never lists a missing file
+    backend
+        .put(
+            &format!("{}{}", prefix, MANIFEST_NAME),
+            serde_json::to_vec_pretty(&manifest)?,
+        )
+        .await?;
+    for entry in &pruned {
+        backend.delete(&format!("{}{}", prefix, entry.name)).await?;
+    }
+    let listed: HashSet<&String> = manifest.backups.iter().flat_map(|e| &e.files).collect();
+    for name in stored.iter().filter(|name| !listed.contains(name)) {
+        backend.delete(&format!("{}{}", files_prefix, name)).await?;
+    }
+    Ok(pruned.into_iter().map(|e| e.name).collect())
+}
+
+/// The history under `prefix`, newest first.
+pub async fn list_backups(backend: &dyn SyncBackend, prefix: &str) -> Result<Vec<BackupEntry>> {
+    let mut backups = read_manifest(backend, prefix).await?.backups;
+    backups.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(backups)
+}
+
+/// Names of the backups `policy` keeps as of `now`.
+pub fn backups_to_keep(
+    backups: &[BackupEntry],
+    policy: &RetentionPolicy,
+    now: DateTime<Utc>,
+) -> HashSet<String> {
+    let mut newest_first: Vec<&BackupEntry> = backups.iter().collect();
+    newest_first.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+    let mut keep: HashSet<String> = newest_first
+        .iter()
+        .take(policy.keep_last)
+        .map(|e| e.name.clone())
+        .collect();
+    let mut days = HashSet::new();
+    let mut months = HashSet::new();
+    let this_month = now.year() as i64 * 12 + now.month0() as i64;
+    for entry in newest_first {
+        let Ok(created) = DateTime::parse_from_rfc3339(&entry.created_at) else {
+            // Never prune something we cannot date
+            keep.insert(entry.name.clone());
+            continue;
+        };
+        let created = created.with_timezone(&Utc);
+        let day = created.date_naive();
+        if (now.date_naive() - day).num_days() < policy.daily_days && days.insert(day) {
+            keep.insert(entry.name.clone());
+        }
+        let month = created.year() as i64 * 12 + created.month0() as i64;
+        if this_month - month < policy.monthly_months && months.insert(month) {
+            keep.insert(entry.name.clone());
+        }
+    }
+    keep
+}
+
+/// The history under `prefix` as backend objects with full names, newest
+/// first.
+pub async fn list_history(backend: &dyn SyncBackend, prefix: &str) -> Result<Vec<RemoteObject>> {
+    Ok(list_backups(backend, prefix)
+        .await?
+        .into_iter()
+        .map(|e| RemoteObject {
+            name: format!("{}{}", prefix, e.name),
+            size: e.size,
+            modified: DateTime::parse_from_rfc3339(&e.created_at)
+                .ok()
+                .map(|d| d.with_timezone(&Utc)),
+            etag: e.sha256,
+        })
+        .collect())
+}
+
+/// Every backup in the cloud `backend`, newest first: the history of each
+/// device under [`BACKUP_PREFIX`] plus the single snapshot older builds
+/// uploaded. Names are full backend names, ready for [`download_backup`].
+pub async fn list_remote_backups(backend: &dyn SyncBackend) -> Result<Vec<RemoteObject>> {
+    let mut backups = Vec::new();
+    for object in backend.list(BACKUP_PREFIX).await? {
+        let history = object
+            .name
+            .strip_suffix(MANIFEST_NAME)
+            .filter(|prefix| prefix.ends_with('/'));
+        if let Some(prefix) = history {
+            backups.extend(list_history(backend, prefix).await?);
+        }
+    }
+    if let Some(snapshot) = backend.metadata(SNAPSHOT_NAME).await? {
+        backups.push(snapshot);
+    }
+    backups.sort_by(|a, b| b.modified.cmp(&a.modified).then(a.name.cmp(&b.name)));
+    Ok(backups)
+}
+
+/// Downloads backup `name` to `target`. Compressed copies from the history
+/// are checked against the manifest and decompressed.
+pub async fn download_backup(backend: &dyn SyncBackend, name: &str, target: &Path) -> Result<()> {
+    let mut data = backend.get(name).await?;
+
+    if name.ends_with(".gz") {
+        let (prefix, file_name) = match name.rfind('/') {
+            Some(i) => name.split_at(i + 1),
+            None => ("", name),
+        };
+        let manifest = read_manifest(backend, prefix).await?;
+        let entry = manifest
+            .backups
+            .iter()
+            .find(|e| e.name == file_name)
+            .ok_or_else(|| anyhow!("{} is not in the backup manifest", name))?;
+        if sha256_hex(&data) != entry.sha256 {
+            bail!("{} does not match its checksum", name);
+        }
+        let mut raw = Vec::new();
+        GzDecoder::new(data.as_slice())
+            .read_to_end(&mut raw)
+            .with_context(|| format!("Failed to decompress {}", name))?;
+        data = raw;
+    }
+
+    tokio::fs::write(target, data)
+        .await
+        .with_context(|| format!("Failed to write {}", target.display()))
+}
+
+/// Downloads the files backup `name` lists into the directories next to the
+/// database at `db_path`, skipping the ones already there. Returns the
+/// number downloaded.
+pub async fn download_files(
+    backend: &dyn SyncBackend,
+    name: &str,
+    db_path: &Path,
+) -> Result<usize> {
+    let (prefix, file_name) = match name.rfind('/') {
+        Some(i) => name.split_at(i + 1),
+        None => ("", name),
+    };
+    let manifest = read_manifest(backend, prefix).await?;
+    let Some(entry) = manifest.backups.iter().find(|e| e.name == file_name) else {
+        return Ok(0);
+    };
+
+    let dirs = file_dirs(db_path);
+    let mut downloaded = 0;
+    for file in &entry.files {
+        // Names come from the backend, so never let one leave its folder
+        let Some((dir, local_name)) = file
+            .split_once('/')
+            .filter(|(_, n)| Path::new(n).file_name() == Some(n.as_ref()))
+            .and_then(|(folder, n)| {
+                dirs.iter()
+                    .find(|d| d.folder == folder)
+                    .map(|d| (&d.dir, n))
+            })
+        else {
+            bail!("{} lists an invalid file '{}'", name, file);
+        };
+        let target = dir.join(local_name);
+        if target.exists() {
+            continue;
+        }
+        let contents = backend
+            .get(&format!("{}{}{}", prefix, FILES_PREFIX, file))
+            .await?;
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        tokio::fs::write(&target, contents)
+            .await
+            .with_context(|| format!("Failed to write {}", target.display()))?;
+        downloaded += 1;
+    }
+    Ok(downloaded)
+}
+
+async fn read_manifest(backend: &dyn SyncBackend, prefix: &str) -> Result<BackupManifest> {
+    let name = format!("{}{}", prefix, MANIFEST_NAME);
+    if backend.metadata(&name).await?.is_none() {
+        return Ok(BackupManifest::default());
+    }
+    let bytes = backend.get(&name).await?;
+    serde_json::from_slice(&bytes).with_context(|| format!("{} is not a backup manifest", name))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Replaces the database at `db_path` with the downloaded backup at
+/// `downloaded`. The backup is checked first and the current database is
+/// copied next to it, so a bad restore can be undone. Returns the path of
+/// that safety copy.
+pub fn restore_downloaded(db: &mut Db, db_path: &Path, downloaded: &Path) -> Result<PathBuf> {
+    db.check_backup(downloaded)
+        .with_context(|| format!("{} cannot be restored", downloaded.display()))?;
+
+    let safety = db_path.with_extension(format!(
+        "before-restore-{}.db",
+        Utc::now().format("%Y%m%dT%H%M%S")
+    ));
+    db.backup(&safety)
+        .context("Failed to back up the current database")?;
+
+    let device_id = SyncService::new(db).device_id()?;
+    db.restore(downloaded)?;
+    // Backups from an older build still need this build's migrations
+    db.init()?;
+    // The backup may come from another device; writing under its id would
+    // make our changes indistinguishable from its own
+    let new_device_id = SyncService::new(db).reset_device_id()?;
+    // This device keeps the household access it had before the restore
+    HouseholdService::new(db).keep_device_access(&device_id, &new_device_id)?;
+    Ok(safety)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::finance::FinanceService;
+    use crate::modules::sync::backend::MemoryBackend;
+
+    #[tokio::test]
+    async fn test_restore_from_remote_backup() {
+        let dir = std::env::temp_dir().join(format!("myhome_restore_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let backend = MemoryBackend::new();
+
+        // The old phone uploads a snapshot
+        let old_path = dir.join("old.db");
+        let old_phone = Db::new(&old_path).unwrap();
+        old_phone.init().unwrap();
+        FinanceService::new(&old_phone)
+            .create_account("Savings", "savings", 250_000)
+            .unwrap();
+        old_phone.backup(dir.join("upload.db")).unwrap();
+        backend
+            .put(SNAPSHOT_NAME, std::fs::read(dir.join("upload.db")).unwrap())
+            .await
+            .unwrap();
+        backend
+            .put("changes/old.json", b"{}".to_vec())
+            .await
+            .unwrap();
+
+        let backups = list_remote_backups(&backend).await.unwrap();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].name, SNAPSHOT_NAME);
+
+        // A new phone restores it
+        let new_path = dir.join("new.db");
+        let mut new_phone = Db::new(&new_path).unwrap();
+        new_phone.init().unwrap();
+        let downloaded = dir.join("download.db");
+        download_backup(&backend, SNAPSHOT_NAME, &downloaded)
+            .await
+            .unwrap();
+
+        let garbage = dir.join("garbage.db");
+        std::fs::write(&garbage, b"not a database").unwrap();
+        assert!(restore_downloaded(&mut new_phone, &new_path, &garbage).is_err());
+
+        let safety = restore_downloaded(&mut new_phone, &new_path, &downloaded).unwrap();
+        assert!(safety.exists());
+        let accounts = FinanceService::new(&new_phone).get_accounts().unwrap();
+        assert_eq!(accounts[0].name, "Savings");
+        assert_ne!(
+            SyncService::new(&new_phone).device_id().unwrap(),
+            SyncService::new(&old_phone).device_id().unwrap()
+        );
+
+        drop(old_phone);
+        drop(new_phone);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn entry(created_at: &str) -> BackupEntry {
+        BackupEntry {
+            name: created_at.to_string(),
+            created_at: created_at.to_string(),
+            size: 0,
+            sha256: String::new(),
+            schema_version: 1,
+            files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_retention_keeps_recent_daily_and_monthly() {
+        let now = DateTime::parse_from_rfc3339("2024-06-15T12:00:00+00:00")
+            .unwrap()
+            .with_timezone(&Utc);
+        let backups: Vec<BackupEntry> = [
+            "2024-06-15T11:00:00+00:00",
+            "2024-06-15T10:00:00+00:00",
+            "2024-06-15T09:00:00+00:00",
+            "2024-06-14T20:00:00+00:00",
+            "2024-06-14T08:00:00+00:00",
+            "2024-06-01T08:00:00+00:00",
+            "2024-05-20T08:00:00+00:00",
+            "2024-05-02T08:00:00+00:00",
+            "2023-07-10T08:00:00+00:00",
+            "2023-05-10T08:00:00+00:00",
+        ]
+        .into_iter()
+        .map(entry)
+        .collect();
+        let policy = RetentionPolicy {
+            keep_last: 2,
+            daily_days: 7,
+            monthly_months: 12,
+        };
+
+        let mut kept: Vec<String> = backups_to_keep(&backups, &policy, now)
+            .into_iter()
+            .collect();
+        kept.sort();
+        assert_eq!(
+            kept,
+            [
+                "2023-07-10T08:00:00+00:00", // newest of July 2023
+                "2024-05-20T08:00:00+00:00", // newest of May 2024
+                "2024-06-14T20:00:00+00:00", // newest of yesterday
+                "2024-06-15T10:00:00+00:00", // among the last two
+                "2024-06-15T11:00:00+00:00", // newest of today and of June
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_backup_history_round_trip() {
+        let dir = std::env::temp_dir().join(format!("myhome_history_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Db::new(dir.join("live.db")).unwrap();
+        db.init().unwrap();
+        let account = FinanceService::new(&db)
+            .create_account("Checking", "checking", 1_000)
+            .unwrap();
+        let rent = FinanceService::new(&db)
+            .create_transaction(&account, -500, "Rent", "2024-06-01", None)
+            .unwrap();
+        ReceiptStore::new(&db, ReceiptStore::default_dir(&dir.join("live.db")))
+            .attach_bytes(&rent, b"%PDF-1.4 rent receipt")
+            .unwrap();
+        let doc_id = HouseholdService::new(&db)
+            .add_document(None, "Passport", "Passport", None, None)
+            .unwrap();
+        let vault =
+            DocumentVault::open(&db, DocumentVault::default_dir(&dir.join("live.db")), "pw")
+                .unwrap();
+
+        let backend = MemoryBackend::new();
+        let policy = RetentionPolicy {
+            keep_last: 2,
+            daily_days: 0,
+            monthly_months: 0,
+        };
+        let prefix = device_prefix("phone");
+        let mut pruned = Vec::new();
+        for scan in [b"%PDF old scan", b"%PDF new scan", b"%PDF new scan"] {
+            if vault.read_file(&doc_id).map(|f| f.contents).ok().as_deref() != Some(scan) {
+                vault.attach_bytes(&doc_id, "passport.pdf", scan).unwrap();
+            }
+            let snapshot = snapshot(&db, &dir.join("live.db")).unwrap();
+            assert!(snapshot.entry.size > 0);
+            assert_eq!(snapshot.entry.files.len(), 2);
+            pruned.extend(
+                store_snapshot(&backend, &prefix, snapshot, &policy)
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert_eq!(pruned.len(), 1);
+        assert!(!dir.join("live.db.backup").exists());
+        // Only the file of the pruned backup is gone; the others are stored once
+        let stored = backend
+            .list(&format!("{}{}", prefix, FILES_PREFIX))
+            .await
+            .unwrap();
+        assert_eq!(stored.len(), 2);
+
+        // Another device's history sits next to this one's without touching it
+        let other = snapshot(&db, &dir.join("live.db")).unwrap();
+        store_snapshot(&backend, &device_prefix("tablet"), other, &policy)
+            .await
+            .unwrap();
+        assert_eq!(list_backups(&backend, &prefix).await.unwrap().len(), 2);
+
+        let remote = list_remote_backups(&backend).await.unwrap();
+        assert_eq!(remote.len(), 3);
+        assert!(remote[0].name.starts_with(&device_prefix("tablet")));
+        let remote = &remote[1..];
+        let target = dir.join("restored.db");
+        download_backup(&backend, &remote[0].name, &target)
+            .await
+            .unwrap();
+        assert!(db.check_backup(&target).is_ok());
+        assert_eq!(
+            download_files(&backend, &remote[0].name, &target)
+                .await
+                .unwrap(),
+            2
+        );
+        let restored = Db::new(&target).unwrap();
+        let receipt = ReceiptStore::new(&restored, ReceiptStore::default_dir(&target))
+            .get_receipt(&rent)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            std::fs::read(receipt.path).unwrap(),
+            b"%PDF-1.4 rent receipt"
+        );
+        let restored_vault =
+            DocumentVault::open(&restored, DocumentVault::default_dir(&target), "pw").unwrap();
+        assert_eq!(
+            restored_vault.read_file(&doc_id).unwrap().contents,
+            b"%PDF new scan"
+        );
+        drop(restored_vault);
+        drop(restored);
+
+        // A corrupted copy is caught by its checksum
+        backend
+            .put(&remote[1].name, b"tampered".to_vec())
+            .await
+            .unwrap();
+        assert!(download_backup(&backend, &remote[1].name, &target)
+            .await
+            .is_err());
+
+        drop(db);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,5 +1,11 @@
 use crate::db::Db;
+use crate::modules::backup::{self, RetentionPolicy};
+use crate::modules::finance::receipts::ReceiptStore;
+use crate::modules::household::sharing::{current_role, Role};
+use crate::modules::sync::backend::{LocalFolderBackend, RemoteObject, SyncBackend};
+use crate::modules::sync::SyncService;
 use anyhow::{anyhow, Result};
+use async_trait::async_trait;
 use google_drive3::hyper_rustls::HttpsConnectorBuilder;
 use google_drive3::{api::File, DriveHub};
 use std::fs;
@@ -14,6 +20,9 @@ type Connector = google_drive3::hyper_rustls::HttpsConnector<
 type Authenticator = yup_oauth2::authenticator::Authenticator<Connector>;
 type Hub = DriveHub<Connector>;
 
+/// Drive folder everything is synced to.
+const APP_FOLDER: &str = "Applications/myHome";
+
 pub struct CloudService<'a> {
     db: &'a Db,
 }
@@ -32,6 +41,13 @@ fn config_dir() -> std::path::PathBuf {
     return std::path::PathBuf::from(".");
 }
 
+/// Where the Drive OAuth tokens are kept once an account is linked.
+fn token_path() -> std::path::PathBuf {
+    config_dir()
+        .join(".tokens")
+        .join("google_drive_tokens.json")
+}
+
 impl<'a> CloudService<'a> {
     pub fn new(db: &'a Db) -> Self {
         Self { db }
@@ -52,15 +68,15 @@ impl<'a> CloudService<'a> {
             return Err(anyhow!("client_secret.json not found"));
         }
 
-        let token_dir = config.join(".tokens");
-        if !token_dir.exists() {
-            fs::create_dir_all(&token_dir)?;
+        let token_path = token_path();
+        if let Some(token_dir) = token_path.parent() {
+            fs::create_dir_all(token_dir)?;
         }
 
         let secret = yup_oauth2::read_application_secret(&secret_path).await?;
         let auth =
             InstalledFlowAuthenticator::builder(secret, InstalledFlowReturnMethod::HTTPRedirect)
-                .persist_tokens_to_disk(token_dir.join("google_drive_tokens.json"))
+                .persist_tokens_to_disk(token_path)
                 .build()
                 .await?;
 
@@ -83,116 +99,42 @@ impl<'a> CloudService<'a> {
         Ok(DriveHub::new(client, auth))
     }
 
-    pub async fn sync_database(&self, db_path: &Path) -> Result<()> {
+    /// Drive as a sync backend, rooted at the app folder.
+    pub async fn drive_backend(&self) -> Result<DriveBackend> {
         let hub = self.get_drive_hub().await?;
-        let folder_id = self
-            .ensure_folder_exists(&hub, "Applications/myHome")
-            .await?;
-
-        let backup_path = db_path.with_extension("db.backup");
-        self.db.backup(&backup_path)?;
-
-        let remote_file = self.find_file(&hub, &folder_id, "myhome.db").await?;
-
-        match remote_file {
-            Some(file) => {
-                self.upload_file(
-                    &hub,
-                    &folder_id,
-                    &backup_path,
-                    "myhome.db",
-                    file.id.as_deref(),
-                )
-                .await?;
-            }
-            None => {
-                self.upload_file(&hub, &folder_id, &backup_path, "myhome.db", None)
-                    .await?;
-            }
-        }
-
-        Ok(())
+        let folder_id = ensure_folder_exists(&hub, APP_FOLDER).await?;
+        Ok(DriveBackend { hub, folder_id })
     }
 
-    async fn ensure_folder_exists(&self, hub: &Hub, path: &str) -> Result<String> {
-        let parts: Vec<&str> = path.split('/').collect();
-        let mut parent_id = "root".to_string();
-
-        for part in parts {
-            let existing = self.find_file(hub, &parent_id, part).await?;
-            parent_id = match existing {
-                Some(file) => file.id.ok_or_else(|| anyhow!("Found folder without ID"))?,
-                None => {
-                    let folder = File {
-                        name: Some(part.to_string()),
-                        mime_type: Some("application/vnd.google-apps.folder".to_string()),
-                        parents: Some(vec![parent_id]),
-                        ..Default::default()
-                    };
-
-                    // Workaround: Use upload() with empty cursor for metadata-only create
-                    let (_, res) = hub
-                        .files()
-                        .create(folder)
-                        .upload(
-                            Cursor::new(vec![]),
-                            "application/octet-stream".parse().unwrap(),
-                        )
-                        .await?;
-                    res.id.ok_or_else(|| anyhow!("Failed to create folder"))?
-                }
-            };
-        }
-
-        Ok(parent_id)
-    }
-
-    async fn find_file(&self, hub: &Hub, parent_id: &str, name: &str) -> Result<Option<File>> {
-        let query = format!(
-            "name = '{}' and '{}' in parents and trashed = false",
-            name, parent_id
-        );
-        let (_, list) = hub.files().list().q(&query).spaces("drive").doit().await?;
-
-        Ok(list.files.and_then(|files| files.into_iter().next()))
+    pub async fn sync_database(&self, db_path: &Path) -> Result<()> {
+        let backend = self.drive_backend().await?;
+        self.sync_with(&backend, db_path).await
     }
 
-    async fn upload_file(
-        &self,
-        hub: &Hub,
-        parent_id: &str,
-        local_path: &Path,
-        remote_name: &str,
-        remote_id: Option<&str>,
-    ) -> Result<()> {
-        let mut file = File {
-            name: Some(remote_name.to_string()),
-            ..Default::default()
-        };
-
-        let mut f = fs::File::open(local_path)?;
-
-        if let Some(id) = remote_id {
-            hub.files()
-                .update(file, id)
-                .upload(&mut f, "application/octet-stream".parse().unwrap())
-                .await?;
-        } else {
-            file.parents = Some(vec![parent_id.to_string()]);
-            hub.files()
-                .create(file)
-                .upload(&mut f, "application/octet-stream".parse().unwrap())
+    /// Exchanges row-level changes and receipt files with the other devices
+    /// using `backend`, then adds a snapshot of the database to the local
+    /// and the remote backup history.
+    pub async fn sync_with(&self, backend: &dyn SyncBackend, db_path: &Path) -> Result<()> {
+        SyncService::new(self.db).exchange(backend).await?;
+        // Only finance editors share receipt files
+        if current_role(self.db, "finance")? >= Role::Editor {
+            ReceiptStore::new(self.db, ReceiptStore::default_dir(db_path))
+                .sync_files(backend)
                 .await?;
         }
 
+        let snapshot = backup::snapshot(self.db, db_path)?;
+        let policy = RetentionPolicy::default();
+        let local = LocalFolderBackend::new(backup::local_history_dir(db_path));
+        backup::store_snapshot(&local, "", snapshot.clone(), &policy).await?;
+        let remote = backup::device_prefix(&SyncService::new(self.db).device_id()?);
+        backup::store_snapshot(backend, &remote, snapshot, &policy).await?;
         Ok(())
     }
 
     pub async fn invite_member(&self, email: &str) -> Result<()> {
         let hub = self.get_drive_hub().await?;
-        let folder_id = self
-            .ensure_folder_exists(&hub, "Applications/myHome")
-            .await?;
+        let folder_id = ensure_folder_exists(&hub, APP_FOLDER).await?;
 
         let permission = google_drive3::api::Permission {
             role: Some("writer".to_string()),
@@ -208,21 +150,251 @@ impl<'a> CloudService<'a> {
         Ok(())
     }
 
+    /// Withdraws the Drive access `invite_member` granted to `email`.
+    pub async fn remove_member(&self, email: &str) -> Result<()> {
+        let hub = self.get_drive_hub().await?;
+        let folder_id = ensure_folder_exists(&hub, APP_FOLDER).await?;
+
+        let (_, list) = hub
+            .permissions()
+            .list(&folder_id)
+            .param("fields", "permissions(id,emailAddress)")
+            .doit()
+            .await?;
+        let permission_ids = list
+            .permissions
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|p| {
+                p.email_address
+                    .as_deref()
+                    .is_some_and(|e| e.eq_ignore_ascii_case(email))
+            })
+            .filter_map(|p| p.id);
+        for id in permission_ids {
+            hub.permissions().delete(&folder_id, &id).doit().await?;
+        }
+        Ok(())
+    }
+
+    /// Whether a Google account has been linked on this device.
+    pub fn is_linked() -> bool {
+        token_path().exists()
+    }
+
     pub fn get_sync_status(&self) -> String {
-        let token_path = Path::new(".tokens/google_drive_tokens.json");
-        if token_path.exists() {
-            "Linked & Ready".to_string()
-        } else {
-            "Not Linked".to_string()
+        if !Self::is_linked() {
+            return "Not Linked".to_string();
+        }
+        match SyncService::new(self.db).get_status() {
+            Ok(status) if status.state == "syncing" => "Syncing…".to_string(),
+            Ok(status) if status.state == "error" => format!(
+                "Sync failed: {}",
+                status
+                    .message
+                    .unwrap_or_else(|| "unknown error".to_string())
+            ),
+            _ => "Linked & Ready".to_string(),
         }
     }
 
     pub fn get_last_sync_time(&self) -> String {
-        // In a real app we'd store this in the settings or a separate file
-        "Never".to_string()
+        SyncService::new(self.db)
+            .get_status()
+            .ok()
+            .and_then(|status| status.last_success_at)
+            .and_then(|at| chrono::DateTime::parse_from_rfc3339(&at).ok())
+            .map(|at| {
+                at.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_else(|| "Never".to_string())
     }
 }
 
-// Ensure Send/Sync for Slint
-unsafe impl<'a> Send for CloudService<'a> {}
-unsafe impl<'a> Sync for CloudService<'a> {}
+/// Files in the app folder on Google Drive.
+pub struct DriveBackend {
+    hub: Hub,
+    folder_id: String,
+}
+
+impl DriveBackend {
+    async fn find(&self, name: &str) -> Result<Option<File>> {
+        find_file(&self.hub, &self.folder_id, name).await
+    }
+}
+
+fn remote_object(file: File) -> RemoteObject {
+    RemoteObject {
+        name: file.name.unwrap_or_default(),
+        size: file.size.unwrap_or(0).max(0) as u64,
+        modified: file.modified_time,
+        etag: file
+            .md5_checksum
+            .or_else(|| file.version.map(|v| v.to_string()))
+            .unwrap_or_default(),
+    }
+}
+
+#[async_trait]
+impl SyncBackend for DriveBackend {
+    async fn list(&self, prefix: &str) -> Result<Vec<RemoteObject>> {
+        let query = format!(
+            "'{}' in parents and trashed = false and name contains '{}'",
+            self.folder_id,
+            escape_query(prefix)
+        );
+        let mut objects = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut request = self
+                .hub
+                .files()
+                .list()
+                .q(&query)
+                .spaces("drive")
+                .page_size(1000)
+                .param(
+                    "fields",
+                    "nextPageToken,files(id,name,size,modifiedTime,md5Checksum,version)",
+                );
+            if let Some(token) = &page_token {
+                request = request.page_token(token);
+            }
+            let (_, list) = request.doit().await?;
+            objects.extend(
+                list.files
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(remote_object)
+                    .filter(|o| o.name.starts_with(prefix)),
+            );
+            page_token = list.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+        objects.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(objects)
+    }
+
+    async fn metadata(&self, name: &str) -> Result<Option<RemoteObject>> {
+        Ok(self.find(name).await?.map(remote_object))
+    }
+
+    async fn get(&self, name: &str) -> Result<Vec<u8>> {
+        let file = self
+            .find(name)
+            .await?
+            .ok_or_else(|| anyhow!("No file called '{}' on Drive", name))?;
+        let id = file.id.ok_or_else(|| anyhow!("Found file without ID"))?;
+        let (response, _) = self
+            .hub
+            .files()
+            .get(&id)
+            .param("alt", "media")
+            .doit()
+            .await?;
+        let bytes = google_drive3::common::to_bytes(response.into_body())
+            .await
+            .ok_or_else(|| anyhow!("Failed to download '{}'", name))?;
+        Ok(bytes.to_vec())
+    }
+
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<RemoteObject> {
+        let existing = self.find(name).await?.and_then(|f| f.id);
+        let mut file = File {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+        let mime = "application/octet-stream".parse().unwrap();
+
+        let (_, uploaded) = match existing {
+            Some(id) => {
+                self.hub
+                    .files()
+                    .update(file, &id)
+                    .param("fields", "id,name,size,modifiedTime,md5Checksum,version")
+                    .upload(Cursor::new(data), mime)
+                    .await?
+            }
+            None => {
+                file.parents = Some(vec![self.folder_id.clone()]);
+                self.hub
+                    .files()
+                    .create(file)
+                    .param("fields", "id,name,size,modifiedTime,md5Checksum,version")
+                    .upload(Cursor::new(data), mime)
+                    .await?
+            }
+        };
+        Ok(remote_object(uploaded))
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        if let Some(id) = self.find(name).await?.and_then(|f| f.id) {
+            self.hub.files().delete(&id).doit().await?;
+        }
+        Ok(())
+    }
+}
+
+async fn ensure_folder_exists(hub: &Hub, path: &str) -> Result<String> {
+    let parts: Vec<&str> = path.split('/').collect();
+    let mut parent_id = "root".to_string();
+
+    for part in parts {
+        let existing = find_file(hub, &parent_id, part).await?;
+        parent_id = match existing {
+            Some(file) => file.id.ok_or_else(|| anyhow!("Found folder without ID"))?,
+            None => {
+                let folder = File {
+                    name: Some(part.to_string()),
+                    mime_type: Some("application/vnd.google-apps.folder".to_string()),
+                    parents: Some(vec![parent_id]),
+                    ..Default::default()
+                };
+
+                // Workaround: Use upload() with empty cursor for metadata-only create
+                let (_, res) = hub
+                    .files()
+                    .create(folder)
+                    .upload(
+                        Cursor::new(vec![]),
+                        "application/octet-stream".parse().unwrap(),
+                    )
+                    .await?;
+                res.id.ok_or_else(|| anyhow!("Failed to create folder"))?
+            }
+        };
+    }
+
+    Ok(parent_id)
+}
+
+async fn find_file(hub: &Hub, parent_id: &str, name: &str) -> Result<Option<File>> {
+    let query = format!(
+        "name = '{}' and '{}' in parents and trashed = false",
+        escape_query(name),
+        parent_id
+    );
+    let (_, list) = hub
+        .files()
+        .list()
+        .q(&query)
+        .spaces("drive")
+        .param(
+            "fields",
+            "files(id,name,size,modifiedTime,md5Checksum,version)",
+        )
+        .doit()
+        .await?;
+
+    Ok(list.files.and_then(|files| files.into_iter().next()))
+}
+
+/// Escapes a value for use inside a quoted Drive query string.
+fn escape_query(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
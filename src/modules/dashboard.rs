@@ -1,8 +1,14 @@
 use crate::db::Db;
+use crate::modules::finance::rates::RateTable;
+use crate::modules::finance::splits::SPENDING_LINES;
 use crate::modules::finance::FinanceService;
 use crate::modules::grocery::GroceryService;
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::settings::SettingsService;
 use crate::modules::travel::TravelService;
+use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DashboardSummary {
     pub net_balance: f64,
     pub active_trips: usize,
@@ -28,7 +34,7 @@ impl<'a> DashboardService<'a> {
         let grocery_service = GroceryService::new(self.db);
 
         Ok(DashboardSummary {
-            net_balance: finance_service.get_total_balance(),
+            net_balance: finance_service.get_total_balance()?,
             active_trips: travel_service.get_trips().map(|t| t.len()).unwrap_or(0),
             grocery_items: grocery_service
                 .get_grocery_list()
@@ -37,24 +43,47 @@ impl<'a> DashboardService<'a> {
         })
     }
 
+    /// Spending per category in the household currency. Each day's
+    /// spending is converted at that day's rate; currencies with no known
+    /// rate are left out.
     pub fn get_expenditure_by_category(&self) -> Result<Vec<(String, f64)>, rusqlite::Error> {
-        let mut stmt = self.db.conn.prepare(
-            "SELECT c.name, SUM(ABS(t.amount_cents)) as total_cents 
-             FROM transactions t
-             JOIN categories c ON t.category_id = c.id
-             WHERE t.amount_cents < 0
-             GROUP BY c.id",
-        )?;
+        require(self.db, "finance", Role::Viewer)?;
+        let currency_code = SettingsService::new(self.db).get_settings()?.currency_code;
+        let rates = RateTable::load(&self.db.conn)?;
+        let mut stmt = self.db.conn.prepare(&format!(
+            "SELECT c.id, c.name, substr(l.date, 1, 10), l.currency_code, SUM(ABS(l.amount_cents)) as total_cents
+             FROM ({}) l
+             JOIN categories c ON l.category_id = c.id
+             WHERE l.amount_cents < 0
+             GROUP BY c.id, substr(l.date, 1, 10), l.currency_code",
+            SPENDING_LINES
+        ))?;
         let rows = stmt.query_map([], |row| {
-            let name: String = row.get(0)?;
-            let total_cents: i64 = row.get(1)?;
-            Ok((name, (total_cents as f64) / 100.0))
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
         })?;
 
-        let mut results = Vec::new();
+        let mut totals: Vec<(String, String, i64)> = Vec::new();
         for res in rows {
-            results.push(res?);
+            let (id, name, date, currency, total_cents) = res?;
+            let Some(cents) = rates.convert(total_cents, &currency, &currency_code, &date) else {
+                continue;
+            };
+            match totals.iter_mut().find(|(i, _, _)| *i == id) {
+                Some((_, _, sum)) => *sum += cents,
+                None => totals.push((id, name, cents)),
+            }
         }
+
+        let results = totals
+            .into_iter()
+            .map(|(_, name, cents)| (name, (cents as f64) / 100.0))
+            .collect();
         Ok(results)
     }
 }
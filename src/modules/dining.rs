@@ -1,4 +1,6 @@
-use crate::db::Db;
+use crate::db::{expect_changed, Db};
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::trash::{self, TrashKind};
 use chrono::Utc;
 use rusqlite::Result;
 use serde::{Deserialize, Serialize};
@@ -25,6 +27,7 @@ impl<'a> DiningService<'a> {
     }
 
     pub fn get_restaurants(&self) -> Result<Vec<Restaurant>> {
+        require(self.db, "dining", Role::Viewer)?;
         let mut stmt = self.db.conn.prepare(
             "SELECT id, name, cuisine_type, location, price_range, visited_flag, rating FROM restaurants WHERE deleted_at IS NULL ORDER BY name ASC"
         )?;
@@ -54,7 +57,8 @@ impl<'a> DiningService<'a> {
         cuisine_type: Option<&str>,
         location: Option<&str>,
         visited: bool,
-    ) -> Result<()> {
+    ) -> Result<String> {
+        require(self.db, "dining", Role::Editor)?;
         let id = Uuid::new_v4().to_string();
         let now = Utc::now().to_rfc3339();
         let visited_int = if visited { 1 } else { 0 };
@@ -62,9 +66,47 @@ impl<'a> DiningService<'a> {
         self.db.conn.execute(
             "INSERT INTO restaurants (id, name, cuisine_type, location, visited_flag, created_at, updated_at)
              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            (id, name, cuisine_type, location, visited_int, &now, &now),
+            (&id, name, cuisine_type, location, visited_int, &now, &now),
         )?;
-        Ok(())
+        Ok(id)
+    }
+
+    pub fn update_restaurant(&self, restaurant: &Restaurant) -> Result<()> {
+        require(self.db, "dining", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+        let visited_int = if restaurant.visited_flag { 1 } else { 0 };
+
+        let changed = self.db.conn.execute(
+            "UPDATE restaurants SET name = ?1, cuisine_type = ?2, location = ?3, price_range = ?4, visited_flag = ?5, rating = ?6, updated_at = ?7
+             WHERE id = ?8 AND deleted_at IS NULL",
+            (
+                &restaurant.name,
+                &restaurant.cuisine_type,
+                &restaurant.location,
+                &restaurant.price_range,
+                visited_int,
+                restaurant.rating,
+                &now,
+                &restaurant.id,
+            ),
+        )?;
+        expect_changed(changed)
+    }
+
+    pub fn delete_restaurant(&self, id: &str) -> Result<()> {
+        require(self.db, "dining", Role::Editor)?;
+        self.db.soft_delete("restaurants", id)
+    }
+
+    pub fn restore_restaurant(&self, id: &str) -> Result<()> {
+        require(self.db, "dining", Role::Editor)?;
+        self.db.restore_deleted("restaurants", id)
+    }
+
+    /// Permanently removes restaurants deleted more than `older_than_days` ago.
+    pub fn purge_deleted(&self, older_than_days: i64) -> Result<usize> {
+        require(self.db, "dining", Role::Editor)?;
+        trash::purge_older_than(&self.db.conn, &[TrashKind::Restaurant], older_than_days)
     }
 }
 
@@ -100,4 +142,34 @@ mod tests {
         assert_eq!(restaurants[1].name, "Sushi Spot");
         assert!(restaurants[1].visited_flag);
     }
+
+    #[test]
+    fn test_restaurant_update_delete_restore() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+
+        let service = DiningService::new(&db);
+        let id = service
+            .add_restaurant("Toit", Some("Pub"), Some("Indiranagar"), false)
+            .unwrap();
+
+        let mut restaurant = service.get_restaurants().unwrap().remove(0);
+        restaurant.visited_flag = true;
+        restaurant.rating = Some(4);
+        restaurant.price_range = Some("$$".to_string());
+        service.update_restaurant(&restaurant).unwrap();
+
+        let updated = &service.get_restaurants().unwrap()[0];
+        assert!(updated.visited_flag);
+        assert_eq!(updated.rating, Some(4));
+        assert_eq!(updated.price_range.as_deref(), Some("$$"));
+
+        service.delete_restaurant(&id).unwrap();
+        assert!(service.get_restaurants().unwrap().is_empty());
+        service.restore_restaurant(&id).unwrap();
+        assert_eq!(service.get_restaurants().unwrap().len(), 1);
+
+        service.delete_restaurant(&id).unwrap();
+        assert_eq!(service.purge_deleted(0).unwrap(), 1);
+    }
 }
@@ -0,0 +1,566 @@
+//! Recurring bills: due-date rollover, payment history, autopay posting and
+//! due-date reminders.
+
+use super::{insert_transaction, invalid_input, parse_date, FinanceService, TransactionDetails};
+use crate::db::expect_changed;
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::notifications;
+use crate::modules::settings::SettingsService;
+use chrono::{Datelike, Days, Months, NaiveDate, Utc};
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+pub const RECURRENCE_TYPES: [&str; 5] = ["weekly", "biweekly", "monthly", "quarterly", "annual"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bill {
+    pub id: String,
+    pub name: String,
+    pub amount_cents: i64,
+    pub is_estimated: bool,
+    pub currency_code: String,
+    pub recurrence_type: String, // weekly|biweekly|monthly|quarterly|annual
+    /// Day of the month the bill falls on, clamped to shorter months.
+    pub recurrence_day: Option<u32>,
+    pub next_due: String,
+    pub account_id: Option<String>,
+    pub category_id: Option<String>,
+    pub is_autopay: bool,
+    pub alert_days_before: i64,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BillPayment {
+    pub id: String,
+    pub bill_id: String,
+    pub transaction_id: Option<String>,
+    pub due_date: String,
+    pub amount_cents: i64,
+    pub is_autopay: bool,
+    pub created_at: String,
+}
+
+impl<'a> FinanceService<'a> {
+    /// Live bills, soonest due first.
+    pub fn get_bills(&self) -> Result<Vec<Bill>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let mut stmt = self.db.conn.prepare(
+            "SELECT id, name, amount_cents, is_estimated, currency_code, recurrence_type, recurrence_day, next_due,
+                    account_id, category_id, is_autopay, alert_days_before, notes
+             FROM bills WHERE deleted_at IS NULL ORDER BY next_due, name",
+        )?;
+
+        let bills = stmt
+            .query_map([], bill_from_row)?
+            .filter_map(Result::ok)
+            .collect();
+
+        Ok(bills)
+    }
+
+    /// Creates a bill first due on `first_due` (`YYYY-MM-DD`). Monthly and
+    /// longer bills keep recurring on that day of the month. Autopay and the
+    /// reminder lead time (3 days by default) are set through `update_bill`.
+    pub fn create_bill(
+        &self,
+        name: &str,
+        amount_cents: i64,
+        recurrence_type: &str,
+        first_due: &str,
+        account_id: Option<&str>,
+        category_id: Option<&str>,
+    ) -> Result<String> {
+        require(self.db, "finance", Role::Editor)?;
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+        let first_due = parse_date(first_due)?;
+        validate_recurrence(recurrence_type)?;
+        let recurrence_day = (!recurrence_type.ends_with("weekly")).then(|| first_due.day());
+
+        let tx = self.db.conn.unchecked_transaction()?;
+        let currency_code: String = match account_id {
+            Some(account_id) => tx.query_row(
+                "SELECT currency_code FROM accounts WHERE id = ?1 AND deleted_at IS NULL",
+                [account_id],
+                |row| row.get(0),
+            )?,
+            None => SettingsService::new(self.db).get_settings()?.currency_code,
+        };
+
+        tx.execute(
+            "INSERT INTO bills (id, name, amount_cents, currency_code, recurrence_type, recurrence_day, next_due, account_id, category_id, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            (
+                &id,
+                name,
+                amount_cents,
+                &currency_code,
+                recurrence_type,
+                recurrence_day,
+                first_due.to_string(),
+                account_id,
+                category_id,
+                &now,
+                &now,
+            ),
+        )?;
+        schedule_reminder(&tx, &id)?;
+
+        tx.commit()?;
+        Ok(id)
+    }
+
+    pub fn update_bill(&self, bill: &Bill) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+        validate_recurrence(&bill.recurrence_type)?;
+        let next_due = parse_date(&bill.next_due)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+        check_account_currency(&tx, bill)?;
+
+        let changed = tx.execute(
+            "UPDATE bills SET name = ?1, amount_cents = ?2, is_estimated = ?3, currency_code = ?4, recurrence_type = ?5,
+             recurrence_day = ?6, next_due = ?7, account_id = ?8, category_id = ?9, is_autopay = ?10,
+             alert_days_before = ?11, notes = ?12, updated_at = ?13
+             WHERE id = ?14 AND deleted_at IS NULL",
+            (
+                &bill.name,
+                bill.amount_cents,
+                bill.is_estimated,
+                &bill.currency_code,
+                &bill.recurrence_type,
+                bill.recurrence_day,
+                next_due.to_string(),
+                &bill.account_id,
+                &bill.category_id,
+                bill.is_autopay,
+                bill.alert_days_before,
+                &bill.notes,
+                &now,
+                &bill.id,
+            ),
+        )?;
+        expect_changed(changed)?;
+        schedule_reminder(&tx, &bill.id)?;
+
+        tx.commit()
+    }
+
+    /// Soft-deletes a bill and drops its pending reminder.
+    pub fn delete_bill(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+        self.db.soft_delete("bills", id)?;
+        clear_reminder(&tx, id)?;
+        tx.commit()
+    }
+
+    pub fn restore_bill(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+        self.db.restore_deleted("bills", id)?;
+        schedule_reminder(&tx, id)?;
+        tx.commit()
+    }
+
+    /// Records `transaction_id` as the payment for the bill's current due
+    /// date and moves the bill on to its next due date. A transaction can
+    /// only pay for one due date.
+    pub fn mark_bill_paid(&self, bill_id: &str, transaction_id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+        let bill = live_bill(&tx, bill_id)?;
+
+        let already_linked = tx
+            .query_row(
+                "SELECT 1 FROM bill_payments WHERE transaction_id = ?1",
+                [transaction_id],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if already_linked {
+            return Err(invalid_input(format!(
+                "transaction {} is already recorded as a bill payment",
+                transaction_id
+            )));
+        }
+
+        let amount_cents: i64 = tx.query_row(
+            "SELECT amount_cents FROM transactions WHERE id = ?1 AND deleted_at IS NULL",
+            [transaction_id],
+            |row| row.get(0),
+        )?;
+        record_payment(
+            &tx,
+            &bill,
+            &Uuid::new_v4().to_string(),
+            Some(transaction_id),
+            -amount_cents,
+            false,
+        )?;
+        schedule_reminder(&tx, bill_id)?;
+
+        tx.commit()
+    }
+
+    /// Payment history of a bill, newest due date first.
+    pub fn get_bill_payments(&self, bill_id: &str) -> Result<Vec<BillPayment>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let mut stmt = self.db.conn.prepare(
+            "SELECT id, bill_id, transaction_id, due_date, amount_cents, is_autopay, created_at
+             FROM bill_payments WHERE bill_id = ?1 ORDER BY due_date DESC",
+        )?;
+
+        let payments = stmt
+            .query_map([bill_id], |row| {
+                Ok(BillPayment {
+                    id: row.get(0)?,
+                    bill_id: row.get(1)?,
+                    transaction_id: row.get(2)?,
+                    due_date: row.get(3)?,
+                    amount_cents: row.get(4)?,
+                    is_autopay: row.get(5)?,
+                    created_at: row.get(6)?,
+                })
+            })?
+            .filter_map(Result::ok)
+            .collect();
+
+        Ok(payments)
+    }
+
+    /// Posts a transaction for every autopay bill due on or before `on`
+    /// (`YYYY-MM-DD`), catching up on any missed periods, and returns the ids
+    /// of the transactions created. Bills without an account are skipped, as
+    /// are bills whose currency no longer matches their account's.
+    ///
+    /// Every device of a shared household posts due bills, so the posted
+    /// rows get ids derived from the bill and due date (see [`autopay_id`])
+    /// and the copies merge into one when the devices sync.
+    pub fn post_autopay_bills(&self, on: &str) -> Result<Vec<String>> {
+        require(self.db, "finance", Role::Editor)?;
+        let on = parse_date(on)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let due_ids: Vec<String> = {
+            let mut stmt = tx.prepare(
+                "SELECT b.id FROM bills b JOIN accounts a ON a.id = b.account_id
+                 WHERE b.deleted_at IS NULL AND a.deleted_at IS NULL AND b.is_autopay = 1 AND b.next_due <= ?1
+                   AND b.currency_code = a.currency_code",
+            )?;
+            let ids = stmt
+                .query_map([on.to_string()], |row| row.get(0))?
+                .collect::<Result<_>>()?;
+            ids
+        };
+
+        let mut posted = Vec::new();
+        for bill_id in due_ids {
+            let mut bill = live_bill(&tx, &bill_id)?;
+            let account_id = bill.account_id.clone().unwrap_or_default();
+
+            while parse_date(&bill.next_due)? <= on {
+                let transaction_id = autopay_id("transaction", &bill.id, &bill.next_due);
+                // Already posted, e.g. before the due date was moved back
+                let posted_before = tx
+                    .query_row(
+                        "SELECT 1 FROM transactions WHERE id = ?1",
+                        [&transaction_id],
+                        |_| Ok(()),
+                    )
+                    .optional()?
+                    .is_some();
+                if !posted_before {
+                    insert_transaction(
+                        &tx,
+                        &transaction_id,
+                        &account_id,
+                        -bill.amount_cents,
+                        &bill.name,
+                        &bill.next_due,
+                        bill.category_id.as_deref(),
+                        &TransactionDetails::default(),
+                    )?;
+                    posted.push(transaction_id.clone());
+                }
+                bill.next_due = record_payment(
+                    &tx,
+                    &bill,
+                    &autopay_id("payment", &bill.id, &bill.next_due),
+                    Some(&transaction_id),
+                    bill.amount_cents,
+                    true,
+                )?
+                .to_string();
+            }
+            schedule_reminder(&tx, &bill_id)?;
+        }
+
+        tx.commit()?;
+        Ok(posted)
+    }
+}
+
+/// The due date following `due` for a recurrence kind. Month-based bills land
+/// on `recurrence_day`, or the last day of the month when it is shorter.
+pub fn next_due_after(
+    recurrence_type: &str,
+    recurrence_day: Option<u32>,
+    due: NaiveDate,
+) -> Option<NaiveDate> {
+    let months = match recurrence_type {
+        "weekly" => return due.checked_add_days(Days::new(7)),
+        "biweekly" => return due.checked_add_days(Days::new(14)),
+        "monthly" => 1,
+        "quarterly" => 3,
+        "annual" => 12,
+        _ => return None,
+    };
+
+    let month = NaiveDate::from_ymd_opt(due.year(), due.month(), 1)?
+        .checked_add_months(Months::new(months))?;
+    let last_day = month.checked_add_months(Months::new(1))?.pred_opt()?.day();
+    month.with_day(recurrence_day.unwrap_or(due.day()).clamp(1, last_day))
+}
+
+fn bill_from_row(row: &rusqlite::Row) -> Result<Bill> {
+    Ok(Bill {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        amount_cents: row.get(2)?,
+        is_estimated: row.get(3)?,
+        currency_code: row.get(4)?,
+        recurrence_type: row.get(5)?,
+        recurrence_day: row.get(6)?,
+        next_due: row.get(7)?,
+        account_id: row.get(8)?,
+        category_id: row.get(9)?,
+        is_autopay: row.get(10)?,
+        alert_days_before: row.get(11)?,
+        notes: row.get(12)?,
+    })
+}
+
+fn live_bill(conn: &Connection, id: &str) -> Result<Bill> {
+    conn.query_row(
+        "SELECT id, name, amount_cents, is_estimated, currency_code, recurrence_type, recurrence_day, next_due,
+                account_id, category_id, is_autopay, alert_days_before, notes
+         FROM bills WHERE id = ?1 AND deleted_at IS NULL",
+        [id],
+        bill_from_row,
+    )
+}
+
+/// Id of the `kind` row autopay posts for a bill's due date. Every device
+/// derives the same one.
+fn autopay_id(kind: &str, bill_id: &str, due: &str) -> String {
+    let digest = Sha256::digest(format!("autopay:{}:{}:{}", kind, bill_id, due));
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    uuid::Builder::from_custom_bytes(bytes)
+        .into_uuid()
+        .to_string()
+}
+
+/// Logs payment `id` against the bill's current due date and advances
+/// `next_due`. Returns the new due date.
+fn record_payment(
+    conn: &Connection,
+    bill: &Bill,
+    id: &str,
+    transaction_id: Option<&str>,
+    amount_cents: i64,
+    is_autopay: bool,
+) -> Result<NaiveDate> {
+    let now = Utc::now().to_rfc3339();
+    let due = parse_date(&bill.next_due)?;
+    let next_due = next_due_after(&bill.recurrence_type, bill.recurrence_day, due)
+        .ok_or_else(|| invalid_input(format!("cannot advance bill '{}'", bill.name)))?;
+
+    conn.execute(
+        "INSERT INTO bill_payments (id, bill_id, transaction_id, due_date, amount_cents, is_autopay, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT(id) DO NOTHING",
+        (
+            id,
+            &bill.id,
+            transaction_id,
+            due.to_string(),
+            amount_cents,
+            is_autopay,
+            &now,
+        ),
+    )?;
+    conn.execute(
+        "UPDATE bills SET next_due = ?1, updated_at = ?2 WHERE id = ?3",
+        (next_due.to_string(), &now, &bill.id),
+    )?;
+    Ok(next_due)
+}
+
+/// Replaces the bill's pending reminder with one `alert_days_before` days
+/// ahead of its current due date.
+fn schedule_reminder(conn: &Connection, bill_id: &str) -> Result<()> {
+    let Some(bill) = live_bill(conn, bill_id).optional()? else {
+        return clear_reminder(conn, bill_id);
+    };
+
+    let due = parse_date(&bill.next_due)?;
+    let remind_on = due
+        .checked_sub_days(Days::new(bill.alert_days_before.max(0) as u64))
+        .unwrap_or(due);
+    let mut body = format!(
+        "{:.2} {} due on {}",
+        bill.amount_cents as f64 / 100.0,
+        bill.currency_code,
+        due
+    );
+    if bill.is_autopay {
+        body.push_str(", paid automatically");
+    }
+
+    notifications::schedule(
+        conn,
+        "finance",
+        bill_id,
+        "bill_due",
+        &format!("{} due", bill.name),
+        &body,
+        remind_on.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+    )?;
+    Ok(())
+}
+
+fn clear_reminder(conn: &Connection, bill_id: &str) -> Result<()> {
+    notifications::cancel(conn, "finance", bill_id, "bill_due")
+}
+
+/// Fails when the bill is paid from an account in another currency, since
+/// autopay posts `amount_cents` into that account as it is.
+fn check_account_currency(conn: &Connection, bill: &Bill) -> Result<()> {
+    let Some(account_id) = &bill.account_id else {
+        return Ok(());
+    };
+    let account_currency: String = conn.query_row(
+        "SELECT currency_code FROM accounts WHERE id = ?1 AND deleted_at IS NULL",
+        [account_id],
+        |row| row.get(0),
+    )?;
+    if account_currency != bill.currency_code {
+        return Err(invalid_input(format!(
+            "bill '{}' is in {} but its account keeps {}",
+            bill.name, bill.currency_code, account_currency
+        )));
+    }
+    Ok(())
+}
+
+fn validate_recurrence(recurrence_type: &str) -> Result<()> {
+    if RECURRENCE_TYPES.contains(&recurrence_type) {
+        Ok(())
+    } else {
+        Err(invalid_input(format!(
+            "unknown bill recurrence '{}'",
+            recurrence_type
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+
+    fn date(s: &str) -> NaiveDate {
+        parse_date(s).unwrap()
+    }
+
+    #[test]
+    fn test_bills_rollover_payment_and_autopay() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = FinanceService::new(&db);
+        let account = service
+            .create_account("Checking", "checking", 100000)
+            .unwrap();
+
+        // Month-end bills clamp to short months and return to the 31st after
+        let mut due = date("2024-01-31");
+        let mut dues = Vec::new();
+        for _ in 0..3 {
+            due = next_due_after("monthly", Some(31), due).unwrap();
+            dues.push(due.to_string());
+        }
+        assert_eq!(dues, ["2024-02-29", "2024-03-31", "2024-04-30"]);
+        assert_eq!(
+            next_due_after("annual", Some(29), date("2024-02-29")),
+            Some(date("2025-02-28"))
+        );
+        assert_eq!(
+            next_due_after("biweekly", None, date("2024-12-25")),
+            Some(date("2025-01-08"))
+        );
+
+        // Manual payment links the transaction and moves the due date on
+        let rent = service
+            .create_bill("Rent", 50000, "monthly", "2024-01-31", Some(&account), None)
+            .unwrap();
+        let reminder: String = db
+            .conn
+            .query_row(
+                "SELECT scheduled_for FROM scheduled_notifications WHERE entity_id = ?1 AND is_fired = 0",
+                [&rent],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(reminder.starts_with("2024-01-28"));
+
+        let paid = service
+            .create_transaction(&account, -50000, "Landlord", "2024-01-30", None)
+            .unwrap();
+        service.mark_bill_paid(&rent, &paid).unwrap();
+        assert_eq!(service.get_bills().unwrap()[0].next_due, "2024-02-29");
+        let payments = service.get_bill_payments(&rent).unwrap();
+        assert_eq!(payments[0].transaction_id.as_deref(), Some(paid.as_str()));
+        assert_eq!(payments[0].due_date, "2024-01-31");
+        assert!(service.mark_bill_paid(&rent, &paid).is_err());
+
+        // Autopay catches up on every missed due date
+        let mut bill = service.get_bills().unwrap().remove(0);
+        let in_dollars = Bill {
+            currency_code: "USD".to_string(),
+            ..bill.clone()
+        };
+        assert!(service.update_bill(&in_dollars).is_err());
+        bill.is_autopay = true;
+        bill.amount_cents = 1000;
+        service.update_bill(&bill).unwrap();
+        let posted = service.post_autopay_bills("2024-04-15").unwrap();
+        assert_eq!(posted.len(), 2);
+        assert_eq!(service.get_bills().unwrap()[0].next_due, "2024-04-30");
+        assert_eq!(
+            service.get_accounts().unwrap()[0].current_balance_cents,
+            100000 - 50000 - 2000
+        );
+        assert!(service.post_autopay_bills("2024-04-15").unwrap().is_empty());
+
+        let pending: i64 = db
+            .conn
+            .query_row(
+                "SELECT count(*) FROM scheduled_notifications WHERE entity_id = ?1 AND is_fired = 0",
+                [&rent],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(pending, 1);
+
+        service.delete_bill(&rent).unwrap();
+        assert!(service.get_bills().unwrap().is_empty());
+        service.restore_bill(&rent).unwrap();
+        assert_eq!(service.get_bills().unwrap().len(), 1);
+    }
+}
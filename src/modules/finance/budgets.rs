@@ -0,0 +1,499 @@
+//! Budgets: spending limits per calendar period, optionally narrowed to a
+//! category or account, with rollover and threshold alerts.
+
+use super::categories::subtree_sql;
+use super::rates::RateTable;
+use super::splits::SPENDING_LINES;
+use super::{invalid_input, parse_date, FinanceService};
+use crate::db::expect_changed;
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::notifications;
+use crate::modules::settings;
+use chrono::{Datelike, Months, NaiveDate, Utc};
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const PERIOD_TYPES: [&str; 4] = ["monthly", "quarterly", "annual", "custom"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Budget {
+    pub id: String,
+    pub name: String,
+    pub category_id: Option<String>,
+    pub account_id: Option<String>,
+    pub amount_cents: i64,
+    pub period_type: String, // monthly|quarterly|annual|custom
+    /// First day the budget applies. For custom budgets, paired with `period_end`.
+    pub period_start: Option<String>,
+    pub period_end: Option<String>,
+    pub rollover: bool,
+    pub alert_threshold_pct: i64,
+}
+
+/// Spending against a budget for the period containing a given date.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BudgetStatus {
+    pub budget_id: String,
+    pub name: String,
+    pub period_start: String,
+    pub period_end: String,
+    /// Unspent amount carried in from earlier periods; already part of `allotted_cents`.
+    pub rollover_cents: i64,
+    pub allotted_cents: i64,
+    pub spent_cents: i64,
+    pub remaining_cents: i64,
+    pub percent_used: i64,
+    pub threshold_crossed: bool,
+}
+
+impl<'a> FinanceService<'a> {
+    pub fn get_budgets(&self) -> Result<Vec<Budget>> {
+        require(self.db, "finance", Role::Viewer)?;
+        load_budgets(&self.db.conn)
+    }
+
+    /// Creates a budget with rollover off and the default 80% alert
+    /// threshold. Recurring budgets start at `period_start` (today if not
+    /// given); custom budgets need both `period_start` and `period_end`.
+    pub fn create_budget(
+        &self,
+        name: &str,
+        category_id: Option<&str>,
+        amount_cents: i64,
+        period_type: &str,
+        period_start: Option<&str>,
+        period_end: Option<&str>,
+    ) -> Result<String> {
+        require(self.db, "finance", Role::Editor)?;
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+        let today = Utc::now().date_naive().to_string();
+        let period_start = period_start.unwrap_or(&today);
+        validate_period(period_type, Some(period_start), period_end)?;
+
+        self.db.conn.execute(
+            "INSERT INTO budgets (id, name, category_id, amount_cents, period_type, period_start, period_end, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            (
+                &id,
+                name,
+                category_id,
+                amount_cents,
+                period_type,
+                period_start,
+                period_end,
+                &now,
+                &now,
+            ),
+        )?;
+        Ok(id)
+    }
+
+    pub fn update_budget(&self, budget: &Budget) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+        validate_period(
+            &budget.period_type,
+            budget.period_start.as_deref(),
+            budget.period_end.as_deref(),
+        )?;
+
+        let changed = self.db.conn.execute(
+            "UPDATE budgets SET name = ?1, category_id = ?2, account_id = ?3, amount_cents = ?4, period_type = ?5,
+             period_start = ?6, period_end = ?7, rollover = ?8, alert_threshold_pct = ?9, updated_at = ?10
+             WHERE id = ?11 AND deleted_at IS NULL",
+            (
+                &budget.name,
+                &budget.category_id,
+                &budget.account_id,
+                budget.amount_cents,
+                &budget.period_type,
+                &budget.period_start,
+                &budget.period_end,
+                budget.rollover,
+                budget.alert_threshold_pct,
+                &now,
+                &budget.id,
+            ),
+        )?;
+        expect_changed(changed)
+    }
+
+    pub fn delete_budget(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        self.db.soft_delete("budgets", id)
+    }
+
+    pub fn restore_budget(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        self.db.restore_deleted("budgets", id)
+    }
+
+    /// Spent vs. allotted for every budget whose period covers `on`
+    /// (`YYYY-MM-DD`). Custom budgets outside their date range are left out.
+    pub fn get_budget_statuses(&self, on: &str) -> Result<Vec<BudgetStatus>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let on = parse_date(on)?;
+        let mut statuses = Vec::new();
+        for budget in load_budgets(&self.db.conn)? {
+            if let Some(status) = budget_status(&self.db.conn, &budget, on)? {
+                statuses.push(status);
+            }
+        }
+        Ok(statuses)
+    }
+
+    /// Records a `budget_threshold` notification for every budget that has
+    /// crossed its alert threshold in the current period and has not been
+    /// alerted yet. Returns the budgets that were newly alerted.
+    pub fn check_budget_alerts(&self) -> Result<Vec<BudgetStatus>> {
+        raise_budget_alerts(&self.db.conn)
+    }
+}
+
+/// Shared with transaction creation and import, which call it inside their
+/// own SQL transaction so the alert lands together with the spending.
+pub(super) fn raise_budget_alerts(conn: &Connection) -> Result<Vec<BudgetStatus>> {
+    let now = Utc::now();
+    let today = now.date_naive();
+    let mut alerted = Vec::new();
+
+    for budget in load_budgets(conn)? {
+        let Some(status) = budget_status(conn, &budget, today)? else {
+            continue;
+        };
+        if !status.threshold_crossed {
+            continue;
+        }
+
+        let already_alerted = conn
+            .query_row(
+                "SELECT 1 FROM scheduled_notifications
+                 WHERE module_id = 'finance' AND entity_id = ?1 AND notification_type = 'budget_threshold'
+                   AND substr(scheduled_for, 1, 10) BETWEEN ?2 AND ?3",
+                (&budget.id, &status.period_start, &status.period_end),
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if already_alerted {
+            continue;
+        }
+
+        notifications::schedule(
+            conn,
+            "finance",
+            &budget.id,
+            "budget_threshold",
+            &format!("{} budget at {}%", budget.name, status.percent_used),
+            &format!(
+                "Spent {:.2} of {:.2} between {} and {}.",
+                status.spent_cents as f64 / 100.0,
+                status.allotted_cents as f64 / 100.0,
+                status.period_start,
+                status.period_end
+            ),
+            now,
+        )?;
+        alerted.push(status);
+    }
+    Ok(alerted)
+}
+
+fn load_budgets(conn: &Connection) -> Result<Vec<Budget>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, name, category_id, account_id, amount_cents, period_type, period_start, period_end, rollover, alert_threshold_pct
+         FROM budgets WHERE deleted_at IS NULL ORDER BY name",
+    )?;
+
+    let budgets = stmt
+        .query_map([], |row| {
+            Ok(Budget {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                category_id: row.get(2)?,
+                account_id: row.get(3)?,
+                amount_cents: row.get(4)?,
+                period_type: row.get(5)?,
+                period_start: row.get(6)?,
+                period_end: row.get(7)?,
+                rollover: row.get(8)?,
+                alert_threshold_pct: row.get(9)?,
+            })
+        })?
+        .filter_map(Result::ok)
+        .collect();
+
+    Ok(budgets)
+}
+
+fn budget_status(
+    conn: &Connection,
+    budget: &Budget,
+    on: NaiveDate,
+) -> Result<Option<BudgetStatus>> {
+    let start = budget.period_start.as_deref().map(parse_date).transpose()?;
+
+    let (period_start, period_end) = if budget.period_type == "custom" {
+        let (Some(start), Some(end)) = (start, budget.period_end.as_deref()) else {
+            return Ok(None);
+        };
+        let end = parse_date(end)?;
+        if on < start || on > end {
+            return Ok(None);
+        }
+        (start, end)
+    } else {
+        match period_bounds(&budget.period_type, on) {
+            Some(bounds) => bounds,
+            None => return Ok(None),
+        }
+    };
+
+    // Budgets are set in the household currency
+    let currency_code = settings::currency_code(conn)?;
+    let rates = RateTable::load(conn)?;
+    // A budget starting mid-period only counts spending from its first day
+    let spent_cents = |(from, to): (NaiveDate, NaiveDate)| {
+        let from = start.map_or(from, |start| from.max(start));
+        spent_cents(conn, budget, (from, to), &rates, &currency_code)
+    };
+
+    // Walk every earlier period since the budget started, carrying forward
+    // whatever was left. Overspending is not carried as a debt.
+    let mut rollover_cents = 0;
+    if budget.rollover && budget.period_type != "custom" {
+        if let Some(mut period) = start.and_then(|s| period_bounds(&budget.period_type, s)) {
+            while period.0 < period_start {
+                let spent = spent_cents(period)?;
+                rollover_cents = (budget.amount_cents + rollover_cents - spent).max(0);
+                period = match period.1.succ_opt() {
+                    Some(next) => period_bounds(&budget.period_type, next).unwrap_or(period),
+                    None => break,
+                };
+            }
+        }
+    }
+
+    let allotted_cents = budget.amount_cents + rollover_cents;
+    let spent_cents = spent_cents((period_start, period_end))?;
+    let percent_used = if allotted_cents > 0 {
+        spent_cents * 100 / allotted_cents
+    } else if spent_cents > 0 {
+        100
+    } else {
+        0
+    };
+
+    Ok(Some(BudgetStatus {
+        budget_id: budget.id.clone(),
+        name: budget.name.clone(),
+        period_start: period_start.to_string(),
+        period_end: period_end.to_string(),
+        rollover_cents,
+        allotted_cents,
+        spent_cents,
+        remaining_cents: allotted_cents - spent_cents,
+        percent_used,
+        threshold_crossed: percent_used >= budget.alert_threshold_pct,
+    }))
+}
+
+/// Total expenses (as a positive number) counted against a budget in a
+/// period, including the subcategories of its category, converted into
+/// `currency_code`. Lines in a currency without a known rate are left out.
+fn spent_cents(
+    conn: &Connection,
+    budget: &Budget,
+    period: (NaiveDate, NaiveDate),
+    rates: &RateTable,
+    currency_code: &str,
+) -> Result<i64> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT substr(date, 1, 10), currency_code, SUM(-amount_cents) FROM ({})
+         WHERE amount_cents < 0
+           AND substr(date, 1, 10) BETWEEN ?1 AND ?2
+           AND (?3 IS NULL OR category_id IN ({}))
+           AND (?4 IS NULL OR account_id = ?4)
+         GROUP BY substr(date, 1, 10), currency_code",
+        SPENDING_LINES,
+        subtree_sql("?3")
+    ))?;
+    let rows = stmt.query_map(
+        (
+            period.0.to_string(),
+            period.1.to_string(),
+            &budget.category_id,
+            &budget.account_id,
+        ),
+        |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        },
+    )?;
+
+    let mut total = 0;
+    for row in rows {
+        let (date, currency, cents) = row?;
+        total += rates
+            .convert(cents, &currency, currency_code, &date)
+            .unwrap_or(0);
+    }
+    Ok(total)
+}
+
+/// The calendar month, quarter or year containing `on`, inclusive.
+fn period_bounds(period_type: &str, on: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+    let (start, months) = match period_type {
+        "monthly" => (NaiveDate::from_ymd_opt(on.year(), on.month(), 1)?, 1),
+        "quarterly" => (
+            NaiveDate::from_ymd_opt(on.year(), on.month0() / 3 * 3 + 1, 1)?,
+            3,
+        ),
+        "annual" => (NaiveDate::from_ymd_opt(on.year(), 1, 1)?, 12),
+        _ => return None,
+    };
+    let end = start.checked_add_months(Months::new(months))?.pred_opt()?;
+    Some((start, end))
+}
+
+fn validate_period(
+    period_type: &str,
+    period_start: Option<&str>,
+    period_end: Option<&str>,
+) -> Result<()> {
+    if !PERIOD_TYPES.contains(&period_type) {
+        return Err(invalid_input(format!(
+            "unknown budget period '{}'",
+            period_type
+        )));
+    }
+    let start = period_start.map(parse_date).transpose()?;
+    let end = period_end.map(parse_date).transpose()?;
+    if period_type == "custom" {
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end => {}
+            _ => {
+                return Err(invalid_input(
+                    "custom budgets need a period_start on or before period_end".to_string(),
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+
+    #[test]
+    fn test_budget_periods_rollover_and_alerts() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = FinanceService::new(&db);
+        let account = service.create_account("Checking", "checking", 0).unwrap();
+        let food = service
+            .create_category("Food", "expense", "#ff0000")
+            .unwrap();
+
+        let id = service
+            .create_budget(
+                "Food",
+                Some(&food),
+                10000,
+                "monthly",
+                Some("2024-01-15"),
+                None,
+            )
+            .unwrap();
+        let mut budget = service.get_budgets().unwrap().remove(0);
+        budget.rollover = true;
+        service.update_budget(&budget).unwrap();
+
+        // January leaves 40.00 unspent; uncategorised spending and spending
+        // before the budget started do not count
+        service
+            .create_transaction(&account, -5000, "Brunch", "2024-01-05", Some(&food))
+            .unwrap();
+        service
+            .create_transaction(&account, -6000, "Lunch", "2024-01-20", Some(&food))
+            .unwrap();
+        service
+            .create_transaction(&account, -9999, "Fuel", "2024-01-21", None)
+            .unwrap();
+        service
+            .create_transaction(
+                &account,
+                -3000,
+                "Dinner",
+                "2024-02-02T19:00:00+00:00",
+                Some(&food),
+            )
+            .unwrap();
+
+        let jan = service.get_budget_statuses("2024-01-25").unwrap().remove(0);
+        assert_eq!(jan.spent_cents, 6000);
+        let feb = service.get_budget_statuses("2024-02-10").unwrap().remove(0);
+        assert_eq!(feb.period_start, "2024-02-01");
+        assert_eq!(feb.period_end, "2024-02-29");
+        assert_eq!(feb.rollover_cents, 4000);
+        assert_eq!(feb.allotted_cents, 14000);
+        assert_eq!(feb.spent_cents, 3000);
+        assert!(!feb.threshold_crossed);
+
+        // Spending in another currency counts at that day's rate
+        let card = service
+            .create_account_in_currency("Travel Card", "credit", 0, "USD")
+            .unwrap();
+        service
+            .set_exchange_rate("USD", "INR", "2024-02-01", 80.0)
+            .unwrap();
+        service
+            .create_transaction(&card, -25, "Snack", "2024-02-05", Some(&food))
+            .unwrap();
+        let feb = service.get_budget_statuses("2024-02-10").unwrap().remove(0);
+        assert_eq!(feb.spent_cents, 3000 + 2000);
+
+        let quarter = period_bounds("quarterly", NaiveDate::from_ymd_opt(2024, 8, 9).unwrap());
+        assert_eq!(
+            quarter.map(|(s, e)| (s.to_string(), e.to_string())),
+            Some(("2024-07-01".to_string(), "2024-09-30".to_string()))
+        );
+        assert!(service
+            .create_budget("Trip", None, 100, "custom", Some("2024-05-01"), None)
+            .is_err());
+
+        // Spending today past the threshold raises exactly one alert
+        let today = Utc::now().date_naive().to_string();
+        service
+            .create_budget("Everything", None, 1000, "monthly", None, None)
+            .unwrap();
+        service
+            .create_transaction(&account, -850, "Groceries", &today, None)
+            .unwrap();
+        service
+            .create_transaction(&account, -50, "Milk", &today, None)
+            .unwrap();
+        assert!(service.check_budget_alerts().unwrap().is_empty());
+        let alerts: i64 = db
+            .conn
+            .query_row(
+                "SELECT count(*) FROM scheduled_notifications WHERE notification_type = 'budget_threshold'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(alerts, 1);
+
+        service.delete_budget(&id).unwrap();
+        assert_eq!(service.get_budgets().unwrap().len(), 1);
+        service.restore_budget(&id).unwrap();
+        assert_eq!(service.get_budgets().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,725 @@
+//! Categories form a tree through `parent_id`, e.g. "Food > Dining Out".
+//! Budgets and reports on a category include everything filed under its
+//! subcategories.
+
+use super::rates::RateTable;
+use super::splits::SPENDING_LINES;
+use super::{invalid_input, parse_date, Category, FinanceService};
+use crate::db::expect_changed;
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::settings::SettingsService;
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Separates the levels of a category path.
+pub const PATH_SEPARATOR: &str = " > ";
+
+/// Colors handed out to categories created without one.
+const PALETTE: [&str; 8] = [
+    "#E67E22", "#3498DB", "#2ECC71", "#9B59B6", "#E91E63", "#F1C40F", "#1ABC9C", "#E74C3C",
+];
+
+struct DefaultCategory {
+    key: &'static str,
+    name: &'static str,
+    category_type: &'static str,
+    color: &'static str,
+    icon: &'static str,
+    /// (key, name) of each subcategory
+    children: &'static [(&'static str, &'static str)],
+}
+
+/// The tree a new household starts with. Ids are derived from the keys, so
+/// devices that seed independently end up with the same rows.
+const DEFAULT_CATEGORIES: &[DefaultCategory] = &[
+    DefaultCategory {
+        key: "food",
+        name: "Food",
+        category_type: "expense",
+        color: "#E67E22",
+        icon: "🍽️",
+        children: &[
+            ("groceries", "Groceries"),
+            ("dining-out", "Dining Out"),
+            ("coffee", "Coffee & Snacks"),
+        ],
+    },
+    DefaultCategory {
+        key: "housing",
+        name: "Housing",
+        category_type: "expense",
+        color: "#8E44AD",
+        icon: "🏠",
+        children: &[
+            ("rent", "Rent & Mortgage"),
+            ("utilities", "Utilities"),
+            ("maintenance", "Repairs & Maintenance"),
+        ],
+    },
+    DefaultCategory {
+        key: "transport",
+        name: "Transport",
+        category_type: "expense",
+        color: "#3498DB",
+        icon: "🚗",
+        children: &[
+            ("fuel", "Fuel"),
+            ("public-transport", "Public Transport"),
+            ("taxi", "Taxi & Rideshare"),
+        ],
+    },
+    DefaultCategory {
+        key: "shopping",
+        name: "Shopping",
+        category_type: "expense",
+        color: "#E91E63",
+        icon: "🛍️",
+        children: &[
+            ("clothing", "Clothing"),
+            ("electronics", "Electronics"),
+            ("household", "Household Supplies"),
+        ],
+    },
+    DefaultCategory {
+        key: "health",
+        name: "Health",
+        category_type: "expense",
+        color: "#2ECC71",
+        icon: "⚕️",
+        children: &[
+            ("medical", "Doctor & Hospital"),
+            ("pharmacy", "Pharmacy"),
+            ("fitness", "Fitness"),
+        ],
+    },
+    DefaultCategory {
+        key: "entertainment",
+        name: "Entertainment",
+        category_type: "expense",
+        color: "#F1C40F",
+        icon: "🎬",
+        children: &[
+            ("events", "Movies & Events"),
+            ("subscriptions", "Subscriptions"),
+            ("hobbies", "Hobbies"),
+        ],
+    },
+    DefaultCategory {
+        key: "travel",
+        name: "Travel",
+        category_type: "expense",
+        color: "#1ABC9C",
+        icon: "✈️",
+        children: &[],
+    },
+    DefaultCategory {
+        key: "education",
+        name: "Education",
+        category_type: "expense",
+        color: "#34495E",
+        icon: "🎓",
+        children: &[],
+    },
+    DefaultCategory {
+        key: "gifts",
+        name: "Gifts & Donations",
+        category_type: "expense",
+        color: "#C0392B",
+        icon: "🎁",
+        children: &[],
+    },
+    DefaultCategory {
+        key: "salary",
+        name: "Salary",
+        category_type: "income",
+        color: "#27AE60",
+        icon: "💼",
+        children: &[],
+    },
+    DefaultCategory {
+        key: "interest",
+        name: "Interest & Dividends",
+        category_type: "income",
+        color: "#16A085",
+        icon: "📈",
+        children: &[],
+    },
+    DefaultCategory {
+        key: "refunds",
+        name: "Refunds",
+        category_type: "income",
+        color: "#2980B9",
+        icon: "↩️",
+        children: &[],
+    },
+    DefaultCategory {
+        key: "other-income",
+        name: "Other Income",
+        category_type: "income",
+        color: "#7F8C8D",
+        icon: "💰",
+        children: &[],
+    },
+];
+
+/// A category's own and rolled-up amounts for a period, in the household
+/// currency. Amounts are signed like transactions: spending is negative.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CategoryTotal {
+    pub category_id: String,
+    pub parent_id: Option<String>,
+    /// Names from the root down, joined with `PATH_SEPARATOR`.
+    pub path: String,
+    pub depth: usize,
+    /// Filed directly under this category.
+    pub own_cents: i64,
+    /// This category and all its subcategories.
+    pub total_cents: i64,
+}
+
+/// SQL selecting the ids of the category bound to `param` and everything
+/// below it, for use in `category_id IN (...)`.
+pub fn subtree_sql(param: &str) -> String {
+    format!(
+        "WITH RECURSIVE subtree(id) AS (
+             SELECT {}
+             UNION SELECT c.id FROM categories c JOIN subtree s ON c.parent_id = s.id
+         )
+         SELECT id FROM subtree",
+        param
+    )
+}
+
+impl<'a> FinanceService<'a> {
+    /// Live categories, parents before their children and siblings in
+    /// `sort_order`.
+    pub fn get_categories(&self) -> Result<Vec<Category>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let categories = live_categories(&self.db.conn)?;
+        Ok(walk_tree(&categories)
+            .into_iter()
+            .map(|(index, _, _)| categories[index].clone())
+            .collect())
+    }
+
+    /// Creates a top-level category.
+    pub fn create_category(&self, name: &str, category_type: &str, color: &str) -> Result<String> {
+        require(self.db, "finance", Role::Editor)?;
+        insert_category(&self.db.conn, None, name, category_type, color)
+    }
+
+    /// Creates a category under `parent_id`, with the parent's type and color.
+    pub fn create_subcategory(&self, parent_id: &str, name: &str) -> Result<String> {
+        require(self.db, "finance", Role::Editor)?;
+        let parent = live_category(&self.db.conn, parent_id)?;
+        insert_category(
+            &self.db.conn,
+            Some(parent_id),
+            name,
+            &parent.category_type,
+            &parent.color,
+        )
+    }
+
+    /// The category at `path` (e.g. "Food > Dining Out", or just a name),
+    /// matched without regard to case; missing levels are created.
+    pub fn find_or_create_category(&self, path: &str, category_type: &str) -> Result<String> {
+        require(self.db, "finance", Role::Editor)?;
+        let names: Vec<&str> = path
+            .split(PATH_SEPARATOR.trim())
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .collect();
+        if names.is_empty() {
+            return Err(invalid_input("category name is empty".to_string()));
+        }
+
+        let tx = self.db.conn.unchecked_transaction()?;
+        let categories = live_categories(&tx)?;
+        // A lone name may be a subcategory anywhere in the tree
+        if let [name] = names[..] {
+            if let Some(found) = categories
+                .iter()
+                .find(|c| c.name.eq_ignore_ascii_case(name) && c.category_type == category_type)
+            {
+                return Ok(found.id.clone());
+            }
+        }
+
+        let mut parent: Option<Category> = None;
+        for name in names {
+            let parent_id = parent.as_ref().map(|p| p.id.as_str());
+            let existing = categories.iter().find(|c| {
+                c.parent_id.as_deref() == parent_id
+                    && c.name.eq_ignore_ascii_case(name)
+                    && c.category_type == category_type
+            });
+            parent = Some(match existing {
+                Some(category) => category.clone(),
+                None => {
+                    let color = match &parent {
+                        Some(p) => p.color.clone(),
+                        None => PALETTE[categories.len() % PALETTE.len()].to_string(),
+                    };
+                    let id = insert_category(&tx, parent_id, name, category_type, &color)?;
+                    live_category(&tx, &id)?
+                }
+            });
+        }
+        tx.commit()?;
+        Ok(parent.map(|c| c.id).unwrap_or_default())
+    }
+
+    /// Saves edits to a category, including moving it under another parent.
+    /// A category always has its parent's type; changing the type of a
+    /// parent changes its subcategories too.
+    pub fn update_category(&self, category: &Category) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        if let Some(parent_id) = &category.parent_id {
+            let parent = live_category(&tx, parent_id)?;
+            if in_subtree(&tx, &category.id, parent_id)? {
+                return Err(invalid_input(format!(
+                    "'{}' cannot be moved under its own subcategory '{}'",
+                    category.name, parent.name
+                )));
+            }
+            if parent.category_type != category.category_type {
+                return Err(invalid_input(format!(
+                    "'{}' is an {} category but '{}' is {}",
+                    category.name, category.category_type, parent.name, parent.category_type
+                )));
+            }
+        }
+
+        let changed = tx.execute(
+            "UPDATE categories SET name = ?1, type = ?2, color = ?3, parent_id = ?4, icon = ?5, sort_order = ?6, updated_at = ?7
+             WHERE id = ?8 AND deleted_at IS NULL",
+            (
+                &category.name,
+                &category.category_type,
+                &category.color,
+                &category.parent_id,
+                &category.icon,
+                category.sort_order,
+                &now,
+                &category.id,
+            ),
+        )?;
+        expect_changed(changed)?;
+        tx.execute(
+            &format!(
+                "UPDATE categories SET type = ?1, updated_at = ?2
+                 WHERE id IN ({}) AND type != ?1",
+                subtree_sql("?3")
+            ),
+            (&category.category_type, &now, &category.id),
+        )?;
+
+        tx.commit()
+    }
+
+    /// Moves a category to the trash. Its subcategories have to be moved
+    /// or merged away first.
+    pub fn delete_category(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let children: i64 = self.db.conn.query_row(
+            "SELECT COUNT(*) FROM categories WHERE parent_id = ?1 AND deleted_at IS NULL",
+            [id],
+            |row| row.get(0),
+        )?;
+        if children > 0 {
+            return Err(invalid_input(format!(
+                "category {} still has {} subcategories",
+                id, children
+            )));
+        }
+        self.db.soft_delete("categories", id)
+    }
+
+    /// Brings a category back, at the top level if its parent is gone.
+    pub fn restore_category(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+        self.db.restore_deleted("categories", id)?;
+        tx.execute(
+            "UPDATE categories SET parent_id = NULL
+             WHERE id = ?1 AND parent_id IN (SELECT id FROM categories WHERE deleted_at IS NOT NULL)",
+            [id],
+        )?;
+        tx.commit()
+    }
+
+    /// Folds `source_id` into `target_id`: its transactions, split lines,
+    /// budgets, bills, rules and subcategories move over and `source_id` goes to
+    /// the trash. Returns how many live transactions were moved.
+    pub fn merge_category(&self, source_id: &str, target_id: &str) -> Result<usize> {
+        require(self.db, "finance", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let source = live_category(&tx, source_id)?;
+        let target = live_category(&tx, target_id)?;
+        if in_subtree(&tx, source_id, target_id)? {
+            return Err(invalid_input(format!(
+                "'{}' cannot be merged into itself or its own subcategory '{}'",
+                source.name, target.name
+            )));
+        }
+        if source.category_type != target.category_type {
+            return Err(invalid_input(format!(
+                "cannot merge {} category '{}' into {} category '{}'",
+                source.category_type, source.name, target.category_type, target.name
+            )));
+        }
+
+        let moved = tx.query_row(
+            "SELECT COUNT(*) FROM transactions WHERE category_id = ?1 AND deleted_at IS NULL",
+            [source_id],
+            |row| row.get::<_, i64>(0),
+        )? as usize;
+        // Trashed rows move too, so restoring one lands in the merged category
+        for table in [
+            "transactions",
+            "transaction_splits",
+            "budgets",
+            "bills",
+            "categorization_rules",
+        ] {
+            tx.execute(
+                &format!(
+                    "UPDATE {} SET category_id = ?1, updated_at = ?2 WHERE category_id = ?3",
+                    table
+                ),
+                (target_id, &now, source_id),
+            )?;
+        }
+        tx.execute(
+            "UPDATE categories SET parent_id = ?1, updated_at = ?2 WHERE parent_id = ?3",
+            (target_id, &now, source_id),
+        )?;
+        tx.execute(
+            "UPDATE categories SET deleted_at = ?1, updated_at = ?1 WHERE id = ?2",
+            (&now, source_id),
+        )?;
+
+        tx.commit()?;
+        Ok(moved)
+    }
+
+    /// Adds the default category tree to a household that has no
+    /// categories yet, live or trashed. Returns how many were created.
+    pub fn seed_default_categories(&self) -> Result<usize> {
+        require(self.db, "finance", Role::Editor)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+        let existing: i64 =
+            tx.query_row("SELECT COUNT(*) FROM categories", [], |row| row.get(0))?;
+        if existing > 0 {
+            return Ok(0);
+        }
+
+        let now = Utc::now().to_rfc3339();
+        let mut created = 0;
+        let mut insert = |id: &str,
+                          parent_id: Option<&str>,
+                          name: &str,
+                          default: &DefaultCategory,
+                          sort_order: usize| {
+            created += tx.execute(
+                "INSERT OR IGNORE INTO categories (id, parent_id, name, icon, color, type, is_system, sort_order, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, 1, ?7, ?8, ?8)",
+                (
+                    id,
+                    parent_id,
+                    name,
+                    parent_id.is_none().then_some(default.icon),
+                    default.color,
+                    default.category_type,
+                    sort_order as i64,
+                    &now,
+                ),
+            )?;
+            Ok::<_, rusqlite::Error>(())
+        };
+        for (order, default) in DEFAULT_CATEGORIES.iter().enumerate() {
+            let id = format!("default-{}", default.key);
+            insert(&id, None, default.name, default, order)?;
+            for (child_order, (key, name)) in default.children.iter().enumerate() {
+                let child_id = format!("{}-{}", id, key);
+                insert(&child_id, Some(&id), name, default, child_order)?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(created)
+    }
+
+    /// Income and spending per category between two dates (inclusive),
+    /// with each category's total including its subcategories. Categories
+    /// come in tree order; amounts in currencies with no known rate are
+    /// left out.
+    pub fn get_category_totals(&self, from: &str, to: &str) -> Result<Vec<CategoryTotal>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let (from, to) = (parse_date(from)?, parse_date(to)?);
+        let currency_code = SettingsService::new(self.db).get_settings()?.currency_code;
+        let rates = RateTable::load(&self.db.conn)?;
+
+        let mut stmt = self.db.conn.prepare(&format!(
+            "SELECT category_id, substr(date, 1, 10), currency_code, SUM(amount_cents)
+             FROM ({})
+             WHERE category_id IS NOT NULL AND substr(date, 1, 10) BETWEEN ?1 AND ?2
+             GROUP BY category_id, substr(date, 1, 10), currency_code",
+            SPENDING_LINES
+        ))?;
+        let rows = stmt.query_map((from.to_string(), to.to_string()), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })?;
+        let mut own: HashMap<String, i64> = HashMap::new();
+        for row in rows {
+            let (category_id, date, currency, cents) = row?;
+            if let Some(cents) = rates.convert(cents, &currency, &currency_code, &date) {
+                *own.entry(category_id).or_default() += cents;
+            }
+        }
+
+        let categories = live_categories(&self.db.conn)?;
+        let order = walk_tree(&categories);
+        let mut totals: Vec<CategoryTotal> = order
+            .iter()
+            .map(|(index, depth, path)| {
+                let category = &categories[*index];
+                let own_cents = own.get(&category.id).copied().unwrap_or(0);
+                CategoryTotal {
+                    category_id: category.id.clone(),
+                    parent_id: category.parent_id.clone(),
+                    path: path.clone(),
+                    depth: *depth,
+                    own_cents,
+                    total_cents: own_cents,
+                }
+            })
+            .collect();
+        // Children follow their parent in tree order, so walking backwards
+        // finishes every subtree before its parent is added to
+        for i in (0..totals.len()).rev() {
+            let depth = totals[i].depth;
+            let subtree: i64 = totals[i + 1..]
+                .iter()
+                .take_while(|t| t.depth > depth)
+                .filter(|t| t.depth == depth + 1)
+                .map(|t| t.total_cents)
+                .sum();
+            totals[i].total_cents += subtree;
+        }
+        Ok(totals)
+    }
+}
+
+fn insert_category(
+    conn: &Connection,
+    parent_id: Option<&str>,
+    name: &str,
+    category_type: &str,
+    color: &str,
+) -> Result<String> {
+    let id = Uuid::new_v4().to_string();
+    let now = Utc::now().to_rfc3339();
+
+    conn.execute(
+        "INSERT INTO categories (id, parent_id, name, type, color, sort_order, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5,
+                 (SELECT COALESCE(MAX(sort_order) + 1, 0) FROM categories WHERE parent_id IS ?2 AND deleted_at IS NULL),
+                 ?6, ?6)",
+        (&id, parent_id, name, category_type, color, &now),
+    )?;
+    Ok(id)
+}
+
+fn read_category(row: &rusqlite::Row) -> Result<Category> {
+    Ok(Category {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        category_type: row.get(2)?,
+        color: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+        parent_id: row.get(4)?,
+        icon: row.get(5)?,
+        is_system: row.get(6)?,
+        sort_order: row.get(7)?,
+    })
+}
+
+const CATEGORY_COLUMNS: &str = "id, name, type, color, parent_id, icon, is_system, sort_order";
+
+fn live_categories(conn: &Connection) -> Result<Vec<Category>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM categories WHERE deleted_at IS NULL ORDER BY sort_order, name",
+        CATEGORY_COLUMNS
+    ))?;
+    let categories = stmt.query_map([], read_category)?.collect();
+    categories
+}
+
+fn live_category(conn: &Connection, id: &str) -> Result<Category> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM categories WHERE id = ?1 AND deleted_at IS NULL",
+            CATEGORY_COLUMNS
+        ),
+        [id],
+        read_category,
+    )
+    .optional()?
+    .ok_or(rusqlite::Error::QueryReturnedNoRows)
+}
+
+/// Whether `id` is `root_id` or one of its descendants.
+fn in_subtree(conn: &Connection, root_id: &str, id: &str) -> Result<bool> {
+    conn.query_row(
+        &format!("SELECT ?2 IN ({})", subtree_sql("?1")),
+        (root_id, id),
+        |row| row.get(0),
+    )
+}
+
+/// Pre-order walk of `categories` (already sorted by `sort_order`) as
+/// (index, depth, path). Categories whose parent is not live count as
+/// top-level.
+fn walk_tree(categories: &[Category]) -> Vec<(usize, usize, String)> {
+    let ids: Vec<&str> = categories.iter().map(|c| c.id.as_str()).collect();
+    let mut children: HashMap<Option<&str>, Vec<usize>> = HashMap::new();
+    for (index, category) in categories.iter().enumerate() {
+        let parent = category
+            .parent_id
+            .as_deref()
+            .filter(|p| ids.contains(p) && *p != category.id);
+        children.entry(parent).or_default().push(index);
+    }
+
+    let mut order = Vec::with_capacity(categories.len());
+    let mut stack: Vec<(usize, usize, String)> = children
+        .get(&None)
+        .into_iter()
+        .flatten()
+        .rev()
+        .map(|&index| (index, 0, categories[index].name.clone()))
+        .collect();
+    while let Some((index, depth, path)) = stack.pop() {
+        if let Some(kids) = children.get(&Some(categories[index].id.as_str())) {
+            for &kid in kids.iter().rev() {
+                let kid_path = format!("{}{}{}", path, PATH_SEPARATOR, categories[kid].name);
+                stack.push((kid, depth + 1, kid_path));
+            }
+        }
+        order.push((index, depth, path));
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+
+    #[test]
+    fn test_category_tree_rolls_up_and_merges() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = FinanceService::new(&db);
+        assert!(service.seed_default_categories().unwrap() > 20);
+        assert_eq!(service.seed_default_categories().unwrap(), 0);
+
+        let categories = service.get_categories().unwrap();
+        assert_eq!(categories[0].name, "Food");
+        assert_eq!(categories[1].parent_id.as_deref(), Some("default-food"));
+        assert!(categories.iter().all(|c| c.is_system));
+
+        let account = service.create_account("Card", "credit", 0).unwrap();
+        let dining = service
+            .find_or_create_category("dining out", "expense")
+            .unwrap();
+        assert_eq!(dining, "default-food-dining-out");
+        let street_food = service
+            .find_or_create_category("Food > Dining Out > Street Food", "expense")
+            .unwrap();
+        let street = service
+            .get_categories()
+            .unwrap()
+            .into_iter()
+            .find(|c| c.id == street_food)
+            .unwrap();
+        assert_eq!(street.color, "#E67E22");
+        assert!(!street.is_system);
+
+        for (amount, category) in [
+            (-1_000, "default-food-groceries"),
+            (-500, dining.as_str()),
+            (-200, street_food.as_str()),
+        ] {
+            service
+                .create_transaction(&account, amount, "Shop", "2024-03-05", Some(category))
+                .unwrap();
+        }
+        let totals = service
+            .get_category_totals("2024-03-01", "2024-03-31")
+            .unwrap();
+        let total = |path: &str| {
+            let t = totals.iter().find(|t| t.path == path).unwrap();
+            (t.own_cents, t.total_cents)
+        };
+        assert_eq!(total("Food"), (0, -1_700));
+        assert_eq!(total("Food > Dining Out"), (-500, -700));
+        assert_eq!(total("Food > Dining Out > Street Food"), (-200, -200));
+        assert_eq!(total("Housing"), (0, 0));
+
+        // Budgets on a parent count its subcategories
+        service
+            .create_budget(
+                "Food",
+                Some("default-food"),
+                10_000,
+                "monthly",
+                Some("2024-03-01"),
+                None,
+            )
+            .unwrap();
+        let status = service.get_budget_statuses("2024-03-10").unwrap().remove(0);
+        assert_eq!(status.spent_cents, 1_700);
+
+        // No cycles, no deleting a category with children
+        let mut food = service.get_categories().unwrap().remove(0);
+        food.parent_id = Some(street_food.clone());
+        assert!(service.update_category(&food).is_err());
+        assert!(service.delete_category(&dining).is_err());
+        assert!(service.merge_category(&dining, &street_food).is_err());
+
+        // Merging moves transactions and subcategories
+        assert_eq!(
+            service
+                .merge_category(&dining, "default-food-coffee")
+                .unwrap(),
+            1
+        );
+        let totals = service
+            .get_category_totals("2024-03-01", "2024-03-31")
+            .unwrap();
+        assert!(totals.iter().all(|t| t.category_id != dining));
+        let coffee = totals
+            .iter()
+            .find(|t| t.category_id == "default-food-coffee")
+            .unwrap();
+        assert_eq!((coffee.own_cents, coffee.total_cents), (-500, -700));
+        assert!(totals
+            .iter()
+            .any(|t| t.path == "Food > Coffee & Snacks > Street Food"));
+    }
+}
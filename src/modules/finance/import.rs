@@ -0,0 +1,652 @@
+//! Bank statement import from CSV, OFX and QIF files.
+//!
+//! Every imported row gets a stable `import_hash`, so importing the same
+//! statement twice (or two overlapping statements) only adds new rows.
+
+use super::budgets::raise_budget_alerts;
+use super::rules::{tags_json, RuleSet, RuleSubject};
+use super::{adjust_balance, invalid_input, FinanceService};
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::registry::ModuleRegistry;
+use chrono::{NaiveDate, Utc};
+use rusqlite::{OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Key under the finance module's `settings_json` holding saved CSV profiles.
+const PROFILES_KEY: &str = "import_profiles";
+
+/// Describes how one bank lays out its CSV export. Columns are zero-based.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CsvProfile {
+    pub name: String,
+    /// Account rows are imported into when the caller does not name one.
+    pub account_id: Option<String>,
+    pub delimiter: char,
+    /// Lines before the first transaction (header row, bank banners).
+    pub skip_rows: usize,
+    pub date_column: usize,
+    /// chrono format string, e.g. `%d/%m/%Y`.
+    pub date_format: String,
+    pub description_column: usize,
+    pub amount: AmountColumns,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AmountColumns {
+    /// One signed column. `negate` is for banks that print spending as positive.
+    Signed { column: usize, negate: bool },
+    /// Separate withdrawal and deposit columns, both written as positive numbers.
+    DebitCredit { debit: usize, credit: usize },
+}
+
+impl CsvProfile {
+    /// `date,description,amount` with ISO dates and a header row.
+    pub fn generic() -> Self {
+        Self {
+            name: "generic".to_string(),
+            account_id: None,
+            delimiter: ',',
+            skip_rows: 1,
+            date_column: 0,
+            date_format: "%Y-%m-%d".to_string(),
+            description_column: 1,
+            amount: AmountColumns::Signed {
+                column: 2,
+                negate: false,
+            },
+        }
+    }
+
+    /// HDFC Bank statement export: Date, Narration, Ref No, Value Date,
+    /// Withdrawal, Deposit, Closing Balance.
+    pub fn hdfc() -> Self {
+        Self {
+            name: "hdfc".to_string(),
+            account_id: None,
+            delimiter: ',',
+            skip_rows: 1,
+            date_column: 0,
+            date_format: "%d/%m/%y".to_string(),
+            description_column: 1,
+            amount: AmountColumns::DebitCredit {
+                debit: 4,
+                credit: 5,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportFailure {
+    /// Line number for CSV, record number for OFX and QIF (both 1-based).
+    pub row: usize,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped_duplicates: usize,
+    pub failed: Vec<ImportFailure>,
+}
+
+/// A statement line after parsing, before it is matched against the database.
+#[derive(Debug, Clone, PartialEq)]
+struct ParsedRow {
+    row: usize,
+    date: NaiveDate,
+    amount_cents: i64,
+    description: String,
+    memo: Option<String>,
+    category: Option<String>,
+    /// Bank-assigned transaction id (OFX FITID), preferred for dedup.
+    external_id: Option<String>,
+}
+
+type ParseResult = std::result::Result<ParsedRow, ImportFailure>;
+
+impl<'a> FinanceService<'a> {
+    /// Built-in CSV profiles followed by the ones saved by the user. A saved
+    /// profile with a built-in's name replaces it.
+    pub fn get_import_profiles(&self) -> Result<Vec<CsvProfile>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let settings = ModuleRegistry::new(self.db).get_settings("finance")?;
+        let saved: Vec<CsvProfile> = settings
+            .get(PROFILES_KEY)
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default();
+
+        let mut profiles: Vec<CsvProfile> = [CsvProfile::generic(), CsvProfile::hdfc()]
+            .into_iter()
+            .filter(|p| !saved.iter().any(|s| s.name == p.name))
+            .collect();
+        profiles.extend(saved);
+        Ok(profiles)
+    }
+
+    /// Saves a CSV profile, replacing any saved profile with the same name.
+    pub fn save_import_profile(&self, profile: &CsvProfile) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        csv_delimiter(profile)?;
+        let registry = ModuleRegistry::new(self.db);
+        let mut settings = registry.get_settings("finance")?;
+        let mut saved: Vec<CsvProfile> = settings
+            .get(PROFILES_KEY)
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+            .unwrap_or_default();
+
+        saved.retain(|p| p.name != profile.name);
+        saved.push(profile.clone());
+        settings[PROFILES_KEY] = serde_json::to_value(saved).unwrap_or_default();
+        registry.save_settings("finance", &settings)
+    }
+
+    /// Imports a CSV statement. `account_id` overrides the profile's account.
+    pub fn import_csv(
+        &self,
+        account_id: Option<&str>,
+        content: &str,
+        profile: &CsvProfile,
+    ) -> Result<ImportSummary> {
+        require(self.db, "finance", Role::Editor)?;
+        let account_id = account_id
+            .or(profile.account_id.as_deref())
+            .ok_or_else(|| invalid_input("no account given for the import".to_string()))?;
+        let delimiter = csv_delimiter(profile)?;
+        self.import_rows(account_id, parse_csv(content, delimiter, profile))
+    }
+
+    pub fn import_ofx(&self, account_id: &str, content: &str) -> Result<ImportSummary> {
+        require(self.db, "finance", Role::Editor)?;
+        self.import_rows(account_id, parse_ofx(content))
+    }
+
+    /// Imports a QIF file. QIF has no standard date order, so the caller
+    /// passes one (e.g. `%m/%d/%Y`); two-digit years are also accepted.
+    pub fn import_qif(
+        &self,
+        account_id: &str,
+        content: &str,
+        date_format: &str,
+    ) -> Result<ImportSummary> {
+        require(self.db, "finance", Role::Editor)?;
+        self.import_rows(account_id, parse_qif(content, date_format))
+    }
+
+    /// Inserts every parsed row that is not already in the database, in one
+    /// SQL transaction, and moves the account balance by the imported total.
+    fn import_rows(&self, account_id: &str, rows: Vec<ParseResult>) -> Result<ImportSummary> {
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let currency_code: String = tx.query_row(
+            "SELECT currency_code FROM accounts WHERE id = ?1 AND deleted_at IS NULL",
+            [account_id],
+            |row| row.get(0),
+        )?;
+
+        let rules = RuleSet::load(&tx)?;
+        let mut summary = ImportSummary::default();
+        let mut occurrences: HashMap<(NaiveDate, i64, String), usize> = HashMap::new();
+        let mut total_cents = 0;
+
+        for parsed in rows {
+            let row = match parsed {
+                Ok(row) => row,
+                Err(failure) => {
+                    summary.failed.push(failure);
+                    continue;
+                }
+            };
+
+            // Identical lines on the same day are real (two coffees), so the
+            // n-th copy within a file hashes differently from the first.
+            let description = normalize(&row.description);
+            let occurrence = occurrences
+                .entry((row.date, row.amount_cents, description.clone()))
+                .or_insert(0);
+            *occurrence += 1;
+            let hash = import_hash(account_id, &row, &description, *occurrence);
+
+            // Deleted rows count too, or a trashed transaction would come back
+            let exists = tx
+                .query_row(
+                    "SELECT 1 FROM transactions WHERE import_hash = ?1",
+                    [&hash],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+            if exists {
+                summary.skipped_duplicates += 1;
+                continue;
+            }
+
+            let category_id: Option<String> = match &row.category {
+                Some(name) => tx
+                    .query_row(
+                        "SELECT id FROM categories WHERE name = ?1 COLLATE NOCASE AND deleted_at IS NULL",
+                        [name],
+                        |r| r.get(0),
+                    )
+                    .optional()?,
+                None => None,
+            };
+            let ruled = rules.apply(&RuleSubject {
+                account_id,
+                amount_cents: row.amount_cents,
+                merchant: row.description.trim(),
+            });
+
+            tx.execute(
+                "INSERT INTO transactions (id, account_id, amount_cents, currency_code, date, merchant, category_id, payee, tags, notes, is_pending, import_hash, source, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, 0, ?11, 'import', ?12, ?12)",
+                rusqlite::params![
+                    Uuid::new_v4().to_string(),
+                    account_id,
+                    row.amount_cents,
+                    &currency_code,
+                    row.date.and_hms_opt(0, 0, 0).unwrap().and_utc().to_rfc3339(),
+                    row.description.trim(),
+                    category_id.or(ruled.category_id),
+                    &ruled.payee,
+                    tags_json(&ruled.tags),
+                    &row.memo,
+                    &hash,
+                    &now,
+                ],
+            )?;
+            total_cents += row.amount_cents;
+            summary.imported += 1;
+        }
+
+        adjust_balance(&tx, account_id, total_cents, &now)?;
+        if summary.imported > 0 {
+            raise_budget_alerts(&tx)?;
+        }
+        tx.commit()?;
+        Ok(summary)
+    }
+}
+
+fn import_hash(account_id: &str, row: &ParsedRow, description: &str, occurrence: usize) -> String {
+    let key = match &row.external_id {
+        Some(id) => format!("{}|id|{}", account_id, id),
+        None => format!(
+            "{}|{}|{}|{}|{}",
+            account_id, row.date, row.amount_cents, description, occurrence
+        ),
+    };
+    let digest = Sha256::digest(key.as_bytes());
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Lowercases and collapses whitespace so cosmetic changes between two
+/// exports of the same statement do not defeat dedup.
+fn normalize(description: &str) -> String {
+    description
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Parses an amount as printed on a statement into cents. Accepts thousands
+/// separators, currency symbols, a leading minus or accounting parentheses.
+fn parse_amount_cents(raw: &str) -> Option<i64> {
+    let raw = raw.trim();
+    let negative = raw.starts_with('-') || (raw.starts_with('(') && raw.ends_with(')'));
+    let digits: String = raw
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    if digits.is_empty() {
+        return None;
+    }
+
+    let (whole, fraction) = match digits.split_once('.') {
+        Some((w, f)) => (w, f),
+        None => (digits.as_str(), ""),
+    };
+    if fraction.len() > 2 || fraction.contains('.') {
+        return None;
+    }
+    let whole: i64 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().ok()?
+    };
+    let fraction: i64 = format!("{:0<2}", fraction).parse().ok()?;
+
+    let cents = whole.checked_mul(100)?.checked_add(fraction)?;
+    Some(if negative { -cents } else { cents })
+}
+
+/// The profile's delimiter as the single byte the CSV reader splits on.
+fn csv_delimiter(profile: &CsvProfile) -> Result<u8> {
+    u8::try_from(profile.delimiter)
+        .ok()
+        .filter(u8::is_ascii)
+        .ok_or_else(|| {
+            invalid_input(format!(
+                "the CSV delimiter must be an ASCII character, got '{}'",
+                profile.delimiter
+            ))
+        })
+}
+
+fn parse_csv(content: &str, delimiter: u8, profile: &CsvProfile) -> Vec<ParseResult> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(content.as_bytes());
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                let row = e.position().map(|p| p.line() as usize).unwrap_or(0);
+                rows.push(Err(ImportFailure {
+                    row,
+                    reason: e.to_string(),
+                }));
+                continue;
+            }
+        };
+        let line = record.position().map(|p| p.line() as usize).unwrap_or(0);
+        if line <= profile.skip_rows || record.iter().all(|f| f.trim().is_empty()) {
+            continue;
+        }
+        rows.push(parse_csv_record(&record, line, profile));
+    }
+    rows
+}
+
+fn parse_csv_record(record: &csv::StringRecord, line: usize, profile: &CsvProfile) -> ParseResult {
+    let fail = |reason: String| ImportFailure { row: line, reason };
+    let field = |index: usize| {
+        record
+            .get(index)
+            .map(str::trim)
+            .ok_or_else(|| fail(format!("missing column {}", index)))
+    };
+
+    let raw_date = field(profile.date_column)?;
+    let date = NaiveDate::parse_from_str(raw_date, &profile.date_format)
+        .map_err(|_| fail(format!("unrecognised date '{}'", raw_date)))?;
+    let description = field(profile.description_column)?.to_string();
+
+    let amount_cents = match profile.amount {
+        AmountColumns::Signed { column, negate } => {
+            let raw = field(column)?;
+            let cents = parse_amount_cents(raw)
+                .ok_or_else(|| fail(format!("unrecognised amount '{}'", raw)))?;
+            if negate {
+                -cents
+            } else {
+                cents
+            }
+        }
+        AmountColumns::DebitCredit { debit, credit } => {
+            let debit = field(debit)?;
+            let credit = field(credit)?;
+            match (parse_amount_cents(debit), parse_amount_cents(credit)) {
+                (Some(d), _) if d != 0 => -d.abs(),
+                (_, Some(c)) => c.abs(),
+                (Some(d), None) => -d.abs(),
+                (None, None) => {
+                    return Err(fail(format!(
+                        "no amount in debit '{}' or credit '{}'",
+                        debit, credit
+                    )))
+                }
+            }
+        }
+    };
+
+    Ok(ParsedRow {
+        row: line,
+        date,
+        amount_cents,
+        description,
+        memo: None,
+        category: None,
+        external_id: None,
+    })
+}
+
+/// Reads `<STMTTRN>` blocks from OFX 1.x (SGML, unclosed tags) or 2.x (XML).
+fn parse_ofx(content: &str) -> Vec<ParseResult> {
+    let mut rows = Vec::new();
+
+    for (index, block) in content.split("<STMTTRN>").skip(1).enumerate() {
+        let block = block.split("</STMTTRN>").next().unwrap_or(block);
+        let row = index + 1;
+        let fail = |reason: String| ImportFailure { row, reason };
+
+        let parsed = (|| -> ParseResult {
+            let raw_date =
+                ofx_value(block, "DTPOSTED").ok_or_else(|| fail("missing DTPOSTED".into()))?;
+            let date = raw_date
+                .get(..8)
+                .and_then(|d| NaiveDate::parse_from_str(d, "%Y%m%d").ok())
+                .ok_or_else(|| fail(format!("unrecognised date '{}'", raw_date)))?;
+            let raw_amount =
+                ofx_value(block, "TRNAMT").ok_or_else(|| fail("missing TRNAMT".into()))?;
+            let amount_cents = parse_amount_cents(&raw_amount)
+                .ok_or_else(|| fail(format!("unrecognised amount '{}'", raw_amount)))?;
+            let name = ofx_value(block, "NAME");
+            let memo = ofx_value(block, "MEMO");
+
+            Ok(ParsedRow {
+                row,
+                date,
+                amount_cents,
+                description: name.clone().or_else(|| memo.clone()).unwrap_or_default(),
+                memo: if name.is_some() { memo } else { None },
+                category: None,
+                external_id: ofx_value(block, "FITID"),
+            })
+        })();
+        rows.push(parsed);
+    }
+    rows
+}
+
+/// Value of an OFX element: the text after `<TAG>` up to the next tag or
+/// line break, with the XML entities OFX uses decoded.
+fn ofx_value(block: &str, tag: &str) -> Option<String> {
+    let start = block.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let rest = &block[start..];
+    let end = rest.find(['<', '\n', '\r']).unwrap_or(rest.len());
+    let value = rest[..end]
+        .trim()
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Reads QIF bank records (`D`, `T`/`U`, `P`, `M`, `L`, terminated by `^`).
+fn parse_qif(content: &str, date_format: &str) -> Vec<ParseResult> {
+    let mut rows = Vec::new();
+    let mut record: HashMap<char, String> = HashMap::new();
+    let mut row = 0;
+
+    // Files saved by Windows tools often start with a byte order mark
+    for line in content.trim_start_matches('\u{FEFF}').lines() {
+        let line = line.trim_end();
+        let Some(code) = line.chars().next() else {
+            continue;
+        };
+        if code == '!' {
+            continue;
+        }
+        if code != '^' {
+            // `T` and `U` carry the same amount; keep whichever came first
+            record
+                .entry(code)
+                .or_insert_with(|| line[code.len_utf8()..].trim().to_string());
+            continue;
+        }
+
+        row += 1;
+        if !record.is_empty() {
+            rows.push(parse_qif_record(&record, row, date_format));
+        }
+        record.clear();
+    }
+    rows
+}
+
+fn parse_qif_record(record: &HashMap<char, String>, row: usize, date_format: &str) -> ParseResult {
+    let fail = |reason: String| ImportFailure { row, reason };
+
+    let raw_date = record
+        .get(&'D')
+        .ok_or_else(|| fail("missing date".into()))?;
+    // Quicken writes years after 1999 as `1/ 5'24`
+    let cleaned = raw_date.replace('\'', "/").replace(' ', "");
+    let two_digit_year = date_format.ends_with("%Y")
+        && cleaned
+            .rsplit(['/', '-', '.'])
+            .next()
+            .is_some_and(|year| year.len() == 2);
+    let format = if two_digit_year {
+        date_format.replace("%Y", "%y")
+    } else {
+        date_format.to_string()
+    };
+    let date = NaiveDate::parse_from_str(&cleaned, &format)
+        .map_err(|_| fail(format!("unrecognised date '{}'", raw_date)))?;
+
+    let raw_amount = record
+        .get(&'T')
+        .or_else(|| record.get(&'U'))
+        .ok_or_else(|| fail("missing amount".into()))?;
+    let amount_cents = parse_amount_cents(raw_amount)
+        .ok_or_else(|| fail(format!("unrecognised amount '{}'", raw_amount)))?;
+
+    let payee = record.get(&'P').cloned();
+    let memo = record.get(&'M').cloned();
+    // `[Account]` categories are transfers, not categories
+    let category = record
+        .get(&'L')
+        .filter(|l| !l.starts_with('['))
+        .map(|l| l.rsplit(':').next().unwrap_or(l).to_string());
+
+    Ok(ParsedRow {
+        row,
+        date,
+        amount_cents,
+        description: payee.clone().or_else(|| memo.clone()).unwrap_or_default(),
+        memo: if payee.is_some() { memo } else { None },
+        category,
+        external_id: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+
+    #[test]
+    fn test_import_formats_and_skip_duplicates() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = FinanceService::new(&db);
+        let account = service.create_account("HDFC", "checking", 100000).unwrap();
+        service
+            .create_category("Groceries", "expense", "#00aa00")
+            .unwrap();
+
+        let csv = "Date,Narration,Ref,Value Date,Withdrawal,Deposit,Balance\n\
+                   01/01/24,UPI-SWIGGY,1,01/01/24,\"1,250.50\",,98749.50\n\
+                   01/01/24,UPI-SWIGGY,2,01/01/24,\"1,250.50\",,97499.00\n\
+                   02/01/24,SALARY,3,02/01/24,,50000,147499.00\n\
+                   bad-date,NOTHING,4,,10,,0\n";
+        let summary = service
+            .import_csv(Some(&account), csv, &CsvProfile::hdfc())
+            .unwrap();
+        assert_eq!(summary.imported, 3);
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].row, 5);
+
+        // Re-importing the same statement adds nothing
+        let again = service
+            .import_csv(Some(&account), csv, &CsvProfile::hdfc())
+            .unwrap();
+        assert_eq!(again.imported, 0);
+        assert_eq!(again.skipped_duplicates, 3);
+        assert_eq!(
+            service.get_accounts().unwrap()[0].current_balance_cents,
+            100000 - 125050 * 2 + 5000000
+        );
+
+        let ofx = "<OFX><BANKTRANLIST>\n<STMTTRN>\n<TRNTYPE>DEBIT\n<DTPOSTED>20240105120000[0:GMT]\n\
+                   <TRNAMT>-45.00\n<FITID>A1\n<NAME>Book &amp; Co\n</STMTTRN>\n\
+                   <STMTTRN><DTPOSTED>20240106<TRNAMT>-10<FITID>A2<NAME>Tea</STMTTRN>\n</BANKTRANLIST></OFX>";
+        assert_eq!(service.import_ofx(&account, ofx).unwrap().imported, 2);
+        assert_eq!(
+            service
+                .import_ofx(&account, ofx)
+                .unwrap()
+                .skipped_duplicates,
+            2
+        );
+
+        let qif = "!Type:Bank\nD01/07'24\nT-300.00\nPBig Bazaar\nLGroceries\n^\nD01/08'24\nPNo amount\n^\n";
+        let summary = service.import_qif(&account, qif, "%m/%d/%Y").unwrap();
+        assert_eq!(summary.imported, 1);
+        assert_eq!(summary.failed.len(), 1);
+
+        let transactions = service.get_transactions(10).unwrap();
+        assert_eq!(transactions.len(), 6);
+        let bazaar = transactions
+            .iter()
+            .find(|t| t.merchant == "Big Bazaar")
+            .unwrap();
+        assert_eq!(bazaar.amount_cents, -30000);
+        assert_eq!(bazaar.category_name.as_deref(), Some("Groceries"));
+        assert!(transactions.iter().any(|t| t.merchant == "Book & Co"));
+
+        // Saved profiles round-trip through the module settings
+        let mut profile = CsvProfile::generic();
+        profile.name = "icici".to_string();
+        profile.account_id = Some(account.clone());
+        service.save_import_profile(&profile).unwrap();
+        let profiles = service.get_import_profiles().unwrap();
+        assert_eq!(profiles.len(), 3);
+        assert!(profiles.contains(&profile));
+
+        // A delimiter the reader cannot split on is refused, not truncated
+        profile.delimiter = '¦';
+        assert!(service.save_import_profile(&profile).is_err());
+        assert!(service
+            .import_csv(Some(&account), "01/01/24¦Tea¦10", &profile)
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_qif_with_byte_order_mark() {
+        let qif = "\u{FEFF}!Type:Bank\nD01/07'24\nT-300.00\nPBig Bazaar\n^\n€stray\n^\n";
+        let rows = parse_qif(qif, "%m/%d/%Y");
+        assert_eq!(rows.len(), 2);
+        let bazaar = rows[0].as_ref().unwrap();
+        assert_eq!(bazaar.amount_cents, -30000);
+        assert_eq!(bazaar.description, "Big Bazaar");
+        // A record of unknown codes fails instead of panicking
+        assert_eq!(rows[1].as_ref().unwrap_err().reason, "missing date");
+    }
+}
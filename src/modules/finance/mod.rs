@@ -0,0 +1,760 @@
+use crate::db::{expect_changed, Db};
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::settings::SettingsService;
+use crate::modules::trash::{self, TrashKind};
+use chrono::{NaiveDate, Utc};
+use rusqlite::{OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub mod bills;
+pub mod budgets;
+pub mod categories;
+pub mod import;
+pub mod rates;
+pub mod receipts;
+pub mod rules;
+pub mod search;
+pub mod splits;
+pub mod tags;
+pub mod transfers;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub id: String,
+    pub name: String,
+    pub account_type: String, // checking, savings, credit, etc.
+    pub currency_code: String,
+    pub current_balance_cents: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Category {
+    pub id: String,
+    pub name: String,
+    pub category_type: String, // income or expense
+    pub color: String,
+    pub parent_id: Option<String>,
+    pub icon: Option<String>,
+    /// Part of the default tree rather than created by the household.
+    pub is_system: bool,
+    /// Position among its siblings.
+    pub sort_order: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    pub id: String,
+    pub account_id: String,
+    pub amount_cents: i64,
+    pub currency_code: String,
+    pub date: String,
+    pub merchant: String,
+    pub category_id: Option<String>,
+    pub category_name: Option<String>,
+    /// Set on both sides of a transfer between two accounts.
+    pub transfer_id: Option<String>,
+    /// Divided between categories; see `get_splits`.
+    pub is_split: bool,
+    /// Who was actually paid, when the merchant line does not say.
+    pub payee: Option<String>,
+    pub notes: Option<String>,
+    pub tags: Vec<String>,
+    pub receipt_path: Option<String>,
+}
+
+/// The optional parts of a new transaction.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransactionDetails {
+    pub payee: Option<String>,
+    pub notes: Option<String>,
+    pub tags: Vec<String>,
+    pub receipt_path: Option<String>,
+}
+
+pub struct FinanceService<'a> {
+    db: &'a Db,
+}
+
+impl<'a> FinanceService<'a> {
+    pub fn new(db: &'a Db) -> Self {
+        Self { db }
+    }
+
+    pub fn get_accounts(&self) -> Result<Vec<Account>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let mut stmt = self.db.conn.prepare(
+            "SELECT id, name, account_type, currency_code, current_balance_cents FROM accounts WHERE deleted_at IS NULL"
+        )?;
+
+        let accounts = stmt
+            .query_map([], |row| {
+                Ok(Account {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    account_type: row.get(2)?,
+                    currency_code: row.get(3)?,
+                    current_balance_cents: row.get(4)?,
+                })
+            })?
+            .filter_map(Result::ok)
+            .collect();
+
+        Ok(accounts)
+    }
+
+    /// Creates an account in the household currency.
+    pub fn create_account(
+        &self,
+        name: &str,
+        account_type: &str,
+        starting_balance_cents: i64,
+    ) -> Result<String> {
+        let currency_code = SettingsService::new(self.db).get_settings()?.currency_code;
+        self.create_account_in_currency(name, account_type, starting_balance_cents, &currency_code)
+    }
+
+    /// Creates an account kept in another currency, e.g. a travel card. Its
+    /// balance and transactions stay in that currency; totals convert it
+    /// with the stored exchange rates.
+    pub fn create_account_in_currency(
+        &self,
+        name: &str,
+        account_type: &str,
+        starting_balance_cents: i64,
+        currency_code: &str,
+    ) -> Result<String> {
+        require(self.db, "finance", Role::Editor)?;
+        let currency_code = rates::currency_code(currency_code).map_err(invalid_input)?;
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+
+        self.db.conn.execute(
+            "INSERT INTO accounts (id, name, account_type, currency_code, current_balance_cents, opening_balance_cents, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?5, ?6, ?7)",
+            (&id, name, account_type, &currency_code, starting_balance_cents, &now, &now),
+        )?;
+        Ok(id)
+    }
+
+    /// Saves edits to an account. The balance is written as given, which is
+    /// how a mistyped starting balance gets corrected; the opening balance
+    /// moves with it. The currency can only change while the account has no
+    /// transactions, transfers included, since their amounts are in the old
+    /// one.
+    pub fn update_account(&self, account: &Account) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let currency_code = rates::currency_code(&account.currency_code).map_err(invalid_input)?;
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let (old_currency_code, has_transactions): (String, bool) = tx.query_row(
+            "SELECT currency_code, EXISTS (SELECT 1 FROM transactions WHERE account_id = ?1)
+             FROM accounts WHERE id = ?1 AND deleted_at IS NULL",
+            [&account.id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        if old_currency_code != currency_code && has_transactions {
+            return Err(invalid_input(format!(
+                "account {} has transactions in {}, so its currency cannot change",
+                account.name, old_currency_code
+            )));
+        }
+
+        let changed = tx.execute(
+            &format!(
+                "UPDATE accounts SET name = ?1, account_type = ?2, currency_code = ?3, current_balance_cents = ?4,
+                     opening_balance_cents = ?4 - {}, updated_at = ?5
+                 WHERE id = ?6 AND deleted_at IS NULL",
+                TRANSACTION_TOTAL
+            ),
+            (
+                &account.name,
+                &account.account_type,
+                &currency_code,
+                account.current_balance_cents,
+                &now,
+                &account.id,
+            ),
+        )?;
+        expect_changed(changed)?;
+
+        tx.commit()
+    }
+
+    /// Soft-deletes an account and its transactions. The transactions keep
+    /// their effect on the (now hidden) balance so a restore is lossless.
+    /// The other side of each of its transfers is deleted too, and taken
+    /// out of that account's balance, so no transfer is left half live.
+    pub fn delete_account(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let changed = tx.execute(
+            "UPDATE accounts SET deleted_at = ?1, updated_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+            (&now, id),
+        )?;
+        expect_changed(changed)?;
+        let partners = partner_legs(&tx, id, None)?;
+        tx.execute(
+            "UPDATE transactions SET deleted_at = ?1, updated_at = ?1 WHERE account_id = ?2 AND deleted_at IS NULL",
+            (&now, id),
+        )?;
+        for (leg_id, account_id, _) in &partners {
+            tx.execute(
+                "UPDATE transactions SET deleted_at = ?1, updated_at = ?1 WHERE id = ?2",
+                (&now, leg_id),
+            )?;
+            recompute_balance(&tx, account_id)?;
+        }
+
+        tx.commit()
+    }
+
+    /// Brings back an account with the transactions and transfers deleted
+    /// along with it. A transfer whose other account has been deleted since
+    /// stays deleted and comes back with that account instead.
+    pub fn restore_account(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let deleted_at: String = tx.query_row(
+            "SELECT deleted_at FROM accounts WHERE id = ?1 AND deleted_at IS NOT NULL",
+            [id],
+            |row| row.get(0),
+        )?;
+        for (leg_id, account_id, account_deleted_at) in partner_legs(&tx, id, Some(&deleted_at))? {
+            match account_deleted_at {
+                None => {
+                    tx.execute(
+                        "UPDATE transactions SET deleted_at = NULL, updated_at = ?1 WHERE id = ?2",
+                        (&now, &leg_id),
+                    )?;
+                }
+                Some(later) => {
+                    tx.execute(
+                        "UPDATE transactions SET deleted_at = ?1, updated_at = ?2
+                         WHERE transfer_id = (SELECT transfer_id FROM transactions WHERE id = ?3)",
+                        (&later, &now, &leg_id),
+                    )?;
+                }
+            }
+            recompute_balance(&tx, &account_id)?;
+        }
+
+        // Only transactions removed by the same delete_account call share its timestamp
+        tx.execute(
+            "UPDATE transactions SET deleted_at = NULL, updated_at = ?1
+             WHERE account_id = ?2 AND deleted_at = (SELECT deleted_at FROM accounts WHERE id = ?2)",
+            (&now, id),
+        )?;
+        tx.execute(
+            "UPDATE accounts SET deleted_at = NULL, updated_at = ?1 WHERE id = ?2",
+            (&now, id),
+        )?;
+        recompute_balance(&tx, id)?;
+
+        tx.commit()
+    }
+
+    /// Today's net worth in the household currency; see `get_net_worth`.
+    /// Fails when an account's currency has no rate into it, rather than
+    /// leaving the account out.
+    pub fn get_total_balance(&self) -> Result<f64> {
+        let today = Utc::now().date_naive().to_string();
+        let worth = self.get_net_worth(&today)?;
+        if !worth.missing_rates.is_empty() {
+            return Err(invalid_input(format!(
+                "no exchange rate from {} into {}",
+                worth.missing_rates.join(", "),
+                worth.currency_code
+            )));
+        }
+        Ok(worth.total_cents as f64 / 100.0)
+    }
+
+    pub fn get_transactions(&self, limit: usize) -> Result<Vec<Transaction>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let mut stmt = self.db.conn.prepare(&format!(
+            "SELECT {} FROM transactions t
+             LEFT JOIN categories c ON t.category_id = c.id
+             WHERE t.deleted_at IS NULL
+             ORDER BY t.date DESC
+             LIMIT ?1",
+            TRANSACTION_COLUMNS
+        ))?;
+
+        let transactions = stmt
+            .query_map([limit], read_transaction)?
+            .filter_map(Result::ok)
+            .collect();
+
+        Ok(transactions)
+    }
+
+    pub fn create_transaction(
+        &self,
+        account_id: &str,
+        amount_cents: i64,
+        merchant: &str,
+        date: &str,
+        category_id: Option<&str>,
+    ) -> Result<String> {
+        self.create_transaction_with_details(
+            account_id,
+            amount_cents,
+            merchant,
+            date,
+            category_id,
+            &TransactionDetails::default(),
+        )
+    }
+
+    /// Like `create_transaction`, also setting the payee, notes, tags and
+    /// receipt. Tags from matching rules are added to the given ones and a
+    /// given payee wins over a rule's.
+    pub fn create_transaction_with_details(
+        &self,
+        account_id: &str,
+        amount_cents: i64,
+        merchant: &str,
+        date: &str,
+        category_id: Option<&str>,
+        details: &TransactionDetails,
+    ) -> Result<String> {
+        require(self.db, "finance", Role::Editor)?;
+        let id = Uuid::new_v4().to_string();
+        let tx = self.db.conn.unchecked_transaction()?;
+        insert_transaction(
+            &tx,
+            &id,
+            account_id,
+            amount_cents,
+            merchant,
+            date,
+            category_id,
+            details,
+        )?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Saves edits to a transaction and moves its balance effect from the old
+    /// amount/account to the new one. The currency always follows the
+    /// account. Transfers are edited through `update_transfer`.
+    pub fn update_transaction(&self, transaction: &Transaction) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        if let Some(transfer_id) = self.transfer_of(&transaction.id)? {
+            return Err(invalid_input(format!(
+                "transaction {} is part of transfer {}; edit the transfer instead",
+                transaction.id, transfer_id
+            )));
+        }
+        self.ensure_splits_match(&transaction.id, transaction.amount_cents)?;
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let (old_account_id, old_amount_cents) = self.live_transaction(&tx, &transaction.id)?;
+        tx.query_row(
+            "SELECT 1 FROM accounts WHERE id = ?1 AND deleted_at IS NULL",
+            [&transaction.account_id],
+            |_| Ok(()),
+        )?;
+
+        tx.execute(
+            "UPDATE transactions SET account_id = ?1, amount_cents = ?2, date = ?3, merchant = ?4, category_id = ?5, updated_at = ?6,
+                 currency_code = (SELECT currency_code FROM accounts WHERE id = ?1),
+                 payee = ?8, notes = ?9, tags = ?10, receipt_path = ?11
+             WHERE id = ?7",
+            rusqlite::params![
+                &transaction.account_id,
+                transaction.amount_cents,
+                &transaction.date,
+                &transaction.merchant,
+                &transaction.category_id,
+                &now,
+                &transaction.id,
+                &transaction.payee,
+                &transaction.notes,
+                rules::tags_json(&rules::merge_tags(&[], &transaction.tags)),
+                &transaction.receipt_path,
+            ],
+        )?;
+        adjust_balance(&tx, &old_account_id, -old_amount_cents, &now)?;
+        adjust_balance(&tx, &transaction.account_id, transaction.amount_cents, &now)?;
+        if transaction.amount_cents < 0 {
+            budgets::raise_budget_alerts(&tx)?;
+        }
+
+        tx.commit()
+    }
+
+    /// Soft-deletes a transaction and takes its amount back out of the account.
+    /// Either side of a transfer deletes the whole transfer.
+    pub fn delete_transaction(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        if let Some(transfer_id) = self.transfer_of(id)? {
+            return self.delete_transfer(&transfer_id);
+        }
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let (account_id, amount_cents) = self.live_transaction(&tx, id)?;
+        tx.execute(
+            "UPDATE transactions SET deleted_at = ?1, updated_at = ?1 WHERE id = ?2",
+            (&now, id),
+        )?;
+        adjust_balance(&tx, &account_id, -amount_cents, &now)?;
+
+        tx.commit()
+    }
+
+    /// Brings back a soft-deleted transaction and re-applies its amount.
+    /// Transactions of a deleted account come back through `restore_account`.
+    pub fn restore_transaction(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        if let Some(transfer_id) = self.transfer_of(id)? {
+            return self.restore_transfer(&transfer_id);
+        }
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let (account_id, amount_cents): (String, i64) = tx.query_row(
+            "SELECT t.account_id, t.amount_cents FROM transactions t
+             JOIN accounts a ON a.id = t.account_id
+             WHERE t.id = ?1 AND t.deleted_at IS NOT NULL AND a.deleted_at IS NULL",
+            [id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        tx.execute(
+            "UPDATE transactions SET deleted_at = NULL, updated_at = ?1 WHERE id = ?2",
+            (&now, id),
+        )?;
+        adjust_balance(&tx, &account_id, amount_cents, &now)?;
+
+        tx.commit()
+    }
+
+    /// Permanently removes accounts, categories, transactions, budgets, bills
+    /// and rules deleted more than `older_than_days` ago, the same way purging
+    /// them from the trash does, receipts no one uses any more included.
+    /// Balances were already settled at delete time.
+    pub fn purge_deleted(&self, older_than_days: i64) -> Result<usize> {
+        require(self.db, "finance", Role::Editor)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+        let purged = trash::purge_older_than(
+            &tx,
+            &[
+                TrashKind::Transaction,
+                TrashKind::Budget,
+                TrashKind::Bill,
+                TrashKind::Rule,
+                TrashKind::Category,
+                TrashKind::Account,
+            ],
+            older_than_days,
+        )?;
+        tx.commit()?;
+        trash::remove_orphaned_files(self.db);
+        Ok(purged)
+    }
+
+    fn live_transaction(&self, conn: &rusqlite::Connection, id: &str) -> Result<(String, i64)> {
+        conn.query_row(
+            "SELECT account_id, amount_cents FROM transactions WHERE id = ?1 AND deleted_at IS NULL",
+            [id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?
+        .ok_or(rusqlite::Error::QueryReturnedNoRows)
+    }
+}
+
+/// Inserts transaction `id` and applies it to the account balance. Callers
+/// own the surrounding SQL transaction.
+#[allow(clippy::too_many_arguments)]
+fn insert_transaction(
+    conn: &rusqlite::Connection,
+    id: &str,
+    account_id: &str,
+    amount_cents: i64,
+    merchant: &str,
+    date: &str,
+    category_id: Option<&str>,
+    details: &TransactionDetails,
+) -> Result<()> {
+    let now = Utc::now().to_rfc3339();
+    let currency_code: String = conn.query_row(
+        "SELECT currency_code FROM accounts WHERE id = ?1 AND deleted_at IS NULL",
+        [account_id],
+        |row| row.get(0),
+    )?;
+    let ruled = rules::RuleSet::load(conn)?.apply(&rules::RuleSubject {
+        account_id,
+        amount_cents,
+        merchant,
+    });
+
+    // 1. Insert the transaction in the account's currency, with whatever
+    //    the categorization rules fill in
+    conn.execute(
+        "INSERT INTO transactions (id, account_id, amount_cents, currency_code, date, merchant, category_id, payee, notes, tags, receipt_path,
+             is_pending, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, 0, ?12, ?12)",
+        rusqlite::params![
+            id,
+            account_id,
+            amount_cents,
+            &currency_code,
+            date,
+            merchant,
+            category_id.map(str::to_string).or(ruled.category_id),
+            details.payee.clone().or(ruled.payee),
+            &details.notes,
+            rules::tags_json(&rules::merge_tags(&details.tags, &ruled.tags)),
+            &details.receipt_path,
+            &now,
+        ],
+    )?;
+
+    // 2. Adjust the account balance
+    adjust_balance(conn, account_id, amount_cents, &now)?;
+
+    // 3. Record any budget this pushed past its alert threshold
+    if amount_cents < 0 {
+        budgets::raise_budget_alerts(conn)?;
+    }
+
+    Ok(())
+}
+
+fn adjust_balance(
+    conn: &rusqlite::Connection,
+    account_id: &str,
+    delta_cents: i64,
+    now: &str,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE accounts SET current_balance_cents = current_balance_cents + ?1, updated_at = ?2 WHERE id = ?3",
+        (delta_cents, now, account_id),
+    )?;
+    Ok(())
+}
+
+/// Sum of the transactions counting towards an account's balance, for use
+/// in a statement on `accounts`. A deleted account keeps the transactions
+/// that were deleted along with it.
+const TRANSACTION_TOTAL: &str = "COALESCE(
+    (SELECT SUM(t.amount_cents) FROM transactions t
+     WHERE t.account_id = accounts.id
+       AND (t.deleted_at IS NULL OR t.deleted_at = accounts.deleted_at)), 0)";
+
+/// The other side of each transfer of `account_id` whose legs are both
+/// deleted as of `deleted_at` (live for `None`), as (transaction id, account
+/// id, that account's `deleted_at`).
+fn partner_legs(
+    conn: &rusqlite::Connection,
+    account_id: &str,
+    deleted_at: Option<&str>,
+) -> Result<Vec<(String, String, Option<String>)>> {
+    let mut stmt = conn.prepare(
+        "SELECT p.id, p.account_id, a.deleted_at FROM transactions own
+         JOIN transactions p ON p.transfer_id = own.transfer_id AND p.id != own.id
+         JOIN accounts a ON a.id = p.account_id
+         WHERE own.account_id = ?1 AND own.deleted_at IS ?2 AND p.deleted_at IS ?2",
+    )?;
+    let legs = stmt
+        .query_map(rusqlite::params![account_id, deleted_at], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect();
+    legs
+}
+
+/// Sets an account's balance to its opening balance plus its transactions,
+/// e.g. after transactions from another device were merged.
+pub(crate) fn recompute_balance(conn: &rusqlite::Connection, account_id: &str) -> Result<()> {
+    conn.execute(
+        &format!(
+            "UPDATE accounts SET current_balance_cents = opening_balance_cents + {} WHERE id = ?1",
+            TRANSACTION_TOTAL
+        ),
+        [account_id],
+    )?;
+    Ok(())
+}
+
+/// Error for arguments the database would accept but the finance rules do not.
+fn invalid_input(message: String) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_MISUSE),
+        Some(message),
+    )
+}
+
+/// Columns read by `read_transaction`, for a query over `transactions t`
+/// left-joined to `categories c`.
+const TRANSACTION_COLUMNS: &str = "t.id, t.account_id, t.amount_cents, t.currency_code, t.date, t.merchant, t.category_id, c.name, t.transfer_id,
+     EXISTS (SELECT 1 FROM transaction_splits s WHERE s.transaction_id = t.id), t.payee, t.notes, t.tags, t.receipt_path";
+
+fn read_transaction(row: &rusqlite::Row) -> Result<Transaction> {
+    Ok(Transaction {
+        id: row.get(0)?,
+        account_id: row.get(1)?,
+        amount_cents: row.get(2)?,
+        currency_code: row.get(3)?,
+        date: row.get(4)?,
+        merchant: row.get(5)?,
+        category_id: row.get(6)?,
+        category_name: row.get(7).unwrap_or(None),
+        transfer_id: row.get(8)?,
+        is_split: row.get(9)?,
+        payee: row.get(10)?,
+        notes: row.get(11)?,
+        tags: rules::parse_tags(row.get(12)?),
+        receipt_path: row.get(13)?,
+    })
+}
+
+/// Accepts plain dates and the RFC 3339 timestamps stored elsewhere.
+fn parse_date(value: &str) -> Result<NaiveDate> {
+    value
+        .get(..10)
+        .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+        .ok_or_else(|| invalid_input(format!("invalid date '{}'", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finance_service() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+
+        let service = FinanceService::new(&db);
+
+        service
+            .create_account("My Checking", "checking", 50000)
+            .unwrap();
+        service
+            .create_account("My Savings", "savings", 100000)
+            .unwrap();
+
+        let accounts = service.get_accounts().unwrap();
+        assert_eq!(accounts.len(), 2);
+
+        let checking_id = &accounts[0].id;
+
+        service
+            .create_category("Groceries", "expense", "#FF0000")
+            .unwrap();
+        let categories = service.get_categories().unwrap();
+        assert_eq!(categories.len(), 1);
+        let grocery_id = &categories[0].id;
+
+        // Add a transaction of -$100.00
+        service
+            .create_transaction(
+                checking_id,
+                -10000,
+                "Whole Foods",
+                "2023-11-01T00:00:00Z",
+                Some(grocery_id),
+            )
+            .unwrap();
+
+        let txs = service.get_transactions(10).unwrap();
+        assert_eq!(txs.len(), 1);
+        assert_eq!(txs[0].merchant, "Whole Foods");
+        assert_eq!(txs[0].category_name.as_deref(), Some("Groceries"));
+
+        let total = service.get_total_balance().unwrap();
+        // 500 + 1000 - 100 = 1400
+        assert_eq!(total, 1400.0);
+    }
+
+    #[test]
+    fn test_transaction_edits_keep_balances_in_sync() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = FinanceService::new(&db);
+
+        let checking = service
+            .create_account("Checking", "checking", 10000)
+            .unwrap();
+        let savings = service.create_account("Savings", "savings", 0).unwrap();
+        let balance_of = |id: &str| {
+            service
+                .get_accounts()
+                .unwrap()
+                .into_iter()
+                .find(|a| a.id == id)
+                .map(|a| a.current_balance_cents)
+        };
+
+        let tx_id = service
+            .create_transaction(&checking, -2500, "Cafe", "2024-01-01", None)
+            .unwrap();
+        assert_eq!(balance_of(&checking), Some(7500));
+
+        // Typo in the amount and the wrong account
+        let mut transaction = service.get_transactions(1).unwrap().remove(0);
+        transaction.amount_cents = -250;
+        transaction.account_id = savings.clone();
+        service.update_transaction(&transaction).unwrap();
+        assert_eq!(balance_of(&checking), Some(10000));
+        assert_eq!(balance_of(&savings), Some(-250));
+
+        // Deleted accounts cannot take transactions
+        let closed = service.create_account("Closed", "credit", 0).unwrap();
+        service.delete_account(&closed).unwrap();
+        let mut moved = transaction.clone();
+        moved.account_id = closed;
+        assert!(service.update_transaction(&moved).is_err());
+        assert_eq!(balance_of(&savings), Some(-250));
+
+        service.delete_transaction(&tx_id).unwrap();
+        assert_eq!(balance_of(&savings), Some(0));
+        assert!(service.get_transactions(10).unwrap().is_empty());
+        assert!(service.delete_transaction(&tx_id).is_err());
+
+        service.restore_transaction(&tx_id).unwrap();
+        assert_eq!(balance_of(&savings), Some(-250));
+
+        // Deleting the account hides its transactions; restoring brings both back
+        service.delete_account(&savings).unwrap();
+        assert!(service.get_transactions(10).unwrap().is_empty());
+        assert!(service.restore_transaction(&tx_id).is_err());
+        service.restore_account(&savings).unwrap();
+        assert_eq!(service.get_transactions(10).unwrap().len(), 1);
+        assert_eq!(balance_of(&savings), Some(-250));
+
+        let mut account = service
+            .get_accounts()
+            .unwrap()
+            .into_iter()
+            .find(|a| a.id == checking)
+            .unwrap();
+        account.name = "Main Checking".to_string();
+        service.update_account(&account).unwrap();
+
+        let category_id = service
+            .create_category("Food", "expense", "#00FF00")
+            .unwrap();
+        let mut category = service.get_categories().unwrap().remove(0);
+        category.name = "Dining".to_string();
+        service.update_category(&category).unwrap();
+        assert_eq!(service.get_categories().unwrap()[0].name, "Dining");
+
+        service.delete_category(&category_id).unwrap();
+        service.delete_account(&savings).unwrap();
+        // Category + both accounts + the transaction
+        assert_eq!(service.purge_deleted(0).unwrap(), 4);
+        assert!(service.restore_category(&category_id).is_err());
+        assert_eq!(service.get_accounts().unwrap().len(), 1);
+    }
+}
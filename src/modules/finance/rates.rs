@@ -0,0 +1,544 @@
+//! Exchange rates between currencies, and totals converted into the
+//! household currency (`AppSettings::currency_code`).
+//!
+//! A rate is stored once per currency pair and day as "1 base = rate quote".
+//! Conversions use the latest rate on or before the date asked for, falling
+//! back to the inverse of a stored pair or a cross rate through a third
+//! currency, since published tables (e.g. the ECB's) quote everything
+//! against one base.
+
+use super::import::{ImportFailure, ImportSummary};
+use super::{invalid_input, parse_date, FinanceService};
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::settings::SettingsService;
+use chrono::{NaiveDate, Utc};
+use rusqlite::{Connection, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Base currency of the European Central Bank's reference rates.
+const ECB_BASE: &str = "EUR";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExchangeRate {
+    pub id: String,
+    pub base_code: String,
+    pub quote_code: String,
+    pub rate_date: String,
+    /// Units of `quote_code` one unit of `base_code` buys.
+    pub rate: f64,
+    /// `manual`, `csv` or `ecb`.
+    pub source: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetWorth {
+    pub currency_code: String,
+    pub on: String,
+    pub total_cents: i64,
+    /// Currencies of accounts left out of the total because no rate into
+    /// `currency_code` is known.
+    pub missing_rates: Vec<String>,
+}
+
+/// Every stored rate, loaded once for a batch of conversions.
+pub struct RateTable {
+    /// Both directions of each stored pair, sorted by date.
+    pairs: HashMap<(String, String), Vec<(String, f64)>>,
+}
+
+impl RateTable {
+    pub fn load(conn: &Connection) -> Result<Self> {
+        let mut stmt = conn.prepare(
+            "SELECT base_code, quote_code, rate_date, rate FROM exchange_rates WHERE rate > 0",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, f64>(3)?,
+            ))
+        })?;
+
+        let mut pairs: HashMap<(String, String), Vec<(String, f64)>> = HashMap::new();
+        for row in rows {
+            let (base, quote, date, rate) = row?;
+            pairs
+                .entry((quote.clone(), base.clone()))
+                .or_default()
+                .push((date.clone(), 1.0 / rate));
+            pairs.entry((base, quote)).or_default().push((date, rate));
+        }
+        for rates in pairs.values_mut() {
+            rates.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        Ok(Self { pairs })
+    }
+
+    /// Units of `to` one unit of `from` bought on `on` (`YYYY-MM-DD`).
+    pub fn rate(&self, from: &str, to: &str, on: &str) -> Option<f64> {
+        if from == to {
+            return Some(1.0);
+        }
+        if let Some(rate) = self.direct(from, to, on) {
+            return Some(rate);
+        }
+        let mut pivots: Vec<&str> = self
+            .pairs
+            .keys()
+            .filter(|(base, quote)| base == from && quote != to)
+            .map(|(_, quote)| quote.as_str())
+            .collect();
+        pivots.sort_unstable();
+        pivots
+            .into_iter()
+            .find_map(|pivot| Some(self.direct(from, pivot, on)? * self.direct(pivot, to, on)?))
+    }
+
+    pub fn convert(&self, amount_cents: i64, from: &str, to: &str, on: &str) -> Option<i64> {
+        self.rate(from, to, on)
+            .map(|rate| (amount_cents as f64 * rate).round() as i64)
+    }
+
+    /// The latest rate on or before `on`, or the earliest one known when
+    /// every rate is newer.
+    fn direct(&self, from: &str, to: &str, on: &str) -> Option<f64> {
+        let rates = self.pairs.get(&(from.to_string(), to.to_string()))?;
+        let on = on.get(..10).unwrap_or(on);
+        let known = rates.partition_point(|(date, _)| date.as_str() <= on);
+        rates.get(known.saturating_sub(1)).map(|(_, rate)| *rate)
+    }
+}
+
+/// A rate as read from an import file.
+struct ParsedRate {
+    base_code: String,
+    quote_code: String,
+    date: NaiveDate,
+    rate: f64,
+}
+
+type RateResult = std::result::Result<ParsedRate, ImportFailure>;
+
+impl<'a> FinanceService<'a> {
+    /// Stored rates, newest first.
+    pub fn get_exchange_rates(&self) -> Result<Vec<ExchangeRate>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let mut stmt = self.db.conn.prepare(
+            "SELECT id, base_code, quote_code, rate_date, rate, source FROM exchange_rates
+             ORDER BY rate_date DESC, base_code, quote_code",
+        )?;
+
+        let rates = stmt
+            .query_map([], |row| {
+                Ok(ExchangeRate {
+                    id: row.get(0)?,
+                    base_code: row.get(1)?,
+                    quote_code: row.get(2)?,
+                    rate_date: row.get(3)?,
+                    rate: row.get(4)?,
+                    source: row.get(5)?,
+                })
+            })?
+            .filter_map(Result::ok)
+            .collect();
+
+        Ok(rates)
+    }
+
+    /// Records that one `base_code` bought `rate` of `quote_code` on
+    /// `rate_date`, replacing any rate already stored for that pair and day.
+    pub fn set_exchange_rate(
+        &self,
+        base_code: &str,
+        quote_code: &str,
+        rate_date: &str,
+        rate: f64,
+    ) -> Result<String> {
+        require(self.db, "finance", Role::Editor)?;
+        let parsed = ParsedRate {
+            base_code: currency_code(base_code).map_err(invalid_input)?,
+            quote_code: currency_code(quote_code).map_err(invalid_input)?,
+            date: parse_date(rate_date)?,
+            rate: checked_rate(rate).map_err(invalid_input)?,
+        };
+        write_rate(&self.db.conn, &parsed, "manual", &Utc::now().to_rfc3339())?;
+        Ok(rate_id(&parsed))
+    }
+
+    /// Imports an ECB reference-rate XML file (`eurofxref-daily.xml` or the
+    /// history files) or a CSV. The CSV is either `date,base,quote,rate` with
+    /// a header row, or the ECB layout with a `Date` column followed by one
+    /// column per currency quoted against EUR.
+    pub fn import_exchange_rates(&self, content: &str) -> Result<ImportSummary> {
+        require(self.db, "finance", Role::Editor)?;
+        let (source, rows) = if content.trim_start().starts_with('<') {
+            ("ecb", parse_ecb_xml(content))
+        } else {
+            ("csv", parse_rate_csv(content))
+        };
+
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+        let mut summary = ImportSummary::default();
+        for parsed in rows {
+            match parsed {
+                Ok(rate) => {
+                    if write_rate(&tx, &rate, source, &now)? {
+                        summary.imported += 1;
+                    } else {
+                        summary.skipped_duplicates += 1;
+                    }
+                }
+                Err(failure) => summary.failed.push(failure),
+            }
+        }
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    /// Converts an amount between currencies at the rate of `on`.
+    pub fn convert_amount(&self, amount_cents: i64, from: &str, to: &str, on: &str) -> Result<i64> {
+        require(self.db, "finance", Role::Viewer)?;
+        RateTable::load(&self.db.conn)?
+            .convert(amount_cents, from, to, on)
+            .ok_or_else(|| {
+                invalid_input(format!(
+                    "no exchange rate from {} to {} on or before {}",
+                    from, to, on
+                ))
+            })
+    }
+
+    /// The balances of all live accounts converted into the household
+    /// currency at the rates of `on`.
+    pub fn get_net_worth(&self, on: &str) -> Result<NetWorth> {
+        require(self.db, "finance", Role::Viewer)?;
+        let on = parse_date(on)?.to_string();
+        let currency_code = SettingsService::new(self.db).get_settings()?.currency_code;
+        let rates = RateTable::load(&self.db.conn)?;
+
+        let mut stmt = self.db.conn.prepare(
+            "SELECT currency_code, SUM(current_balance_cents) FROM accounts
+             WHERE deleted_at IS NULL GROUP BY currency_code",
+        )?;
+        let balances = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut total_cents = 0;
+        let mut missing_rates = Vec::new();
+        for (code, cents) in balances {
+            match rates.convert(cents, &code, &currency_code, &on) {
+                Some(converted) => total_cents += converted,
+                None => missing_rates.push(code),
+            }
+        }
+
+        Ok(NetWorth {
+            currency_code,
+            on,
+            total_cents,
+            missing_rates,
+        })
+    }
+}
+
+/// Every device names the rate for a pair and day the same way, so rates
+/// entered on two devices merge instead of piling up.
+fn rate_id(rate: &ParsedRate) -> String {
+    format!("{}/{}/{}", rate.base_code, rate.quote_code, rate.date)
+}
+
+/// Inserts or replaces a rate. Returns false when the same rate was already
+/// stored.
+fn write_rate(conn: &Connection, rate: &ParsedRate, source: &str, now: &str) -> Result<bool> {
+    let changed = conn.execute(
+        "INSERT INTO exchange_rates (id, base_code, quote_code, rate_date, rate, source, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)
+         ON CONFLICT(id) DO UPDATE SET rate = excluded.rate, source = excluded.source, updated_at = excluded.updated_at
+         WHERE rate != excluded.rate",
+        (
+            rate_id(rate),
+            &rate.base_code,
+            &rate.quote_code,
+            rate.date.to_string(),
+            rate.rate,
+            source,
+            now,
+        ),
+    )?;
+    Ok(changed > 0)
+}
+
+/// Upper-cases and checks an ISO 4217 style code.
+pub(super) fn currency_code(raw: &str) -> std::result::Result<String, String> {
+    let code = raw.trim().to_uppercase();
+    if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(code)
+    } else {
+        Err(format!("'{}' is not a three-letter currency code", raw))
+    }
+}
+
+fn checked_rate(rate: f64) -> std::result::Result<f64, String> {
+    if rate.is_finite() && rate > 0.0 {
+        Ok(rate)
+    } else {
+        Err(format!("exchange rate must be positive, got {}", rate))
+    }
+}
+
+fn parse_rate(raw: &str) -> std::result::Result<f64, String> {
+    raw.trim()
+        .parse::<f64>()
+        .map_err(|_| format!("unrecognised rate '{}'", raw.trim()))
+        .and_then(checked_rate)
+}
+
+/// ISO dates, or the `01 March 2024` style of the ECB's daily CSV.
+fn parse_rate_date(raw: &str) -> std::result::Result<NaiveDate, String> {
+    let raw = raw.trim();
+    NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(raw, "%d %B %Y"))
+        .map_err(|_| format!("unrecognised date '{}'", raw))
+}
+
+fn parse_rate_csv(content: &str) -> Vec<RateResult> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+    let header: Vec<String> = match reader.headers() {
+        Ok(header) => header.iter().map(str::to_lowercase).collect(),
+        Err(e) => {
+            return vec![Err(ImportFailure {
+                row: 1,
+                reason: e.to_string(),
+            })]
+        }
+    };
+    let column = |name: &str| header.iter().position(|h| h == name);
+    let long = (
+        column("date"),
+        column("base"),
+        column("quote"),
+        column("rate"),
+    );
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                let row = e.position().map(|p| p.line() as usize).unwrap_or(0);
+                rows.push(Err(ImportFailure {
+                    row,
+                    reason: e.to_string(),
+                }));
+                continue;
+            }
+        };
+        let row = record.position().map(|p| p.line() as usize).unwrap_or(0);
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+        let fail = |reason: String| ImportFailure { row, reason };
+        let field = |index: usize| record.get(index).unwrap_or_default();
+
+        if let (Some(date), Some(base), Some(quote), Some(rate)) = long {
+            rows.push(
+                (|| {
+                    Ok(ParsedRate {
+                        base_code: currency_code(field(base))?,
+                        quote_code: currency_code(field(quote))?,
+                        date: parse_rate_date(field(date))?,
+                        rate: parse_rate(field(rate))?,
+                    })
+                })()
+                .map_err(fail),
+            );
+            continue;
+        }
+
+        // ECB layout: one column per currency, blank or N/A where there was
+        // no fixing that day
+        let date = match parse_rate_date(field(0)) {
+            Ok(date) => date,
+            Err(reason) => {
+                rows.push(Err(fail(reason)));
+                continue;
+            }
+        };
+        for (index, quote) in header.iter().enumerate().skip(1) {
+            let raw = field(index);
+            if quote.is_empty() || raw.is_empty() || raw.eq_ignore_ascii_case("n/a") {
+                continue;
+            }
+            rows.push(
+                (|| {
+                    Ok(ParsedRate {
+                        base_code: ECB_BASE.to_string(),
+                        quote_code: currency_code(quote)?,
+                        date,
+                        rate: parse_rate(raw)?,
+                    })
+                })()
+                .map_err(fail),
+            );
+        }
+    }
+    rows
+}
+
+/// Reads `<Cube time='…'>` blocks of `<Cube currency='…' rate='…'/>`
+/// entries. Rows are numbered by entry, since the file is often one line.
+fn parse_ecb_xml(content: &str) -> Vec<RateResult> {
+    let mut rows = Vec::new();
+    let mut date: Option<NaiveDate> = None;
+
+    for (index, tag) in content.split("<Cube").skip(1).enumerate() {
+        let tag = tag.split('>').next().unwrap_or(tag);
+        let fail = |reason: String| ImportFailure {
+            row: index + 1,
+            reason,
+        };
+
+        if let Some(time) = xml_attribute(tag, "time") {
+            match parse_rate_date(time) {
+                Ok(parsed) => date = Some(parsed),
+                Err(reason) => {
+                    date = None;
+                    rows.push(Err(fail(reason)));
+                }
+            }
+            continue;
+        }
+        let (Some(currency), Some(rate)) =
+            (xml_attribute(tag, "currency"), xml_attribute(tag, "rate"))
+        else {
+            continue;
+        };
+        let Some(date) = date else {
+            rows.push(Err(fail(format!("rate for {} has no date", currency))));
+            continue;
+        };
+        rows.push(
+            (|| {
+                Ok(ParsedRate {
+                    base_code: ECB_BASE.to_string(),
+                    quote_code: currency_code(currency)?,
+                    date,
+                    rate: parse_rate(rate)?,
+                })
+            })()
+            .map_err(fail),
+        );
+    }
+    rows
+}
+
+fn xml_attribute<'t>(tag: &'t str, name: &str) -> Option<&'t str> {
+    let start = tag.find(&format!(" {}=", name))? + name.len() + 2;
+    let rest = &tag[start..];
+    let quote = rest.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+    let value = &rest[1..];
+    value.find(quote).map(|end| &value[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::modules::dashboard::DashboardService;
+
+    #[test]
+    fn test_rates_convert_totals_into_household_currency() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = FinanceService::new(&db);
+        service
+            .create_account_in_currency("Savings", "savings", 100_000, "INR")
+            .unwrap();
+        let travel = service
+            .create_account_in_currency("Travel card", "cash", 20_000, "usd")
+            .unwrap();
+        service
+            .create_account_in_currency("Swiss", "checking", 5_000, "CHF")
+            .unwrap();
+        let card = service
+            .get_accounts()
+            .unwrap()
+            .into_iter()
+            .find(|a| a.id == travel)
+            .unwrap();
+        assert_eq!(card.currency_code, "USD");
+        assert!(service
+            .set_exchange_rate("US", "INR", "2024-03-01", 83.0)
+            .is_err());
+
+        let xml = "<gesmes:Envelope><Cube><Cube time='2024-03-01'>
+            <Cube currency='USD' rate='1.0800'/><Cube currency='INR' rate='89.64'/>
+            </Cube></Cube></gesmes:Envelope>";
+        let summary = service.import_exchange_rates(xml).unwrap();
+        assert_eq!((summary.imported, summary.skipped_duplicates), (2, 0));
+        let summary = service.import_exchange_rates(xml).unwrap();
+        assert_eq!((summary.imported, summary.skipped_duplicates), (0, 2));
+        let csv = "Date, USD, INR, \n04 March 2024, 1.0850, N/A, \nbad, 1.0, 2.0,\n";
+        let summary = service.import_exchange_rates(csv).unwrap();
+        assert_eq!((summary.imported, summary.failed.len()), (1, 1));
+
+        // USD -> INR through EUR: 89.64 / 1.08 = 83
+        assert_eq!(
+            service
+                .convert_amount(100, "USD", "INR", "2024-03-02")
+                .unwrap(),
+            8_300
+        );
+        let worth = service.get_net_worth("2024-03-02").unwrap();
+        assert_eq!(worth.currency_code, "INR");
+        assert_eq!(worth.total_cents, 100_000 + 1_660_000);
+        assert_eq!(worth.missing_rates, vec!["CHF".to_string()]);
+        assert!(service.get_total_balance().is_err());
+
+        // A manual rate for the day wins over the cross rate
+        service
+            .set_exchange_rate("USD", "INR", "2024-03-02", 82.5)
+            .unwrap();
+        assert_eq!(
+            service.get_net_worth("2024-03-02").unwrap().total_cents,
+            100_000 + 1_650_000
+        );
+        assert_eq!(service.get_exchange_rates().unwrap().len(), 4);
+
+        // Spending is converted at the rate of the day it happened
+        let food = service
+            .create_category("Food", "expense", "#ff0000")
+            .unwrap();
+        service
+            .create_transaction(&travel, -1_000, "Diner", "2024-03-02", Some(&food))
+            .unwrap();
+        assert_eq!(
+            DashboardService::new(&db)
+                .get_expenditure_by_category()
+                .unwrap(),
+            vec![("Food".to_string(), 825.0)]
+        );
+
+        // Only an account without transactions can change currency
+        let mut card = card;
+        card.currency_code = "EUR".to_string();
+        assert!(service.update_account(&card).is_err());
+        let mut swiss = service
+            .get_accounts()
+            .unwrap()
+            .into_iter()
+            .find(|a| a.name == "Swiss")
+            .unwrap();
+        swiss.currency_code = "EUR".to_string();
+        service.update_account(&swiss).unwrap();
+    }
+}
@@ -0,0 +1,462 @@
+//! Receipt photos and PDFs attached to transactions.
+//!
+//! Files live in a directory next to the database (see
+//! [`ReceiptStore::default_dir`]) named after the SHA-256 of their contents,
+//! so a receipt attached to several transactions is stored once;
+//! `transactions.receipt_path` holds that name. Images get a small JPEG
+//! thumbnail alongside. Other devices get the files through the sync
+//! backend under [`RECEIPTS_PREFIX`], and a file is deleted once no
+//! transaction, trashed ones included, points at it.
+
+use crate::db::Db;
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::registry::ModuleRegistry;
+use crate::modules::sync::backend::SyncBackend;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
+use image::{DynamicImage, ImageFormat};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// Folder in the sync backend holding the receipt files.
+pub const RECEIPTS_PREFIX: &str = "receipts/";
+const THUMBNAIL_SIZE: u32 = 256;
+const THUMBNAIL_SUFFIX: &str = ".thumb.jpg";
+/// Key under the finance module's `settings_json` listing the receipts in
+/// use when this device last synced its files.
+const SYNCED_KEY: &str = "synced_receipts";
+
+/// Accepted files as (extension, media type, leading bytes).
+const FORMATS: [(&str, &str, &[u8]); 3] = [
+    ("jpg", "image/jpeg", &[0xFF, 0xD8, 0xFF]),
+    ("png", "image/png", b"\x89PNG\r\n\x1a\n"),
+    ("pdf", "application/pdf", b"%PDF-"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub transaction_id: String,
+    pub path: PathBuf,
+    pub media_type: String,
+    /// `None` for PDFs.
+    pub thumbnail_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceiptSyncSummary {
+    pub uploaded: usize,
+    pub downloaded: usize,
+    /// Files no transaction refers to any more, removed from the backend.
+    pub deleted: usize,
+}
+
+pub struct ReceiptStore<'a> {
+    db: &'a Db,
+    dir: PathBuf,
+}
+
+impl<'a> ReceiptStore<'a> {
+    /// Receipt directory used for a database at `db_path`: the same path
+    /// with a `.receipts` extension.
+    pub fn default_dir(db_path: &Path) -> PathBuf {
+        db_path.with_extension("receipts")
+    }
+
+    pub fn new(db: &'a Db, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            db,
+            dir: dir.into(),
+        }
+    }
+
+    /// Attaches the JPEG, PNG or PDF at `source` to a transaction,
+    /// replacing any receipt attached before.
+    pub fn attach_file(&self, transaction_id: &str, source: &Path) -> Result<Receipt> {
+        let contents =
+            fs::read(source).with_context(|| format!("Failed to read {}", source.display()))?;
+        self.attach_bytes(transaction_id, &contents)
+    }
+
+    pub fn attach_bytes(&self, transaction_id: &str, contents: &[u8]) -> Result<Receipt> {
+        require(self.db, "finance", Role::Editor)?;
+        let previous = self.receipt_name(transaction_id, true)?;
+        let (extension, _, _) = FORMATS
+            .iter()
+            .find(|(_, _, magic)| contents.starts_with(magic))
+            .ok_or_else(|| anyhow!("A receipt must be a JPEG, PNG or PDF file"))?;
+
+        let name = format!("{:x}.{}", Sha256::digest(contents), extension);
+        self.save(&name, contents)?;
+        self.db.conn.execute(
+            "UPDATE transactions SET receipt_path = ?1, updated_at = ?2 WHERE id = ?3",
+            params![name, Utc::now().to_rfc3339(), transaction_id],
+        )?;
+        if let Some(previous) = previous.filter(|p| *p != name) {
+            self.remove_if_unused(&previous)?;
+        }
+        self.receipt(transaction_id, &name)
+    }
+
+    /// The receipt attached to a transaction, if any. Works for
+    /// transactions in the trash too.
+    pub fn get_receipt(&self, transaction_id: &str) -> Result<Option<Receipt>> {
+        require(self.db, "finance", Role::Viewer)?;
+        self.receipt_name(transaction_id, false)?
+            .map(|name| self.receipt(transaction_id, &name))
+            .transpose()
+    }
+
+    /// Detaches a transaction's receipt, deleting the file unless another
+    /// transaction shares it.
+    pub fn remove_receipt(&self, transaction_id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let name = self
+            .receipt_name(transaction_id, true)?
+            .ok_or_else(|| anyhow!("Transaction {} has no receipt", transaction_id))?;
+        self.db.conn.execute(
+            "UPDATE transactions SET receipt_path = NULL, updated_at = ?1 WHERE id = ?2",
+            params![Utc::now().to_rfc3339(), transaction_id],
+        )?;
+        self.remove_if_unused(&name)
+    }
+
+    /// Deletes files in `dir` that no transaction points at any more, e.g.
+    /// after the trash has been purged. Receipts of transactions still in
+    /// the trash are kept so a restore brings them back. Returns the number
+    /// of receipts removed.
+    pub fn remove_orphans(db: &Db, dir: &Path) -> Result<usize> {
+        if !dir.is_dir() {
+            return Ok(0);
+        }
+        let referenced: HashSet<String> = referenced_names(db)?
+            .iter()
+            .map(|name| content_hash(name).to_string())
+            .collect();
+
+        let mut removed = 0;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if (is_receipt_name(&name) || name.ends_with(THUMBNAIL_SUFFIX))
+                && !referenced.contains(content_hash(&name))
+            {
+                fs::remove_file(entry.path())?;
+                removed += is_receipt_name(&name) as usize;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Copies the receipts and thumbnails from one directory to another,
+    /// e.g. alongside a database backup. Files already there are skipped
+    /// since a name always means the same contents. Returns the number of
+    /// receipts copied.
+    pub fn copy_files(from: &Path, to: &Path) -> Result<usize> {
+        let files = Self::stored_files(from)?;
+        if files.is_empty() {
+            return Ok(0);
+        }
+        fs::create_dir_all(to)
+            .with_context(|| format!("Failed to create receipt directory {}", to.display()))?;
+        let mut copied = 0;
+        for file in &files {
+            let Some(name) = file.file_name() else {
+                continue;
+            };
+            let target = to.join(name);
+            if !target.exists() {
+                fs::copy(file, target)?;
+                copied += is_receipt_name(&name.to_string_lossy()) as usize;
+            }
+        }
+        Ok(copied)
+    }
+
+    /// The receipts and thumbnails in receipt directory `dir`, leaving out
+    /// unfinished writes.
+    pub fn stored_files(dir: &Path) -> Result<Vec<PathBuf>> {
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if is_receipt_name(&name) || name.ends_with(THUMBNAIL_SUFFIX) {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Uploads the receipts the backend is missing, downloads the ones
+    /// this device is missing and removes files no transaction refers to
+    /// any more. Run after the row-level exchange so references from other
+    /// devices are known.
+    pub async fn sync_files(&self, backend: &dyn SyncBackend) -> Result<ReceiptSyncSummary> {
+        require(self.db, "finance", Role::Editor)?;
+        let referenced = referenced_names(self.db)?;
+        let registry = ModuleRegistry::new(self.db);
+        let mut settings = registry.get_settings("finance")?;
+        let synced: HashSet<String> =
+            serde_json::from_value(settings[SYNCED_KEY].clone()).unwrap_or_default();
+        let remote: HashSet<String> = backend
+            .list(RECEIPTS_PREFIX)
+            .await?
+            .into_iter()
+            .filter_map(|o| o.name.strip_prefix(RECEIPTS_PREFIX).map(str::to_string))
+            .collect();
+
+        let mut summary = ReceiptSyncSummary::default();
+        for name in &referenced {
+            let local = self.dir.join(name);
+            let remote_name = format!("{}{}", RECEIPTS_PREFIX, name);
+            match (local.exists(), remote.contains(name)) {
+                (true, false) => {
+                    let contents = fs::read(&local)
+                        .with_context(|| format!("Failed to read {}", local.display()))?;
+                    backend.put(&remote_name, contents).await?;
+                    summary.uploaded += 1;
+                }
+                (false, true) => {
+                    let contents = backend.get(&remote_name).await?;
+                    if format!("{:x}", Sha256::digest(&contents)) != content_hash(name) {
+                        bail!("{} does not match its checksum", remote_name);
+                    }
+                    self.save(name, &contents)?;
+                    summary.downloaded += 1;
+                }
+                _ => {}
+            }
+        }
+        // A file this device never saw in use may belong to a transaction
+        // another device added after our last exchange
+        for name in remote.intersection(&synced) {
+            if !referenced.contains(name) {
+                backend
+                    .delete(&format!("{}{}", RECEIPTS_PREFIX, name))
+                    .await?;
+                summary.deleted += 1;
+            }
+        }
+
+        settings[SYNCED_KEY] = serde_json::to_value(&referenced)?;
+        registry.save_settings("finance", &settings)?;
+        Ok(summary)
+    }
+
+    /// Writes a receipt and its thumbnail unless they are already stored.
+    fn save(&self, name: &str, contents: &[u8]) -> Result<()> {
+        fs::create_dir_all(&self.dir).with_context(|| {
+            format!("Failed to create receipt directory {}", self.dir.display())
+        })?;
+        let thumbnail = self.thumbnail_path(name);
+        if let Some(thumbnail) = thumbnail.filter(|t| !t.exists()) {
+            let image = image::load_from_memory(contents).context("Failed to read the image")?;
+            let mut encoded = Cursor::new(Vec::new());
+            DynamicImage::ImageRgb8(image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8())
+                .write_to(&mut encoded, ImageFormat::Jpeg)?;
+            write_atomically(&thumbnail, &encoded.into_inner())?;
+        }
+        let path = self.dir.join(name);
+        if !path.exists() {
+            write_atomically(&path, contents)?;
+        }
+        Ok(())
+    }
+
+    fn remove_if_unused(&self, name: &str) -> Result<()> {
+        let users: i64 = self.db.conn.query_row(
+            "SELECT COUNT(*) FROM transactions WHERE receipt_path = ?1",
+            params![name],
+            |row| row.get(0),
+        )?;
+        if users == 0 {
+            let _ = fs::remove_file(self.dir.join(name));
+            if let Some(thumbnail) = self.thumbnail_path(name) {
+                let _ = fs::remove_file(thumbnail);
+            }
+        }
+        Ok(())
+    }
+
+    /// The stored receipt name of a transaction; `live` leaves out trashed
+    /// transactions.
+    fn receipt_name(&self, transaction_id: &str, live: bool) -> Result<Option<String>> {
+        let name: Option<String> = self
+            .db
+            .conn
+            .query_row(
+                "SELECT receipt_path FROM transactions WHERE id = ?1 AND (?2 = 0 OR deleted_at IS NULL)",
+                params![transaction_id, live],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| anyhow!("Transaction {} not found", transaction_id))?;
+        Ok(name)
+    }
+
+    fn receipt(&self, transaction_id: &str, name: &str) -> Result<Receipt> {
+        if !is_receipt_name(name) {
+            bail!(
+                "Transaction {} has an invalid receipt '{}'",
+                transaction_id,
+                name
+            );
+        }
+        let extension = name.rsplit('.').next().unwrap_or_default();
+        let media_type = FORMATS
+            .iter()
+            .find(|(ext, _, _)| *ext == extension)
+            .map(|(_, media_type, _)| media_type.to_string())
+            .unwrap_or_default();
+        Ok(Receipt {
+            transaction_id: transaction_id.to_string(),
+            path: self.dir.join(name),
+            media_type,
+            thumbnail_path: self.thumbnail_path(name),
+        })
+    }
+
+    fn thumbnail_path(&self, name: &str) -> Option<PathBuf> {
+        (!name.ends_with(".pdf")).then(|| {
+            self.dir
+                .join(format!("{}{}", content_hash(name), THUMBNAIL_SUFFIX))
+        })
+    }
+}
+
+/// Receipt names referenced by any transaction, trashed ones included.
+/// Names that could not have come from `attach_bytes` (e.g. a path synced
+/// from a tampered device) are ignored.
+fn referenced_names(db: &Db) -> Result<HashSet<String>> {
+    let mut stmt = db
+        .conn
+        .prepare("SELECT DISTINCT receipt_path FROM transactions WHERE receipt_path IS NOT NULL")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let names = rows.collect::<Result<Vec<_>, _>>()?;
+    Ok(names.into_iter().filter(|n| is_receipt_name(n)).collect())
+}
+
+/// `<sha256 hex>.<extension>` for one of the accepted formats.
+fn is_receipt_name(name: &str) -> bool {
+    name.split_once('.').is_some_and(|(hash, extension)| {
+        hash.len() == 64
+            && hash.bytes().all(|b| b.is_ascii_hexdigit())
+            && FORMATS.iter().any(|(ext, _, _)| *ext == extension)
+    })
+}
+
+fn content_hash(name: &str) -> &str {
+    name.split('.').next().unwrap_or(name)
+}
+
+/// Writes to a temporary name first so an interrupted write never leaves a
+/// truncated file under a content hash.
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let partial = path.with_extension("partial");
+    fs::write(&partial, contents)
+        .with_context(|| format!("Failed to write {}", partial.display()))?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::finance::FinanceService;
+    use crate::modules::sync::backend::MemoryBackend;
+    use crate::modules::trash::TrashService;
+    use image::RgbImage;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut encoded = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut encoded, ImageFormat::Png)
+            .unwrap();
+        encoded.into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_receipts_are_stored_once_synced_and_cleaned_up() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let finance = FinanceService::new(&db);
+        let account = finance.create_account("Card", "credit", 0).unwrap();
+        let lunch = finance
+            .create_transaction(&account, -1_200, "Cafe", "2024-03-01", None)
+            .unwrap();
+        let dinner = finance
+            .create_transaction(&account, -3_400, "Bistro", "2024-03-01", None)
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("myhome_receipts_{}", uuid::Uuid::new_v4()));
+        let store = ReceiptStore::new(&db, &dir);
+
+        assert!(store.attach_bytes(&lunch, b"not a receipt").is_err());
+        let photo = png(1200, 900);
+        let receipt = store.attach_bytes(&lunch, &photo).unwrap();
+        assert_eq!(receipt.media_type, "image/png");
+        let thumbnail = image::open(receipt.thumbnail_path.as_ref().unwrap()).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (256, 192));
+
+        // The same file attached twice is stored once
+        assert_eq!(
+            store.attach_bytes(&dinner, &photo).unwrap().path,
+            receipt.path
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        assert_eq!(
+            finance.get_transactions(10).unwrap()[0].receipt_path,
+            receipt
+                .path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+        );
+
+        // Another device picks the file up through the backend
+        let backend = MemoryBackend::new();
+        let summary = store.sync_files(&backend).await.unwrap();
+        assert_eq!(summary.uploaded, 1);
+        let other_dir = dir.with_extension("other");
+        let other = ReceiptStore::new(&db, &other_dir);
+        assert_eq!(other.sync_files(&backend).await.unwrap().downloaded, 1);
+        assert_eq!(
+            fs::read(other_dir.join(receipt.path.file_name().unwrap())).unwrap(),
+            photo
+        );
+        let backup = dir.with_extension("backup");
+        assert_eq!(ReceiptStore::copy_files(&dir, &backup).unwrap(), 1);
+
+        // Kept while a transaction, even a trashed one, still uses it
+        store.remove_receipt(&lunch).unwrap();
+        assert!(receipt.path.exists());
+        finance.delete_transaction(&dinner).unwrap();
+        assert_eq!(ReceiptStore::remove_orphans(&db, &dir).unwrap(), 0);
+        assert!(store.get_receipt(&dinner).unwrap().is_some());
+
+        TrashService::new(&db).empty_trash(0).unwrap();
+        assert_eq!(ReceiptStore::remove_orphans(&db, &dir).unwrap(), 1);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(store.sync_files(&backend).await.unwrap().deleted, 1);
+
+        // A file another device uploaded for a transaction not merged here
+        // yet is left alone
+        let scan = b"%PDF-1.4 elsewhere";
+        let name = format!("{}{:x}.pdf", RECEIPTS_PREFIX, Sha256::digest(scan));
+        backend.put(&name, scan.to_vec()).await.unwrap();
+        assert_eq!(store.sync_files(&backend).await.unwrap().deleted, 0);
+        assert_eq!(backend.list(RECEIPTS_PREFIX).await.unwrap().len(), 1);
+
+        for dir in [dir, other_dir, backup] {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
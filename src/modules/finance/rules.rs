@@ -0,0 +1,592 @@
+//! Rules that fill in a transaction's category, tags and payee from its
+//! merchant, amount, account and UPI app.
+//!
+//! Rules run in `priority` order whenever a transaction is created or
+//! imported. The first matching rule that sets a category or payee decides
+//! it; tags from every matching rule are combined. A category chosen by the
+//! user always wins over the rules.
+
+use super::{invalid_input, FinanceService};
+use crate::db::expect_changed;
+use crate::modules::household::sharing::{require, Role};
+use chrono::Utc;
+use regex::{Regex, RegexBuilder};
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// What a transaction has to look like for a rule to apply. Unset fields
+/// match anything, but a rule needs at least one.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleConditions {
+    /// Case-insensitive substring of the merchant.
+    pub merchant_contains: Option<String>,
+    /// Case-insensitive regular expression searched for in the merchant.
+    pub merchant_regex: Option<String>,
+    /// Bounds on the amount regardless of sign, inclusive.
+    pub min_amount_cents: Option<i64>,
+    pub max_amount_cents: Option<i64>,
+    pub account_id: Option<String>,
+    /// App a UPI payment was made with, see `upi_app_of`.
+    pub upi_app: Option<String>,
+}
+
+/// What a matching rule fills in.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleActions {
+    pub category_id: Option<String>,
+    pub tags: Vec<String>,
+    pub payee: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategorizationRule {
+    pub id: String,
+    pub name: String,
+    /// Lower runs first.
+    pub priority: i64,
+    pub is_enabled: bool,
+    #[serde(flatten)]
+    pub conditions: RuleConditions,
+    #[serde(flatten)]
+    pub actions: RuleActions,
+}
+
+/// The category past transactions from the same merchant were filed under.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CategorySuggestion {
+    pub category_id: String,
+    pub category_name: String,
+    /// How many earlier transactions from the merchant used this category,
+    /// out of how many had one.
+    pub matches: usize,
+    pub out_of: usize,
+}
+
+/// The parts of a transaction rules look at.
+pub(super) struct RuleSubject<'s> {
+    pub account_id: &'s str,
+    pub amount_cents: i64,
+    pub merchant: &'s str,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct RuleOutcome {
+    pub category_id: Option<String>,
+    pub tags: Vec<String>,
+    pub payee: Option<String>,
+}
+
+/// The enabled rules, loaded and compiled once for a batch of transactions.
+pub(super) struct RuleSet {
+    rules: Vec<(CategorizationRule, Option<Regex>)>,
+}
+
+impl RuleSet {
+    pub fn load(conn: &Connection) -> Result<Self> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM categorization_rules
+             WHERE deleted_at IS NULL AND is_enabled = 1
+             ORDER BY priority, created_at",
+            RULE_COLUMNS
+        ))?;
+        let rules = stmt
+            .query_map([], read_rule)?
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            // A pattern this build cannot compile (e.g. synced from a newer
+            // version) disables its rule rather than matching everything
+            .filter_map(|rule| match &rule.conditions.merchant_regex {
+                Some(pattern) => compile(pattern).ok().map(|re| (rule, Some(re))),
+                None => Some((rule, None)),
+            })
+            .collect();
+        Ok(Self { rules })
+    }
+
+    pub fn apply(&self, subject: &RuleSubject) -> RuleOutcome {
+        let mut outcome = RuleOutcome::default();
+        for (rule, regex) in &self.rules {
+            if !matches(&rule.conditions, regex.as_ref(), subject) {
+                continue;
+            }
+            if outcome.category_id.is_none() {
+                outcome.category_id = rule.actions.category_id.clone();
+            }
+            if outcome.payee.is_none() {
+                outcome.payee = rule.actions.payee.clone();
+            }
+            outcome.tags = merge_tags(&outcome.tags, &rule.actions.tags);
+        }
+        outcome
+    }
+}
+
+fn matches(conditions: &RuleConditions, regex: Option<&Regex>, subject: &RuleSubject) -> bool {
+    let merchant = subject.merchant.to_lowercase();
+    let amount = subject.amount_cents.abs();
+    conditions
+        .merchant_contains
+        .as_ref()
+        .is_none_or(|needle| merchant.contains(&needle.to_lowercase()))
+        && regex.is_none_or(|re| re.is_match(subject.merchant))
+        && conditions.min_amount_cents.is_none_or(|min| amount >= min)
+        && conditions.max_amount_cents.is_none_or(|max| amount <= max)
+        && conditions
+            .account_id
+            .as_ref()
+            .is_none_or(|id| id == subject.account_id)
+        && conditions.upi_app.as_ref().is_none_or(|app| {
+            upi_app_of(subject.merchant).is_some_and(|used| used.eq_ignore_ascii_case(app))
+        })
+}
+
+fn compile(pattern: &str) -> std::result::Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
+
+/// The app a UPI payment went through. The app records UPI payments with
+/// the merchant written as "Merchant (via App)".
+pub fn upi_app_of(merchant: &str) -> Option<&str> {
+    let (_, app) = merchant
+        .trim_end()
+        .strip_suffix(')')?
+        .rsplit_once(" (via ")?;
+    Some(app.trim()).filter(|a| !a.is_empty())
+}
+
+/// `existing` followed by the tags in `more` it does not have yet,
+/// compared without regard to case.
+pub(super) fn merge_tags(existing: &[String], more: &[String]) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::with_capacity(existing.len() + more.len());
+    for tag in existing.iter().chain(more) {
+        let tag = tag.trim();
+        if !tag.is_empty() && !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+/// Reads the `tags` column, a JSON array of strings.
+pub(super) fn parse_tags(json: Option<String>) -> Vec<String> {
+    json.and_then(|j| serde_json::from_str(&j).ok())
+        .unwrap_or_default()
+}
+
+/// Writes the `tags` column; no tags is NULL.
+pub(super) fn tags_json(tags: &[String]) -> Option<String> {
+    (!tags.is_empty()).then(|| serde_json::to_string(tags).unwrap_or_default())
+}
+
+const RULE_COLUMNS: &str = "id, name, priority, is_enabled, merchant_contains, merchant_regex, min_amount_cents, max_amount_cents,
+     account_id, upi_app, category_id, tags, payee";
+
+fn read_rule(row: &rusqlite::Row) -> Result<CategorizationRule> {
+    Ok(CategorizationRule {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        priority: row.get(2)?,
+        is_enabled: row.get(3)?,
+        conditions: RuleConditions {
+            merchant_contains: row.get(4)?,
+            merchant_regex: row.get(5)?,
+            min_amount_cents: row.get(6)?,
+            max_amount_cents: row.get(7)?,
+            account_id: row.get(8)?,
+            upi_app: row.get(9)?,
+        },
+        actions: RuleActions {
+            category_id: row.get(10)?,
+            tags: parse_tags(row.get(11)?),
+            payee: row.get(12)?,
+        },
+    })
+}
+
+fn validate(name: &str, conditions: &RuleConditions, actions: &RuleActions) -> Result<()> {
+    if name.trim().is_empty() {
+        return Err(invalid_input("a rule needs a name".to_string()));
+    }
+    if *conditions == RuleConditions::default() {
+        return Err(invalid_input(format!(
+            "rule '{}' has no conditions and would match every transaction",
+            name
+        )));
+    }
+    if actions.category_id.is_none() && actions.payee.is_none() && actions.tags.is_empty() {
+        return Err(invalid_input(format!(
+            "rule '{}' does not set anything",
+            name
+        )));
+    }
+    if let Some(pattern) = &conditions.merchant_regex {
+        compile(pattern).map_err(|e| invalid_input(format!("invalid merchant pattern: {}", e)))?;
+    }
+    if let (Some(min), Some(max)) = (conditions.min_amount_cents, conditions.max_amount_cents) {
+        if min > max {
+            return Err(invalid_input(format!(
+                "minimum amount {} is above the maximum {}",
+                min, max
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Lowercased merchant without the UPI app, store numbers and punctuation,
+/// so "SWIGGY*1234 (via GPay)" and "Swiggy" count as the same merchant.
+fn merchant_key(merchant: &str) -> String {
+    let merchant = match merchant.trim_end().rfind(" (via ") {
+        Some(at) if upi_app_of(merchant).is_some() => &merchant[..at],
+        _ => merchant,
+    };
+    merchant
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !word.chars().any(|c| c.is_ascii_digit()))
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl<'a> FinanceService<'a> {
+    /// Live rules in the order they run.
+    pub fn get_rules(&self) -> Result<Vec<CategorizationRule>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let mut stmt = self.db.conn.prepare(&format!(
+            "SELECT {} FROM categorization_rules WHERE deleted_at IS NULL ORDER BY priority, created_at",
+            RULE_COLUMNS
+        ))?;
+
+        let rules = stmt
+            .query_map([], read_rule)?
+            .filter_map(Result::ok)
+            .collect();
+
+        Ok(rules)
+    }
+
+    /// Adds an enabled rule after the existing ones.
+    pub fn create_rule(
+        &self,
+        name: &str,
+        conditions: &RuleConditions,
+        actions: &RuleActions,
+    ) -> Result<String> {
+        require(self.db, "finance", Role::Editor)?;
+        validate(name, conditions, actions)?;
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+
+        self.db.conn.execute(
+            "INSERT INTO categorization_rules (id, name, priority, is_enabled, merchant_contains, merchant_regex, min_amount_cents, max_amount_cents,
+                 account_id, upi_app, category_id, tags, payee, created_at, updated_at)
+             VALUES (?1, ?2, (SELECT COALESCE(MAX(priority) + 1, 0) FROM categorization_rules WHERE deleted_at IS NULL),
+                 1, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?12)",
+            (
+                &id,
+                name,
+                &conditions.merchant_contains,
+                &conditions.merchant_regex,
+                conditions.min_amount_cents,
+                conditions.max_amount_cents,
+                &conditions.account_id,
+                &conditions.upi_app,
+                &actions.category_id,
+                tags_json(&merge_tags(&[], &actions.tags)),
+                &actions.payee,
+                &now,
+            ),
+        )?;
+        Ok(id)
+    }
+
+    pub fn update_rule(&self, rule: &CategorizationRule) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        validate(&rule.name, &rule.conditions, &rule.actions)?;
+        let now = Utc::now().to_rfc3339();
+
+        let changed = self.db.conn.execute(
+            "UPDATE categorization_rules SET name = ?1, priority = ?2, is_enabled = ?3, merchant_contains = ?4, merchant_regex = ?5,
+                 min_amount_cents = ?6, max_amount_cents = ?7, account_id = ?8, upi_app = ?9, category_id = ?10, tags = ?11, payee = ?12,
+                 updated_at = ?13
+             WHERE id = ?14 AND deleted_at IS NULL",
+            rusqlite::params![
+                &rule.name,
+                rule.priority,
+                rule.is_enabled,
+                &rule.conditions.merchant_contains,
+                &rule.conditions.merchant_regex,
+                rule.conditions.min_amount_cents,
+                rule.conditions.max_amount_cents,
+                &rule.conditions.account_id,
+                &rule.conditions.upi_app,
+                &rule.actions.category_id,
+                tags_json(&merge_tags(&[], &rule.actions.tags)),
+                &rule.actions.payee,
+                &now,
+                &rule.id,
+            ],
+        )?;
+        expect_changed(changed)
+    }
+
+    pub fn delete_rule(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        self.db.soft_delete("categorization_rules", id)
+    }
+
+    pub fn restore_rule(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        self.db.restore_deleted("categorization_rules", id)
+    }
+
+    /// Runs the rules over every live transaction that is not a transfer.
+    /// Without `overwrite` only a missing category or payee is filled in;
+    /// with it, whatever a rule sets replaces what is there. Tags are always
+    /// added to. Returns how many transactions changed.
+    pub fn apply_rules_to_history(&self, overwrite: bool) -> Result<usize> {
+        require(self.db, "finance", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+        let rules = RuleSet::load(&tx)?;
+
+        let transactions = {
+            let mut stmt = tx.prepare(
+                "SELECT id, account_id, amount_cents, COALESCE(merchant, ''), category_id, payee, tags
+                 FROM transactions WHERE deleted_at IS NULL AND transfer_id IS NULL",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                    parse_tags(row.get(6)?),
+                ))
+            })?;
+            rows.collect::<Result<Vec<_>>>()?
+        };
+
+        let mut changed = 0;
+        for (id, account_id, amount_cents, merchant, category_id, payee, tags) in transactions {
+            let outcome = rules.apply(&RuleSubject {
+                account_id: &account_id,
+                amount_cents,
+                merchant: &merchant,
+            });
+            let (new_category_id, new_payee) = if overwrite {
+                (
+                    outcome.category_id.or(category_id.clone()),
+                    outcome.payee.or(payee.clone()),
+                )
+            } else {
+                (
+                    category_id.clone().or(outcome.category_id),
+                    payee.clone().or(outcome.payee),
+                )
+            };
+            let new_tags = merge_tags(&tags, &outcome.tags);
+            if new_category_id == category_id && new_payee == payee && new_tags == tags {
+                continue;
+            }
+
+            tx.execute(
+                "UPDATE transactions SET category_id = ?1, payee = ?2, tags = ?3, updated_at = ?4 WHERE id = ?5",
+                (&new_category_id, &new_payee, tags_json(&new_tags), &now, &id),
+            )?;
+            changed += 1;
+        }
+
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    /// The category most often used for earlier transactions from the same
+    /// merchant, if any of them had one.
+    pub fn suggest_category(&self, merchant: &str) -> Result<Option<CategorySuggestion>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let key = merchant_key(merchant);
+        if key.is_empty() {
+            return Ok(None);
+        }
+
+        let mut stmt = self.db.conn.prepare(
+            "SELECT t.merchant, t.category_id FROM transactions t
+             JOIN categories c ON c.id = t.category_id AND c.deleted_at IS NULL
+             WHERE t.deleted_at IS NULL AND t.transfer_id IS NULL AND t.merchant IS NOT NULL",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut out_of = 0;
+        for row in rows {
+            let (past_merchant, category_id) = row?;
+            if merchant_key(&past_merchant) == key {
+                *counts.entry(category_id).or_default() += 1;
+                out_of += 1;
+            }
+        }
+        let Some((category_id, matches)) = counts
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+        else {
+            return Ok(None);
+        };
+
+        let category_name = self
+            .db
+            .conn
+            .query_row(
+                "SELECT name FROM categories WHERE id = ?1",
+                [&category_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or_default();
+        Ok(Some(CategorySuggestion {
+            category_id,
+            category_name,
+            matches,
+            out_of,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+
+    #[test]
+    fn test_rules_categorize_new_and_past_transactions() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = FinanceService::new(&db);
+        let account = service.create_account("Card", "credit", 0).unwrap();
+        let dining = service
+            .create_category("Dining", "expense", "#ff0000")
+            .unwrap();
+        let fuel = service
+            .create_category("Fuel", "expense", "#0000ff")
+            .unwrap();
+
+        // Recorded before any rules existed
+        let old = service
+            .create_transaction(&account, -45_000, "HP PETROL PUMP 0042", "2024-03-01", None)
+            .unwrap();
+
+        assert!(service
+            .create_rule(
+                "Everything",
+                &RuleConditions::default(),
+                &RuleActions::default()
+            )
+            .is_err());
+        assert!(service
+            .create_rule(
+                "Broken",
+                &RuleConditions {
+                    merchant_regex: Some("(".to_string()),
+                    ..Default::default()
+                },
+                &RuleActions {
+                    category_id: Some(fuel.clone()),
+                    ..Default::default()
+                },
+            )
+            .is_err());
+        service
+            .create_rule(
+                "Food delivery",
+                &RuleConditions {
+                    merchant_regex: Some(r"^(swiggy|zomato)\b".to_string()),
+                    max_amount_cents: Some(200_000),
+                    ..Default::default()
+                },
+                &RuleActions {
+                    category_id: Some(dining.clone()),
+                    payee: Some("Food delivery".to_string()),
+                    tags: vec!["delivery".to_string()],
+                },
+            )
+            .unwrap();
+        service
+            .create_rule(
+                "Paid with GPay",
+                &RuleConditions {
+                    upi_app: Some("gpay".to_string()),
+                    ..Default::default()
+                },
+                &RuleActions {
+                    tags: vec!["upi".to_string(), "Delivery".to_string()],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        service
+            .create_rule(
+                "Fuel",
+                &RuleConditions {
+                    merchant_contains: Some("petrol".to_string()),
+                    ..Default::default()
+                },
+                &RuleActions {
+                    category_id: Some(fuel.clone()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let row = |id: &str| {
+            db.conn
+                .query_row(
+                    "SELECT category_id, payee, tags FROM transactions WHERE id = ?1",
+                    [id],
+                    |r| {
+                        Ok((
+                            r.get::<_, Option<String>>(0)?,
+                            r.get::<_, Option<String>>(1)?,
+                            parse_tags(r.get(2)?),
+                        ))
+                    },
+                )
+                .unwrap()
+        };
+        let order = service
+            .create_transaction(&account, -35_000, "Swiggy (via GPay)", "2024-03-02", None)
+            .unwrap();
+        assert_eq!(
+            row(&order),
+            (
+                Some(dining.clone()),
+                Some("Food delivery".to_string()),
+                vec!["delivery".to_string(), "upi".to_string()]
+            )
+        );
+        // Too large for the delivery rule, and the user's choice wins anyway
+        let party = service
+            .create_transaction(&account, -500_000, "Zomato", "2024-03-03", Some(&fuel))
+            .unwrap();
+        assert_eq!(row(&party), (Some(fuel.clone()), None, vec![]));
+
+        assert_eq!(row(&old).0, None);
+        assert_eq!(service.apply_rules_to_history(false).unwrap(), 1);
+        assert_eq!(row(&old).0, Some(fuel.clone()));
+        assert_eq!(service.apply_rules_to_history(false).unwrap(), 0);
+
+        // Learned from what was filed before
+        let suggestion = service
+            .suggest_category("SWIGGY*8812 (via PhonePe)")
+            .unwrap()
+            .unwrap();
+        assert_eq!(suggestion.category_name, "Dining");
+        assert_eq!((suggestion.matches, suggestion.out_of), (1, 1));
+        assert!(service.suggest_category("Unknown").unwrap().is_none());
+    }
+}
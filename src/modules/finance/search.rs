@@ -0,0 +1,442 @@
+//! Searching transactions: filters, sorting, cursor pagination and totals
+//! for everything that matched, e.g. "how much did we spend at Swiggy in
+//! March".
+
+use super::categories::subtree_sql;
+use super::{
+    invalid_input, parse_date, read_transaction, FinanceService, Transaction, TRANSACTION_COLUMNS,
+};
+use crate::modules::household::sharing::{require, Role};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Result};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Which transactions to return and in what order. Unset filters match
+/// everything.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransactionQuery {
+    /// First and last day, YYYY-MM-DD, both inclusive.
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub account_id: Option<String>,
+    /// This category or any below it. A split transaction matches when one
+    /// of its lines does.
+    pub category_id: Option<String>,
+    /// Bounds on the amount regardless of sign, inclusive.
+    pub min_amount_cents: Option<i64>,
+    pub max_amount_cents: Option<i64>,
+    /// Case-insensitive text in the merchant or payee.
+    pub text: Option<String>,
+    /// Transactions carrying every one of these tags.
+    pub tags: Vec<String>,
+    pub is_pending: Option<bool>,
+    /// manual, import or bank_sync.
+    pub source: Option<String>,
+    pub sort: TransactionSort,
+    /// Page size; 0 means the default of 50.
+    pub limit: usize,
+    /// `next_cursor` of the previous page, with the same filters and sort.
+    pub cursor: Option<String>,
+}
+
+impl Default for TransactionQuery {
+    fn default() -> Self {
+        Self {
+            from: None,
+            to: None,
+            account_id: None,
+            category_id: None,
+            min_amount_cents: None,
+            max_amount_cents: None,
+            text: None,
+            tags: Vec::new(),
+            is_pending: None,
+            source: None,
+            sort: TransactionSort::default(),
+            limit: DEFAULT_PAGE_SIZE,
+            cursor: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionSort {
+    #[default]
+    Newest,
+    Oldest,
+    /// Biggest amounts first, regardless of sign.
+    Largest,
+    Smallest,
+}
+
+impl TransactionSort {
+    fn key(self) -> &'static str {
+        match self {
+            TransactionSort::Newest | TransactionSort::Oldest => "t.date",
+            TransactionSort::Largest | TransactionSort::Smallest => "ABS(t.amount_cents)",
+        }
+    }
+
+    fn descending(self) -> bool {
+        matches!(self, TransactionSort::Newest | TransactionSort::Largest)
+    }
+}
+
+/// Sums over every transaction the filters matched, not just the page.
+/// Transfers between the household's own accounts are listed but neither
+/// counted nor summed, as they are neither income nor spending.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransactionTotals {
+    pub currency_code: String,
+    pub count: usize,
+    /// Both as positive amounts.
+    pub income_cents: i64,
+    pub expense_cents: i64,
+    pub net_cents: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionPage {
+    pub transactions: Vec<Transaction>,
+    /// Pass back as `cursor` for the next page; `None` on the last one.
+    pub next_cursor: Option<String>,
+    /// One entry per currency, largest count first.
+    pub totals: Vec<TransactionTotals>,
+}
+
+/// A WHERE clause and its parameters, numbered in the order they were added.
+struct Filter {
+    clauses: Vec<String>,
+    params: Vec<SqlValue>,
+}
+
+impl Filter {
+    fn new() -> Self {
+        Self {
+            clauses: vec!["t.deleted_at IS NULL".to_string()],
+            params: Vec::new(),
+        }
+    }
+
+    /// Adds `clause` with one more parameter, which every `?` in it refers
+    /// to.
+    fn push(&mut self, clause: &str, value: impl Into<SqlValue>) {
+        self.params.push(value.into());
+        self.clauses
+            .push(clause.replace('?', &format!("?{}", self.params.len())));
+    }
+
+    fn sql(&self) -> String {
+        self.clauses.join(" AND ")
+    }
+}
+
+impl<'a> FinanceService<'a> {
+    /// One page of the transactions matching `query`, plus totals over all
+    /// of them.
+    pub fn search_transactions(&self, query: &TransactionQuery) -> Result<TransactionPage> {
+        require(self.db, "finance", Role::Viewer)?;
+        let filter = build_filter(query)?;
+
+        // With a category filter, split transactions only count the lines
+        // in that category towards the totals
+        let amount = match &query.category_id {
+            Some(_) => format!(
+                "CASE WHEN EXISTS (SELECT 1 FROM transaction_splits s WHERE s.transaction_id = t.id)
+                 THEN (SELECT SUM(s.amount_cents) FROM transaction_splits s
+                       WHERE s.transaction_id = t.id AND s.category_id IN ({}))
+                 ELSE t.amount_cents END",
+                subtree_sql("?1")
+            ),
+            None => "t.amount_cents".to_string(),
+        };
+        let mut stmt = self.db.conn.prepare(&format!(
+            "SELECT currency_code, COUNT(*),
+                    SUM(CASE WHEN amount > 0 THEN amount ELSE 0 END),
+                    SUM(CASE WHEN amount < 0 THEN -amount ELSE 0 END)
+             FROM (SELECT t.currency_code, {} AS amount FROM transactions t
+                   WHERE {} AND t.transfer_id IS NULL)
+             GROUP BY currency_code
+             ORDER BY COUNT(*) DESC, currency_code",
+            amount,
+            filter.sql()
+        ))?;
+        let totals = stmt
+            .query_map(params_from_iter(&filter.params), |row| {
+                let income_cents: i64 = row.get(2)?;
+                let expense_cents: i64 = row.get(3)?;
+                Ok(TransactionTotals {
+                    currency_code: row.get(0)?,
+                    count: row.get(1)?,
+                    income_cents,
+                    expense_cents,
+                    net_cents: income_cents - expense_cents,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut page_filter = filter;
+        let sort = query.sort;
+        if let Some(cursor) = &query.cursor {
+            let (key, id) = parse_cursor(cursor, sort)?;
+            let op = if sort.descending() { "<" } else { ">" };
+            page_filter.params.push(key);
+            page_filter.params.push(SqlValue::Text(id));
+            let (k, i) = (page_filter.params.len() - 1, page_filter.params.len());
+            page_filter.clauses.push(format!(
+                "({key} {op} ?{k} OR ({key} = ?{k} AND t.id {op} ?{i}))",
+                key = sort.key(),
+            ));
+        }
+        let limit = match query.limit {
+            0 => DEFAULT_PAGE_SIZE,
+            n => n,
+        };
+        let direction = if sort.descending() { "DESC" } else { "ASC" };
+        let mut stmt = self.db.conn.prepare(&format!(
+            "SELECT {}, {} AS sort_key FROM transactions t
+             LEFT JOIN categories c ON t.category_id = c.id
+             WHERE {}
+             ORDER BY {} {dir}, t.id {dir}
+             LIMIT {}",
+            TRANSACTION_COLUMNS,
+            sort.key(),
+            page_filter.sql(),
+            sort.key(),
+            limit + 1,
+            dir = direction,
+        ))?;
+        let mut rows = stmt
+            .query_map(params_from_iter(&page_filter.params), |row| {
+                let key: SqlValue = row.get("sort_key")?;
+                Ok((read_transaction(row)?, key))
+            })?
+            .collect::<Result<Vec<_>>>()?;
+
+        let next_cursor = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|(t, key)| match key {
+                SqlValue::Integer(n) => format!("{}|{}", n, t.id),
+                _ => format!("{}|{}", t.date, t.id),
+            })
+        } else {
+            None
+        };
+
+        Ok(TransactionPage {
+            transactions: rows.into_iter().map(|(t, _)| t).collect(),
+            next_cursor,
+            totals,
+        })
+    }
+}
+
+fn build_filter(query: &TransactionQuery) -> Result<Filter> {
+    let mut filter = Filter::new();
+    // First so the totals query can refer to it as ?1
+    if let Some(category_id) = &query.category_id {
+        let subtree = subtree_sql("?");
+        filter.push(
+            &format!(
+                "(t.category_id IN ({subtree}) AND NOT EXISTS (SELECT 1 FROM transaction_splits s WHERE s.transaction_id = t.id)
+                  OR EXISTS (SELECT 1 FROM transaction_splits s WHERE s.transaction_id = t.id AND s.category_id IN ({subtree})))",
+            ),
+            category_id.clone(),
+        );
+    }
+    if let Some(from) = &query.from {
+        filter.push("substr(t.date, 1, 10) >= ?", parse_date(from)?.to_string());
+    }
+    if let Some(to) = &query.to {
+        filter.push("substr(t.date, 1, 10) <= ?", parse_date(to)?.to_string());
+    }
+    if let Some(account_id) = &query.account_id {
+        filter.push("t.account_id = ?", account_id.clone());
+    }
+    if let Some(min) = query.min_amount_cents {
+        filter.push("ABS(t.amount_cents) >= ?", min);
+    }
+    if let Some(max) = query.max_amount_cents {
+        filter.push("ABS(t.amount_cents) <= ?", max);
+    }
+    if let Some(text) = query
+        .text
+        .as_deref()
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+    {
+        let pattern = format!(
+            "%{}%",
+            text.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        filter.push(
+            "(t.merchant LIKE ? ESCAPE '\\' OR t.payee LIKE ? ESCAPE '\\')",
+            pattern,
+        );
+    }
+    for tag in &query.tags {
+        filter.push(
+            "EXISTS (SELECT 1 FROM json_each(t.tags) WHERE lower(json_each.value) = lower(?))",
+            tag.trim().to_string(),
+        );
+    }
+    if let Some(is_pending) = query.is_pending {
+        filter.push("t.is_pending = ?", is_pending);
+    }
+    if let Some(source) = &query.source {
+        filter.push("t.source = ?", source.clone());
+    }
+    Ok(filter)
+}
+
+/// Splits a cursor from `search_transactions` back into the sort key and id
+/// of the last transaction on the previous page.
+fn parse_cursor(cursor: &str, sort: TransactionSort) -> Result<(SqlValue, String)> {
+    let invalid = || invalid_input(format!("invalid cursor '{}'", cursor));
+    let (key, id) = cursor.rsplit_once('|').ok_or_else(invalid)?;
+    let key = match sort {
+        TransactionSort::Newest | TransactionSort::Oldest => SqlValue::Text(key.to_string()),
+        TransactionSort::Largest | TransactionSort::Smallest => {
+            SqlValue::Integer(key.parse().map_err(|_| invalid())?)
+        }
+    };
+    Ok((key, id.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::modules::finance::splits::SplitLine;
+
+    #[test]
+    fn test_search_filters_pages_and_totals() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = FinanceService::new(&db);
+        let card = service.create_account("Card", "credit", 0).unwrap();
+        let checking = service.create_account("Checking", "checking", 0).unwrap();
+        let food = service
+            .create_category("Food", "expense", "#ff0000")
+            .unwrap();
+        let delivery = service.create_subcategory(&food, "Delivery").unwrap();
+        let household = service
+            .create_category("Household", "expense", "#0000ff")
+            .unwrap();
+
+        for (day, amount) in [(3, -45_000), (10, -12_000), (21, -30_000)] {
+            service
+                .create_transaction(
+                    &card,
+                    amount,
+                    "SWIGGY BANGALORE",
+                    &format!("2024-03-{:02}", day),
+                    Some(&delivery),
+                )
+                .unwrap();
+        }
+        service
+            .create_transaction(&card, -8_000, "Swiggy", "2024-04-02", Some(&delivery))
+            .unwrap();
+        let receipt = service
+            .create_transaction(&checking, -10_000, "Big Bazaar", "2024-03-15", None)
+            .unwrap();
+        service
+            .set_splits(
+                &receipt,
+                &[
+                    SplitLine {
+                        category_id: Some(food.clone()),
+                        amount_cents: -7_000,
+                        note: None,
+                    },
+                    SplitLine {
+                        category_id: Some(household),
+                        amount_cents: -3_000,
+                        note: None,
+                    },
+                ],
+            )
+            .unwrap();
+        service
+            .create_transaction(&checking, 500_000, "Salary", "2024-03-31", None)
+            .unwrap();
+
+        let march = TransactionQuery {
+            from: Some("2024-03-01".to_string()),
+            to: Some("2024-03-31".to_string()),
+            ..Default::default()
+        };
+        let swiggy = service
+            .search_transactions(&TransactionQuery {
+                text: Some("swiggy".to_string()),
+                ..march.clone()
+            })
+            .unwrap();
+        assert_eq!(swiggy.transactions.len(), 3);
+        assert_eq!(swiggy.totals[0].expense_cents, 87_000);
+        assert_eq!(swiggy.totals[0].count, 3);
+
+        // The food subtree takes in delivery and only the food line of the split
+        let food_page = service
+            .search_transactions(&TransactionQuery {
+                category_id: Some(food),
+                ..march.clone()
+            })
+            .unwrap();
+        assert_eq!(food_page.transactions.len(), 4);
+        assert_eq!(food_page.totals[0].expense_cents, 94_000);
+
+        let large = service
+            .search_transactions(&TransactionQuery {
+                min_amount_cents: Some(20_000),
+                account_id: Some(card.clone()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(large.totals[0].count, 2);
+
+        // Paging visits every match once, in order
+        let mut seen = Vec::new();
+        let mut query = TransactionQuery {
+            sort: TransactionSort::Largest,
+            limit: 2,
+            ..march
+        };
+        loop {
+            let page = service.search_transactions(&query).unwrap();
+            assert_eq!(page.totals[0].count, 5);
+            seen.extend(page.transactions.iter().map(|t| t.amount_cents.abs()));
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(seen, vec![500_000, 45_000, 30_000, 12_000, 10_000]);
+
+        query.cursor = Some("garbage".to_string());
+        assert!(service.search_transactions(&query).is_err());
+
+        // Paying off the card shows up in the results but not in the totals
+        service
+            .create_transfer(&checking, &card, 87_000, "2024-03-28", None)
+            .unwrap();
+        let march_page = service
+            .search_transactions(&TransactionQuery {
+                cursor: None,
+                limit: 0,
+                ..query
+            })
+            .unwrap();
+        assert_eq!(march_page.transactions.len(), 7);
+        assert_eq!(march_page.totals[0].count, 5);
+        assert_eq!(march_page.totals[0].income_cents, 500_000);
+        assert_eq!(march_page.totals[0].expense_cents, 97_000);
+    }
+}
@@ -0,0 +1,257 @@
+//! Transactions divided between several categories, e.g. one supermarket
+//! receipt covering groceries, household supplies and personal care.
+//!
+//! While a transaction has split lines, its own `category_id` is ignored by
+//! reports and budgets in favour of the lines.
+
+use super::{invalid_input, FinanceService};
+use crate::modules::household::sharing::{require, Role};
+use chrono::Utc;
+use rusqlite::{Connection, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Every live, non-transfer transaction broken into the amounts each
+/// category should be charged: its split lines if it has any, otherwise the
+/// whole transaction. Columns: transaction_id, account_id, date,
+/// category_id, amount_cents, currency_code.
+pub const SPENDING_LINES: &str = "
+    SELECT t.id AS transaction_id, t.account_id, t.date, s.category_id, s.amount_cents, t.currency_code
+    FROM transactions t JOIN transaction_splits s ON s.transaction_id = t.id
+    WHERE t.deleted_at IS NULL AND t.transfer_id IS NULL
+    UNION ALL
+    SELECT t.id, t.account_id, t.date, t.category_id, t.amount_cents, t.currency_code
+    FROM transactions t
+    WHERE t.deleted_at IS NULL AND t.transfer_id IS NULL
+      AND NOT EXISTS (SELECT 1 FROM transaction_splits s WHERE s.transaction_id = t.id)";
+
+/// One line of a split as entered.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SplitLine {
+    pub category_id: Option<String>,
+    /// Same sign convention as the transaction: negative for expenses.
+    pub amount_cents: i64,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionSplit {
+    pub id: String,
+    pub transaction_id: String,
+    pub category_id: Option<String>,
+    pub category_name: Option<String>,
+    pub amount_cents: i64,
+    pub note: Option<String>,
+}
+
+impl<'a> FinanceService<'a> {
+    /// Lines of a split transaction in the order they were entered; empty if
+    /// it is not split.
+    pub fn get_splits(&self, transaction_id: &str) -> Result<Vec<TransactionSplit>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let mut stmt = self.db.conn.prepare(
+            "SELECT s.id, s.transaction_id, s.category_id, c.name, s.amount_cents, s.note
+             FROM transaction_splits s
+             LEFT JOIN categories c ON c.id = s.category_id
+             WHERE s.transaction_id = ?1
+             ORDER BY s.sort_order",
+        )?;
+
+        let splits = stmt
+            .query_map([transaction_id], |row| {
+                Ok(TransactionSplit {
+                    id: row.get(0)?,
+                    transaction_id: row.get(1)?,
+                    category_id: row.get(2)?,
+                    category_name: row.get(3)?,
+                    amount_cents: row.get(4)?,
+                    note: row.get(5)?,
+                })
+            })?
+            .filter_map(Result::ok)
+            .collect();
+
+        Ok(splits)
+    }
+
+    /// Replaces a transaction's split lines. The lines must add up to the
+    /// transaction's amount; an empty list removes the split.
+    pub fn set_splits(&self, transaction_id: &str, lines: &[SplitLine]) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let (amount_cents, transfer_id): (i64, Option<String>) = tx.query_row(
+            "SELECT amount_cents, transfer_id FROM transactions WHERE id = ?1 AND deleted_at IS NULL",
+            [transaction_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        if transfer_id.is_some() {
+            return Err(invalid_input(format!(
+                "transaction {} is a transfer and cannot be split",
+                transaction_id
+            )));
+        }
+        if !lines.is_empty() {
+            check_total(amount_cents, lines.iter().map(|l| l.amount_cents))?;
+        }
+
+        tx.execute(
+            "DELETE FROM transaction_splits WHERE transaction_id = ?1",
+            [transaction_id],
+        )?;
+        let now = Utc::now().to_rfc3339();
+        for (order, line) in lines.iter().enumerate() {
+            insert_split(&tx, transaction_id, order, line, &now)?;
+        }
+
+        if amount_cents < 0 {
+            super::budgets::raise_budget_alerts(&tx)?;
+        }
+        tx.commit()
+    }
+
+    pub(super) fn ensure_splits_match(
+        &self,
+        transaction_id: &str,
+        amount_cents: i64,
+    ) -> Result<()> {
+        let mut stmt = self
+            .db
+            .conn
+            .prepare("SELECT amount_cents FROM transaction_splits WHERE transaction_id = ?1")?;
+        let amounts = stmt
+            .query_map([transaction_id], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>>>()?;
+        if amounts.is_empty() {
+            return Ok(());
+        }
+        check_total(amount_cents, amounts)
+    }
+}
+
+fn check_total(amount_cents: i64, lines: impl IntoIterator<Item = i64>) -> Result<()> {
+    let total: i64 = lines.into_iter().sum();
+    if total != amount_cents {
+        return Err(invalid_input(format!(
+            "split lines add up to {} but the transaction is {}",
+            total, amount_cents
+        )));
+    }
+    Ok(())
+}
+
+fn insert_split(
+    conn: &Connection,
+    transaction_id: &str,
+    order: usize,
+    line: &SplitLine,
+    now: &str,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO transaction_splits (id, transaction_id, category_id, amount_cents, note, sort_order, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)",
+        (
+            Uuid::new_v4().to_string(),
+            transaction_id,
+            &line.category_id,
+            line.amount_cents,
+            &line.note,
+            order as i64,
+            now,
+        ),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::modules::dashboard::DashboardService;
+
+    #[test]
+    fn test_splits_feed_reports_and_budgets() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = FinanceService::new(&db);
+        let account = service.create_account("Card", "credit", 0).unwrap();
+        let groceries = service
+            .create_category("Groceries", "expense", "#00ff00")
+            .unwrap();
+        let household = service
+            .create_category("Household", "expense", "#0000ff")
+            .unwrap();
+        let receipt = service
+            .create_transaction(
+                &account,
+                -10_000,
+                "Supermarket",
+                "2024-03-05",
+                Some(&groceries),
+            )
+            .unwrap();
+
+        let line = |category: &str, amount_cents: i64, note: Option<&str>| SplitLine {
+            category_id: Some(category.to_string()),
+            amount_cents,
+            note: note.map(str::to_string),
+        };
+        assert!(service
+            .set_splits(&receipt, &[line(&groceries, -6_000, None)])
+            .is_err());
+        service
+            .set_splits(
+                &receipt,
+                &[
+                    line(&groceries, -6_000, None),
+                    line(&household, -4_000, Some("Detergent")),
+                ],
+            )
+            .unwrap();
+
+        let splits = service.get_splits(&receipt).unwrap();
+        assert_eq!(splits.len(), 2);
+        assert_eq!(splits[1].category_name.as_deref(), Some("Household"));
+        assert_eq!(splits[1].note.as_deref(), Some("Detergent"));
+
+        let mut spending = DashboardService::new(&db)
+            .get_expenditure_by_category()
+            .unwrap();
+        spending.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            spending,
+            vec![
+                ("Groceries".to_string(), 60.0),
+                ("Household".to_string(), 40.0)
+            ]
+        );
+
+        service
+            .create_budget(
+                "Household",
+                Some(&household),
+                5_000,
+                "monthly",
+                Some("2024-03-01"),
+                None,
+            )
+            .unwrap();
+        let status = service.get_budget_statuses("2024-03-10").unwrap().remove(0);
+        assert_eq!(status.spent_cents, 4_000);
+
+        // The amount cannot drift away from the lines
+        let mut transaction = service.get_transactions(1).unwrap().remove(0);
+        assert!(transaction.is_split);
+        transaction.amount_cents = -12_000;
+        assert!(service.update_transaction(&transaction).is_err());
+
+        service.set_splits(&receipt, &[]).unwrap();
+        assert!(service.get_splits(&receipt).unwrap().is_empty());
+        assert_eq!(
+            DashboardService::new(&db)
+                .get_expenditure_by_category()
+                .unwrap(),
+            vec![("Groceries".to_string(), 100.0)]
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! Free-form tags on transactions, e.g. "goa-trip" or "reimbursable", kept
+//! as a JSON array in `transactions.tags`. Tags are compared without regard
+//! to case.
+
+use super::rates::RateTable;
+use super::rules::{merge_tags, parse_tags, tags_json};
+use super::{invalid_input, parse_date, FinanceService};
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::settings::SettingsService;
+use chrono::Utc;
+use rusqlite::{Connection, Result};
+use serde::{Deserialize, Serialize};
+
+/// Income and spending on one tag, in the household currency.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TagTotal {
+    pub tag: String,
+    pub count: usize,
+    /// Both as positive amounts.
+    pub income_cents: i64,
+    pub expense_cents: i64,
+}
+
+impl<'a> FinanceService<'a> {
+    /// Every tag on a live transaction with how often it is used, most used
+    /// first.
+    pub fn get_tags(&self) -> Result<Vec<(String, usize)>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let mut stmt = self.db.conn.prepare(
+            "SELECT tags FROM transactions WHERE deleted_at IS NULL AND tags IS NOT NULL",
+        )?;
+        let rows = stmt.query_map([], |row| row.get::<_, Option<String>>(0))?;
+
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for tags in rows {
+            for tag in parse_tags(tags?) {
+                match counts
+                    .iter_mut()
+                    .find(|(t, _)| t.eq_ignore_ascii_case(&tag))
+                {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((tag, 1)),
+                }
+            }
+        }
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(counts)
+    }
+
+    /// Income and spending per tag between two dates (inclusive), biggest
+    /// spending first. A transaction counts in full towards each of its
+    /// tags; transfers and amounts in currencies with no known rate are
+    /// left out.
+    pub fn get_tag_totals(&self, from: &str, to: &str) -> Result<Vec<TagTotal>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let (from, to) = (parse_date(from)?, parse_date(to)?);
+        let currency_code = SettingsService::new(self.db).get_settings()?.currency_code;
+        let rates = RateTable::load(&self.db.conn)?;
+
+        let mut stmt = self.db.conn.prepare(
+            "SELECT tags, substr(date, 1, 10), currency_code, amount_cents FROM transactions
+             WHERE deleted_at IS NULL AND transfer_id IS NULL AND tags IS NOT NULL
+               AND substr(date, 1, 10) BETWEEN ?1 AND ?2",
+        )?;
+        let rows = stmt.query_map((from.to_string(), to.to_string()), |row| {
+            Ok((
+                parse_tags(row.get(0)?),
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })?;
+
+        let mut totals: Vec<TagTotal> = Vec::new();
+        for row in rows {
+            let (tags, date, currency, cents) = row?;
+            let Some(cents) = rates.convert(cents, &currency, &currency_code, &date) else {
+                continue;
+            };
+            for tag in tags {
+                let index = match totals.iter().position(|t| t.tag.eq_ignore_ascii_case(&tag)) {
+                    Some(index) => index,
+                    None => {
+                        totals.push(TagTotal {
+                            tag,
+                            ..Default::default()
+                        });
+                        totals.len() - 1
+                    }
+                };
+                let total = &mut totals[index];
+                total.count += 1;
+                if cents < 0 {
+                    total.expense_cents -= cents;
+                } else {
+                    total.income_cents += cents;
+                }
+            }
+        }
+        totals.sort_by(|a, b| {
+            b.expense_cents
+                .cmp(&a.expense_cents)
+                .then_with(|| a.tag.cmp(&b.tag))
+        });
+        Ok(totals)
+    }
+
+    /// Renames `from` to `to` on every transaction and rule, trashed ones
+    /// included. If `to` is already in use the two tags merge. Returns how
+    /// many transactions changed.
+    pub fn rename_tag(&self, from: &str, to: &str) -> Result<usize> {
+        require(self.db, "finance", Role::Editor)?;
+        let (from, to) = (from.trim(), to.trim());
+        if from.is_empty() || to.is_empty() {
+            return Err(invalid_input("tag names cannot be empty".to_string()));
+        }
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let changed = retag(&tx, "transactions", from, to, &now)?;
+        retag(&tx, "categorization_rules", from, to, &now)?;
+
+        tx.commit()?;
+        Ok(changed)
+    }
+}
+
+/// Replaces `from` with `to` in the `tags` column of every row of `table`
+/// that has it.
+fn retag(conn: &Connection, table: &str, from: &str, to: &str, now: &str) -> Result<usize> {
+    let rows = {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, tags FROM {} t
+             WHERE EXISTS (SELECT 1 FROM json_each(t.tags) WHERE lower(json_each.value) = lower(?1))",
+            table
+        ))?;
+        let rows = stmt.query_map([from], |row| {
+            Ok((row.get::<_, String>(0)?, parse_tags(row.get(1)?)))
+        })?;
+        rows.collect::<Result<Vec<_>>>()?
+    };
+
+    let renamed = [to.to_string()];
+    for (id, tags) in &rows {
+        let mut new_tags = Vec::new();
+        for tag in tags {
+            let tag = if tag.eq_ignore_ascii_case(from) {
+                &renamed
+            } else {
+                std::slice::from_ref(tag)
+            };
+            new_tags = merge_tags(&new_tags, tag);
+        }
+        conn.execute(
+            &format!(
+                "UPDATE {} SET tags = ?1, updated_at = ?2 WHERE id = ?3",
+                table
+            ),
+            (tags_json(&new_tags), now, id),
+        )?;
+    }
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::modules::finance::TransactionDetails;
+
+    #[test]
+    fn test_tags_report_and_rename() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = FinanceService::new(&db);
+        let card = service.create_account("Card", "credit", 0).unwrap();
+        let tagged = |tags: &[&str]| TransactionDetails {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        };
+
+        let hotel = service
+            .create_transaction_with_details(
+                &card,
+                -20_000,
+                "Taj",
+                "2024-03-02",
+                None,
+                &TransactionDetails {
+                    payee: Some("Taj Hotels".to_string()),
+                    notes: Some("Two nights".to_string()),
+                    ..tagged(&["goa", "Reimbursable", "goa"])
+                },
+            )
+            .unwrap();
+        service
+            .create_transaction_with_details(
+                &card,
+                -5_000,
+                "Beach shack",
+                "2024-03-03",
+                None,
+                &tagged(&["Goa-Trip"]),
+            )
+            .unwrap();
+        service
+            .create_transaction_with_details(
+                &card,
+                7_000,
+                "Refund",
+                "2024-03-20",
+                None,
+                &tagged(&["reimbursable"]),
+            )
+            .unwrap();
+
+        let mut transaction = service
+            .get_transactions(10)
+            .unwrap()
+            .into_iter()
+            .find(|t| t.id == hotel)
+            .unwrap();
+        assert_eq!(transaction.tags, vec!["goa", "Reimbursable"]);
+        assert_eq!(transaction.payee.as_deref(), Some("Taj Hotels"));
+        assert_eq!(transaction.notes.as_deref(), Some("Two nights"));
+        transaction.notes = Some("Two nights, sea view".to_string());
+        service.update_transaction(&transaction).unwrap();
+
+        let totals = service.get_tag_totals("2024-03-01", "2024-03-31").unwrap();
+        assert_eq!(
+            totals
+                .iter()
+                .map(|t| (t.tag.as_str(), t.count, t.income_cents, t.expense_cents))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Reimbursable", 2, 7_000, 20_000),
+                ("goa", 1, 0, 20_000),
+                ("Goa-Trip", 1, 0, 5_000),
+            ]
+        );
+
+        // Renaming onto an existing tag merges the two
+        assert_eq!(service.rename_tag("goa-trip", "goa").unwrap(), 1);
+        assert_eq!(
+            service.get_tags().unwrap(),
+            vec![("Reimbursable".to_string(), 2), ("goa".to_string(), 2)]
+        );
+        assert!(service.rename_tag("goa", " ").is_err());
+    }
+}
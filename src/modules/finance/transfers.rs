@@ -0,0 +1,429 @@
+//! Money moved between two of the household's own accounts.
+//!
+//! A transfer is stored as two transactions sharing a `transfer_id`: the
+//! outgoing one on the source account and the incoming one on the
+//! destination. They are created, edited, deleted and restored together and
+//! are left out of spending reports and budgets. Both legs carry the same
+//! amount, so the two accounts must share a currency.
+
+use super::{adjust_balance, invalid_input, parse_date, FinanceService};
+use crate::modules::household::sharing::{require, Role};
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transfer {
+    pub id: String,
+    pub from_account_id: String,
+    pub to_account_id: String,
+    /// Always positive; the source account goes down by this much.
+    pub amount_cents: i64,
+    pub date: String,
+    pub notes: Option<String>,
+}
+
+impl<'a> FinanceService<'a> {
+    /// Live transfers, newest first.
+    pub fn get_transfers(&self) -> Result<Vec<Transfer>> {
+        require(self.db, "finance", Role::Viewer)?;
+        let mut stmt = self.db.conn.prepare(
+            "SELECT o.transfer_id, o.account_id, i.account_id, -o.amount_cents, o.date, o.notes
+             FROM transactions o
+             JOIN transactions i ON i.transfer_id = o.transfer_id AND i.id != o.id
+             WHERE o.amount_cents < 0 AND o.deleted_at IS NULL AND i.deleted_at IS NULL
+             ORDER BY o.date DESC",
+        )?;
+
+        let transfers = stmt
+            .query_map([], |row| {
+                Ok(Transfer {
+                    id: row.get(0)?,
+                    from_account_id: row.get(1)?,
+                    to_account_id: row.get(2)?,
+                    amount_cents: row.get(3)?,
+                    date: row.get(4)?,
+                    notes: row.get(5)?,
+                })
+            })?
+            .filter_map(Result::ok)
+            .collect();
+
+        Ok(transfers)
+    }
+
+    /// Moves `amount_cents` from one account to another and returns the
+    /// transfer's id.
+    pub fn create_transfer(
+        &self,
+        from_account_id: &str,
+        to_account_id: &str,
+        amount_cents: i64,
+        date: &str,
+        notes: Option<&str>,
+    ) -> Result<String> {
+        require(self.db, "finance", Role::Editor)?;
+        validate(from_account_id, to_account_id, amount_cents, date)?;
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let (from_name, to_name) = account_names(&tx, from_account_id, to_account_id)?;
+        same_currency(&tx, from_account_id, to_account_id)?;
+        insert_leg(
+            &tx,
+            &id,
+            from_account_id,
+            -amount_cents,
+            &format!("Transfer to {}", to_name),
+            date,
+            notes,
+            &now,
+        )?;
+        insert_leg(
+            &tx,
+            &id,
+            to_account_id,
+            amount_cents,
+            &format!("Transfer from {}", from_name),
+            date,
+            notes,
+            &now,
+        )?;
+
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Saves edits to both sides of a transfer and moves the balance
+    /// changes along with them.
+    pub fn update_transfer(&self, transfer: &Transfer) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        validate(
+            &transfer.from_account_id,
+            &transfer.to_account_id,
+            transfer.amount_cents,
+            &transfer.date,
+        )?;
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let legs = live_legs(&tx, &transfer.id)?;
+        for (_, account_id, amount_cents) in &legs {
+            adjust_balance(&tx, account_id, -amount_cents, &now)?;
+        }
+
+        let (from_name, to_name) =
+            account_names(&tx, &transfer.from_account_id, &transfer.to_account_id)?;
+        same_currency(&tx, &transfer.from_account_id, &transfer.to_account_id)?;
+        for (leg_id, _, old_amount_cents) in &legs {
+            let (account_id, amount_cents, merchant) = if *old_amount_cents < 0 {
+                (
+                    &transfer.from_account_id,
+                    -transfer.amount_cents,
+                    format!("Transfer to {}", to_name),
+                )
+            } else {
+                (
+                    &transfer.to_account_id,
+                    transfer.amount_cents,
+                    format!("Transfer from {}", from_name),
+                )
+            };
+            tx.execute(
+                "UPDATE transactions SET account_id = ?1, amount_cents = ?2, date = ?3, merchant = ?4, notes = ?5, updated_at = ?6
+                 WHERE id = ?7",
+                (
+                    account_id,
+                    amount_cents,
+                    &transfer.date,
+                    &merchant,
+                    &transfer.notes,
+                    &now,
+                    leg_id,
+                ),
+            )?;
+            adjust_balance(&tx, account_id, amount_cents, &now)?;
+        }
+
+        tx.commit()
+    }
+
+    /// Moves both sides of a transfer to the trash and undoes its effect on
+    /// the two balances.
+    pub fn delete_transfer(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        for (leg_id, account_id, amount_cents) in live_legs(&tx, id)? {
+            tx.execute(
+                "UPDATE transactions SET deleted_at = ?1, updated_at = ?1 WHERE id = ?2",
+                (&now, &leg_id),
+            )?;
+            adjust_balance(&tx, &account_id, -amount_cents, &now)?;
+        }
+
+        tx.commit()
+    }
+
+    /// Brings back both sides of a deleted transfer. Both accounts must
+    /// still exist.
+    pub fn restore_transfer(&self, id: &str) -> Result<()> {
+        require(self.db, "finance", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let legs = {
+            let mut stmt = tx.prepare(
+                "SELECT t.id, t.account_id, t.amount_cents FROM transactions t
+                 JOIN accounts a ON a.id = t.account_id
+                 WHERE t.transfer_id = ?1 AND t.deleted_at IS NOT NULL AND a.deleted_at IS NULL",
+            )?;
+            let rows = stmt.query_map([id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })?;
+            rows.collect::<Result<Vec<_>>>()?
+        };
+        if legs.len() != 2 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+
+        for (leg_id, account_id, amount_cents) in legs {
+            tx.execute(
+                "UPDATE transactions SET deleted_at = NULL, updated_at = ?1 WHERE id = ?2",
+                (&now, &leg_id),
+            )?;
+            adjust_balance(&tx, &account_id, amount_cents, &now)?;
+        }
+
+        tx.commit()
+    }
+
+    /// The transfer a transaction belongs to, if any.
+    pub(super) fn transfer_of(&self, transaction_id: &str) -> Result<Option<String>> {
+        Ok(self
+            .db
+            .conn
+            .query_row(
+                "SELECT transfer_id FROM transactions WHERE id = ?1",
+                [transaction_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten())
+    }
+}
+
+fn validate(
+    from_account_id: &str,
+    to_account_id: &str,
+    amount_cents: i64,
+    date: &str,
+) -> Result<()> {
+    if from_account_id == to_account_id {
+        return Err(invalid_input(
+            "a transfer needs two different accounts".to_string(),
+        ));
+    }
+    if amount_cents <= 0 {
+        return Err(invalid_input(format!(
+            "transfer amount must be positive, got {}",
+            amount_cents
+        )));
+    }
+    parse_date(date).map(|_| ())
+}
+
+/// Names of the two live accounts, used to label each side.
+fn account_names(
+    conn: &Connection,
+    from_account_id: &str,
+    to_account_id: &str,
+) -> Result<(String, String)> {
+    let name = |id: &str| {
+        conn.query_row(
+            "SELECT name FROM accounts WHERE id = ?1 AND deleted_at IS NULL",
+            [id],
+            |row| row.get::<_, String>(0),
+        )
+    };
+    Ok((name(from_account_id)?, name(to_account_id)?))
+}
+
+/// Fails unless both accounts keep the same currency; moving money between
+/// currencies would need a different amount on each side.
+fn same_currency(conn: &Connection, from_account_id: &str, to_account_id: &str) -> Result<()> {
+    let currency = |id: &str| {
+        conn.query_row(
+            "SELECT currency_code FROM accounts WHERE id = ?1",
+            [id],
+            |row| row.get::<_, String>(0),
+        )
+    };
+    let (from, to) = (currency(from_account_id)?, currency(to_account_id)?);
+    if from != to {
+        return Err(invalid_input(format!(
+            "cannot transfer between a {} and a {} account",
+            from, to
+        )));
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn insert_leg(
+    conn: &Connection,
+    transfer_id: &str,
+    account_id: &str,
+    amount_cents: i64,
+    merchant: &str,
+    date: &str,
+    notes: Option<&str>,
+    now: &str,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO transactions (id, account_id, amount_cents, currency_code, date, merchant, notes, transfer_id, is_pending, created_at, updated_at)
+         SELECT ?1, id, ?2, currency_code, ?3, ?4, ?5, ?6, 0, ?7, ?7 FROM accounts WHERE id = ?8",
+        (
+            Uuid::new_v4().to_string(),
+            amount_cents,
+            date,
+            merchant,
+            notes,
+            transfer_id,
+            now,
+            account_id,
+        ),
+    )?;
+    adjust_balance(conn, account_id, amount_cents, now)
+}
+
+/// Both live sides of a transfer as (transaction id, account id, amount).
+fn live_legs(conn: &Connection, transfer_id: &str) -> Result<Vec<(String, String, i64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, account_id, amount_cents FROM transactions
+         WHERE transfer_id = ?1 AND deleted_at IS NULL",
+    )?;
+    let legs = stmt
+        .query_map([transfer_id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    if legs.len() != 2 {
+        return Err(rusqlite::Error::QueryReturnedNoRows);
+    }
+    Ok(legs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::modules::dashboard::DashboardService;
+    use crate::modules::trash::TrashService;
+
+    #[test]
+    fn test_transfers_move_money_as_a_pair() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = FinanceService::new(&db);
+        let checking = service
+            .create_account("Checking", "checking", 100_000)
+            .unwrap();
+        let savings = service.create_account("Savings", "savings", 0).unwrap();
+        let balance = |id: &str| {
+            service
+                .get_accounts()
+                .unwrap()
+                .into_iter()
+                .find(|a| a.id == id)
+                .unwrap()
+                .current_balance_cents
+        };
+
+        assert!(service
+            .create_transfer(&checking, &checking, 100, "2024-03-01", None)
+            .is_err());
+        assert!(service
+            .create_transfer(&checking, &savings, -100, "2024-03-01", None)
+            .is_err());
+        let travel_card = service
+            .create_account_in_currency("Travel Card", "credit", 0, "USD")
+            .unwrap();
+        assert!(service
+            .create_transfer(&checking, &travel_card, 100, "2024-03-01", None)
+            .is_err());
+
+        let id = service
+            .create_transfer(&checking, &savings, 30_000, "2024-03-01", Some("Rainy day"))
+            .unwrap();
+        assert_eq!((balance(&checking), balance(&savings)), (70_000, 30_000));
+        let legs = service.get_transactions(10).unwrap();
+        assert_eq!(legs.len(), 2);
+        assert!(legs
+            .iter()
+            .all(|t| t.transfer_id.as_deref() == Some(id.as_str())));
+        assert!(legs.iter().any(|t| t.merchant == "Transfer to Savings"));
+
+        // Transfers are not spending
+        let groceries = service
+            .create_category("Groceries", "expense", "#00ff00")
+            .unwrap();
+        service
+            .create_transaction(&checking, -2_500, "Market", "2024-03-02", Some(&groceries))
+            .unwrap();
+        assert_eq!(
+            DashboardService::new(&db)
+                .get_expenditure_by_category()
+                .unwrap(),
+            vec![("Groceries".to_string(), 25.0)]
+        );
+
+        // Edited as a unit
+        let mut transfer = service.get_transfers().unwrap().remove(0);
+        assert_eq!(transfer.amount_cents, 30_000);
+        let leg = legs[0].clone();
+        assert!(service.update_transaction(&leg).is_err());
+        let mut to_card = transfer.clone();
+        to_card.to_account_id = travel_card.clone();
+        assert!(service.update_transfer(&to_card).is_err());
+        transfer.amount_cents = 50_000;
+        service.update_transfer(&transfer).unwrap();
+        assert_eq!((balance(&checking), balance(&savings)), (47_500, 50_000));
+
+        // Deleting one side removes both, restoring one brings both back
+        service.delete_transaction(&leg.id).unwrap();
+        assert!(service.get_transfers().unwrap().is_empty());
+        assert_eq!((balance(&checking), balance(&savings)), (97_500, 0));
+        service.restore_transaction(&leg.id).unwrap();
+        assert_eq!((balance(&checking), balance(&savings)), (47_500, 50_000));
+        assert!(service.restore_transfer(&id).is_err());
+
+        // Deleting an account takes its transfers out of the other account
+        service.delete_account(&savings).unwrap();
+        assert!(service.get_transfers().unwrap().is_empty());
+        assert_eq!(balance(&checking), 97_500);
+        service.restore_account(&savings).unwrap();
+        assert_eq!((balance(&checking), balance(&savings)), (47_500, 50_000));
+        service.update_transfer(&transfer).unwrap();
+
+        // A transfer whose other account was deleted since waits for it
+        service.delete_account(&savings).unwrap();
+        service.delete_account(&checking).unwrap();
+        service.restore_account(&savings).unwrap();
+        assert_eq!(balance(&savings), 0);
+        assert!(service.get_transactions(10).unwrap().is_empty());
+        let trash = TrashService::new(&db).get_trash().unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].id, checking);
+        service.restore_account(&checking).unwrap();
+        assert_eq!((balance(&checking), balance(&savings)), (47_500, 50_000));
+        assert_eq!(service.get_transfers().unwrap().len(), 1);
+    }
+}
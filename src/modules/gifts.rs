@@ -1,4 +1,6 @@
-use crate::db::Db;
+use crate::db::{expect_changed, Db};
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::trash::{self, TrashKind};
 use rusqlite::params;
 use serde::{Deserialize, Serialize};
 
@@ -34,6 +36,7 @@ impl<'a> GiftsService<'a> {
         relationship: Option<&str>,
         date_of_birth: Option<&str>,
     ) -> Result<String, rusqlite::Error> {
+        require(self.db, "gifts", Role::Editor)?;
         let conn = &self.db.conn;
         let id = uuid::Uuid::new_v4().to_string();
         let now = chrono::Utc::now().to_rfc3339();
@@ -47,6 +50,7 @@ impl<'a> GiftsService<'a> {
     }
 
     pub fn get_people(&self) -> Result<Vec<Person>, rusqlite::Error> {
+        require(self.db, "gifts", Role::Viewer)?;
         let conn = &self.db.conn;
         let mut stmt = conn.prepare(
             "SELECT id, name, relationship, date_of_birth FROM people WHERE deleted_at IS NULL",
@@ -74,6 +78,7 @@ impl<'a> GiftsService<'a> {
         description: &str,
         estimated_price_cents: Option<i64>,
     ) -> Result<String, rusqlite::Error> {
+        require(self.db, "gifts", Role::Editor)?;
         let conn = &self.db.conn;
         let id = uuid::Uuid::new_v4().to_string();
         let now = chrono::Utc::now().to_rfc3339();
@@ -94,6 +99,7 @@ impl<'a> GiftsService<'a> {
     }
 
     pub fn get_gift_ideas(&self) -> Result<Vec<GiftIdea>, rusqlite::Error> {
+        require(self.db, "gifts", Role::Viewer)?;
         let conn = &self.db.conn;
         let mut stmt = conn.prepare(
             "SELECT id, person_id, description, estimated_price_cents, status FROM gift_ideas WHERE deleted_at IS NULL",
@@ -115,6 +121,111 @@ impl<'a> GiftsService<'a> {
         }
         Ok(ideas)
     }
+
+    pub fn update_person(&self, person: &Person) -> Result<(), rusqlite::Error> {
+        require(self.db, "gifts", Role::Editor)?;
+        let conn = &self.db.conn;
+        let now = chrono::Utc::now().to_rfc3339();
+
+        let changed = conn.execute(
+            "UPDATE people SET name = ?1, relationship = ?2, date_of_birth = ?3, updated_at = ?4
+             WHERE id = ?5 AND deleted_at IS NULL",
+            params![
+                person.name,
+                person.relationship,
+                person.date_of_birth,
+                now,
+                person.id
+            ],
+        )?;
+        expect_changed(changed)
+    }
+
+    /// Soft-deletes a person together with their gift ideas, so restoring the
+    /// person brings the ideas back as well.
+    pub fn delete_person(&self, id: &str) -> Result<(), rusqlite::Error> {
+        require(self.db, "gifts", Role::Editor)?;
+        let conn = &self.db.conn;
+        let now = chrono::Utc::now().to_rfc3339();
+        let tx = conn.unchecked_transaction()?;
+
+        let changed = tx.execute(
+            "UPDATE people SET deleted_at = ?1, updated_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+            params![now, id],
+        )?;
+        expect_changed(changed)?;
+        tx.execute(
+            "UPDATE gift_ideas SET deleted_at = ?1, updated_at = ?1 WHERE person_id = ?2 AND deleted_at IS NULL",
+            params![now, id],
+        )?;
+
+        tx.commit()
+    }
+
+    pub fn restore_person(&self, id: &str) -> Result<(), rusqlite::Error> {
+        require(self.db, "gifts", Role::Editor)?;
+        let conn = &self.db.conn;
+        let now = chrono::Utc::now().to_rfc3339();
+        let tx = conn.unchecked_transaction()?;
+
+        // Only ideas removed by the same delete_person call share its timestamp
+        tx.execute(
+            "UPDATE gift_ideas SET deleted_at = NULL, updated_at = ?1
+             WHERE person_id = ?2 AND deleted_at = (SELECT deleted_at FROM people WHERE id = ?2)",
+            params![now, id],
+        )?;
+        let changed = tx.execute(
+            "UPDATE people SET deleted_at = NULL, updated_at = ?1 WHERE id = ?2 AND deleted_at IS NOT NULL",
+            params![now, id],
+        )?;
+        expect_changed(changed)?;
+
+        tx.commit()
+    }
+
+    pub fn update_gift_idea(&self, idea: &GiftIdea) -> Result<(), rusqlite::Error> {
+        require(self.db, "gifts", Role::Editor)?;
+        let conn = &self.db.conn;
+        let now = chrono::Utc::now().to_rfc3339();
+
+        let changed = conn.execute(
+            "UPDATE gift_ideas SET person_id = ?1, description = ?2, estimated_price_cents = ?3, status = ?4, updated_at = ?5
+             WHERE id = ?6 AND deleted_at IS NULL",
+            params![
+                idea.person_id,
+                idea.description,
+                idea.estimated_price_cents,
+                idea.status,
+                now,
+                idea.id
+            ],
+        )?;
+        expect_changed(changed)
+    }
+
+    pub fn delete_gift_idea(&self, id: &str) -> Result<(), rusqlite::Error> {
+        require(self.db, "gifts", Role::Editor)?;
+        self.db.soft_delete("gift_ideas", id)
+    }
+
+    pub fn restore_gift_idea(&self, id: &str) -> Result<(), rusqlite::Error> {
+        require(self.db, "gifts", Role::Editor)?;
+        self.db.restore_deleted("gift_ideas", id)
+    }
+
+    /// Permanently removes people and gift ideas deleted more than
+    /// `older_than_days` ago. Ideas of a purged person go with them.
+    pub fn purge_deleted(&self, older_than_days: i64) -> Result<usize, rusqlite::Error> {
+        require(self.db, "gifts", Role::Editor)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+        let purged = trash::purge_older_than(
+            &tx,
+            &[TrashKind::GiftIdea, TrashKind::Person],
+            older_than_days,
+        )?;
+        tx.commit()?;
+        Ok(purged)
+    }
 }
 
 #[cfg(test)]
@@ -145,4 +256,47 @@ mod tests {
         assert_eq!(ideas.len(), 1);
         assert_eq!(ideas[0].description, "Book");
     }
+
+    #[test]
+    fn test_gifts_update_delete_restore() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = GiftsService::new(&db);
+
+        let person_id = service.add_person("Alice", None, None).unwrap();
+        let kept_id = service.add_gift_idea(&person_id, "Book", None).unwrap();
+        let dropped_id = service.add_gift_idea(&person_id, "Scarf", None).unwrap();
+
+        let mut person = service.get_people().unwrap().remove(0);
+        person.relationship = Some("Sister".to_string());
+        service.update_person(&person).unwrap();
+        assert_eq!(
+            service.get_people().unwrap()[0].relationship.as_deref(),
+            Some("Sister")
+        );
+
+        let mut idea = service
+            .get_gift_ideas()
+            .unwrap()
+            .into_iter()
+            .find(|i| i.id == kept_id)
+            .unwrap();
+        idea.status = "purchased".to_string();
+        service.update_gift_idea(&idea).unwrap();
+
+        // An idea deleted on its own stays deleted when the person comes back
+        service.delete_gift_idea(&dropped_id).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        service.delete_person(&person_id).unwrap();
+        assert!(service.get_people().unwrap().is_empty());
+        assert!(service.get_gift_ideas().unwrap().is_empty());
+
+        service.restore_person(&person_id).unwrap();
+        let ideas = service.get_gift_ideas().unwrap();
+        assert_eq!(ideas.len(), 1);
+        assert_eq!(ideas[0].status, "purchased");
+
+        service.delete_person(&person_id).unwrap();
+        assert_eq!(service.purge_deleted(0).unwrap(), 3);
+    }
 }
@@ -1,4 +1,6 @@
-use crate::db::Db;
+use crate::db::{expect_changed, Db};
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::trash::{self, TrashKind};
 use chrono::Utc;
 use rusqlite::Result;
 use serde::{Deserialize, Serialize};
@@ -34,8 +36,9 @@ impl<'a> GroceryService<'a> {
     }
 
     pub fn get_grocery_list(&self) -> Result<Vec<GroceryItem>> {
+        require(self.db, "grocery", Role::Viewer)?;
         let mut stmt = self.db.conn.prepare(
-            "SELECT id, name, quantity, unit, category, is_checked FROM shopping_list_items WHERE is_checked = 0 ORDER BY created_at DESC"
+            "SELECT id, name, quantity, unit, category, is_checked FROM shopping_list_items WHERE is_checked = 0 AND deleted_at IS NULL ORDER BY created_at DESC"
         )?;
 
         // In SQLite, boolean is 0 or 1
@@ -57,7 +60,8 @@ impl<'a> GroceryService<'a> {
         Ok(items)
     }
 
-    pub fn add_grocery_item(&self, name: &str, category: Option<&str>) -> Result<()> {
+    pub fn add_grocery_item(&self, name: &str, category: Option<&str>) -> Result<String> {
+        require(self.db, "grocery", Role::Editor)?;
         let id = Uuid::new_v4().to_string();
         let now = Utc::now().to_rfc3339();
         let list_id = "default_list";
@@ -71,12 +75,45 @@ impl<'a> GroceryService<'a> {
         self.db.conn.execute(
             "INSERT INTO shopping_list_items (id, list_id, name, quantity, unit, is_checked, category, created_at, updated_at)
              VALUES (?1, ?2, ?3, 1.0, 'unit', 0, ?4, ?5, ?6)",
-            (id, list_id, name, category, &now, &now),
+            (&id, list_id, name, category, &now, &now),
         )?;
-        Ok(())
+        Ok(id)
+    }
+
+    /// Saves edits to a shopping list item, including checking it off.
+    pub fn update_grocery_item(&self, item: &GroceryItem) -> Result<()> {
+        require(self.db, "grocery", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+        let is_checked_int = if item.is_purchased { 1 } else { 0 };
+
+        let changed = self.db.conn.execute(
+            "UPDATE shopping_list_items SET name = ?1, quantity = ?2, unit = ?3, category = ?4, is_checked = ?5, updated_at = ?6
+             WHERE id = ?7 AND deleted_at IS NULL",
+            (
+                &item.name,
+                item.quantity.unwrap_or(1.0),
+                &item.unit,
+                &item.category,
+                is_checked_int,
+                &now,
+                &item.id,
+            ),
+        )?;
+        expect_changed(changed)
+    }
+
+    pub fn delete_grocery_item(&self, id: &str) -> Result<()> {
+        require(self.db, "grocery", Role::Editor)?;
+        self.db.soft_delete("shopping_list_items", id)
+    }
+
+    pub fn restore_grocery_item(&self, id: &str) -> Result<()> {
+        require(self.db, "grocery", Role::Editor)?;
+        self.db.restore_deleted("shopping_list_items", id)
     }
 
     pub fn get_inventory(&self) -> Result<Vec<InventoryItem>> {
+        require(self.db, "grocery", Role::Viewer)?;
         let mut stmt = self.db.conn.prepare(
             "SELECT id, name, quantity, unit, location, expiry_date FROM inventory_items WHERE deleted_at IS NULL ORDER BY created_at DESC"
         )?;
@@ -98,16 +135,61 @@ impl<'a> GroceryService<'a> {
         Ok(items)
     }
 
-    pub fn add_inventory_item(&self, name: &str, quantity: f64) -> Result<()> {
+    pub fn add_inventory_item(&self, name: &str, quantity: f64) -> Result<String> {
+        require(self.db, "grocery", Role::Editor)?;
         let id = Uuid::new_v4().to_string();
         let now = Utc::now().to_rfc3339();
 
         self.db.conn.execute(
             "INSERT INTO inventory_items (id, name, quantity, unit, created_at, updated_at)
              VALUES (?1, ?2, ?3, 'unit', ?4, ?5)",
-            (id, name, quantity, &now, &now),
+            (&id, name, quantity, &now, &now),
+        )?;
+        Ok(id)
+    }
+
+    pub fn update_inventory_item(&self, item: &InventoryItem) -> Result<()> {
+        require(self.db, "grocery", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+
+        let changed = self.db.conn.execute(
+            "UPDATE inventory_items SET name = ?1, quantity = ?2, unit = ?3, location = ?4, expiry_date = ?5, updated_at = ?6
+             WHERE id = ?7 AND deleted_at IS NULL",
+            (
+                &item.name,
+                item.quantity.unwrap_or(0.0),
+                &item.unit,
+                &item.location_id,
+                &item.expiration_date,
+                &now,
+                &item.id,
+            ),
+        )?;
+        expect_changed(changed)
+    }
+
+    pub fn delete_inventory_item(&self, id: &str) -> Result<()> {
+        require(self.db, "grocery", Role::Editor)?;
+        self.db.soft_delete("inventory_items", id)
+    }
+
+    pub fn restore_inventory_item(&self, id: &str) -> Result<()> {
+        require(self.db, "grocery", Role::Editor)?;
+        self.db.restore_deleted("inventory_items", id)
+    }
+
+    /// Permanently removes list and inventory items deleted more than
+    /// `older_than_days` ago.
+    pub fn purge_deleted(&self, older_than_days: i64) -> Result<usize> {
+        require(self.db, "grocery", Role::Editor)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+        let purged = trash::purge_older_than(
+            &tx,
+            &[TrashKind::GroceryItem, TrashKind::InventoryItem],
+            older_than_days,
         )?;
-        Ok(())
+        tx.commit()?;
+        Ok(purged)
     }
 }
 
@@ -135,4 +217,43 @@ mod tests {
         assert_eq!(inventory[0].name, "Pasta");
         assert_eq!(inventory[0].quantity, Some(3.0));
     }
+
+    #[test]
+    fn test_grocery_update_delete_restore() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+
+        let service = GroceryService::new(&db);
+        let milk_id = service.add_grocery_item("Milk", Some("Dairy")).unwrap();
+        let bread_id = service.add_grocery_item("Bread", None).unwrap();
+
+        // Checking an item off removes it from the open list
+        let mut milk = service
+            .get_grocery_list()
+            .unwrap()
+            .into_iter()
+            .find(|i| i.id == milk_id)
+            .unwrap();
+        milk.is_purchased = true;
+        service.update_grocery_item(&milk).unwrap();
+        assert_eq!(service.get_grocery_list().unwrap().len(), 1);
+
+        service.delete_grocery_item(&bread_id).unwrap();
+        assert!(service.get_grocery_list().unwrap().is_empty());
+        service.restore_grocery_item(&bread_id).unwrap();
+        assert_eq!(service.get_grocery_list().unwrap().len(), 1);
+
+        let pasta_id = service.add_inventory_item("Pasta", 3.0).unwrap();
+        let mut pasta = service.get_inventory().unwrap().remove(0);
+        pasta.quantity = Some(1.0);
+        pasta.location_id = Some("Pantry".to_string());
+        service.update_inventory_item(&pasta).unwrap();
+        assert_eq!(service.get_inventory().unwrap()[0].quantity, Some(1.0));
+
+        service.delete_inventory_item(&pasta_id).unwrap();
+        service.delete_grocery_item(&bread_id).unwrap();
+        assert!(service.get_inventory().unwrap().is_empty());
+        assert_eq!(service.purge_deleted(0).unwrap(), 2);
+        assert!(service.restore_inventory_item(&pasta_id).is_err());
+    }
 }
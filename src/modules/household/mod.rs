@@ -0,0 +1,619 @@
+pub mod sharing;
+pub mod vault;
+
+use crate::db::{expect_changed, Db};
+use crate::modules::notifications;
+use crate::modules::registry::ModuleRegistry;
+use crate::modules::trash::{self, TrashKind};
+use chrono::{Days, NaiveDate};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sharing::{require, Role};
+use std::collections::HashMap;
+
+/// Key under the household module's `settings_json` holding reminder lead
+/// times per document type.
+const LEAD_DAYS_KEY: &str = "expiry_lead_days";
+
+/// Lead time for document types without their own setting.
+pub const DEFAULT_LEAD_DAYS: i64 = 30;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Member {
+    pub id: String,
+    pub name: String,
+    pub relationship: String,
+    pub date_of_birth: Option<String>,
+    pub profile_photo_path: Option<String>,
+    pub is_primary: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Document {
+    pub id: String,
+    pub member_id: Option<String>,
+    pub name: String,
+    pub document_type: String,
+    pub document_number: Option<String>,
+    pub issue_date: Option<String>,
+    pub expiry_date: Option<String>,
+    pub issuing_authority: Option<String>,
+}
+
+/// Where a document stands relative to its expiry date.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiryStatus {
+    Expired,
+    Within30Days,
+    Within60Days,
+    Within90Days,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DocumentExpiry {
+    pub document_id: String,
+    pub name: String,
+    pub document_type: String,
+    pub member_id: Option<String>,
+    pub member_name: Option<String>,
+    pub expiry_date: String,
+    /// Negative once the document has expired.
+    pub days_left: i64,
+    pub status: ExpiryStatus,
+}
+
+/// Documents that have expired or expire within 90 days, soonest first.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ExpiryReport {
+    pub expired: Vec<DocumentExpiry>,
+    pub within_30_days: Vec<DocumentExpiry>,
+    pub within_60_days: Vec<DocumentExpiry>,
+    pub within_90_days: Vec<DocumentExpiry>,
+    pub by_member: Vec<MemberExpiries>,
+}
+
+/// The report's documents for one member; `member_id` is `None` for
+/// documents that belong to nobody in particular.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MemberExpiries {
+    pub member_id: Option<String>,
+    pub member_name: Option<String>,
+    pub documents: Vec<DocumentExpiry>,
+}
+
+pub struct HouseholdService<'a> {
+    db: &'a Db,
+}
+
+impl<'a> HouseholdService<'a> {
+    pub fn new(db: &'a Db) -> Self {
+        Self { db }
+    }
+
+    pub fn add_member(
+        &self,
+        name: &str,
+        relationship: &str,
+        date_of_birth: Option<&str>,
+        is_primary: bool,
+    ) -> Result<String, rusqlite::Error> {
+        require(self.db, "household", Role::Editor)?;
+        let conn = &self.db.conn;
+        let id = uuid::Uuid::new_v4().to_string();
+        let now = chrono::Utc::now().to_rfc3339();
+
+        conn.execute(
+            "INSERT INTO members (id, name, relationship, date_of_birth, is_primary, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                id,
+                name,
+                relationship,
+                date_of_birth,
+                is_primary as i32,
+                now,
+                now
+            ],
+        )?;
+        Ok(id)
+    }
+
+    pub fn get_members(&self) -> Result<Vec<Member>, rusqlite::Error> {
+        require(self.db, "household", Role::Viewer)?;
+        let conn = &self.db.conn;
+        let mut stmt = conn.prepare(
+            "SELECT id, name, relationship, date_of_birth, profile_photo_path, is_primary 
+             FROM members WHERE deleted_at IS NULL",
+        )?;
+
+        let iter = stmt.query_map([], |row| -> rusqlite::Result<Member> {
+            let is_primary_int: i32 = row.get(5)?;
+            Ok(Member {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                relationship: row.get(2)?,
+                date_of_birth: row.get(3)?,
+                profile_photo_path: row.get(4)?,
+                is_primary: is_primary_int != 0,
+            })
+        })?;
+
+        let mut members = Vec::new();
+        for member in iter {
+            members.push(member?);
+        }
+        Ok(members)
+    }
+
+    pub fn add_document(
+        &self,
+        member_id: Option<&str>,
+        name: &str,
+        document_type: &str,
+        document_number: Option<&str>,
+        expiry_date: Option<&str>,
+    ) -> Result<String, rusqlite::Error> {
+        require(self.db, "household", Role::Editor)?;
+        let conn = &self.db.conn;
+        let id = uuid::Uuid::new_v4().to_string();
+        let now = chrono::Utc::now().to_rfc3339();
+
+        conn.execute(
+            "INSERT INTO documents (id, member_id, name, document_type, document_number, expiry_date, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                id,
+                member_id,
+                name,
+                document_type,
+                document_number,
+                expiry_date,
+                now,
+                now
+            ],
+        )?;
+        self.schedule_expiry_reminder(&id)?;
+        Ok(id)
+    }
+
+    pub fn get_documents(&self) -> Result<Vec<Document>, rusqlite::Error> {
+        require(self.db, "household", Role::Viewer)?;
+        let conn = &self.db.conn;
+        let mut stmt = conn.prepare(
+            "SELECT id, member_id, name, document_type, document_number, issue_date, expiry_date, issuing_authority 
+             FROM documents WHERE deleted_at IS NULL",
+        )?;
+
+        let iter = stmt.query_map([], |row| -> rusqlite::Result<Document> {
+            Ok(Document {
+                id: row.get(0)?,
+                member_id: row.get(1)?,
+                name: row.get(2)?,
+                document_type: row.get(3)?,
+                document_number: row.get(4)?,
+                issue_date: row.get(5)?,
+                expiry_date: row.get(6)?,
+                issuing_authority: row.get(7)?,
+            })
+        })?;
+
+        let mut docs = Vec::new();
+        for doc in iter {
+            docs.push(doc?);
+        }
+        Ok(docs)
+    }
+
+    pub fn update_member(&self, member: &Member) -> Result<(), rusqlite::Error> {
+        require(self.db, "household", Role::Editor)?;
+        let conn = &self.db.conn;
+        let now = chrono::Utc::now().to_rfc3339();
+
+        let changed = conn.execute(
+            "UPDATE members SET name = ?1, relationship = ?2, date_of_birth = ?3, profile_photo_path = ?4, is_primary = ?5, updated_at = ?6
+             WHERE id = ?7 AND deleted_at IS NULL",
+            params![
+                member.name,
+                member.relationship,
+                member.date_of_birth,
+                member.profile_photo_path,
+                member.is_primary as i32,
+                now,
+                member.id
+            ],
+        )?;
+        expect_changed(changed)
+    }
+
+    pub fn delete_member(&self, id: &str) -> Result<(), rusqlite::Error> {
+        require(self.db, "household", Role::Editor)?;
+        self.db.soft_delete("members", id)
+    }
+
+    pub fn restore_member(&self, id: &str) -> Result<(), rusqlite::Error> {
+        require(self.db, "household", Role::Editor)?;
+        self.db.restore_deleted("members", id)
+    }
+
+    pub fn update_document(&self, doc: &Document) -> Result<(), rusqlite::Error> {
+        require(self.db, "household", Role::Editor)?;
+        let conn = &self.db.conn;
+        let now = chrono::Utc::now().to_rfc3339();
+
+        let changed = conn.execute(
+            "UPDATE documents SET member_id = ?1, name = ?2, document_type = ?3, document_number = ?4, issue_date = ?5, expiry_date = ?6, issuing_authority = ?7, updated_at = ?8
+             WHERE id = ?9 AND deleted_at IS NULL",
+            params![
+                doc.member_id,
+                doc.name,
+                doc.document_type,
+                doc.document_number,
+                doc.issue_date,
+                doc.expiry_date,
+                doc.issuing_authority,
+                now,
+                doc.id
+            ],
+        )?;
+        expect_changed(changed)?;
+        self.schedule_expiry_reminder(&doc.id)
+    }
+
+    pub fn delete_document(&self, id: &str) -> Result<(), rusqlite::Error> {
+        require(self.db, "household", Role::Editor)?;
+        self.db.soft_delete("documents", id)?;
+        notifications::cancel(&self.db.conn, "household", id, "document_expiry")
+    }
+
+    pub fn restore_document(&self, id: &str) -> Result<(), rusqlite::Error> {
+        require(self.db, "household", Role::Editor)?;
+        self.db.restore_deleted("documents", id)?;
+        self.schedule_expiry_reminder(id)
+    }
+
+    /// Expired documents and those expiring within 30, 60 or 90 days of
+    /// `today`, bucketed by urgency and grouped per member.
+    pub fn get_expiry_report(&self, today: NaiveDate) -> Result<ExpiryReport, rusqlite::Error> {
+        require(self.db, "household", Role::Viewer)?;
+        let conn = &self.db.conn;
+        let mut stmt = conn.prepare(
+            "SELECT d.id, d.name, d.document_type, d.member_id, m.name, d.expiry_date
+             FROM documents d
+             LEFT JOIN members m ON m.id = d.member_id AND m.deleted_at IS NULL
+             WHERE d.deleted_at IS NULL AND d.expiry_date IS NOT NULL
+             ORDER BY d.expiry_date",
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, String>(5)?,
+            ))
+        })?;
+
+        let mut report = ExpiryReport::default();
+        for row in rows {
+            let (document_id, name, document_type, member_id, member_name, expiry_date) = row?;
+            let Some(expires) = parse_date(&expiry_date) else {
+                continue;
+            };
+            let days_left = (expires - today).num_days();
+            let status = match days_left {
+                d if d < 0 => ExpiryStatus::Expired,
+                0..=30 => ExpiryStatus::Within30Days,
+                31..=60 => ExpiryStatus::Within60Days,
+                61..=90 => ExpiryStatus::Within90Days,
+                _ => continue,
+            };
+
+            let entry = DocumentExpiry {
+                document_id,
+                name,
+                document_type,
+                member_id,
+                member_name,
+                expiry_date,
+                days_left,
+                status,
+            };
+            match report
+                .by_member
+                .iter_mut()
+                .find(|m| m.member_id == entry.member_id)
+            {
+                Some(member) => member.documents.push(entry.clone()),
+                None => report.by_member.push(MemberExpiries {
+                    member_id: entry.member_id.clone(),
+                    member_name: entry.member_name.clone(),
+                    documents: vec![entry.clone()],
+                }),
+            }
+            match status {
+                ExpiryStatus::Expired => report.expired.push(entry),
+                ExpiryStatus::Within30Days => report.within_30_days.push(entry),
+                ExpiryStatus::Within60Days => report.within_60_days.push(entry),
+                ExpiryStatus::Within90Days => report.within_90_days.push(entry),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Reminder lead times in days, keyed by lowercase document type.
+    /// Passports default to 180 days since many countries refuse entry on
+    /// one with less than six months left; visas to 60.
+    pub fn get_expiry_lead_days(&self) -> Result<HashMap<String, i64>, rusqlite::Error> {
+        let settings = ModuleRegistry::new(self.db).get_settings("household")?;
+        let mut lead_days: HashMap<String, i64> =
+            HashMap::from([("passport".to_string(), 180), ("visa".to_string(), 60)]);
+        if let Some(saved) = settings
+            .get(LEAD_DAYS_KEY)
+            .and_then(|v| serde_json::from_value::<HashMap<String, i64>>(v.clone()).ok())
+        {
+            lead_days.extend(saved);
+        }
+        Ok(lead_days)
+    }
+
+    /// Sets how many days before expiry documents of `document_type` are
+    /// reminded about, and reschedules their reminders.
+    pub fn set_expiry_lead_days(
+        &self,
+        document_type: &str,
+        days: i64,
+    ) -> Result<(), rusqlite::Error> {
+        require(self.db, "household", Role::Editor)?;
+        let registry = ModuleRegistry::new(self.db);
+        let mut settings = registry.get_settings("household")?;
+        if !settings[LEAD_DAYS_KEY].is_object() {
+            settings[LEAD_DAYS_KEY] = serde_json::json!({});
+        }
+        settings[LEAD_DAYS_KEY][document_type.to_lowercase()] = days.max(0).into();
+        registry.save_settings("household", &settings)?;
+        self.sync_expiry_reminders().map(|_| ())
+    }
+
+    /// Schedules an expiry reminder for every live document with an expiry
+    /// date and cancels the rest. Returns the number scheduled.
+    pub fn sync_expiry_reminders(&self) -> Result<usize, rusqlite::Error> {
+        let conn = &self.db.conn;
+        conn.execute(
+            "DELETE FROM scheduled_notifications
+             WHERE module_id = 'household' AND notification_type = 'document_expiry' AND is_fired = 0
+               AND entity_id NOT IN (SELECT id FROM documents WHERE deleted_at IS NULL AND expiry_date IS NOT NULL)",
+            [],
+        )?;
+
+        let ids: Vec<String> = {
+            let mut stmt = conn.prepare(
+                "SELECT id FROM documents WHERE deleted_at IS NULL AND expiry_date IS NOT NULL",
+            )?;
+            let ids = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            ids
+        };
+        for id in &ids {
+            self.schedule_expiry_reminder(id)?;
+        }
+        Ok(ids.len())
+    }
+
+    /// Replaces the document's pending expiry reminder according to the lead
+    /// time for its type, or cancels it if the document has no expiry date.
+    fn schedule_expiry_reminder(&self, id: &str) -> Result<(), rusqlite::Error> {
+        let conn = &self.db.conn;
+        let document: Option<(String, String, Option<String>, Option<String>)> = conn
+            .query_row(
+                "SELECT d.name, d.document_type, d.expiry_date, m.name
+                 FROM documents d LEFT JOIN members m ON m.id = d.member_id
+                 WHERE d.id = ?1 AND d.deleted_at IS NULL",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()?;
+
+        let Some((name, document_type, Some(expiry_date), member_name)) = document else {
+            return notifications::cancel(conn, "household", id, "document_expiry");
+        };
+        let Some(expires) = parse_date(&expiry_date) else {
+            return notifications::cancel(conn, "household", id, "document_expiry");
+        };
+
+        let lead_days = self
+            .get_expiry_lead_days()?
+            .get(&document_type.to_lowercase())
+            .copied()
+            .unwrap_or(DEFAULT_LEAD_DAYS);
+        let remind_on = expires
+            .checked_sub_days(Days::new(lead_days.max(0) as u64))
+            .unwrap_or(expires);
+        let owner = member_name.map(|m| format!("{}'s ", m)).unwrap_or_default();
+
+        notifications::schedule(
+            conn,
+            "household",
+            id,
+            "document_expiry",
+            &format!("Renew {}{}", owner, name),
+            &format!("{}{} expires on {}", owner, name, expires),
+            remind_on.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+        )?;
+        Ok(())
+    }
+
+    /// Permanently removes members and documents deleted more than
+    /// `older_than_days` ago, along with the vault files of the documents.
+    /// Records that pointed at a purged member keep existing but are no
+    /// longer attached to anyone.
+    pub fn purge_deleted(&self, older_than_days: i64) -> Result<usize, rusqlite::Error> {
+        require(self.db, "household", Role::Editor)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+        let purged = trash::purge_older_than(
+            &tx,
+            &[TrashKind::Document, TrashKind::Member],
+            older_than_days,
+        )?;
+        tx.commit()?;
+        trash::remove_orphaned_files(self.db);
+        Ok(purged)
+    }
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    value
+        .get(..10)
+        .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_household_service() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = HouseholdService::new(&db);
+
+        // Add a member
+        let member_id = service
+            .add_member("Bob", "Spouse", Some("1980-05-10"), true)
+            .unwrap();
+
+        let members = service.get_members().unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].name, "Bob");
+
+        // Add a document
+        service
+            .add_document(
+                Some(&member_id),
+                "Passport",
+                "ID",
+                Some("A123"),
+                Some("2030-01-01"),
+            )
+            .unwrap();
+
+        let docs = service.get_documents().unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].name, "Passport");
+    }
+
+    #[test]
+    fn test_household_update_delete_restore() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = HouseholdService::new(&db);
+
+        let member_id = service.add_member("Bob", "Spouse", None, false).unwrap();
+        let doc_id = service
+            .add_document(Some(&member_id), "Licence", "ID", Some("DL-1"), None)
+            .unwrap();
+
+        let mut member = service.get_members().unwrap().remove(0);
+        member.is_primary = true;
+        service.update_member(&member).unwrap();
+        assert!(service.get_members().unwrap()[0].is_primary);
+
+        let mut doc = service.get_documents().unwrap().remove(0);
+        doc.document_number = Some("DL-2".to_string());
+        doc.issuing_authority = Some("RTO".to_string());
+        service.update_document(&doc).unwrap();
+        let docs = service.get_documents().unwrap();
+        assert_eq!(docs[0].document_number.as_deref(), Some("DL-2"));
+        assert_eq!(docs[0].issuing_authority.as_deref(), Some("RTO"));
+
+        service.delete_document(&doc_id).unwrap();
+        assert!(service.get_documents().unwrap().is_empty());
+        service.restore_document(&doc_id).unwrap();
+
+        service.delete_member(&member_id).unwrap();
+        assert!(service.get_members().unwrap().is_empty());
+        assert_eq!(service.purge_deleted(0).unwrap(), 1);
+
+        // The document survives its member but is detached
+        let docs = service.get_documents().unwrap();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs[0].member_id, None);
+        assert!(service.restore_member(&member_id).is_err());
+    }
+
+    #[test]
+    fn test_document_expiry_report_and_reminders() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = HouseholdService::new(&db);
+        let today = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+
+        let asha = service.add_member("Asha", "Self", None, true).unwrap();
+        let passport = service
+            .add_document(
+                Some(&asha),
+                "Passport",
+                "Passport",
+                None,
+                Some("2024-07-15"),
+            )
+            .unwrap();
+        service
+            .add_document(Some(&asha), "Licence", "licence", None, Some("2024-05-01"))
+            .unwrap();
+        service
+            .add_document(
+                None,
+                "Home insurance",
+                "insurance",
+                None,
+                Some("2024-08-20"),
+            )
+            .unwrap();
+        service
+            .add_document(None, "Aadhaar", "id", None, Some("2034-01-01"))
+            .unwrap();
+        service
+            .add_document(None, "Birth certificate", "id", None, None)
+            .unwrap();
+
+        let report = service.get_expiry_report(today).unwrap();
+        assert_eq!(report.expired[0].name, "Licence");
+        assert_eq!(report.expired[0].days_left, -31);
+        assert!(report.within_30_days.is_empty());
+        assert_eq!(report.within_60_days[0].name, "Passport");
+        assert_eq!(report.within_90_days[0].name, "Home insurance");
+        assert_eq!(report.by_member.len(), 2);
+        assert_eq!(report.by_member[0].member_name.as_deref(), Some("Asha"));
+        assert_eq!(report.by_member[0].documents.len(), 2);
+
+        let reminder_for = |id: &str| -> String {
+            db.conn
+                .query_row(
+                    "SELECT scheduled_for FROM scheduled_notifications WHERE entity_id = ?1 AND is_fired = 0",
+                    params![id],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        // Passports default to six months' notice
+        assert!(reminder_for(&passport).starts_with("2024-01-17"));
+
+        service.set_expiry_lead_days("passport", 90).unwrap();
+        assert!(reminder_for(&passport).starts_with("2024-04-16"));
+
+        service.delete_document(&passport).unwrap();
+        let pending: i64 = db
+            .conn
+            .query_row(
+                "SELECT count(*) FROM scheduled_notifications WHERE notification_type = 'document_expiry'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(pending, 3);
+    }
+}
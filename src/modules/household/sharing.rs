@@ -0,0 +1,519 @@
+//! Household sharing: who may see and change what.
+//!
+//! A member's `permission_json` links them to the sync device ids they use
+//! and holds their role in each module. Until someone invites another member
+//! nobody is linked and the single user may do everything; once the
+//! household is shared, a device can only do what its member's roles allow.
+//! The same roles decide which of another device's synced changes are
+//! merged (see [`Permissions`]), taking the device a changeset names at its
+//! word; roles guard against members' mistakes, not against someone who can
+//! write to the sync folder. Invite codes are only stored hashed, so
+//! reading the synced members table does not reveal them.
+
+use super::HouseholdService;
+use crate::db::{expect_changed, Db};
+use crate::modules::sync::SyncService;
+use chrono::{Duration, Utc};
+use rusqlite::{params, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Modules roles are granted for.
+pub const SHARED_MODULES: &[&str] = &[
+    "finance",
+    "grocery",
+    "travel",
+    "dining",
+    "gifts",
+    "maintenance",
+    "household",
+];
+
+/// How long an invite code can be accepted.
+pub const INVITE_VALID_DAYS: i64 = 7;
+
+/// What a member may do in one module, weakest first.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    None,
+    Viewer,
+    Editor,
+    /// Can also manage other members' access (in "household").
+    Owner,
+}
+
+/// A member's sharing state, stored as `members.permission_json`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct MemberAccess {
+    pub status: String,
+    pub email: Option<String>,
+    /// Sync device ids that act as this member.
+    #[serde(default)]
+    pub devices: Vec<String>,
+    /// Modules missing from the map are `Role::None`.
+    #[serde(default)]
+    pub roles: BTreeMap<String, Role>,
+    /// SHA-256 of the open invite's code.
+    pub invite_code_hash: Option<String>,
+    pub invite_expires_at: Option<String>,
+}
+
+/// Roles offered when inviting someone: everyday modules can be edited,
+/// household documents only viewed.
+pub fn default_roles() -> BTreeMap<String, Role> {
+    SHARED_MODULES
+        .iter()
+        .map(|&module| {
+            let role = if module == "household" {
+                Role::Viewer
+            } else {
+                Role::Editor
+            };
+            (module.to_string(), role)
+        })
+        .collect()
+}
+
+impl MemberAccess {
+    pub fn role(&self, module_id: &str) -> Role {
+        self.roles.get(module_id).copied().unwrap_or(Role::None)
+    }
+}
+
+/// Fails with `SQLITE_AUTH` unless this device's member has at least
+/// `needed` in `module_id`.
+pub fn require(db: &Db, module_id: &str, needed: Role) -> Result<()> {
+    let role = current_role(db, module_id)?;
+    if role >= needed {
+        return Ok(());
+    }
+    Err(rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_AUTH),
+        Some(format!(
+            "{} access to {} is required, this device has {}",
+            role_name(needed),
+            module_id,
+            role_name(role)
+        )),
+    ))
+}
+
+/// This device's role in `module_id`; `Role::Owner` while the household is
+/// not shared.
+pub fn current_role(db: &Db, module_id: &str) -> Result<Role> {
+    let device_id = SyncService::new(db).device_id()?;
+    Ok(Permissions::load(db)?.role(&device_id, module_id))
+}
+
+/// Every member's access as it stood when loaded, e.g. before another
+/// device's changes are merged, so a changeset cannot grant the access it
+/// is checked against.
+pub struct Permissions {
+    accesses: Vec<(String, MemberAccess)>,
+}
+
+impl Permissions {
+    pub fn load(db: &Db) -> Result<Self> {
+        Ok(Self {
+            accesses: member_accesses(db)?,
+        })
+    }
+
+    /// Role of the member `device_id` acts as; `Role::Owner` while the
+    /// household is not shared.
+    pub fn role(&self, device_id: &str, module_id: &str) -> Role {
+        if self
+            .accesses
+            .iter()
+            .all(|(_, access)| access.devices.is_empty())
+        {
+            return Role::Owner;
+        }
+        self.accesses
+            .iter()
+            .find(|(_, access)| access.devices.iter().any(|d| d == device_id))
+            .map_or(Role::None, |(_, access)| access.role(module_id))
+    }
+
+    /// Whether `device_id` may set member `member_id`'s access to `access`
+    /// (`None` takes it away). Household owners manage everyone's access;
+    /// any other device may only accept an open invite for itself, the way
+    /// [`HouseholdService::accept_invite`] does.
+    pub fn may_set_access(
+        &self,
+        device_id: &str,
+        member_id: &str,
+        access: Option<&MemberAccess>,
+    ) -> bool {
+        let current = self
+            .accesses
+            .iter()
+            .find(|(id, _)| id == member_id)
+            .map(|(_, access)| access);
+        if current == access || self.role(device_id, "household") == Role::Owner {
+            return true;
+        }
+        let (Some(invited), Some(accepted)) = (current, access) else {
+            return false;
+        };
+        let now = Utc::now().to_rfc3339();
+        let open = invited.invite_code_hash.is_some()
+            && invited.invite_expires_at.as_deref() > Some(now.as_str())
+            && invited.status != "revoked";
+        let already_member = self
+            .accesses
+            .iter()
+            .any(|(_, access)| access.devices.iter().any(|d| d == device_id));
+
+        let mut expected = invited.clone();
+        expected.status = "active".to_string();
+        expected.devices.push(device_id.to_string());
+        expected.invite_code_hash = None;
+        expected.invite_expires_at = None;
+        open && !already_member && *accepted == expected
+    }
+}
+
+fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::None => "no",
+        Role::Viewer => "viewer",
+        Role::Editor => "editor",
+        Role::Owner => "owner",
+    }
+}
+
+/// Access of every current member that has some, keyed by member id.
+fn member_accesses(db: &Db) -> Result<Vec<(String, MemberAccess)>> {
+    let mut stmt = db.conn.prepare(
+        "SELECT id, permission_json FROM members
+         WHERE deleted_at IS NULL AND permission_json IS NOT NULL
+         ORDER BY created_at, id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    let mut accesses = Vec::new();
+    for row in rows {
+        let (id, json) = row?;
+        accesses.push((id, parse_access(&json)?));
+    }
+    Ok(accesses)
+}
+
+pub(crate) fn parse_access(json: &str) -> Result<MemberAccess> {
+    serde_json::from_str(json).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+/// Invite codes are compared without regard to case or surrounding spaces.
+fn hash_invite_code(code: &str) -> String {
+    format!("{:x}", Sha256::digest(code.trim().to_uppercase()))
+}
+
+fn invalid_input(message: String) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_MISUSE),
+        Some(message),
+    )
+}
+
+impl<'a> HouseholdService<'a> {
+    pub fn get_member_access(&self, member_id: &str) -> Result<Option<MemberAccess>> {
+        let json: Option<String> = self.db.conn.query_row(
+            "SELECT permission_json FROM members WHERE id = ?1 AND deleted_at IS NULL",
+            [member_id],
+            |row| row.get(0),
+        )?;
+        json.as_deref().map(parse_access).transpose()
+    }
+
+    fn save_member_access(&self, member_id: &str, access: &MemberAccess) -> Result<()> {
+        let changed = self.db.conn.execute(
+            "UPDATE members SET permission_json = ?1, updated_at = ?2
+             WHERE id = ?3 AND deleted_at IS NULL",
+            params![
+                serde_json::to_string(access).unwrap(),
+                Utc::now().to_rfc3339(),
+                member_id
+            ],
+        )?;
+        expect_changed(changed)
+    }
+
+    /// The member this device acts as, if it has been linked to one.
+    pub fn current_member_id(&self) -> Result<Option<String>> {
+        let device_id = SyncService::new(self.db).device_id()?;
+        Ok(member_accesses(self.db)?
+            .into_iter()
+            .find(|(_, access)| access.devices.contains(&device_id))
+            .map(|(id, _)| id))
+    }
+
+    pub fn find_member_by_email(&self, email: &str) -> Result<Option<String>> {
+        Ok(member_accesses(self.db)?
+            .into_iter()
+            .find(|(_, access)| {
+                access
+                    .email
+                    .as_deref()
+                    .is_some_and(|e| e.eq_ignore_ascii_case(email.trim()))
+            })
+            .map(|(id, _)| id))
+    }
+
+    /// Invites `member_id` with the given roles and returns the code they
+    /// accept on their device. Re-inviting an active member lets them add
+    /// another device.
+    ///
+    /// The first invite shares the household: this device becomes the
+    /// primary member's, with owner access to every module.
+    pub fn invite_member(
+        &self,
+        member_id: &str,
+        email: Option<&str>,
+        roles: &BTreeMap<String, Role>,
+    ) -> Result<String> {
+        if member_accesses(self.db)?
+            .iter()
+            .all(|(_, access)| access.devices.is_empty())
+        {
+            self.claim_household()?;
+        }
+        require(self.db, "household", Role::Owner)?;
+
+        let mut access = self.get_member_access(member_id)?.unwrap_or_default();
+        if access.status != "active" {
+            access.status = "invited".to_string();
+            access.roles = roles.clone();
+        }
+        if let Some(email) = email {
+            access.email = Some(email.to_string());
+        }
+        let code = uuid::Uuid::new_v4().simple().to_string()[..12].to_uppercase();
+        access.invite_code_hash = Some(hash_invite_code(&code));
+        access.invite_expires_at =
+            Some((Utc::now() + Duration::days(INVITE_VALID_DAYS)).to_rfc3339());
+        self.save_member_access(member_id, &access)?;
+        Ok(code)
+    }
+
+    /// Links this device to the primary member as the household's owner.
+    fn claim_household(&self) -> Result<()> {
+        let primary: String = self
+            .db
+            .conn
+            .query_row(
+                "SELECT id FROM members WHERE is_primary = 1 AND deleted_at IS NULL
+                 ORDER BY created_at LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| {
+                invalid_input("mark a member as primary before inviting others".to_string())
+            })?;
+        let mut access = self.get_member_access(&primary)?.unwrap_or_default();
+        access.status = "active".to_string();
+        access.devices = vec![SyncService::new(self.db).device_id()?];
+        access.roles = SHARED_MODULES
+            .iter()
+            .map(|module| (module.to_string(), Role::Owner))
+            .collect();
+        self.save_member_access(&primary, &access)
+    }
+
+    /// Links this device to the member an invite code was issued for and
+    /// returns that member's id. Needs the inviting device's data, so sync
+    /// once before accepting.
+    pub fn accept_invite(&self, code: &str) -> Result<String> {
+        let device_id = SyncService::new(self.db).device_id()?;
+        let now = Utc::now().to_rfc3339();
+        let accesses = member_accesses(self.db)?;
+        if let Some((id, _)) = accesses
+            .iter()
+            .find(|(_, access)| access.devices.contains(&device_id))
+        {
+            return Err(invalid_input(format!(
+                "this device already belongs to member {}",
+                id
+            )));
+        }
+
+        let code_hash = hash_invite_code(code);
+        let (member_id, mut access) = accesses
+            .into_iter()
+            .find(|(_, access)| {
+                access.invite_code_hash.as_deref() == Some(code_hash.as_str())
+                    && access.invite_expires_at.as_deref() > Some(now.as_str())
+                    && access.status != "revoked"
+            })
+            .ok_or_else(|| invalid_input("invalid or expired invite code".to_string()))?;
+
+        access.status = "active".to_string();
+        access.devices.push(device_id);
+        access.invite_code_hash = None;
+        access.invite_expires_at = None;
+        self.save_member_access(&member_id, &access)?;
+        Ok(member_id)
+    }
+
+    /// Replaces a member's roles. Some active member must remain owner of
+    /// the household.
+    pub fn set_member_roles(&self, member_id: &str, roles: &BTreeMap<String, Role>) -> Result<()> {
+        require(self.db, "household", Role::Owner)?;
+        let mut access = self
+            .get_member_access(member_id)?
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+        access.roles = roles.clone();
+        self.ensure_owner_remains(member_id, &access)?;
+        self.save_member_access(member_id, &access)
+    }
+
+    /// Takes away a member's access on all their devices and cancels any
+    /// open invite. Returns their email so cloud sharing can be withdrawn
+    /// too.
+    pub fn revoke_member(&self, member_id: &str) -> Result<Option<String>> {
+        require(self.db, "household", Role::Owner)?;
+        if self.current_member_id()?.as_deref() == Some(member_id) {
+            return Err(invalid_input(
+                "a device cannot revoke its own member".to_string(),
+            ));
+        }
+        let mut access = self
+            .get_member_access(member_id)?
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)?;
+        access.status = "revoked".to_string();
+        access.devices.clear();
+        access.roles.clear();
+        access.invite_code_hash = None;
+        access.invite_expires_at = None;
+        self.ensure_owner_remains(member_id, &access)?;
+        self.save_member_access(member_id, &access)?;
+        Ok(access.email)
+    }
+
+    fn ensure_owner_remains(&self, member_id: &str, changed: &MemberAccess) -> Result<()> {
+        let owner_left = member_accesses(self.db)?.iter().any(|(id, access)| {
+            let access = if id == member_id { changed } else { access };
+            access.status == "active" && access.role("household") == Role::Owner
+        });
+        if owner_left {
+            Ok(())
+        } else {
+            Err(invalid_input(
+                "the household needs at least one owner".to_string(),
+            ))
+        }
+    }
+
+    /// Hands device `from`'s memberships over to device `to`, e.g. when a
+    /// member replaces their phone. Only household owners may.
+    pub fn move_device(&self, from: &str, to: &str) -> Result<()> {
+        require(self.db, "household", Role::Owner)?;
+        self.keep_device_access(from, to)
+    }
+
+    /// [`Self::move_device`] without the role check, for a restore handing
+    /// the access this device had before over to the id it was just given.
+    pub(crate) fn keep_device_access(&self, from: &str, to: &str) -> Result<()> {
+        for (member_id, mut access) in member_accesses(self.db)? {
+            if let Some(device) = access.devices.iter_mut().find(|d| *d == from) {
+                *device = to.to_string();
+                self.save_member_access(&member_id, &access)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::finance::FinanceService;
+
+    #[test]
+    fn test_invite_accept_and_revoke() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let household = HouseholdService::new(&db);
+        let finance = FinanceService::new(&db);
+        let sync = SyncService::new(&db);
+
+        // Nothing is shared yet, so everything is allowed
+        let account = finance.create_account("Joint", "checking", 0).unwrap();
+        let me = household.add_member("Asha", "self", None, true).unwrap();
+        let partner = household.add_member("Ravi", "spouse", None, false).unwrap();
+
+        let roles = BTreeMap::from([
+            ("finance".to_string(), Role::Viewer),
+            ("grocery".to_string(), Role::Editor),
+        ]);
+        let code = household
+            .invite_member(&partner, Some("ravi@example.com"), &roles)
+            .unwrap();
+        assert_eq!(household.current_member_id().unwrap(), Some(me.clone()));
+        assert_eq!(
+            household
+                .get_member_access(&partner)
+                .unwrap()
+                .unwrap()
+                .status,
+            "invited"
+        );
+
+        // The partner's device syncs the database and accepts
+        let owner_device = sync.device_id().unwrap();
+        let partner_device = sync.reset_device_id().unwrap();
+        assert_eq!(current_role(&db, "finance").unwrap(), Role::None);
+        assert!(household.accept_invite("not-a-code").is_err());
+        assert_eq!(
+            household.accept_invite(&code.to_lowercase()).unwrap(),
+            partner
+        );
+        assert!(household.accept_invite(&code).is_err());
+
+        // A viewer can read but not write
+        assert_eq!(finance.get_accounts().unwrap().len(), 1);
+        let denied = finance
+            .create_transaction(&account, -500, "Cafe", "2024-03-01", None)
+            .unwrap_err();
+        assert!(denied.to_string().contains("editor access to finance"));
+        assert!(finance.purge_deleted(0).is_err());
+        assert!(household.revoke_member(&me).is_err());
+        assert!(household.move_device(&owner_device, &partner_device).is_err());
+
+        // Back on the owner's device
+        db.conn
+            .execute(
+                "UPDATE sync_device SET device_id = ?1",
+                [owner_device.as_str()],
+            )
+            .unwrap();
+        let mut roles = roles;
+        roles.insert("finance".to_string(), Role::Editor);
+        household.set_member_roles(&partner, &roles).unwrap();
+        assert!(household.revoke_member(&me).is_err());
+        assert_eq!(
+            household.revoke_member(&partner).unwrap().as_deref(),
+            Some("ravi@example.com")
+        );
+
+        household.move_device(&owner_device, "new-phone").unwrap();
+        db.conn
+            .execute("UPDATE sync_device SET device_id = 'new-phone'", [])
+            .unwrap();
+        assert_eq!(current_role(&db, "finance").unwrap(), Role::Owner);
+
+        db.conn
+            .execute(
+                "UPDATE sync_device SET device_id = ?1",
+                [partner_device.as_str()],
+            )
+            .unwrap();
+        assert_eq!(current_role(&db, "grocery").unwrap(), Role::None);
+    }
+}
@@ -0,0 +1,447 @@
+//! Encrypted storage for scanned documents.
+//!
+//! Every attached file is sealed with its own random AES-256-GCM key. That
+//! key is wrapped with a master key derived from the vault passphrase and
+//! stored in `documents.file_encrypted_key`, so rotating the passphrase only
+//! rewraps keys and never touches the files themselves. Files live in a
+//! directory next to the database (see [`DocumentVault::default_dir`]) under
+//! random names; the original file name is part of the sealed payload.
+
+use super::sharing::{require, Role};
+use crate::db::Db;
+use crate::modules::registry::ModuleRegistry;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::Argon2;
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension};
+use serde_json::json;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Key under the household module's `settings_json` holding the master key
+/// salt and the passphrase check value.
+const VAULT_KEY: &str = "vault";
+const FILE_MAGIC: &[u8; 4] = b"MHV1";
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+const CHECK_AAD: &[u8] = b"myhome-vault-check";
+
+/// A decrypted document file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VaultFile {
+    pub file_name: String,
+    pub contents: Vec<u8>,
+}
+
+pub struct DocumentVault<'a> {
+    db: &'a Db,
+    dir: PathBuf,
+    master_key: Key<Aes256Gcm>,
+}
+
+impl<'a> DocumentVault<'a> {
+    /// Vault directory used for a database at `db_path`: the same path with
+    /// a `.vault` extension.
+    pub fn default_dir(db_path: &Path) -> PathBuf {
+        db_path.with_extension("vault")
+    }
+
+    /// Opens the vault, setting it up with `passphrase` on first use.
+    /// Fails if the passphrase does not match the one the vault was set up
+    /// with.
+    pub fn open(db: &'a Db, dir: impl Into<PathBuf>, passphrase: &str) -> Result<Self> {
+        let registry = ModuleRegistry::new(db);
+        let mut settings = registry.get_settings("household")?;
+
+        let master_key = match settings.get(VAULT_KEY) {
+            Some(vault) => {
+                let salt = hex_field(vault, "salt")?;
+                let check = hex_field(vault, "check")?;
+                let key = derive_key(passphrase, &salt)?;
+                open_sealed(&key, &check, CHECK_AAD)
+                    .map_err(|_| anyhow!("Incorrect vault passphrase"))?;
+                key
+            }
+            None => {
+                let (key, vault) = new_master_key(passphrase)?;
+                settings[VAULT_KEY] = vault;
+                registry.save_settings("household", &settings)?;
+                key
+            }
+        };
+
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create vault directory {}", dir.display()))?;
+        Ok(Self {
+            db,
+            dir,
+            master_key,
+        })
+    }
+
+    /// Encrypts the file at `source` and attaches it to a document,
+    /// replacing any file attached before.
+    pub fn attach_file(&self, document_id: &str, source: &Path) -> Result<()> {
+        let contents =
+            fs::read(source).with_context(|| format!("Failed to read {}", source.display()))?;
+        let file_name = source
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "document".to_string());
+        self.attach_bytes(document_id, &file_name, &contents)
+    }
+
+    pub fn attach_bytes(&self, document_id: &str, file_name: &str, contents: &[u8]) -> Result<()> {
+        require(self.db, "household", Role::Editor)?;
+        let previous: Option<String> = self
+            .db
+            .conn
+            .query_row(
+                "SELECT file_path FROM documents WHERE id = ?1 AND deleted_at IS NULL",
+                params![document_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| anyhow!("Document {} not found", document_id))?;
+
+        let name = file_name.as_bytes();
+        let name_len = u16::try_from(name.len()).context("File name is too long")?;
+        let mut plaintext = Vec::with_capacity(2 + name.len() + contents.len());
+        plaintext.extend_from_slice(&name_len.to_be_bytes());
+        plaintext.extend_from_slice(name);
+        plaintext.extend_from_slice(contents);
+
+        let file_key = Aes256Gcm::generate_key(OsRng);
+        let mut sealed = FILE_MAGIC.to_vec();
+        sealed.extend(seal(&file_key, &plaintext, document_id.as_bytes())?);
+        let wrapped_key = seal(&self.master_key, &file_key, &key_aad(document_id))?;
+
+        let relative = format!("{}.enc", Uuid::new_v4());
+        let path = self.dir.join(&relative);
+        fs::write(&path, &sealed).with_context(|| format!("Failed to write {}", path.display()))?;
+
+        let updated = self.db.conn.execute(
+            "UPDATE documents SET file_path = ?1, file_encrypted_key = ?2, updated_at = ?3
+             WHERE id = ?4",
+            params![relative, wrapped_key, Utc::now().to_rfc3339(), document_id],
+        );
+        if let Err(e) = updated {
+            let _ = fs::remove_file(&path);
+            return Err(e.into());
+        }
+        if let Some(previous) = previous {
+            let _ = fs::remove_file(self.dir.join(previous));
+        }
+        Ok(())
+    }
+
+    /// Decrypts the file attached to a document.
+    pub fn read_file(&self, document_id: &str) -> Result<VaultFile> {
+        require(self.db, "household", Role::Viewer)?;
+        let (relative, wrapped_key) = self.attachment(document_id)?;
+        let file_key = self.unwrap_key(document_id, &wrapped_key)?;
+
+        let path = self.dir.join(&relative);
+        let sealed =
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let body = sealed
+            .strip_prefix(FILE_MAGIC.as_slice())
+            .ok_or_else(|| anyhow!("{} is not a vault file", path.display()))?;
+        let plaintext = open_sealed(&file_key, body, document_id.as_bytes())
+            .with_context(|| format!("Failed to decrypt {}", path.display()))?;
+
+        if plaintext.len() < 2 {
+            bail!("{} is truncated", path.display());
+        }
+        let name_len = u16::from_be_bytes([plaintext[0], plaintext[1]]) as usize;
+        if plaintext.len() < 2 + name_len {
+            bail!("{} is truncated", path.display());
+        }
+        Ok(VaultFile {
+            file_name: String::from_utf8_lossy(&plaintext[2..2 + name_len]).into_owned(),
+            contents: plaintext[2 + name_len..].to_vec(),
+        })
+    }
+
+    /// Detaches and deletes the file attached to a document.
+    pub fn remove_file(&self, document_id: &str) -> Result<()> {
+        require(self.db, "household", Role::Editor)?;
+        let (relative, _) = self.attachment(document_id)?;
+        self.db.conn.execute(
+            "UPDATE documents SET file_path = NULL, file_encrypted_key = NULL, updated_at = ?1
+             WHERE id = ?2",
+            params![Utc::now().to_rfc3339(), document_id],
+        )?;
+        let _ = fs::remove_file(self.dir.join(relative));
+        Ok(())
+    }
+
+    /// Switches the vault to a new passphrase by rewrapping every file key,
+    /// including those of documents in the trash. Returns the number of
+    /// keys rewrapped. Either every key moves to the new master key or none
+    /// does.
+    pub fn rotate_master_key(&mut self, new_passphrase: &str) -> Result<usize> {
+        require(self.db, "household", Role::Owner)?;
+        let (new_key, vault) = new_master_key(new_passphrase)?;
+
+        let conn = &self.db.conn;
+        let tx = conn.unchecked_transaction()?;
+        let wrapped: Vec<(String, Vec<u8>)> = {
+            let mut stmt = tx.prepare(
+                "SELECT id, file_encrypted_key FROM documents WHERE file_encrypted_key IS NOT NULL",
+            )?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<Result<_, _>>()?
+        };
+        for (id, wrapped_key) in &wrapped {
+            let file_key = self.unwrap_key(id, wrapped_key)?;
+            let rewrapped = seal(&new_key, &file_key, &key_aad(id))?;
+            tx.execute(
+                "UPDATE documents SET file_encrypted_key = ?1 WHERE id = ?2",
+                params![rewrapped, id],
+            )?;
+        }
+
+        let registry = ModuleRegistry::new(self.db);
+        let mut settings = registry.get_settings("household")?;
+        settings[VAULT_KEY] = vault;
+        registry.save_settings("household", &settings)?;
+        tx.commit()?;
+
+        self.master_key = new_key;
+        Ok(wrapped.len())
+    }
+
+    /// Deletes files in `dir` that no document points at any more, e.g.
+    /// after the trash has been purged. Files of documents still in the
+    /// trash are kept so a restore brings them back. Returns the number of
+    /// files removed.
+    pub fn remove_orphans(db: &Db, dir: &Path) -> Result<usize> {
+        if !dir.is_dir() {
+            return Ok(0);
+        }
+        let referenced: HashSet<String> = {
+            let mut stmt = db
+                .conn
+                .prepare("SELECT file_path FROM documents WHERE file_path IS NOT NULL")?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<Result<_, _>>()?
+        };
+
+        let mut removed = 0;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".enc") && !referenced.contains(&name) {
+                fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Copies the encrypted files from one vault directory to another, e.g.
+    /// alongside a database backup or back again on restore. Files stay
+    /// encrypted, so no passphrase is needed. Returns the number of files
+    /// copied.
+    pub fn copy_files(from: &Path, to: &Path) -> Result<usize> {
+        let files = Self::stored_files(from)?;
+        if files.is_empty() {
+            return Ok(0);
+        }
+        fs::create_dir_all(to)
+            .with_context(|| format!("Failed to create vault directory {}", to.display()))?;
+        for file in &files {
+            if let Some(name) = file.file_name() {
+                fs::copy(file, to.join(name))?;
+            }
+        }
+        Ok(files.len())
+    }
+
+    /// The encrypted files in vault directory `dir`. Their names are random
+    /// and never reused, so a name always means the same contents.
+    pub fn stored_files(dir: &Path) -> Result<Vec<PathBuf>> {
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "enc") {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    fn attachment(&self, document_id: &str) -> Result<(String, Vec<u8>)> {
+        let row: Option<(Option<String>, Option<Vec<u8>>)> = self
+            .db
+            .conn
+            .query_row(
+                "SELECT file_path, file_encrypted_key FROM documents WHERE id = ?1",
+                params![document_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        match row {
+            None => bail!("Document {} not found", document_id),
+            Some((Some(path), Some(key))) => Ok((path, key)),
+            Some(_) => bail!("Document {} has no attached file", document_id),
+        }
+    }
+
+    fn unwrap_key(&self, document_id: &str, wrapped_key: &[u8]) -> Result<Key<Aes256Gcm>> {
+        let file_key = open_sealed(&self.master_key, wrapped_key, &key_aad(document_id))
+            .with_context(|| format!("Failed to unwrap the file key of {}", document_id))?;
+        if file_key.len() != 32 {
+            bail!("File key of {} has the wrong length", document_id);
+        }
+        Ok(*Key::<Aes256Gcm>::from_slice(&file_key))
+    }
+}
+
+fn key_aad(document_id: &str) -> Vec<u8> {
+    format!("file-key:{}", document_id).into_bytes()
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key<Aes256Gcm>> {
+    let mut key = Key::<Aes256Gcm>::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Failed to derive the vault key: {}", e))?;
+    Ok(key)
+}
+
+/// Derives a master key from a fresh salt, returning it together with the
+/// settings entry needed to check the passphrase later.
+fn new_master_key(passphrase: &str) -> Result<(Key<Aes256Gcm>, serde_json::Value)> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_key(passphrase, &salt)?;
+    let check = seal(&key, CHECK_AAD, CHECK_AAD)?;
+    Ok((
+        key,
+        json!({ "salt": to_hex(&salt), "check": to_hex(&check) }),
+    ))
+}
+
+/// Encrypts `plaintext` under a fresh nonce and returns `nonce || ciphertext`.
+fn seal(key: &Key<Aes256Gcm>, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let nonce = Aes256Gcm::generate_nonce(OsRng);
+    let ciphertext = Aes256Gcm::new(key)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow!("Encryption failed"))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+fn open_sealed(key: &Key<Aes256Gcm>, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        bail!("Encrypted data is truncated");
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    Aes256Gcm::new(key)
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow!("Decryption failed"))
+}
+
+fn hex_field(value: &serde_json::Value, field: &str) -> Result<Vec<u8>> {
+    let text = value
+        .get(field)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow!("Vault settings are missing {}", field))?;
+    from_hex(text).ok_or_else(|| anyhow!("Vault settings have an invalid {}", field))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::household::HouseholdService;
+
+    #[test]
+    fn test_vault_attach_read_rotate() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let household = HouseholdService::new(&db);
+        let doc_id = household
+            .add_document(None, "Passport", "Passport", None, None)
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("myhome_vault_{}", Uuid::new_v4()));
+
+        let mut vault = DocumentVault::open(&db, &dir, "open sesame").unwrap();
+        vault
+            .attach_bytes(&doc_id, "passport.pdf", b"%PDF secret scan")
+            .unwrap();
+
+        // Nothing readable ends up on disk.
+        let stored: String = db
+            .conn
+            .query_row(
+                "SELECT file_path FROM documents WHERE id = ?1",
+                params![doc_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let on_disk = fs::read(dir.join(&stored)).unwrap();
+        assert!(!on_disk.windows(6).any(|w| w == b"secret"));
+
+        let file = vault.read_file(&doc_id).unwrap();
+        assert_eq!(file.file_name, "passport.pdf");
+        assert_eq!(file.contents, b"%PDF secret scan");
+
+        assert!(DocumentVault::open(&db, &dir, "wrong").is_err());
+
+        assert_eq!(vault.rotate_master_key("new sesame").unwrap(), 1);
+        assert!(DocumentVault::open(&db, &dir, "open sesame").is_err());
+        let reopened = DocumentVault::open(&db, &dir, "new sesame").unwrap();
+        assert_eq!(
+            reopened.read_file(&doc_id).unwrap().contents,
+            b"%PDF secret scan"
+        );
+
+        // Backups carry the encrypted files; orphans are cleaned up.
+        let backup = dir.with_extension("backup");
+        assert_eq!(DocumentVault::copy_files(&dir, &backup).unwrap(), 1);
+        fs::write(dir.join("stray.enc"), b"x").unwrap();
+        assert_eq!(DocumentVault::remove_orphans(&db, &dir).unwrap(), 1);
+
+        reopened.remove_file(&doc_id).unwrap();
+        assert!(reopened.read_file(&doc_id).is_err());
+        assert!(!dir.join(&stored).exists());
+
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&backup);
+    }
+}
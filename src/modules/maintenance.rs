@@ -1,4 +1,6 @@
-use crate::db::Db;
+use crate::db::{expect_changed, Db};
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::trash::{self, TrashKind};
 use chrono::Utc;
 use rusqlite::Result;
 use serde::{Deserialize, Serialize};
@@ -25,6 +27,7 @@ impl<'a> MaintenanceService<'a> {
     }
 
     pub fn get_appliances(&self) -> Result<Vec<Appliance>> {
+        require(self.db, "maintenance", Role::Viewer)?;
         let mut stmt = self.db.conn.prepare(
             "SELECT id, name, brand, model, serial_number, purchase_date, warranty_expiry FROM appliances WHERE deleted_at IS NULL ORDER BY name ASC"
         )?;
@@ -53,16 +56,54 @@ impl<'a> MaintenanceService<'a> {
         brand: Option<&str>,
         purchase_date: Option<&str>,
         warranty_expiry: Option<&str>,
-    ) -> Result<()> {
+    ) -> Result<String> {
+        require(self.db, "maintenance", Role::Editor)?;
         let id = Uuid::new_v4().to_string();
         let now = Utc::now().to_rfc3339();
 
         self.db.conn.execute(
             "INSERT INTO appliances (id, name, brand, purchase_date, warranty_expiry, created_at, updated_at)
              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            (id, name, brand, purchase_date, warranty_expiry, &now, &now),
+            (&id, name, brand, purchase_date, warranty_expiry, &now, &now),
         )?;
-        Ok(())
+        Ok(id)
+    }
+
+    pub fn update_appliance(&self, appliance: &Appliance) -> Result<()> {
+        require(self.db, "maintenance", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+
+        let changed = self.db.conn.execute(
+            "UPDATE appliances SET name = ?1, brand = ?2, model = ?3, serial_number = ?4, purchase_date = ?5, warranty_expiry = ?6, updated_at = ?7
+             WHERE id = ?8 AND deleted_at IS NULL",
+            (
+                &appliance.name,
+                &appliance.brand,
+                &appliance.model,
+                &appliance.serial_number,
+                &appliance.purchase_date,
+                &appliance.warranty_expiry,
+                &now,
+                &appliance.id,
+            ),
+        )?;
+        expect_changed(changed)
+    }
+
+    pub fn delete_appliance(&self, id: &str) -> Result<()> {
+        require(self.db, "maintenance", Role::Editor)?;
+        self.db.soft_delete("appliances", id)
+    }
+
+    pub fn restore_appliance(&self, id: &str) -> Result<()> {
+        require(self.db, "maintenance", Role::Editor)?;
+        self.db.restore_deleted("appliances", id)
+    }
+
+    /// Permanently removes appliances deleted more than `older_than_days` ago.
+    pub fn purge_deleted(&self, older_than_days: i64) -> Result<usize> {
+        require(self.db, "maintenance", Role::Editor)?;
+        trash::purge_older_than(&self.db.conn, &[TrashKind::Appliance], older_than_days)
     }
 }
 
@@ -101,4 +142,32 @@ mod tests {
         assert_eq!(appliances[0].name, "HVAC System");
         assert_eq!(appliances[1].name, "Refrigerator");
     }
+
+    #[test]
+    fn test_appliance_update_delete_restore() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+
+        let service = MaintenanceService::new(&db);
+        let id = service
+            .add_appliance("Washer", Some("LG"), None, None)
+            .unwrap();
+
+        let mut appliance = service.get_appliances().unwrap().remove(0);
+        appliance.model = Some("FHM1207".to_string());
+        appliance.serial_number = Some("SN-42".to_string());
+        service.update_appliance(&appliance).unwrap();
+
+        let updated = &service.get_appliances().unwrap()[0];
+        assert_eq!(updated.model.as_deref(), Some("FHM1207"));
+        assert_eq!(updated.serial_number.as_deref(), Some("SN-42"));
+
+        service.delete_appliance(&id).unwrap();
+        assert!(service.get_appliances().unwrap().is_empty());
+        service.restore_appliance(&id).unwrap();
+        assert_eq!(service.get_appliances().unwrap().len(), 1);
+
+        service.delete_appliance(&id).unwrap();
+        assert_eq!(service.purge_deleted(0).unwrap(), 1);
+    }
 }
@@ -1,3 +1,4 @@
+pub mod backup;
 pub mod cloud;
 pub mod dashboard;
 pub mod dining;
@@ -6,8 +7,11 @@ pub mod gifts;
 pub mod grocery;
 pub mod household;
 pub mod maintenance;
+pub mod notifications;
 pub mod registry;
 pub mod settings;
+pub mod sync;
+pub mod trash;
 pub mod travel;
 // Modules outlined in plan.md
 // finance/
@@ -0,0 +1,553 @@
+//! Reminders every module can schedule into `scheduled_notifications`, and
+//! the background task that delivers them once they fall due.
+
+use crate::db::Db;
+use crate::modules::finance::FinanceService;
+use crate::modules::household::sharing::{current_role, require, Role};
+use crate::modules::household::HouseholdService;
+use chrono::{DateTime, Datelike, Days, NaiveDate, Utc};
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledNotification {
+    pub id: String,
+    pub module_id: String,
+    pub entity_id: String,
+    pub notification_type: String,
+    pub title: String,
+    pub body: String,
+    pub scheduled_for: String,
+    pub snoozed_until: Option<String>,
+    pub is_fired: bool,
+    pub is_dismissed: bool,
+    pub created_at: String,
+}
+
+/// Somewhere a due notification can be delivered.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, notification: &ScheduledNotification) -> anyhow::Result<()>;
+}
+
+/// Writes notifications to stdout.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify(&self, notification: &ScheduledNotification) -> anyhow::Result<()> {
+        println!(
+            "[{}] {}: {}",
+            notification.module_id, notification.title, notification.body
+        );
+        Ok(())
+    }
+}
+
+/// Shows a native desktop notification using the platform's own tool.
+pub struct DesktopNotifier;
+
+impl Notifier for DesktopNotifier {
+    fn notify(&self, notification: &ScheduledNotification) -> anyhow::Result<()> {
+        let (title, body) = (&notification.title, &notification.body);
+
+        #[cfg(target_os = "macos")]
+        let mut command = {
+            // Passed as arguments so quotes in the text cannot break the script
+            let mut c = std::process::Command::new("osascript");
+            c.args([
+                "-e",
+                "on run argv",
+                "-e",
+                "display notification (item 2 of argv) with title (item 1 of argv)",
+                "-e",
+                "end run",
+            ])
+            .arg(title)
+            .arg(body);
+            c
+        };
+        #[cfg(target_os = "windows")]
+        let mut command = {
+            let mut c = std::process::Command::new("powershell");
+            c.arg("-NoProfile").arg("-Command").arg(format!(
+                "New-BurntToastNotification -Text '{}', '{}'",
+                title.replace('\'', "''"),
+                body.replace('\'', "''")
+            ));
+            c
+        };
+        #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+        let mut command = {
+            let mut c = std::process::Command::new("notify-send");
+            c.arg("--app-name=MyHome").arg(title).arg(body);
+            c
+        };
+
+        let status = command.status()?;
+        anyhow::ensure!(status.success(), "notifier exited with {}", status);
+        Ok(())
+    }
+}
+
+/// A module's dated records that get a reminder `lead_days` before the date.
+struct ReminderSource {
+    module_id: &'static str,
+    notification_type: &'static str,
+    table: &'static str,
+    date_column: &'static str,
+    lead_days: u64,
+    /// Birthdays recur; the reminder is for the next anniversary.
+    yearly: bool,
+}
+
+const REMINDER_SOURCES: &[ReminderSource] = &[
+    ReminderSource {
+        module_id: "household",
+        notification_type: "birthday",
+        table: "members",
+        date_column: "date_of_birth",
+        lead_days: 7,
+        yearly: true,
+    },
+    ReminderSource {
+        module_id: "maintenance",
+        notification_type: "warranty_expiry",
+        table: "appliances",
+        date_column: "warranty_expiry",
+        lead_days: 30,
+        yearly: false,
+    },
+    ReminderSource {
+        module_id: "gifts",
+        notification_type: "birthday",
+        table: "people",
+        date_column: "date_of_birth",
+        lead_days: 7,
+        yearly: true,
+    },
+    ReminderSource {
+        module_id: "grocery",
+        notification_type: "item_expiry",
+        table: "inventory_items",
+        date_column: "expiry_date",
+        lead_days: 2,
+        yearly: false,
+    },
+];
+
+pub struct NotificationService<'a> {
+    db: &'a Db,
+}
+
+impl<'a> NotificationService<'a> {
+    pub fn new(db: &'a Db) -> Self {
+        Self { db }
+    }
+
+    /// Pending notifications, soonest first.
+    pub fn get_upcoming(&self, limit: usize) -> Result<Vec<ScheduledNotification>> {
+        self.query(
+            "WHERE is_fired = 0 AND is_dismissed = 0
+             ORDER BY COALESCE(snoozed_until, scheduled_for) LIMIT ?1",
+            [limit as i64],
+        )
+    }
+
+    /// Fired notifications the user has not dismissed yet, newest first.
+    pub fn get_inbox(&self) -> Result<Vec<ScheduledNotification>> {
+        self.query(
+            "WHERE is_fired = 1 AND is_dismissed = 0
+             ORDER BY COALESCE(snoozed_until, scheduled_for) DESC",
+            [],
+        )
+    }
+
+    /// Notifications that should fire at `now` but have not yet.
+    pub fn get_due(&self, now: DateTime<Utc>) -> Result<Vec<ScheduledNotification>> {
+        self.query(
+            "WHERE is_fired = 0 AND is_dismissed = 0 AND COALESCE(snoozed_until, scheduled_for) <= ?1
+             ORDER BY COALESCE(snoozed_until, scheduled_for)",
+            [now.to_rfc3339()],
+        )
+    }
+
+    /// Delivers every due notification to each notifier and marks it fired.
+    /// A failing notifier is logged but does not hold the others back; the
+    /// entry still reaches the inbox. Notifications for modules this device
+    /// may not view stay pending. Returns the number fired.
+    pub fn fire_due(&self, notifiers: &[Arc<dyn Notifier>], now: DateTime<Utc>) -> Result<usize> {
+        let mut due = Vec::new();
+        for notification in self.get_due(now)? {
+            if may_view(self.db, &notification.module_id)? {
+                due.push(notification);
+            }
+        }
+        for notification in &due {
+            for notifier in notifiers {
+                if let Err(e) = notifier.notify(notification) {
+                    eprintln!("Failed to deliver notification {}: {}", notification.id, e);
+                }
+            }
+            self.db.conn.execute(
+                "UPDATE scheduled_notifications SET is_fired = 1 WHERE id = ?1",
+                [&notification.id],
+            )?;
+        }
+        Ok(due.len())
+    }
+
+    /// Puts a notification off until `until`. Snoozing one from the inbox
+    /// takes it out again until it fires a second time.
+    pub fn snooze(&self, id: &str, until: DateTime<Utc>) -> Result<()> {
+        self.require_viewer(id)?;
+        let changed = self.db.conn.execute(
+            "UPDATE scheduled_notifications SET snoozed_until = ?1, is_fired = 0
+             WHERE id = ?2 AND is_dismissed = 0",
+            (until.to_rfc3339(), id),
+        )?;
+        crate::db::expect_changed(changed)
+    }
+
+    pub fn dismiss(&self, id: &str) -> Result<()> {
+        self.require_viewer(id)?;
+        let changed = self.db.conn.execute(
+            "UPDATE scheduled_notifications SET is_dismissed = 1 WHERE id = ?1",
+            [id],
+        )?;
+        crate::db::expect_changed(changed)
+    }
+
+    /// Brings date-based reminders in line with the records they are about:
+    /// document and warranty expiry, birthdays and grocery expiry. Reminders
+    /// for records that were deleted or lost their date are cancelled.
+    /// Bills and budgets schedule their own as they change. Modules this
+    /// device may not view are left alone.
+    pub fn sync_reminders(&self, today: NaiveDate) -> Result<usize> {
+        // Documents have per-type lead times, so household owns those
+        let mut scheduled = if may_view(self.db, "household")? {
+            HouseholdService::new(self.db).sync_expiry_reminders()?
+        } else {
+            0
+        };
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        for source in REMINDER_SOURCES {
+            if !may_view(self.db, source.module_id)? {
+                continue;
+            }
+            tx.execute(
+                &format!(
+                    "DELETE FROM scheduled_notifications
+                     WHERE module_id = ?1 AND notification_type = ?2 AND is_fired = 0
+                       AND entity_id NOT IN (SELECT id FROM {} WHERE deleted_at IS NULL AND {} IS NOT NULL)",
+                    source.table, source.date_column
+                ),
+                (source.module_id, source.notification_type),
+            )?;
+
+            let rows: Vec<(String, String, String)> = {
+                let mut stmt = tx.prepare(&format!(
+                    "SELECT id, name, {} FROM {} WHERE deleted_at IS NULL AND {} IS NOT NULL",
+                    source.date_column, source.table, source.date_column
+                ))?;
+                let rows = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                    .collect::<Result<_>>()?;
+                rows
+            };
+
+            for (id, name, raw_date) in rows {
+                let Some(date) = raw_date
+                    .get(..10)
+                    .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+                else {
+                    continue;
+                };
+                let date = if source.yearly {
+                    next_anniversary(date, today)
+                } else {
+                    date
+                };
+                let remind_on = date
+                    .checked_sub_days(Days::new(source.lead_days))
+                    .unwrap_or(date);
+
+                let (title, body) = match source.notification_type {
+                    "birthday" => (
+                        format!("{}'s birthday", name),
+                        format!("{}'s birthday is on {}", name, date.format("%-d %B")),
+                    ),
+                    "item_expiry" => (
+                        format!("{} expiring", name),
+                        format!("{} expires on {}", name, date),
+                    ),
+                    _ => (
+                        format!("{} warranty ending", name),
+                        format!("The warranty for {} ends on {}", name, date),
+                    ),
+                };
+
+                schedule(
+                    &tx,
+                    source.module_id,
+                    &id,
+                    source.notification_type,
+                    &title,
+                    &body,
+                    remind_on.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+                )?;
+                scheduled += 1;
+            }
+        }
+
+        tx.commit()?;
+        Ok(scheduled)
+    }
+
+    /// Fails unless this device may view the module notification `id`
+    /// belongs to.
+    fn require_viewer(&self, id: &str) -> Result<()> {
+        let module_id: String = self.db.conn.query_row(
+            "SELECT module_id FROM scheduled_notifications WHERE id = ?1",
+            [id],
+            |row| row.get(0),
+        )?;
+        require(self.db, &module_id, Role::Viewer)
+    }
+
+    fn query<P: rusqlite::Params>(
+        &self,
+        clause: &str,
+        params: P,
+    ) -> Result<Vec<ScheduledNotification>> {
+        let mut stmt = self.db.conn.prepare(&format!(
+            "SELECT id, module_id, entity_id, notification_type, title, body, scheduled_for, snoozed_until,
+                    is_fired, is_dismissed, created_at
+             FROM scheduled_notifications {}",
+            clause
+        ))?;
+
+        let notifications = stmt
+            .query_map(params, |row| {
+                Ok(ScheduledNotification {
+                    id: row.get(0)?,
+                    module_id: row.get(1)?,
+                    entity_id: row.get(2)?,
+                    notification_type: row.get(3)?,
+                    title: row.get(4)?,
+                    body: row.get(5)?,
+                    scheduled_for: row.get(6)?,
+                    snoozed_until: row.get(7)?,
+                    is_fired: row.get(8)?,
+                    is_dismissed: row.get(9)?,
+                    created_at: row.get(10)?,
+                })
+            })?
+            .filter_map(Result::ok)
+            .collect();
+
+        Ok(notifications)
+    }
+}
+
+fn may_view(db: &Db, module_id: &str) -> Result<bool> {
+    Ok(current_role(db, module_id)? >= Role::Viewer)
+}
+
+/// Registers a reminder for one record. A module has at most one pending
+/// reminder per record and type: a new time replaces the pending one, while
+/// scheduling the same time again is a no-op, so a reminder that already
+/// fired is not repeated. Usable inside a caller's SQL transaction.
+pub fn schedule(
+    conn: &Connection,
+    module_id: &str,
+    entity_id: &str,
+    notification_type: &str,
+    title: &str,
+    body: &str,
+    scheduled_for: DateTime<Utc>,
+) -> Result<String> {
+    let scheduled_for = scheduled_for.to_rfc3339();
+
+    let existing: Option<String> = conn
+        .query_row(
+            "SELECT id FROM scheduled_notifications
+             WHERE module_id = ?1 AND entity_id = ?2 AND notification_type = ?3 AND scheduled_for = ?4",
+            (module_id, entity_id, notification_type, &scheduled_for),
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(id) = existing {
+        // Keep the wording current without disturbing fired/snoozed state
+        conn.execute(
+            "UPDATE scheduled_notifications SET title = ?1, body = ?2 WHERE id = ?3",
+            (title, body, &id),
+        )?;
+        return Ok(id);
+    }
+
+    cancel(conn, module_id, entity_id, notification_type)?;
+    let id = Uuid::new_v4().to_string();
+    conn.execute(
+        "INSERT INTO scheduled_notifications (id, module_id, entity_id, notification_type, title, body, scheduled_for, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        (
+            &id,
+            module_id,
+            entity_id,
+            notification_type,
+            title,
+            body,
+            &scheduled_for,
+            Utc::now().to_rfc3339(),
+        ),
+    )?;
+    Ok(id)
+}
+
+/// Drops the pending (not yet fired) reminder of a record, if any.
+pub fn cancel(
+    conn: &Connection,
+    module_id: &str,
+    entity_id: &str,
+    notification_type: &str,
+) -> Result<()> {
+    conn.execute(
+        "DELETE FROM scheduled_notifications
+         WHERE module_id = ?1 AND entity_id = ?2 AND notification_type = ?3 AND is_fired = 0",
+        (module_id, entity_id, notification_type),
+    )?;
+    Ok(())
+}
+
+/// Runs the scheduler every `interval` on a tokio task: refreshes the
+/// date-based reminders, posts autopay bills that are due and fires whatever
+/// is due. Each run opens its own connection through `open_db`.
+pub fn spawn_scheduler<F>(
+    open_db: F,
+    notifiers: Vec<Arc<dyn Notifier>>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()>
+where
+    F: Fn() -> Result<Db> + Send + Sync + 'static,
+{
+    let open_db = Arc::new(open_db);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let open_db = open_db.clone();
+            let notifiers = notifiers.clone();
+
+            let run = tokio::task::spawn_blocking(move || -> Result<usize> {
+                let db = open_db()?;
+                let now = Utc::now();
+                let service = NotificationService::new(&db);
+                service.sync_reminders(now.date_naive())?;
+                // Only devices allowed to add transactions post autopay bills
+                if current_role(&db, "finance")? >= Role::Editor {
+                    FinanceService::new(&db).post_autopay_bills(&now.date_naive().to_string())?;
+                }
+                service.fire_due(&notifiers, now)
+            })
+            .await;
+
+            match run {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => eprintln!("Notification scheduler failed: {}", e),
+                Err(e) => eprintln!("Notification scheduler panicked: {}", e),
+            }
+        }
+    })
+}
+
+/// The first occurrence of `date`'s month and day on or after `today`.
+/// 29 February falls on the 28th in other years.
+fn next_anniversary(date: NaiveDate, today: NaiveDate) -> NaiveDate {
+    let in_year = |year: i32| {
+        NaiveDate::from_ymd_opt(year, date.month(), date.day())
+            .or_else(|| NaiveDate::from_ymd_opt(year, date.month(), date.day() - 1))
+            .unwrap_or(today)
+    };
+    let this_year = in_year(today.year());
+    if this_year >= today {
+        this_year
+    } else {
+        in_year(today.year() + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Notifier for Recorder {
+        fn notify(&self, notification: &ScheduledNotification) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(notification.title.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_reminders_fire_snooze_and_dismiss() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = NotificationService::new(&db);
+        let household = HouseholdService::new(&db);
+        let today = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+
+        let passport = household
+            .add_document(None, "Passport", "passport", None, Some("2024-03-20"))
+            .unwrap();
+        household
+            .add_member("Asha", "daughter", Some("2016-02-29"), false)
+            .unwrap();
+        assert_eq!(service.sync_reminders(today).unwrap(), 2);
+        // Syncing again does not duplicate anything
+        service.sync_reminders(today).unwrap();
+        let upcoming = service.get_upcoming(10).unwrap();
+        assert_eq!(upcoming.len(), 2);
+        assert!(upcoming[0].scheduled_for.starts_with("2023-09-22"));
+        assert!(upcoming[1].scheduled_for.starts_with("2025-02-21"));
+
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        let notifiers: Vec<Arc<dyn Notifier>> = vec![recorder.clone()];
+        let now = at("2024-03-01T09:00:00Z");
+        assert_eq!(service.fire_due(&notifiers, now).unwrap(), 1);
+        assert_eq!(*recorder.0.lock().unwrap(), ["Renew Passport"]);
+        assert_eq!(service.fire_due(&notifiers, now).unwrap(), 0);
+
+        // A fired reminder is not re-created by the next sync
+        service.sync_reminders(today).unwrap();
+        assert!(service.get_due(now).unwrap().is_empty());
+
+        let inbox = service.get_inbox().unwrap();
+        assert_eq!(inbox.len(), 1);
+        service
+            .snooze(&inbox[0].id, at("2024-03-02T09:00:00Z"))
+            .unwrap();
+        assert!(service.get_inbox().unwrap().is_empty());
+        assert_eq!(
+            service
+                .fire_due(&notifiers, at("2024-03-02T09:00:00Z"))
+                .unwrap(),
+            1
+        );
+        service.dismiss(&inbox[0].id).unwrap();
+        assert!(service.get_inbox().unwrap().is_empty());
+
+        // Deleting the record cancels its pending reminder
+        household.delete_document(&passport).unwrap();
+        household
+            .add_document(None, "Licence", "licence", None, Some("2030-01-01"))
+            .unwrap();
+        service.sync_reminders(today).unwrap();
+        assert_eq!(service.get_upcoming(10).unwrap().len(), 2);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::db::Db;
-use rusqlite::Result;
+use rusqlite::{OptionalExtension, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -66,6 +66,57 @@ impl<'a> ModuleRegistry<'a> {
         Ok(())
     }
 
+    /// Returns the module's `settings_json` object, or an empty object if
+    /// nothing has been saved yet.
+    pub fn get_settings(&self, module_id: &str) -> Result<serde_json::Value> {
+        let raw: Option<String> = self
+            .db
+            .conn
+            .query_row(
+                "SELECT settings_json FROM module_state WHERE module_id = ?1",
+                [module_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+
+        match raw {
+            Some(json) => serde_json::from_str(&json).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    0,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
+                )
+            }),
+            None => Ok(serde_json::json!({})),
+        }
+    }
+
+    /// Replaces the module's `settings_json`, creating its state row if needed.
+    pub fn save_settings(&self, module_id: &str, settings: &serde_json::Value) -> Result<()> {
+        self.db.conn.execute(
+            "INSERT INTO module_state (module_id, is_enabled, settings_json) VALUES (?1, ?2, ?3)
+             ON CONFLICT(module_id) DO UPDATE SET settings_json = excluded.settings_json",
+            (
+                module_id,
+                if Self::is_enabled_by_default(module_id) {
+                    1
+                } else {
+                    0
+                },
+                settings.to_string(),
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Whether a module starts out enabled on a fresh database.
+    pub fn is_enabled_by_default(module_id: &str) -> bool {
+        Self::get_hardcoded_modules()
+            .iter()
+            .any(|m| m.id == module_id && m.is_enabled)
+    }
+
     fn get_hardcoded_modules() -> Vec<ModuleManifest> {
         vec![
             ModuleManifest {
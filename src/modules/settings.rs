@@ -1,5 +1,5 @@
 use crate::db::Db;
-use rusqlite::params;
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
 
 /// Represents the global application settings.
@@ -149,6 +149,19 @@ impl<'a> SettingsService<'a> {
     }
 }
 
+/// The household currency, for code that only has a connection, e.g. inside
+/// another service's SQL transaction. Defaults like `get_settings` does.
+pub(crate) fn currency_code(conn: &Connection) -> Result<String, rusqlite::Error> {
+    Ok(conn
+        .query_row(
+            "SELECT currency_code FROM app_preferences WHERE id = 1",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or_else(|| "INR".to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,361 @@
+//! Background sync: runs shortly after local edits settle and on a fixed
+//! interval, retries failures with exponential backoff and records how the
+//! last attempt went in `sync_status`.
+
+use super::SyncService;
+use crate::db::Db;
+use chrono::Utc;
+use rusqlite::{params, Result};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// The outcome of the latest sync attempt, as stored in `sync_status`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SyncStatus {
+    pub state: String,
+    /// Error message when `state` is "error".
+    pub message: Option<String>,
+    pub last_success_at: Option<String>,
+    pub last_attempt_at: Option<String>,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AutoSyncConfig {
+    /// Sync at least this often, changes or not, to pick up other devices'
+    /// edits.
+    pub interval: Duration,
+    /// Wait for local edits to stop for this long before syncing them.
+    pub debounce: Duration,
+    /// How often to look for local edits.
+    pub poll: Duration,
+    /// First retry delay after a failure; doubles with each further failure.
+    pub retry_base: Duration,
+    pub retry_max: Duration,
+}
+
+impl Default for AutoSyncConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15 * 60),
+            debounce: Duration::from_secs(30),
+            poll: Duration::from_secs(5),
+            retry_base: Duration::from_secs(30),
+            retry_max: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+impl<'a> SyncService<'a> {
+    pub fn get_status(&self) -> Result<SyncStatus> {
+        self.db.conn.query_row(
+            "SELECT state, message, last_success_at, last_attempt_at, consecutive_failures
+             FROM sync_status WHERE id = 1",
+            [],
+            |row| {
+                Ok(SyncStatus {
+                    state: row.get(0)?,
+                    message: row.get(1)?,
+                    last_success_at: row.get(2)?,
+                    last_attempt_at: row.get(3)?,
+                    consecutive_failures: row.get(4)?,
+                })
+            },
+        )
+    }
+
+    pub fn mark_sync_started(&self) -> Result<()> {
+        self.db.conn.execute(
+            "UPDATE sync_status SET state = 'syncing', last_attempt_at = ?1 WHERE id = 1",
+            params![Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    pub fn mark_sync_succeeded(&self) -> Result<()> {
+        self.db.conn.execute(
+            "UPDATE sync_status
+             SET state = 'idle', message = NULL, last_success_at = ?1, consecutive_failures = 0
+             WHERE id = 1",
+            params![Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// Records a failed attempt and returns how many attempts in a row have
+    /// now failed.
+    pub fn mark_sync_failed(&self, message: &str) -> Result<u32> {
+        self.db.conn.execute(
+            "UPDATE sync_status
+             SET state = 'error', message = ?1, consecutive_failures = consecutive_failures + 1
+             WHERE id = 1",
+            params![message],
+        )?;
+        Ok(self.get_status()?.consecutive_failures)
+    }
+
+    /// Grows with every local write to a synced table, so a change in the
+    /// value means there is something new to sync.
+    pub fn local_change_count(&self) -> Result<i64> {
+        self.db.conn.query_row(
+            "SELECT COALESCE(SUM(clock), 0) FROM sync_vector_clocks
+             WHERE device_id = (SELECT device_id FROM sync_device)",
+            [],
+            |row| row.get(0),
+        )
+    }
+}
+
+/// Delay before retry number `failures` (counting from 1).
+pub fn retry_delay(failures: u32, config: &AutoSyncConfig) -> Duration {
+    let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+    config
+        .retry_base
+        .checked_mul(factor)
+        .map_or(config.retry_max, |d| d.min(config.retry_max))
+}
+
+/// Decides when the background loop syncs.
+#[derive(Debug, Clone)]
+struct Schedule {
+    config: AutoSyncConfig,
+    last_sync: Option<Instant>,
+    /// When local edits were last seen, if they have not been synced yet.
+    pending_since: Option<Instant>,
+    retry_at: Option<Instant>,
+}
+
+impl Schedule {
+    fn new(config: AutoSyncConfig) -> Self {
+        Self {
+            config,
+            last_sync: None,
+            pending_since: None,
+            retry_at: None,
+        }
+    }
+
+    fn changed(&mut self, now: Instant) {
+        self.pending_since = Some(now);
+    }
+
+    fn is_due(&self, now: Instant) -> bool {
+        if let Some(retry_at) = self.retry_at {
+            return now >= retry_at;
+        }
+        let settled = self
+            .pending_since
+            .is_some_and(|since| now.duration_since(since) >= self.config.debounce);
+        let stale = self
+            .last_sync
+            .is_none_or(|last| now.duration_since(last) >= self.config.interval);
+        settled || stale
+    }
+
+    fn succeeded(&mut self, now: Instant) {
+        self.last_sync = Some(now);
+        self.pending_since = None;
+        self.retry_at = None;
+    }
+
+    fn failed(&mut self, now: Instant, failures: u32) {
+        self.retry_at = Some(now + retry_delay(failures, &self.config));
+    }
+}
+
+/// Handle to the background sync loop, which stops when this is dropped.
+pub struct AutoSync {
+    handle: tokio::task::JoinHandle<()>,
+    trigger: Arc<Notify>,
+}
+
+impl AutoSync {
+    /// Syncs right away, skipping the debounce and any pending retry delay.
+    pub fn sync_now(&self) {
+        self.trigger.notify_one();
+    }
+}
+
+impl Drop for AutoSync {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Runs `sync` in the background whenever the schedule says so and `ready`
+/// allows it (e.g. once an account is linked), recording each attempt in
+/// `sync_status` through a connection from `open_db` and reporting the new
+/// status to `on_status`. A database connection cannot be shared between
+/// threads, so the future `sync` returns need not be `Send`; it runs to
+/// completion on a blocking thread.
+pub fn spawn_auto_sync<F, R, S, Fut, N>(
+    open_db: F,
+    ready: R,
+    sync: S,
+    config: AutoSyncConfig,
+    on_status: N,
+) -> AutoSync
+where
+    F: Fn() -> anyhow::Result<Db> + Send + Sync + 'static,
+    R: Fn() -> bool + Send + 'static,
+    S: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = anyhow::Result<()>>,
+    N: Fn(SyncStatus) + Send + 'static,
+{
+    let trigger = Arc::new(Notify::new());
+    let notified = trigger.clone();
+    let open_db = Arc::new(open_db);
+    let sync = Arc::new(sync);
+
+    let handle = tokio::spawn(async move {
+        let mut schedule = Schedule::new(config);
+        let mut seen_changes: Option<i64> = None;
+
+        loop {
+            let forced = tokio::select! {
+                _ = notified.notified() => true,
+                _ = tokio::time::sleep(config.poll) => false,
+            };
+
+            let changes = record(&open_db, |service| service.local_change_count()).await;
+            let now = Instant::now();
+            match changes {
+                Ok(count) => {
+                    if seen_changes.is_some_and(|seen| seen != count) {
+                        schedule.changed(now);
+                    }
+                    seen_changes = Some(count);
+                }
+                Err(e) => eprintln!("Auto sync could not read changes: {}", e),
+            }
+
+            if !ready() || (!forced && !schedule.is_due(now)) {
+                continue;
+            }
+
+            let result = record(&open_db, |service| service.mark_sync_started()).await;
+            let outcome = match result {
+                Ok(_) => {
+                    let sync = sync.clone();
+                    let runtime = tokio::runtime::Handle::current();
+                    tokio::task::spawn_blocking(move || runtime.block_on(sync()))
+                        .await
+                        .unwrap_or_else(|e| Err(anyhow::anyhow!("sync panicked: {}", e)))
+                }
+                Err(e) => Err(e),
+            };
+            let now = Instant::now();
+            let status = match outcome {
+                Ok(()) => {
+                    schedule.succeeded(now);
+                    record(&open_db, |service| {
+                        service.mark_sync_succeeded()?;
+                        service.get_status()
+                    })
+                    .await
+                }
+                Err(e) => {
+                    let message = format!("{:#}", e);
+                    record(&open_db, move |service| {
+                        let failures = service.mark_sync_failed(&message)?;
+                        Ok((failures, service.get_status()?))
+                    })
+                    .await
+                    .map(|(failures, status)| {
+                        schedule.failed(now, failures);
+                        status
+                    })
+                }
+            };
+            match status {
+                Ok(status) => on_status(status),
+                Err(e) => eprintln!("Auto sync could not record its status: {}", e),
+            }
+        }
+    });
+
+    AutoSync { handle, trigger }
+}
+
+/// Runs `f` on a fresh connection on a blocking thread, keeping database
+/// work off the async workers.
+async fn record<F, T>(
+    open_db: &Arc<F>,
+    f: impl FnOnce(&SyncService) -> Result<T> + Send + 'static,
+) -> anyhow::Result<T>
+where
+    F: Fn() -> anyhow::Result<Db> + Send + Sync + 'static,
+    T: Send + 'static,
+{
+    let open_db = open_db.clone();
+    tokio::task::spawn_blocking(move || {
+        let db = open_db()?;
+        Ok(f(&SyncService::new(&db))?)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::finance::FinanceService;
+
+    #[test]
+    fn test_status_and_schedule() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let service = SyncService::new(&db);
+        assert_eq!(service.get_status().unwrap().state, "idle");
+
+        let before = service.local_change_count().unwrap();
+        FinanceService::new(&db)
+            .create_account("Cash", "cash", 0)
+            .unwrap();
+        assert!(service.local_change_count().unwrap() > before);
+
+        service.mark_sync_started().unwrap();
+        assert_eq!(service.get_status().unwrap().state, "syncing");
+        assert_eq!(service.mark_sync_failed("offline").unwrap(), 1);
+        assert_eq!(service.mark_sync_failed("offline").unwrap(), 2);
+        let failed = service.get_status().unwrap();
+        assert_eq!(failed.state, "error");
+        assert_eq!(failed.message.as_deref(), Some("offline"));
+        service.mark_sync_succeeded().unwrap();
+        let status = service.get_status().unwrap();
+        assert_eq!(
+            (status.state.as_str(), status.consecutive_failures),
+            ("idle", 0)
+        );
+        assert!(status.last_success_at.is_some());
+
+        let config = AutoSyncConfig::default();
+        assert_eq!(retry_delay(1, &config), Duration::from_secs(30));
+        assert_eq!(retry_delay(3, &config), Duration::from_secs(120));
+        assert_eq!(retry_delay(40, &config), config.retry_max);
+
+        let start = Instant::now();
+        let mut schedule = Schedule::new(config);
+        assert!(schedule.is_due(start));
+        schedule.succeeded(start);
+        assert!(!schedule.is_due(start + Duration::from_secs(60)));
+
+        // Edits wait for the debounce, and every new edit restarts it
+        schedule.changed(start + Duration::from_secs(60));
+        schedule.changed(start + Duration::from_secs(80));
+        assert!(!schedule.is_due(start + Duration::from_secs(100)));
+        assert!(schedule.is_due(start + Duration::from_secs(110)));
+
+        // Failures hold off retries
+        schedule.failed(start + Duration::from_secs(110), 2);
+        assert!(!schedule.is_due(start + Duration::from_secs(160)));
+        assert!(schedule.is_due(start + Duration::from_secs(170)));
+
+        // With nothing to sync, the interval still brings a sync around
+        schedule.succeeded(start + Duration::from_secs(170));
+        assert!(!schedule.is_due(start + Duration::from_secs(900)));
+        assert!(schedule.is_due(start + Duration::from_secs(1070)));
+    }
+}
@@ -0,0 +1,251 @@
+//! Storage that devices sync through.
+//!
+//! A backend is a flat namespace of named blobs; names may contain `/` to
+//! group related files (e.g. `changes/<device>.json`). Google Drive lives in
+//! `modules::cloud`; a plain folder covers NAS shares and Syncthing, and the
+//! in-memory backend lets tests sync without credentials.
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// What a backend knows about a stored file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteObject {
+    pub name: String,
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+    /// Changes whenever the contents change.
+    pub etag: String,
+}
+
+#[async_trait]
+pub trait SyncBackend: Send + Sync {
+    /// Files whose name starts with `prefix`, sorted by name.
+    async fn list(&self, prefix: &str) -> Result<Vec<RemoteObject>>;
+    /// `None` if there is no file called `name`.
+    async fn metadata(&self, name: &str) -> Result<Option<RemoteObject>>;
+    async fn get(&self, name: &str) -> Result<Vec<u8>>;
+    /// Creates or replaces `name`.
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<RemoteObject>;
+    /// Deleting a file that does not exist is not an error.
+    async fn delete(&self, name: &str) -> Result<()>;
+}
+
+/// Files in a local or mounted network folder.
+pub struct LocalFolderBackend {
+    root: PathBuf,
+}
+
+impl LocalFolderBackend {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        let relative = Path::new(name);
+        if name.is_empty()
+            || !relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+        {
+            bail!("Invalid file name '{}'", name);
+        }
+        Ok(self.root.join(relative))
+    }
+
+    async fn object(&self, name: &str, path: &Path) -> Result<RemoteObject> {
+        let meta = tokio::fs::metadata(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let modified = meta.modified().ok().map(DateTime::<Utc>::from);
+        Ok(RemoteObject {
+            name: name.to_string(),
+            size: meta.len(),
+            modified,
+            etag: format!(
+                "{}-{}",
+                meta.len(),
+                modified.and_then(|m| m.timestamp_nanos_opt()).unwrap_or(0)
+            ),
+        })
+    }
+}
+
+#[async_trait]
+impl SyncBackend for LocalFolderBackend {
+    async fn list(&self, prefix: &str) -> Result<Vec<RemoteObject>> {
+        let mut objects = Vec::new();
+        let mut pending = vec![self.root.clone()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let name = path
+                    .strip_prefix(&self.root)?
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                // Skip half-written files from an interrupted put
+                if name.starts_with(prefix) && !name.ends_with(".partial") {
+                    objects.push(self.object(&name, &path).await?);
+                }
+            }
+        }
+        objects.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(objects)
+    }
+
+    async fn metadata(&self, name: &str) -> Result<Option<RemoteObject>> {
+        let path = self.path(name)?;
+        if !tokio::fs::try_exists(&path).await? {
+            return Ok(None);
+        }
+        self.object(name, &path).await.map(Some)
+    }
+
+    async fn get(&self, name: &str) -> Result<Vec<u8>> {
+        let path = self.path(name)?;
+        tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))
+    }
+
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<RemoteObject> {
+        let path = self.path(name)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Write then rename so other devices watching the folder never see a
+        // partial file
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, data)
+            .await
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        tokio::fs::rename(&partial, &path).await?;
+        self.object(name, &path).await
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(name)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Keeps files in memory; for tests.
+#[derive(Default)]
+pub struct MemoryBackend {
+    files: Mutex<BTreeMap<String, (Vec<u8>, RemoteObject)>>,
+    next_etag: Mutex<u64>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SyncBackend for MemoryBackend {
+    async fn list(&self, prefix: &str) -> Result<Vec<RemoteObject>> {
+        let files = self.files.lock().unwrap();
+        Ok(files
+            .iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(_, (_, object))| object.clone())
+            .collect())
+    }
+
+    async fn metadata(&self, name: &str) -> Result<Option<RemoteObject>> {
+        let files = self.files.lock().unwrap();
+        Ok(files.get(name).map(|(_, object)| object.clone()))
+    }
+
+    async fn get(&self, name: &str) -> Result<Vec<u8>> {
+        let files = self.files.lock().unwrap();
+        files
+            .get(name)
+            .map(|(data, _)| data.clone())
+            .ok_or_else(|| anyhow!("No file called '{}'", name))
+    }
+
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<RemoteObject> {
+        let etag = {
+            let mut next = self.next_etag.lock().unwrap();
+            *next += 1;
+            next.to_string()
+        };
+        let object = RemoteObject {
+            name: name.to_string(),
+            size: data.len() as u64,
+            modified: Some(Utc::now()),
+            etag,
+        };
+        self.files
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), (data, object.clone()));
+        Ok(object)
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        self.files.lock().unwrap().remove(name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_folder_backend() {
+        let root = std::env::temp_dir().join(format!("myhome_sync_{}", uuid::Uuid::new_v4()));
+        let backend = LocalFolderBackend::new(&root);
+        assert!(backend.list("").await.unwrap().is_empty());
+
+        let first = backend
+            .put("changes/phone.json", b"{}".to_vec())
+            .await
+            .unwrap();
+        backend.put("myhome.db", b"SQLite".to_vec()).await.unwrap();
+        assert_eq!(first.size, 2);
+
+        let listed = backend.list("changes/").await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "changes/phone.json");
+        assert_eq!(backend.get("changes/phone.json").await.unwrap(), b"{}");
+
+        let second = backend
+            .put("changes/phone.json", b"{\"a\":1}".to_vec())
+            .await
+            .unwrap();
+        assert_ne!(first.etag, second.etag);
+        assert_eq!(
+            backend.metadata("changes/phone.json").await.unwrap(),
+            Some(second)
+        );
+
+        assert!(backend.get("../outside").await.is_err());
+
+        backend.delete("changes/phone.json").await.unwrap();
+        backend.delete("changes/phone.json").await.unwrap();
+        assert_eq!(backend.metadata("changes/phone.json").await.unwrap(), None);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
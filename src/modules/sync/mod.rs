@@ -0,0 +1,871 @@
+//! Row-level sync between devices.
+//!
+//! Every write to a synced table bumps the writing device's counter for that
+//! row in `sync_vector_clocks` (the triggers are installed by the core v3
+//! migration). A device exports the rows whose clocks another device has not
+//! seen yet as a [`Changeset`]; importing it keeps whichever side's row is
+//! newer. When both sides changed a row independently, the row with the later
+//! `updated_at` wins on both devices, so they converge no matter in which
+//! order changesets are exchanged.
+//!
+//! Once the household is shared, a changeset's rows are only merged where
+//! the sending device's member may edit the table's module; see
+//! [`Permissions`]. Changesets are not signed: the sending device is the one
+//! the changeset names, so anyone who can write to the shared folder can
+//! pass theirs off as another device's.
+//!
+//! Devices swap changesets through a [`SyncBackend`]: each one keeps its
+//! latest changeset at `changes/<device id>.json` and merges everyone
+//! else's (see [`SyncService::exchange`]).
+
+pub mod auto;
+pub mod backend;
+
+use crate::db::migrations::{self, CORE_MODULE};
+use crate::db::Db;
+use crate::modules::finance;
+use crate::modules::household::sharing::{self, Permissions, Role};
+use anyhow::Context;
+use backend::SyncBackend;
+use chrono::Utc;
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Tables whose rows are exchanged between devices, with the module whose
+/// role is needed to change them. Device-local state such as module
+/// settings and scheduled notifications stays out.
+pub const SYNCED_TABLES: &[(&str, &str)] = &[
+    ("members", "household"),
+    ("accounts", "finance"),
+    ("categories", "finance"),
+    ("transactions", "finance"),
+    ("transaction_splits", "finance"),
+    ("exchange_rates", "finance"),
+    ("categorization_rules", "finance"),
+    ("budgets", "finance"),
+    ("bills", "finance"),
+    ("bill_payments", "finance"),
+    ("documents", "household"),
+    ("shopping_lists", "grocery"),
+    ("shopping_list_items", "grocery"),
+    ("inventory_items", "grocery"),
+    ("trips", "travel"),
+    ("appliances", "maintenance"),
+    ("restaurants", "dining"),
+    ("people", "gifts"),
+    ("gift_ideas", "gifts"),
+];
+
+/// Module a synced table belongs to.
+fn table_module(table: &str) -> Option<&'static str> {
+    SYNCED_TABLES
+        .iter()
+        .find(|(name, _)| *name == table)
+        .map(|(_, module)| *module)
+}
+
+/// Where each device keeps its changeset in the sync backend.
+pub const CHANGES_PREFIX: &str = "changes/";
+
+/// Highest counter seen per device id.
+pub type VersionVector = BTreeMap<String, i64>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RowChange {
+    pub table: String,
+    pub row_id: String,
+    pub clock: VersionVector,
+    /// Column values, or `None` when the row was permanently deleted.
+    /// Blobs are written as arrays of bytes.
+    pub row: Option<Map<String, Value>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Changeset {
+    pub device_id: String,
+    /// Core schema version of the exporting database.
+    pub schema_version: u32,
+    pub created_at: String,
+    /// The exporter's version vector; pass it back as `since` to export only
+    /// what the exporter has not seen.
+    pub vector: VersionVector,
+    pub changes: Vec<RowChange>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    pub applied: usize,
+    pub skipped: usize,
+    /// Rows changed on both sides; the winner is counted in `applied` or
+    /// `skipped` as well.
+    pub conflicts: usize,
+    /// Rows the sending device was not allowed to change. They are left out
+    /// along with their clocks.
+    #[serde(default)]
+    pub denied: usize,
+}
+
+pub struct SyncService<'a> {
+    db: &'a Db,
+}
+
+impl<'a> SyncService<'a> {
+    pub fn new(db: &'a Db) -> Self {
+        Self { db }
+    }
+
+    /// Id this database writes its clock entries under.
+    pub fn device_id(&self) -> Result<String> {
+        self.db
+            .conn
+            .query_row("SELECT device_id FROM sync_device", [], |row| row.get(0))
+    }
+
+    /// Gives this database a fresh device id, e.g. after restoring another
+    /// device's backup, so the two stop writing under the same id.
+    pub fn reset_device_id(&self) -> Result<String> {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        self.db
+            .conn
+            .execute("UPDATE sync_device SET device_id = ?1", params![id])?;
+        Ok(id)
+    }
+
+    /// Highest counter per device across all rows.
+    pub fn version_vector(&self) -> Result<VersionVector> {
+        let mut stmt = self
+            .db
+            .conn
+            .prepare("SELECT device_id, MAX(clock) FROM sync_vector_clocks GROUP BY device_id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    /// Exports every row with a change not covered by `since`. Pass an empty
+    /// vector for a full export.
+    pub fn export_changes(&self, since: &VersionVector) -> Result<Changeset> {
+        let conn = &self.db.conn;
+        let mut clocks: BTreeMap<(String, String), VersionVector> = BTreeMap::new();
+        {
+            let mut stmt = conn
+                .prepare("SELECT table_name, row_id, device_id, clock FROM sync_vector_clocks")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })?;
+            for row in rows {
+                let (table, row_id, device, clock) = row?;
+                clocks
+                    .entry((table, row_id))
+                    .or_default()
+                    .insert(device, clock);
+            }
+        }
+
+        let mut changes = Vec::new();
+        for ((table, row_id), clock) in clocks {
+            if table_module(&table).is_none() {
+                continue;
+            }
+            let unseen = clock
+                .iter()
+                .any(|(device, c)| *c > since.get(device).copied().unwrap_or(0));
+            if unseen {
+                let row = read_row(conn, &table, &row_id)?;
+                changes.push(RowChange {
+                    table,
+                    row_id,
+                    clock,
+                    row,
+                });
+            }
+        }
+
+        Ok(Changeset {
+            device_id: self.device_id()?,
+            schema_version: migrations::current_version(conn, CORE_MODULE)?,
+            created_at: Utc::now().to_rfc3339(),
+            vector: self.version_vector()?,
+            changes,
+        })
+    }
+
+    /// Merges another device's changes. Either the whole changeset is applied
+    /// or, on error, nothing is. Rows the sender's roles do not cover are
+    /// counted as `denied`, judged by the access this database had before
+    /// the merge.
+    pub fn import_changes(&self, changeset: &Changeset) -> Result<MergeSummary> {
+        let conn = &self.db.conn;
+        let local_version = migrations::current_version(conn, CORE_MODULE)?;
+        if changeset.schema_version > local_version {
+            return Err(misuse(format!(
+                "changeset from schema version {} is newer than this database ({})",
+                changeset.schema_version, local_version
+            )));
+        }
+
+        let permissions = Permissions::load(self.db)?;
+
+        let tx = conn.unchecked_transaction()?;
+        // Keep the change-tracking triggers from counting these writes as local
+        tx.execute("UPDATE sync_device SET applying = 1", [])?;
+
+        let mut summary = MergeSummary::default();
+        // Balances are derived, so the accounts whose transactions changed
+        // are recomputed rather than taken from whichever side won
+        let mut touched_accounts = BTreeSet::new();
+        for change in &changeset.changes {
+            let Some(module) = table_module(&change.table) else {
+                return Err(misuse(format!("table '{}' is not synced", change.table)));
+            };
+            let local_clock = read_clock(&tx, &change.table, &change.row_id)?;
+            let ordering = compare_clocks(&local_clock, &change.clock);
+            if matches!(ordering, Some(Ordering::Equal | Ordering::Greater)) {
+                summary.skipped += 1;
+                continue;
+            }
+            if !may_change(&tx, &permissions, &changeset.device_id, module, change)? {
+                summary.denied += 1;
+                continue;
+            }
+            let remote_wins = match ordering {
+                Some(_) => true,
+                None => {
+                    summary.conflicts += 1;
+                    let local_row = read_row(&tx, &change.table, &change.row_id)?;
+                    conflict_key(change.row.as_ref()) > conflict_key(local_row.as_ref())
+                }
+            };
+
+            if remote_wins {
+                match change.table.as_str() {
+                    "accounts" => {
+                        touched_accounts.insert(change.row_id.clone());
+                    }
+                    "transactions" => {
+                        let local_account: Option<String> = tx
+                            .query_row(
+                                "SELECT account_id FROM transactions WHERE id = ?1",
+                                params![change.row_id],
+                                |row| row.get(0),
+                            )
+                            .optional()?;
+                        let remote_account = change
+                            .row
+                            .as_ref()
+                            .and_then(|row| row.get("account_id"))
+                            .and_then(|id| id.as_str())
+                            .map(str::to_string);
+                        touched_accounts.extend(local_account.into_iter().chain(remote_account));
+                    }
+                    _ => {}
+                }
+                match &change.row {
+                    Some(row) if change.table == "transactions" => {
+                        let mut row = row.clone();
+                        touched_accounts.extend(settle_import_hash(&tx, &change.row_id, &mut row)?);
+                        write_row(&tx, &change.table, &change.row_id, &row)?;
+                    }
+                    Some(row) => write_row(&tx, &change.table, &change.row_id, row)?,
+                    None => {
+                        tx.execute(
+                            &format!("DELETE FROM {} WHERE id = ?1", change.table),
+                            params![change.row_id],
+                        )?;
+                    }
+                }
+                summary.applied += 1;
+            } else {
+                summary.skipped += 1;
+            }
+
+            for (device, clock) in &change.clock {
+                if *clock > local_clock.get(device).copied().unwrap_or(0) {
+                    tx.execute(
+                        "INSERT INTO sync_vector_clocks (table_name, row_id, device_id, clock)
+                         VALUES (?1, ?2, ?3, ?4)
+                         ON CONFLICT (table_name, row_id, device_id) DO UPDATE SET clock = excluded.clock",
+                        params![change.table, change.row_id, device, clock],
+                    )?;
+                }
+            }
+        }
+
+        for account_id in &touched_accounts {
+            finance::recompute_balance(&tx, account_id)?;
+        }
+
+        tx.execute("UPDATE sync_device SET applying = 0", [])?;
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    /// Merges the changesets other devices left in `backend`, then publishes
+    /// this device's rows for them to pick up.
+    pub async fn exchange(&self, backend: &dyn SyncBackend) -> anyhow::Result<MergeSummary> {
+        let own = format!("{}{}.json", CHANGES_PREFIX, self.device_id()?);
+
+        let mut summary = MergeSummary::default();
+        for object in backend.list(CHANGES_PREFIX).await? {
+            if object.name == own {
+                continue;
+            }
+            let bytes = backend.get(&object.name).await?;
+            let changeset: Changeset = serde_json::from_slice(&bytes)
+                .with_context(|| format!("{} is not a changeset", object.name))?;
+            // Roles are checked against the changeset's device, so it must
+            // be the one whose file it is. This only catches a changeset
+            // copied to the wrong file; it does not prove who wrote it
+            if object.name != format!("{}{}.json", CHANGES_PREFIX, changeset.device_id) {
+                eprintln!(
+                    "Ignoring {}: it was written by device {}",
+                    object.name, changeset.device_id
+                );
+                continue;
+            }
+            let merged = self.import_changes(&changeset)?;
+            summary.applied += merged.applied;
+            summary.skipped += merged.skipped;
+            summary.conflicts += merged.conflicts;
+            summary.denied += merged.denied;
+        }
+
+        // Every device reads this file, so it carries all rows rather than
+        // just what one particular peer is missing
+        let changeset = self.export_changes(&VersionVector::new())?;
+        backend.put(&own, serde_json::to_vec(&changeset)?).await?;
+        Ok(summary)
+    }
+}
+
+/// Whether `device_id` may make `change` to a table of `module`. Changing a
+/// member's access also has to pass [`Permissions::may_set_access`], which
+/// lets a device without a role accept its own invite.
+fn may_change(
+    conn: &Connection,
+    permissions: &Permissions,
+    device_id: &str,
+    module: &str,
+    change: &RowChange,
+) -> Result<bool> {
+    let editor = permissions.role(device_id, module) >= Role::Editor;
+    if change.table != "members" {
+        return Ok(editor);
+    }
+
+    let local = read_row(conn, "members", &change.row_id)?;
+    let live = |row: &Map<String, Value>| row.get("deleted_at").is_none_or(Value::is_null);
+    let access_of = |row: Option<&Map<String, Value>>| match row
+        .filter(|row| live(row))
+        .and_then(|row| row.get("permission_json"))
+    {
+        Some(Value::String(json)) => sharing::parse_access(json).map(Some).map_err(|_| ()),
+        _ => Ok(None),
+    };
+    let Ok(access) = access_of(change.row.as_ref()) else {
+        return Ok(false);
+    };
+    if !permissions.may_set_access(device_id, &change.row_id, access.as_ref()) {
+        return Ok(false);
+    }
+    // Accepting an invite touches nothing but the member's access
+    let others = |row: &Map<String, Value>| {
+        let mut row = row.clone();
+        row.remove("permission_json");
+        row.remove("updated_at");
+        row
+    };
+    let only_access = match (&local, &change.row) {
+        (Some(local), Some(remote)) => others(local) == others(remote),
+        _ => false,
+    };
+    Ok(editor || only_access)
+}
+
+/// Two devices importing the same statement line end up with different
+/// transactions under one `import_hash`, which is unique. The one with the
+/// lower id keeps the hash; the other is soft-deleted as of its own
+/// `updated_at` and loses the hash, so every device settles on the same rows
+/// in whichever order they arrive. Returns the account of a local row that
+/// was set aside this way.
+fn settle_import_hash(
+    conn: &Connection,
+    row_id: &str,
+    row: &mut Map<String, Value>,
+) -> Result<Option<String>> {
+    let Some(hash) = row.get("import_hash").and_then(Value::as_str) else {
+        return Ok(None);
+    };
+    let holder: Option<(String, String)> = conn
+        .query_row(
+            "SELECT id, account_id FROM transactions WHERE import_hash = ?1 AND id != ?2",
+            params![hash, row_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let Some((holder_id, account_id)) = holder else {
+        return Ok(None);
+    };
+
+    if holder_id.as_str() < row_id {
+        if row.get("deleted_at").is_none_or(Value::is_null) {
+            let updated_at = row.get("updated_at").cloned().unwrap_or(Value::Null);
+            row.insert("deleted_at".to_string(), updated_at);
+        }
+        row.insert("import_hash".to_string(), Value::Null);
+        Ok(None)
+    } else {
+        conn.execute(
+            "UPDATE transactions SET deleted_at = COALESCE(deleted_at, updated_at), import_hash = NULL
+             WHERE id = ?1",
+            params![holder_id],
+        )?;
+        Ok(Some(account_id))
+    }
+}
+
+/// `Less` when `b` has seen everything `a` has and more, `None` when each
+/// side has changes the other has not seen.
+fn compare_clocks(a: &VersionVector, b: &VersionVector) -> Option<Ordering> {
+    let mut ordering = Ordering::Equal;
+    for device in a.keys().chain(b.keys()) {
+        let x = a.get(device).copied().unwrap_or(0);
+        let y = b.get(device).copied().unwrap_or(0);
+        match (ordering, x.cmp(&y)) {
+            (_, Ordering::Equal) => {}
+            (Ordering::Equal, o) => ordering = o,
+            (current, o) if current != o => return None,
+            _ => {}
+        }
+    }
+    Some(ordering)
+}
+
+/// Orders concurrent versions of a row: later `updated_at` first, then the
+/// serialized row so ties still break the same way on every device. A
+/// permanent delete loses to any surviving edit.
+fn conflict_key(row: Option<&Map<String, Value>>) -> (String, String) {
+    match row {
+        None => (String::new(), String::new()),
+        Some(row) => (
+            row.get("updated_at")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            serde_json::to_string(row).unwrap_or_default(),
+        ),
+    }
+}
+
+fn read_clock(conn: &Connection, table: &str, row_id: &str) -> Result<VersionVector> {
+    let mut stmt = conn.prepare(
+        "SELECT device_id, clock FROM sync_vector_clocks WHERE table_name = ?1 AND row_id = ?2",
+    )?;
+    let rows = stmt.query_map(params![table, row_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+fn read_row(conn: &Connection, table: &str, row_id: &str) -> Result<Option<Map<String, Value>>> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {} WHERE id = ?1", table))?;
+    let names: Vec<String> = stmt.column_names().iter().map(|n| n.to_string()).collect();
+    stmt.query_row(params![row_id], |row| {
+        let mut map = Map::new();
+        for (i, name) in names.iter().enumerate() {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null => Value::Null,
+                ValueRef::Integer(n) => Value::from(n),
+                ValueRef::Real(f) => Value::from(f),
+                ValueRef::Text(t) => Value::String(String::from_utf8_lossy(t).into_owned()),
+                ValueRef::Blob(b) => Value::from(b.to_vec()),
+            };
+            map.insert(name.clone(), value);
+        }
+        Ok(map)
+    })
+    .optional()
+}
+
+/// Inserts or replaces a row, ignoring columns this database does not have.
+fn write_row(conn: &Connection, table: &str, row_id: &str, row: &Map<String, Value>) -> Result<()> {
+    let columns: Vec<String> = {
+        let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)")?;
+        let rows = stmt.query_map(params![table], |row| row.get(0))?;
+        rows.collect::<Result<_>>()?
+    };
+    let mut names = vec!["id".to_string()];
+    let mut values = vec![SqlValue::Text(row_id.to_string())];
+    for (name, value) in row {
+        if name != "id" && columns.contains(name) {
+            names.push(name.clone());
+            values.push(to_sql_value(value));
+        }
+    }
+
+    let placeholders: Vec<String> = (1..=names.len()).map(|i| format!("?{}", i)).collect();
+    let updates: Vec<String> = names[1..]
+        .iter()
+        .map(|n| format!("{} = excluded.{}", n, n))
+        .collect();
+    let sql = if updates.is_empty() {
+        format!(
+            "INSERT INTO {} (id) VALUES (?1) ON CONFLICT(id) DO NOTHING",
+            table
+        )
+    } else {
+        format!(
+            "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT(id) DO UPDATE SET {}",
+            table,
+            names.join(", "),
+            placeholders.join(", "),
+            updates.join(", ")
+        )
+    };
+    conn.execute(&sql, params_from_iter(values))?;
+    Ok(())
+}
+
+fn to_sql_value(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        Value::Array(items) => SqlValue::Blob(
+            items
+                .iter()
+                .map(|b| b.as_u64().unwrap_or_default() as u8)
+                .collect(),
+        ),
+        Value::Object(_) => SqlValue::Text(value.to_string()),
+    }
+}
+
+fn misuse(message: String) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_MISUSE),
+        Some(message),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::finance::FinanceService;
+    use crate::modules::household::HouseholdService;
+
+    fn device() -> Db {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        db
+    }
+
+    #[test]
+    fn test_devices_converge_after_concurrent_edits() {
+        let phone = device();
+        let tablet = device();
+        let phone_sync = SyncService::new(&phone);
+        let tablet_sync = SyncService::new(&tablet);
+        assert_ne!(
+            phone_sync.device_id().unwrap(),
+            tablet_sync.device_id().unwrap()
+        );
+
+        let account_id = FinanceService::new(&phone)
+            .create_account("Joint", "checking", 10_000)
+            .unwrap();
+        let initial = phone_sync.export_changes(&VersionVector::new()).unwrap();
+        let summary = tablet_sync.import_changes(&initial).unwrap();
+        assert_eq!(summary.applied, 1);
+        assert_eq!(
+            FinanceService::new(&tablet).get_accounts().unwrap()[0].name,
+            "Joint"
+        );
+
+        // Importing doesn't count as a local change, and re-importing is a no-op
+        assert!(tablet_sync
+            .export_changes(&initial.vector)
+            .unwrap()
+            .changes
+            .is_empty());
+        assert_eq!(tablet_sync.import_changes(&initial).unwrap().skipped, 1);
+
+        // Both devices rename the account offline; the later edit wins on both
+        let rename = |db: &Db, name: &str, at: &str| {
+            db.conn
+                .execute(
+                    "UPDATE accounts SET name = ?1, updated_at = ?2 WHERE id = ?3",
+                    params![name, at, account_id],
+                )
+                .unwrap();
+        };
+        rename(&phone, "Joint (phone)", "2024-05-01T10:00:00+00:00");
+        rename(&tablet, "Joint (tablet)", "2024-05-01T11:00:00+00:00");
+
+        let from_phone = phone_sync.export_changes(&tablet_sync.version_vector().unwrap());
+        let from_tablet = tablet_sync.export_changes(&phone_sync.version_vector().unwrap());
+        let at_tablet = tablet_sync.import_changes(&from_phone.unwrap()).unwrap();
+        let at_phone = phone_sync.import_changes(&from_tablet.unwrap()).unwrap();
+        assert_eq!((at_tablet.conflicts, at_phone.conflicts), (1, 1));
+
+        for db in [&phone, &tablet] {
+            let accounts = FinanceService::new(db).get_accounts().unwrap();
+            assert_eq!(accounts[0].name, "Joint (tablet)");
+        }
+        assert_eq!(
+            read_clock(&phone.conn, "accounts", &account_id).unwrap(),
+            read_clock(&tablet.conn, "accounts", &account_id).unwrap()
+        );
+
+        // Permanent deletes travel as tombstones
+        phone
+            .conn
+            .execute("DELETE FROM accounts WHERE id = ?1", params![account_id])
+            .unwrap();
+        let delete = phone_sync
+            .export_changes(&tablet_sync.version_vector().unwrap())
+            .unwrap();
+        assert_eq!(delete.changes[0].row, None);
+        tablet_sync.import_changes(&delete).unwrap();
+        assert!(FinanceService::new(&tablet)
+            .get_accounts()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_transactions_add_up_on_both_devices() {
+        let phone = device();
+        let tablet = device();
+        let phone_sync = SyncService::new(&phone);
+        let tablet_sync = SyncService::new(&tablet);
+        let account_id = FinanceService::new(&phone)
+            .create_account("Joint", "checking", 10_000)
+            .unwrap();
+        tablet_sync
+            .import_changes(&phone_sync.export_changes(&VersionVector::new()).unwrap())
+            .unwrap();
+
+        // Each device spends offline from the same account
+        FinanceService::new(&phone)
+            .create_transaction(&account_id, -1_000, "Groceries", "2024-05-01", None)
+            .unwrap();
+        let taxi = FinanceService::new(&tablet)
+            .create_transaction(&account_id, -2_500, "Taxi", "2024-05-01", None)
+            .unwrap();
+        FinanceService::new(&tablet)
+            .create_transaction(&account_id, -300, "Tea", "2024-05-02", None)
+            .unwrap();
+        FinanceService::new(&tablet)
+            .delete_transaction(&taxi)
+            .unwrap();
+
+        // Full exports, as `exchange` publishes them
+        let from_phone = phone_sync.export_changes(&VersionVector::new()).unwrap();
+        let from_tablet = tablet_sync.export_changes(&VersionVector::new()).unwrap();
+        tablet_sync.import_changes(&from_phone).unwrap();
+        phone_sync.import_changes(&from_tablet).unwrap();
+
+        for db in [&phone, &tablet] {
+            let accounts = FinanceService::new(db).get_accounts().unwrap();
+            assert_eq!(accounts[0].current_balance_cents, 10_000 - 1_000 - 300);
+        }
+    }
+
+    #[test]
+    fn test_statement_imported_on_both_devices_is_kept_once() {
+        let phone = device();
+        let tablet = device();
+        let phone_sync = SyncService::new(&phone);
+        let tablet_sync = SyncService::new(&tablet);
+        let account_id = FinanceService::new(&phone)
+            .create_account("Joint", "checking", 10_000)
+            .unwrap();
+        tablet_sync
+            .import_changes(&phone_sync.export_changes(&VersionVector::new()).unwrap())
+            .unwrap();
+
+        let ofx = "<OFX><STMTTRN><DTPOSTED>20240501<TRNAMT>-10<FITID>A1<NAME>Tea</STMTTRN>\n\
+                   <STMTTRN><DTPOSTED>20240502<TRNAMT>-25<FITID>A2<NAME>Taxi</STMTTRN></OFX>";
+        for db in [&phone, &tablet] {
+            let summary = FinanceService::new(db)
+                .import_ofx(&account_id, ofx)
+                .unwrap();
+            assert_eq!(summary.imported, 2);
+        }
+
+        let from_phone = phone_sync.export_changes(&VersionVector::new()).unwrap();
+        let from_tablet = tablet_sync.export_changes(&VersionVector::new()).unwrap();
+        tablet_sync.import_changes(&from_phone).unwrap();
+        phone_sync.import_changes(&from_tablet).unwrap();
+
+        let kept = |db: &Db| {
+            let mut stmt = db
+                .conn
+                .prepare("SELECT id, deleted_at, import_hash FROM transactions ORDER BY id")
+                .unwrap();
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                    ))
+                })
+                .unwrap();
+            rows.collect::<Result<Vec<_>>>().unwrap()
+        };
+        assert_eq!(kept(&phone), kept(&tablet));
+        for db in [&phone, &tablet] {
+            let finance = FinanceService::new(db);
+            assert_eq!(finance.get_transactions(10).unwrap().len(), 2);
+            assert_eq!(
+                finance.get_accounts().unwrap()[0].current_balance_cents,
+                10_000 - 1_000 - 2_500
+            );
+            // The statement still counts as imported
+            let again = finance.import_ofx(&account_id, ofx).unwrap();
+            assert_eq!(again.skipped_duplicates, 2);
+        }
+    }
+
+    #[test]
+    fn test_autopay_posted_on_both_devices_is_charged_once() {
+        let phone = device();
+        let tablet = device();
+        let phone_sync = SyncService::new(&phone);
+        let tablet_sync = SyncService::new(&tablet);
+        let finance = FinanceService::new(&phone);
+        let account_id = finance.create_account("Joint", "checking", 10_000).unwrap();
+        let bill_id = finance
+            .create_bill(
+                "Internet",
+                1_500,
+                "monthly",
+                "2024-05-01",
+                Some(&account_id),
+                None,
+            )
+            .unwrap();
+        let mut bill = finance.get_bills().unwrap().remove(0);
+        bill.is_autopay = true;
+        finance.update_bill(&bill).unwrap();
+        tablet_sync
+            .import_changes(&phone_sync.export_changes(&VersionVector::new()).unwrap())
+            .unwrap();
+
+        // Both schedulers run before the devices sync
+        let on_phone = finance.post_autopay_bills("2024-05-02").unwrap();
+        let on_tablet = FinanceService::new(&tablet)
+            .post_autopay_bills("2024-05-02")
+            .unwrap();
+        assert_eq!(on_phone, on_tablet);
+
+        let from_phone = phone_sync.export_changes(&VersionVector::new()).unwrap();
+        let from_tablet = tablet_sync.export_changes(&VersionVector::new()).unwrap();
+        tablet_sync.import_changes(&from_phone).unwrap();
+        phone_sync.import_changes(&from_tablet).unwrap();
+
+        for db in [&phone, &tablet] {
+            let finance = FinanceService::new(db);
+            assert_eq!(finance.get_transactions(10).unwrap().len(), 1);
+            assert_eq!(finance.get_bill_payments(&bill_id).unwrap().len(), 1);
+            assert_eq!(
+                finance.get_accounts().unwrap()[0].current_balance_cents,
+                10_000 - 1_500
+            );
+        }
+    }
+
+    #[test]
+    fn test_changes_need_the_senders_role() {
+        let phone = device();
+        let tablet = device();
+        let phone_sync = SyncService::new(&phone);
+        let tablet_sync = SyncService::new(&tablet);
+        let household = HouseholdService::new(&phone);
+        let account_id = FinanceService::new(&phone)
+            .create_account("Joint", "checking", 10_000)
+            .unwrap();
+        household.add_member("Asha", "self", None, true).unwrap();
+        let partner = household.add_member("Ravi", "spouse", None, false).unwrap();
+        let roles = BTreeMap::from([("finance".to_string(), Role::Viewer)]);
+        let code = household.invite_member(&partner, None, &roles).unwrap();
+
+        // The partner's device takes the household over and accepts
+        tablet_sync
+            .import_changes(&phone_sync.export_changes(&VersionVector::new()).unwrap())
+            .unwrap();
+        HouseholdService::new(&tablet).accept_invite(&code).unwrap();
+        let accepted = phone_sync
+            .import_changes(&tablet_sync.export_changes(&VersionVector::new()).unwrap())
+            .unwrap();
+        assert_eq!((accepted.applied, accepted.denied), (1, 0));
+
+        // A viewer's edits, and its attempt to make itself owner, stay out
+        let mut access = HouseholdService::new(&tablet)
+            .get_member_access(&partner)
+            .unwrap()
+            .unwrap();
+        access.roles.insert("finance".to_string(), Role::Owner);
+        tablet
+            .conn
+            .execute(
+                "UPDATE members SET permission_json = ?1 WHERE id = ?2",
+                params![serde_json::to_string(&access).unwrap(), partner],
+            )
+            .unwrap();
+        tablet
+            .conn
+            .execute(
+                "UPDATE accounts SET name = 'Mine' WHERE id = ?1",
+                params![account_id],
+            )
+            .unwrap();
+        let merged = phone_sync
+            .import_changes(&tablet_sync.export_changes(&VersionVector::new()).unwrap())
+            .unwrap();
+        assert_eq!((merged.applied, merged.denied), (0, 2));
+        assert_eq!(
+            FinanceService::new(&phone).get_accounts().unwrap()[0].name,
+            "Joint"
+        );
+        let partner_access = household.get_member_access(&partner).unwrap().unwrap();
+        assert_eq!(partner_access.role("finance"), Role::Viewer);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_through_backend() {
+        let backend = backend::MemoryBackend::new();
+        let phone = device();
+        let tablet = device();
+
+        FinanceService::new(&phone)
+            .create_account("Phone wallet", "cash", 500)
+            .unwrap();
+        FinanceService::new(&tablet)
+            .create_account("Tablet wallet", "cash", 700)
+            .unwrap();
+
+        SyncService::new(&phone).exchange(&backend).await.unwrap();
+        let at_tablet = SyncService::new(&tablet).exchange(&backend).await.unwrap();
+        assert_eq!(at_tablet.applied, 1);
+        let at_phone = SyncService::new(&phone).exchange(&backend).await.unwrap();
+        assert_eq!(at_phone.applied, 1);
+
+        assert_eq!(backend.list(CHANGES_PREFIX).await.unwrap().len(), 2);
+        for db in [&phone, &tablet] {
+            assert_eq!(FinanceService::new(db).get_accounts().unwrap().len(), 2);
+        }
+    }
+}
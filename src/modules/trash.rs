@@ -0,0 +1,546 @@
+use crate::db::{purge_cutoff, Db};
+use crate::modules::dining::DiningService;
+use crate::modules::finance::receipts::ReceiptStore;
+use crate::modules::finance::FinanceService;
+use crate::modules::gifts::GiftsService;
+use crate::modules::grocery::GroceryService;
+use crate::modules::household::sharing::{current_role, require, Role};
+use crate::modules::household::vault::DocumentVault;
+use crate::modules::household::HouseholdService;
+use crate::modules::maintenance::MaintenanceService;
+use crate::modules::travel::TravelService;
+use rusqlite::{Connection, Result};
+use serde::{Deserialize, Serialize};
+
+/// Every kind of record that can sit in the trash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrashKind {
+    Account,
+    Transaction,
+    Category,
+    Budget,
+    Bill,
+    Rule,
+    Trip,
+    Restaurant,
+    Appliance,
+    Person,
+    GiftIdea,
+    Member,
+    Document,
+    GroceryItem,
+    InventoryItem,
+}
+
+impl TrashKind {
+    pub const ALL: [TrashKind; 15] = [
+        TrashKind::Account,
+        TrashKind::Transaction,
+        TrashKind::Category,
+        TrashKind::Budget,
+        TrashKind::Bill,
+        TrashKind::Rule,
+        TrashKind::Trip,
+        TrashKind::Restaurant,
+        TrashKind::Appliance,
+        TrashKind::Person,
+        TrashKind::GiftIdea,
+        TrashKind::Member,
+        TrashKind::Document,
+        TrashKind::GroceryItem,
+        TrashKind::InventoryItem,
+    ];
+
+    pub fn module_id(self) -> &'static str {
+        match self {
+            TrashKind::Account
+            | TrashKind::Transaction
+            | TrashKind::Category
+            | TrashKind::Budget
+            | TrashKind::Bill
+            | TrashKind::Rule => "finance",
+            TrashKind::Trip => "travel",
+            TrashKind::Restaurant => "dining",
+            TrashKind::Appliance => "maintenance",
+            TrashKind::Person | TrashKind::GiftIdea => "gifts",
+            TrashKind::Member | TrashKind::Document => "household",
+            TrashKind::GroceryItem | TrashKind::InventoryItem => "grocery",
+        }
+    }
+
+    fn table(self) -> &'static str {
+        match self {
+            TrashKind::Account => "accounts",
+            TrashKind::Transaction => "transactions",
+            TrashKind::Category => "categories",
+            TrashKind::Budget => "budgets",
+            TrashKind::Bill => "bills",
+            TrashKind::Rule => "categorization_rules",
+            TrashKind::Trip => "trips",
+            TrashKind::Restaurant => "restaurants",
+            TrashKind::Appliance => "appliances",
+            TrashKind::Person => "people",
+            TrashKind::GiftIdea => "gift_ideas",
+            TrashKind::Member => "members",
+            TrashKind::Document => "documents",
+            TrashKind::GroceryItem => "shopping_list_items",
+            TrashKind::InventoryItem => "inventory_items",
+        }
+    }
+
+    /// Column shown to the user when listing the trash.
+    fn label_column(self) -> &'static str {
+        match self {
+            TrashKind::Transaction => "merchant",
+            TrashKind::GiftIdea => "description",
+            _ => "name",
+        }
+    }
+
+    /// Parent whose deletion also deletes this row, as (table, foreign key).
+    /// Rows removed that way are listed and restored through the parent.
+    fn cascades_from(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            TrashKind::Transaction => Some(("accounts", "account_id")),
+            TrashKind::GiftIdea => Some(("people", "person_id")),
+            _ => None,
+        }
+    }
+
+    /// Statements run with `?1` bound to the id before a row is purged, so
+    /// nothing is left pointing at it.
+    fn purge_side_effects(self) -> &'static [&'static str] {
+        match self {
+            TrashKind::Account => &[
+                "UPDATE budgets SET account_id = NULL WHERE account_id = ?1",
+                "UPDATE bills SET account_id = NULL WHERE account_id = ?1",
+                "UPDATE categorization_rules SET account_id = NULL, is_enabled = 0 WHERE account_id = ?1",
+            ],
+            TrashKind::Category => &[
+                "UPDATE transactions SET category_id = NULL WHERE category_id = ?1",
+                "UPDATE transaction_splits SET category_id = NULL WHERE category_id = ?1",
+                "UPDATE budgets SET category_id = NULL WHERE category_id = ?1",
+                "UPDATE bills SET category_id = NULL WHERE category_id = ?1",
+                "UPDATE categories SET parent_id = NULL WHERE parent_id = ?1",
+                "UPDATE categorization_rules SET category_id = NULL WHERE category_id = ?1",
+            ],
+            TrashKind::Transaction => &[
+                "UPDATE bill_payments SET transaction_id = NULL WHERE transaction_id = ?1",
+                "DELETE FROM transaction_splits WHERE transaction_id = ?1",
+            ],
+            TrashKind::Bill => &["DELETE FROM bill_payments WHERE bill_id = ?1"],
+            TrashKind::Member => &[
+                "UPDATE documents SET member_id = NULL WHERE member_id = ?1",
+                "UPDATE accounts SET member_id = NULL WHERE member_id = ?1",
+            ],
+            _ => &[],
+        }
+    }
+
+    /// Rows deleted through the row `id` (see `cascades_from`), which have
+    /// to be purged before it.
+    fn dependents(self, conn: &Connection, id: &str) -> Result<Vec<(TrashKind, String)>> {
+        let mut rows = Vec::new();
+        for child in TrashKind::ALL {
+            let Some((parent, fk)) = child.cascades_from() else {
+                continue;
+            };
+            if parent == self.table() {
+                let sql = format!("SELECT id FROM {} WHERE {} = ?1", child.table(), fk);
+                for child_id in ids(conn, &sql, id)? {
+                    rows.push((child, child_id));
+                }
+            }
+        }
+        Ok(rows)
+    }
+}
+
+/// Deletes the vault files and receipts next to the database that no row
+/// refers to any more. Run after purging; failures are only reported since
+/// the rows are already gone, and the next purge tries again.
+pub(crate) fn remove_orphaned_files(db: &Db) {
+    let Some(db_path) = db.path() else {
+        return;
+    };
+    if let Err(e) = DocumentVault::remove_orphans(db, &DocumentVault::default_dir(&db_path)) {
+        eprintln!("Failed to remove unused vault files: {}", e);
+    }
+    if let Err(e) = ReceiptStore::remove_orphans(db, &ReceiptStore::default_dir(&db_path)) {
+        eprintln!("Failed to remove unused receipts: {}", e);
+    }
+}
+
+/// Permanently deletes the row `id` of `kind` with everything that only
+/// existed through it. Purging from the trash and each module's
+/// `purge_deleted` both come through here, so a kind is always purged the
+/// same way. Returns the number of records removed, cascaded ones included.
+pub(crate) fn purge_row(conn: &Connection, kind: TrashKind, id: &str) -> Result<usize> {
+    let mut purged = 0;
+    for (dependent, dependent_id) in kind.dependents(conn, id)? {
+        purged += purge_row(conn, dependent, &dependent_id)?;
+    }
+    // The trashed other side of a transfer goes with it
+    let legs = match kind {
+        TrashKind::Transaction => ids(
+            conn,
+            "SELECT id FROM transactions WHERE id != ?1 AND deleted_at IS NOT NULL
+             AND transfer_id = (SELECT transfer_id FROM transactions WHERE id = ?1)",
+            id,
+        )?,
+        _ => Vec::new(),
+    };
+
+    for sql in kind.purge_side_effects() {
+        conn.execute(sql, [id])?;
+    }
+    purged += conn.execute(&format!("DELETE FROM {} WHERE id = ?1", kind.table()), [id])?;
+    for leg in legs {
+        purged += purge_row(conn, TrashKind::Transaction, &leg)?;
+    }
+    Ok(purged)
+}
+
+/// Purges every row of `kinds` deleted more than `older_than_days` ago.
+/// Returns the number of records removed.
+pub(crate) fn purge_older_than(
+    conn: &Connection,
+    kinds: &[TrashKind],
+    older_than_days: i64,
+) -> Result<usize> {
+    let cutoff = purge_cutoff(older_than_days);
+    let mut purged = 0;
+    for &kind in kinds {
+        let sql = format!(
+            "SELECT id FROM {} WHERE deleted_at IS NOT NULL AND deleted_at <= ?1",
+            kind.table()
+        );
+        for id in ids(conn, &sql, &cutoff)? {
+            purged += purge_row(conn, kind, &id)?;
+        }
+    }
+    Ok(purged)
+}
+
+fn ids(conn: &Connection, sql: &str, param: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([param], |row| row.get(0))?;
+    rows.collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashItem {
+    pub kind: TrashKind,
+    pub id: String,
+    pub label: String,
+    pub deleted_at: String,
+}
+
+pub struct TrashService<'a> {
+    db: &'a Db,
+}
+
+impl<'a> TrashService<'a> {
+    pub fn new(db: &'a Db) -> Self {
+        Self { db }
+    }
+
+    /// Lists soft-deleted records across the modules this device may see,
+    /// most recent first.
+    pub fn get_trash(&self) -> Result<Vec<TrashItem>> {
+        let mut items = Vec::new();
+
+        for kind in TrashKind::ALL {
+            if current_role(self.db, kind.module_id())? < Role::Viewer {
+                continue;
+            }
+            let table = kind.table();
+            let mut sql = format!(
+                "SELECT id, COALESCE({}, ''), deleted_at FROM {} WHERE deleted_at IS NOT NULL",
+                kind.label_column(),
+                table
+            );
+            if let Some((parent, fk)) = kind.cascades_from() {
+                sql.push_str(&format!(
+                    " AND NOT EXISTS (SELECT 1 FROM {parent} p WHERE p.id = {table}.{fk} AND p.deleted_at = {table}.deleted_at)"
+                ));
+            }
+            // Transfer legs deleted with the other side's account come back with it
+            if kind == TrashKind::Transaction {
+                sql.push_str(
+                    " AND NOT EXISTS (SELECT 1 FROM transactions o JOIN accounts a ON a.id = o.account_id
+                       WHERE o.transfer_id = transactions.transfer_id AND o.id != transactions.id
+                         AND a.deleted_at = transactions.deleted_at)",
+                );
+            }
+
+            let mut stmt = self.db.conn.prepare(&sql)?;
+            let rows = stmt.query_map([], |row| {
+                Ok(TrashItem {
+                    kind,
+                    id: row.get(0)?,
+                    label: row.get(1)?,
+                    deleted_at: row.get(2)?,
+                })
+            })?;
+            for item in rows {
+                items.push(item?);
+            }
+        }
+
+        items.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+        Ok(items)
+    }
+
+    /// Undoes a delete through the owning service, so cascades and balance
+    /// adjustments are reapplied exactly as they were undone.
+    pub fn restore(&self, kind: TrashKind, id: &str) -> Result<()> {
+        match kind {
+            TrashKind::Account => FinanceService::new(self.db).restore_account(id),
+            TrashKind::Transaction => FinanceService::new(self.db).restore_transaction(id),
+            TrashKind::Category => FinanceService::new(self.db).restore_category(id),
+            TrashKind::Budget => FinanceService::new(self.db).restore_budget(id),
+            TrashKind::Bill => FinanceService::new(self.db).restore_bill(id),
+            TrashKind::Rule => FinanceService::new(self.db).restore_rule(id),
+            TrashKind::Trip => TravelService::new(self.db).restore_trip(id),
+            TrashKind::Restaurant => DiningService::new(self.db).restore_restaurant(id),
+            TrashKind::Appliance => MaintenanceService::new(self.db).restore_appliance(id),
+            TrashKind::Person => GiftsService::new(self.db).restore_person(id),
+            TrashKind::GiftIdea => GiftsService::new(self.db).restore_gift_idea(id),
+            TrashKind::Member => HouseholdService::new(self.db).restore_member(id),
+            TrashKind::Document => HouseholdService::new(self.db).restore_document(id),
+            TrashKind::GroceryItem => GroceryService::new(self.db).restore_grocery_item(id),
+            TrashKind::InventoryItem => GroceryService::new(self.db).restore_inventory_item(id),
+        }
+    }
+
+    /// Permanently deletes one record from the trash along with anything that
+    /// only existed through it.
+    ///
+    /// A transaction's balance change is already taken back out of its account
+    /// when it is moved to the trash, so purging it leaves balances untouched.
+    /// Purging an account removes its transactions with it.
+    pub fn purge(&self, kind: TrashKind, id: &str) -> Result<()> {
+        require(self.db, kind.module_id(), Role::Editor)?;
+        let tx = self.db.conn.unchecked_transaction()?;
+
+        let in_trash: bool = tx.query_row(
+            &format!(
+                "SELECT count(*) > 0 FROM {} WHERE id = ?1 AND deleted_at IS NOT NULL",
+                kind.table()
+            ),
+            [id],
+            |row| row.get(0),
+        )?;
+        if !in_trash {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+
+        purge_row(&tx, kind, id)?;
+
+        tx.commit()?;
+        remove_orphaned_files(self.db);
+        Ok(())
+    }
+
+    /// Purges everything that has been in the trash for more than
+    /// `older_than_days` days, in the modules this device may edit. Returns
+    /// the number of rows removed.
+    pub fn empty_trash(&self, older_than_days: i64) -> Result<usize> {
+        let mut purged = 0;
+        for module_id in [
+            "finance",
+            "travel",
+            "dining",
+            "maintenance",
+            "gifts",
+            "household",
+            "grocery",
+        ] {
+            if current_role(self.db, module_id)? < Role::Editor {
+                continue;
+            }
+            purged += match module_id {
+                "finance" => FinanceService::new(self.db).purge_deleted(older_than_days)?,
+                "travel" => TravelService::new(self.db).purge_deleted(older_than_days)?,
+                "dining" => DiningService::new(self.db).purge_deleted(older_than_days)?,
+                "maintenance" => MaintenanceService::new(self.db).purge_deleted(older_than_days)?,
+                "gifts" => GiftsService::new(self.db).purge_deleted(older_than_days)?,
+                "household" => HouseholdService::new(self.db).purge_deleted(older_than_days)?,
+                _ => GroceryService::new(self.db).purge_deleted(older_than_days)?,
+            };
+        }
+        Ok(purged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trash_lists_restores_and_purges() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let finance = FinanceService::new(&db);
+        let travel = TravelService::new(&db);
+        let trash = TrashService::new(&db);
+
+        let checking = finance
+            .create_account("Checking", "checking", 10000)
+            .unwrap();
+        let savings = finance.create_account("Savings", "savings", 0).unwrap();
+        let coffee = finance
+            .create_transaction(&checking, -500, "Coffee", "2024-01-01", None)
+            .unwrap();
+        finance
+            .create_transaction(&savings, 2000, "Interest", "2024-01-01", None)
+            .unwrap();
+        let trip = travel
+            .add_trip("Goa", "Goa", "2024-12-20", "2024-12-27", None)
+            .unwrap();
+
+        finance.delete_transaction(&coffee).unwrap();
+        finance.delete_account(&savings).unwrap();
+        travel.delete_trip(&trip).unwrap();
+
+        // The interest transaction is represented by its deleted account
+        let items = trash.get_trash().unwrap();
+        assert_eq!(items.len(), 3);
+        assert!(items
+            .iter()
+            .any(|i| i.kind == TrashKind::Transaction && i.label == "Coffee"));
+        assert!(items.iter().any(|i| i.kind == TrashKind::Account));
+        assert!(items.iter().any(|i| i.kind == TrashKind::Trip));
+
+        trash.restore(TrashKind::Trip, &trip).unwrap();
+        assert_eq!(travel.get_trips().unwrap().len(), 1);
+
+        // Purging the deleted transaction keeps the balance it already had
+        trash.purge(TrashKind::Transaction, &coffee).unwrap();
+        assert_eq!(
+            finance.get_accounts().unwrap()[0].current_balance_cents,
+            10000
+        );
+        assert!(trash.restore(TrashKind::Transaction, &coffee).is_err());
+
+        // Purging the account takes its transactions with it
+        trash.purge(TrashKind::Account, &savings).unwrap();
+        let orphans: i64 = db
+            .conn
+            .query_row(
+                "SELECT count(*) FROM transactions WHERE account_id = ?1",
+                [&savings],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(orphans, 0);
+        assert!(trash.get_trash().unwrap().is_empty());
+
+        // Live rows cannot be purged
+        assert!(trash.purge(TrashKind::Trip, &trip).is_err());
+
+        travel.delete_trip(&trip).unwrap();
+        assert_eq!(trash.empty_trash(0).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_purging_removes_files_nothing_uses() {
+        let dir = std::env::temp_dir().join(format!("myhome_trash_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("live.db");
+        let db = Db::new(&db_path).unwrap();
+        db.init().unwrap();
+        let finance = FinanceService::new(&db);
+        let household = HouseholdService::new(&db);
+        let trash = TrashService::new(&db);
+
+        let card = finance.create_account("Card", "credit", 0).unwrap();
+        let lunch = finance
+            .create_transaction(&card, -1_200, "Cafe", "2024-03-01", None)
+            .unwrap();
+        let receipt = ReceiptStore::new(&db, ReceiptStore::default_dir(&db_path))
+            .attach_bytes(&lunch, b"%PDF-1.4 lunch")
+            .unwrap();
+        let passport = household
+            .add_document(None, "Passport", "Passport", None, None)
+            .unwrap();
+        let vault_dir = DocumentVault::default_dir(&db_path);
+        let vault = DocumentVault::open(&db, &vault_dir, "pw").unwrap();
+        vault
+            .attach_bytes(&passport, "passport.pdf", b"%PDF scan")
+            .unwrap();
+
+        // Trashed rows keep their files until they are purged
+        finance.delete_transaction(&lunch).unwrap();
+        household.delete_document(&passport).unwrap();
+        assert!(receipt.path.exists());
+        trash.purge(TrashKind::Transaction, &lunch).unwrap();
+        assert!(!receipt.path.exists());
+
+        assert_eq!(DocumentVault::stored_files(&vault_dir).unwrap().len(), 1);
+        assert_eq!(trash.empty_trash(0).unwrap(), 1);
+        assert!(DocumentVault::stored_files(&vault_dir).unwrap().is_empty());
+
+        drop(vault);
+        drop(db);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_purging_a_transfer_clears_payments_on_both_legs() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+        let finance = FinanceService::new(&db);
+        let trash = TrashService::new(&db);
+        let checking = finance.create_account("Checking", "checking", 0).unwrap();
+        let card = finance.create_account("Card", "credit", 0).unwrap();
+        let bill = finance
+            .create_bill("Card bill", 5000, "monthly", "2024-01-20", None, None)
+            .unwrap();
+        let leg = |transfer_id: &str, incoming: bool| -> String {
+            db.conn
+                .query_row(
+                    "SELECT id FROM transactions WHERE transfer_id = ?1 AND (amount_cents > 0) = ?2",
+                    (transfer_id, incoming),
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        let paid_count = || -> i64 {
+            db.conn
+                .query_row(
+                    "SELECT count(*) FROM bill_payments WHERE transaction_id IS NOT NULL",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+
+        // The payment points at the card side; the checking side is purged
+        let first = finance
+            .create_transfer(&checking, &card, 5000, "2024-01-20", None)
+            .unwrap();
+        finance.mark_bill_paid(&bill, &leg(&first, true)).unwrap();
+        let outgoing = leg(&first, false);
+        finance.delete_transfer(&first).unwrap();
+        trash.purge(TrashKind::Transaction, &outgoing).unwrap();
+        assert_eq!(paid_count(), 0);
+
+        // Emptying the trash goes the same way
+        let second = finance
+            .create_transfer(&checking, &card, 5000, "2024-02-20", None)
+            .unwrap();
+        finance.mark_bill_paid(&bill, &leg(&second, true)).unwrap();
+        finance.delete_transfer(&second).unwrap();
+        assert_eq!(trash.empty_trash(0).unwrap(), 2);
+        assert_eq!(paid_count(), 0);
+        assert_eq!(finance.get_bill_payments(&bill).unwrap().len(), 2);
+        let left: i64 = db
+            .conn
+            .query_row("SELECT count(*) FROM transactions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(left, 0);
+    }
+}
@@ -1,4 +1,6 @@
-use crate::db::Db;
+use crate::db::{expect_changed, Db};
+use crate::modules::household::sharing::{require, Role};
+use crate::modules::trash::{self, TrashKind};
 use chrono::Utc;
 use rusqlite::Result;
 use serde::{Deserialize, Serialize};
@@ -25,6 +27,7 @@ impl<'a> TravelService<'a> {
     }
 
     pub fn get_trips(&self) -> Result<Vec<Trip>> {
+        require(self.db, "travel", Role::Viewer)?;
         let mut stmt = self.db.conn.prepare(
             "SELECT id, name, destination, start_date, end_date, trip_type, status FROM trips WHERE deleted_at IS NULL ORDER BY start_date ASC"
         )?;
@@ -54,16 +57,54 @@ impl<'a> TravelService<'a> {
         start_date: &str,
         end_date: &str,
         trip_type: Option<&str>,
-    ) -> Result<()> {
+    ) -> Result<String> {
+        require(self.db, "travel", Role::Editor)?;
         let id = Uuid::new_v4().to_string();
         let now = Utc::now().to_rfc3339();
 
         self.db.conn.execute(
             "INSERT INTO trips (id, name, destination, start_date, end_date, trip_type, status, currency_code, created_at, updated_at)
              VALUES (?1, ?2, ?3, ?4, ?5, ?6, 'planning', 'INR', ?7, ?8)",
-            (id, name, destination, start_date, end_date, trip_type, &now, &now),
+            (&id, name, destination, start_date, end_date, trip_type, &now, &now),
         )?;
-        Ok(())
+        Ok(id)
+    }
+
+    pub fn update_trip(&self, trip: &Trip) -> Result<()> {
+        require(self.db, "travel", Role::Editor)?;
+        let now = Utc::now().to_rfc3339();
+
+        let changed = self.db.conn.execute(
+            "UPDATE trips SET name = ?1, destination = ?2, start_date = ?3, end_date = ?4, trip_type = ?5, status = ?6, updated_at = ?7
+             WHERE id = ?8 AND deleted_at IS NULL",
+            (
+                &trip.name,
+                &trip.destination,
+                &trip.start_date,
+                &trip.end_date,
+                &trip.trip_type,
+                &trip.status,
+                &now,
+                &trip.id,
+            ),
+        )?;
+        expect_changed(changed)
+    }
+
+    pub fn delete_trip(&self, id: &str) -> Result<()> {
+        require(self.db, "travel", Role::Editor)?;
+        self.db.soft_delete("trips", id)
+    }
+
+    pub fn restore_trip(&self, id: &str) -> Result<()> {
+        require(self.db, "travel", Role::Editor)?;
+        self.db.restore_deleted("trips", id)
+    }
+
+    /// Permanently removes trips deleted more than `older_than_days` ago.
+    pub fn purge_deleted(&self, older_than_days: i64) -> Result<usize> {
+        require(self.db, "travel", Role::Editor)?;
+        trash::purge_older_than(&self.db.conn, &[TrashKind::Trip], older_than_days)
     }
 }
 
@@ -104,4 +145,39 @@ mod tests {
         assert_eq!(trips[0].destination, "New York");
         assert_eq!(trips[1].destination, "Paris");
     }
+
+    #[test]
+    fn test_trip_update_delete_restore() {
+        let db = Db::new(":memory:").unwrap();
+        db.init().unwrap();
+
+        let service = TravelService::new(&db);
+        let id = service
+            .add_trip("Goa", "Goa", "2024-12-20", "2024-12-27", None)
+            .unwrap();
+
+        let mut trip = service.get_trips().unwrap().remove(0);
+        trip.destination = "North Goa".to_string();
+        trip.status = "upcoming".to_string();
+        service.update_trip(&trip).unwrap();
+
+        let trips = service.get_trips().unwrap();
+        assert_eq!(trips[0].destination, "North Goa");
+        assert_eq!(trips[0].status, "upcoming");
+
+        service.delete_trip(&id).unwrap();
+        assert!(service.get_trips().unwrap().is_empty());
+        // Deleted rows cannot be edited or deleted twice
+        assert!(service.update_trip(&trip).is_err());
+        assert!(service.delete_trip(&id).is_err());
+
+        // Purge keeps rows younger than the retention window
+        assert_eq!(service.purge_deleted(30).unwrap(), 0);
+        service.restore_trip(&id).unwrap();
+        assert_eq!(service.get_trips().unwrap().len(), 1);
+
+        service.delete_trip(&id).unwrap();
+        assert_eq!(service.purge_deleted(0).unwrap(), 1);
+        assert!(service.restore_trip(&id).is_err());
+    }
 }